@@ -0,0 +1,53 @@
+//! # Fatal faults
+//!
+//! Access faults, page faults & illegal instructions of user tasks destroy the task instead of
+//! halting the system. A core dump of the task is taken first if enabled. Faults of the kernel
+//! itself are still fatal.
+
+use crate::task;
+
+/// The `SPP` bit in `sstatus`, which is set if the trap was taken from supervisor mode.
+pub(super) const SSTATUS_SPP: usize = 1 << 8;
+
+#[export_name = "fault_trap_handler"]
+extern "C" fn handler(
+	_: usize,
+	_: usize,
+	_: usize,
+	_: usize,
+	_: usize,
+	_: usize,
+	task: task::Task,
+) {
+	let (status, cause, pc, value): (usize, usize, usize, usize);
+	unsafe { asm!("csrr {0}, sstatus", out(reg) status) };
+	unsafe { asm!("csrr {0}, scause", out(reg) cause) };
+	unsafe { asm!("csrr {0}, sepc", out(reg) pc) };
+	unsafe { asm!("csrr {0}, stval", out(reg) value) };
+	if status & SSTATUS_SPP > 0 {
+		panic!(
+			"fault in kernel: scause 0x{:x}, sepc 0x{:x}, stval 0x{:x}",
+			cause, pc, value
+		);
+	}
+	log!("Task {:?} faulted", task::Executor::current_address());
+	kill(task, cause, pc, value)
+}
+
+/// Destroy the current task & all threads sharing its memory after a fault it can't recover
+/// from. A core dump is taken first if enabled. The threads are destroyed too so they can't
+/// change the memory while it is being dumped.
+///
+/// The registers of the task must have been saved by the trap handler.
+pub fn kill(task: task::Task, cause: usize, pc: usize, value: usize) -> ! {
+	let address = task::Executor::current_address();
+	log!(
+		"  scause 0x{:x}, sepc 0x{:x}, stval 0x{:x}",
+		cause,
+		pc,
+		value
+	);
+	crate::coredump::take(&task, address, cause, pc, value);
+	task::Task::destroy_all(address).expect("current task doesn't exist");
+	task::Executor::next()
+}
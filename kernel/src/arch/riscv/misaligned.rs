@@ -0,0 +1,404 @@
+//! # Misaligned load & store emulation
+//!
+//! Implementations may trap on misaligned accesses instead of handling them in hardware. Such
+//! traps of user tasks are handled here by decoding the instruction and performing the access
+//! byte by byte.
+//!
+//! Only integer loads & stores are emulated, both standard and compressed. Floating point
+//! registers aren't saved on traps and atomic operations can't be emulated atomically, so
+//! tasks that perform such accesses are destroyed, as are tasks that opted out of emulation.
+//!
+//! Every emulated access is counted in the stats of the task, which makes it easy to find
+//! code that should be fixed.
+
+use crate::arch::vms::{VirtualMemorySystem, RWX};
+use crate::arch::{self, Page};
+use crate::task;
+use core::ptr;
+
+/// The `MXR` bit in `sstatus`, which allows reading executable pages.
+const SSTATUS_MXR: usize = 1 << 19;
+
+/// The end of the lower half of the Sv39 address space, which is where user tasks live.
+const USER_END: usize = 1 << 38;
+
+/// A decoded load or store instruction.
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Access {
+	kind: Kind,
+	/// The base address register.
+	base: u8,
+	offset: isize,
+	/// The size of the access in bytes.
+	size: u8,
+	/// The length of the instruction in bytes.
+	length: u8,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Kind {
+	Load { rd: u8, signed: bool },
+	Store { rs: u8 },
+}
+
+/// Decode a standard (32-bit) or compressed (16-bit) load or store instruction.
+fn decode(instr: u32) -> Option<Access> {
+	let bits = |lo: u32, len: u32| (instr >> lo) & ((1 << len) - 1);
+	if instr & 0b11 == 0b11 {
+		let (rd, rs1, rs2) = (bits(7, 5) as u8, bits(15, 5) as u8, bits(20, 5) as u8);
+		let funct3 = bits(12, 3);
+		match bits(0, 7) {
+			// LOAD
+			0x03 => {
+				let (size, signed) = match funct3 {
+					1 => (2, true),
+					2 => (4, true),
+					3 => (8, true),
+					5 => (2, false),
+					6 => (4, false),
+					// Byte loads can't be misaligned.
+					_ => return None,
+				};
+				Some(Access {
+					kind: Kind::Load { rd, signed },
+					base: rs1,
+					offset: (instr as i32 >> 20) as isize,
+					size,
+					length: 4,
+				})
+			}
+			// STORE
+			0x23 => {
+				let size = match funct3 {
+					1 => 2,
+					2 => 4,
+					3 => 8,
+					_ => return None,
+				};
+				let offset = ((instr as i32 >> 25) << 5) as u32 | bits(7, 5);
+				Some(Access {
+					kind: Kind::Store { rs: rs2 },
+					base: rs1,
+					offset: offset as i32 as isize,
+					size,
+					length: 4,
+				})
+			}
+			_ => None,
+		}
+	} else {
+		let (funct3, quadrant) = (bits(13, 3), bits(0, 2));
+		// rd' / rs2' and rs1' of the CL & CS formats.
+		let (rs2c, rs1c) = (bits(2, 3) as u8 + 8, bits(7, 3) as u8 + 8);
+		let access = |kind, base, offset: u32, size| {
+			Some(Access {
+				kind,
+				base,
+				offset: offset as isize,
+				size,
+				length: 2,
+			})
+		};
+		let load = |rd| Kind::Load { rd, signed: true };
+		let store = |rs| Kind::Store { rs };
+		match (quadrant, funct3) {
+			// C.LW
+			(0, 2) => {
+				let offset = bits(10, 3) << 3 | bits(6, 1) << 2 | bits(5, 1) << 6;
+				access(load(rs2c), rs1c, offset, 4)
+			}
+			// C.LD
+			(0, 3) => {
+				let offset = bits(10, 3) << 3 | bits(5, 2) << 6;
+				access(load(rs2c), rs1c, offset, 8)
+			}
+			// C.SW
+			(0, 6) => {
+				let offset = bits(10, 3) << 3 | bits(6, 1) << 2 | bits(5, 1) << 6;
+				access(store(rs2c), rs1c, offset, 4)
+			}
+			// C.SD
+			(0, 7) => {
+				let offset = bits(10, 3) << 3 | bits(5, 2) << 6;
+				access(store(rs2c), rs1c, offset, 8)
+			}
+			// C.LWSP
+			(2, 2) if bits(7, 5) != 0 => {
+				let offset = bits(12, 1) << 5 | bits(4, 3) << 2 | bits(2, 2) << 6;
+				access(load(bits(7, 5) as u8), 2, offset, 4)
+			}
+			// C.LDSP
+			(2, 3) if bits(7, 5) != 0 => {
+				let offset = bits(12, 1) << 5 | bits(5, 2) << 3 | bits(2, 3) << 6;
+				access(load(bits(7, 5) as u8), 2, offset, 8)
+			}
+			// C.SWSP
+			(2, 6) => {
+				let offset = bits(9, 4) << 2 | bits(7, 2) << 6;
+				access(store(bits(2, 5) as u8), 2, offset, 4)
+			}
+			// C.SDSP
+			(2, 7) => {
+				let offset = bits(10, 3) << 3 | bits(7, 3) << 6;
+				access(store(bits(2, 5) as u8), 2, offset, 8)
+			}
+			_ => None,
+		}
+	}
+}
+
+/// Check whether the given range of bytes is in user space and the task may access it with
+/// the given permissions.
+fn is_user_range(address: usize, size: usize, permissions: RWX) -> bool {
+	let end = match address.checked_add(size) {
+		Some(end) => end,
+		None => return false,
+	};
+	if end > USER_END {
+		return false;
+	}
+	let mut page = address & !arch::PAGE_MASK;
+	while page < end {
+		let required = permissions as u8;
+		let allowed = Page::from_usize(page)
+			.ok()
+			.and_then(arch::VMS::user_rwx)
+			.map_or(false, |rwx| rwx as u8 & required == required);
+		if !allowed {
+			return false;
+		}
+		page += Page::SIZE;
+	}
+	true
+}
+
+/// Read a halfword of an instruction.
+///
+/// # Safety
+///
+/// The address must be mapped in user space.
+unsafe fn read_instruction(address: usize) -> u16 {
+	arch::set_supervisor_userpage_access(true);
+	asm!("csrs sstatus, {0}", in(reg) SSTATUS_MXR);
+	let parcel = ptr::read_volatile(address as *const u16);
+	asm!("csrc sstatus, {0}", in(reg) SSTATUS_MXR);
+	arch::set_supervisor_userpage_access(false);
+	parcel
+}
+
+/// Fetch the instruction at the given address.
+fn fetch(address: usize) -> Option<u32> {
+	if !is_user_range(address, 2, RWX::X) {
+		return None;
+	}
+	// SAFETY: the address is executable in user space.
+	let low = unsafe { read_instruction(address) };
+	if low & 0b11 != 0b11 {
+		return Some(low.into());
+	}
+	// Fetch the upper half separately as it may be on the next page.
+	if !is_user_range(address + 2, 2, RWX::X) {
+		return None;
+	}
+	// SAFETY: ditto
+	let high = unsafe { read_instruction(address + 2) };
+	Some(u32::from(low) | u32::from(high) << 16)
+}
+
+/// Read a little-endian integer of `size` bytes byte by byte and sign-extend it if `signed`
+/// is set.
+///
+/// # Safety
+///
+/// The range must be readable.
+unsafe fn read(address: *const u8, size: usize, signed: bool) -> u64 {
+	let mut value = 0u64;
+	for i in (0..size).rev() {
+		value = value << 8 | u64::from(ptr::read_volatile(address.add(i)));
+	}
+	if signed {
+		let shift = 64 - size * 8;
+		value = ((value << shift) as i64 >> shift) as u64;
+	}
+	value
+}
+
+/// Write the lower `size` bytes of a value byte by byte in little-endian order.
+///
+/// # Safety
+///
+/// The range must be writeable.
+unsafe fn write(address: *mut u8, size: usize, value: u64) {
+	for i in 0..size {
+		ptr::write_volatile(address.add(i), (value >> (i * 8)) as u8);
+	}
+}
+
+/// Emulate a load or store. Returns `None` if the task may not access the memory.
+fn emulate(task: &task::Task, access: Access) -> Option<()> {
+	let regs = task.register_state();
+	let address = regs.get(access.base).wrapping_add(access.offset as usize);
+	let size = usize::from(access.size);
+	let permissions = match access.kind {
+		Kind::Load { .. } => RWX::R,
+		Kind::Store { .. } => RWX::RW,
+	};
+	if !is_user_range(address, size, permissions) {
+		return None;
+	}
+	let address = address as *mut u8;
+	arch::set_supervisor_userpage_access(true);
+	match access.kind {
+		// SAFETY: the range is readable in user space.
+		Kind::Load { rd, signed } => regs.set(rd, unsafe { read(address, size, signed) } as usize),
+		// SAFETY: the range is writeable in user space.
+		Kind::Store { rs } => unsafe { write(address, size, regs.get(rs) as u64) },
+	}
+	arch::set_supervisor_userpage_access(false);
+	Some(())
+}
+
+/// Handle a misaligned load or store trap of the current task.
+///
+/// The arguments match those of syscalls so that the trap handler can call this directly.
+#[export_name = "misaligned_trap_handler"]
+extern "C" fn handler(
+	_: usize,
+	_: usize,
+	_: usize,
+	_: usize,
+	_: usize,
+	_: usize,
+	task: task::Task,
+) {
+	let (status, pc): (usize, usize);
+	unsafe { asm!("csrr {0}, sstatus", out(reg) status) };
+	unsafe { asm!("csrr {0}, sepc", out(reg) pc) };
+	if status & super::fault::SSTATUS_SPP > 0 {
+		let (cause, value): (usize, usize);
+		unsafe { asm!("csrr {0}, scause", out(reg) cause) };
+		unsafe { asm!("csrr {0}, stval", out(reg) value) };
+		panic!(
+			"misaligned access in kernel: scause 0x{:x}, sepc 0x{:x}, stval 0x{:x}",
+			cause, pc, value
+		);
+	}
+
+	let access = task
+		.emulate_misaligned()
+		.then(|| fetch(pc))
+		.flatten()
+		.and_then(decode);
+	if let Some(access) = access {
+		if emulate(&task, access).is_some() {
+			task.stats_mut().misaligned_emulations += 1;
+			let pc = pc + usize::from(access.length);
+			unsafe { asm!("csrw sepc, {0}", in(reg) pc) };
+			return;
+		}
+	}
+
+	let (cause, value): (usize, usize);
+	unsafe { asm!("csrr {0}, scause", out(reg) cause) };
+	unsafe { asm!("csrr {0}, stval", out(reg) value) };
+	let address = task::Executor::current_address();
+	log!(
+		"Task {:?} performed a misaligned access that can't be emulated",
+		address
+	);
+	super::fault::kill(task, cause, pc, value)
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	fn load(rd: u8, base: u8, offset: isize, size: u8, signed: bool, length: u8) -> Access {
+		Access {
+			kind: Kind::Load { rd, signed },
+			base,
+			offset,
+			size,
+			length,
+		}
+	}
+
+	fn store(rs: u8, base: u8, offset: isize, size: u8, length: u8) -> Access {
+		Access {
+			kind: Kind::Store { rs },
+			base,
+			offset,
+			size,
+			length,
+		}
+	}
+
+	#[test]
+	fn standard() {
+		// lw a0, 3(a1)
+		assert_eq!(decode(0x0035a503), Some(load(10, 11, 3, 4, true, 4)));
+		// ld t0, -1(sp)
+		assert_eq!(decode(0xfff13283), Some(load(5, 2, -1, 8, true, 4)));
+		// lhu a2, 1(a3)
+		assert_eq!(decode(0x0016d603), Some(load(12, 13, 1, 2, false, 4)));
+		// lwu a2, 2047(a3)
+		assert_eq!(decode(0x7ff6e603), Some(load(12, 13, 2047, 4, false, 4)));
+		// sw a0, 5(a1)
+		assert_eq!(decode(0x00a5a2a3), Some(store(10, 11, 5, 4, 4)));
+		// sd s0, -9(sp)
+		assert_eq!(decode(0xfe813ba3), Some(store(8, 2, -9, 8, 4)));
+		// sh a4, -2048(a5)
+		assert_eq!(decode(0x80e79023), Some(store(14, 15, -2048, 2, 4)));
+		// lb & sb can't be misaligned
+		assert_eq!(decode(0x00058503), None);
+		assert_eq!(decode(0x00a58023), None);
+		// flw
+		assert_eq!(decode(0x0005a507), None);
+	}
+
+	#[test]
+	fn compressed() {
+		// c.lw a0, 4(a1)
+		assert_eq!(decode(0x41c8), Some(load(10, 11, 4, 4, true, 2)));
+		// c.lw a0, 64(a1)
+		assert_eq!(decode(0x41a8), Some(load(10, 11, 64, 4, true, 2)));
+		// c.ld a5, 248(a4)
+		assert_eq!(decode(0x7f7c), Some(load(15, 14, 248, 8, true, 2)));
+		// c.sw a0, 124(a1)
+		assert_eq!(decode(0xdde8), Some(store(10, 11, 124, 4, 2)));
+		// c.sd s1, 8(s0)
+		assert_eq!(decode(0xe404), Some(store(9, 8, 8, 8, 2)));
+		// c.lwsp ra, 252(sp)
+		assert_eq!(decode(0x50fe), Some(load(1, 2, 252, 4, true, 2)));
+		// c.ldsp s0, 504(sp)
+		assert_eq!(decode(0x747e), Some(load(8, 2, 504, 8, true, 2)));
+		// c.swsp a0, 12(sp)
+		assert_eq!(decode(0xc62a), Some(store(10, 2, 12, 4, 2)));
+		// c.sdsp ra, 8(sp)
+		assert_eq!(decode(0xe406), Some(store(1, 2, 8, 8, 2)));
+		// c.fld
+		assert_eq!(decode(0x2188), None);
+		// c.lwsp with rd = x0 is reserved
+		assert_eq!(decode(0x4002), None);
+	}
+
+	#[test]
+	fn round_trip() {
+		let value = 0x89ab_cdef_8765_c321u64;
+		for &size in &[2, 4, 8] {
+			let shift = 64 - size * 8;
+			let unsigned = value << shift >> shift;
+			let signed = ((value << shift) as i64 >> shift) as u64;
+			for offset in 1..8 {
+				let mut buf = [0u8; 16];
+				let address = buf[offset..].as_mut_ptr();
+				unsafe { write(address, size, value) };
+				assert_eq!(buf[offset..offset + size], value.to_le_bytes()[..size]);
+				assert_eq!(buf[..offset], [0; 8][..offset]);
+				assert!(buf[offset + size..].iter().all(|&b| b == 0));
+				assert_eq!(unsafe { read(address, size, false) }, unsigned);
+				assert_eq!(unsafe { read(address, size, true) }, signed);
+			}
+		}
+	}
+}
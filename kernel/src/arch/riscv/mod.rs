@@ -8,6 +8,9 @@
 //! [spec]: https://github.com/riscv/riscv-isa-manual/releases/download/Ratified-IMAFDQC/riscv-spec-20191213.pdf
 //! [priv]: https://github.com/riscv/riscv-isa-manual/releases/download/Ratified-IMFDQC-and-Priv-v1.11/riscv-privileged-20190608.pdf
 
+mod fault;
+#[cfg(feature = "emulate-misaligned")]
+mod misaligned;
 pub(super) mod plic;
 pub mod rv64;
 pub mod sbi;
@@ -37,6 +40,34 @@ impl RegisterState {
 	pub fn set_stack_pointer(&mut self, address: *const ()) {
 		self.x[2 - 1] = address as usize;
 	}
+
+	/// Set the first argument register to the given value.
+	#[inline(always)]
+	pub fn set_argument(&mut self, value: usize) {
+		self.x[10 - 1] = value;
+	}
+
+	/// Set the registers holding the return value of a system call.
+	#[inline(always)]
+	pub fn set_return(&mut self, status: usize, value: usize) {
+		self.x[10 - 1] = status;
+		self.x[11 - 1] = value;
+	}
+
+	/// Return the value of an integer register. `x0` is always `0`.
+	pub fn get(&self, register: u8) -> usize {
+		match register {
+			0 => 0,
+			r => self.x[usize::from(r) - 1],
+		}
+	}
+
+	/// Set the value of an integer register. Writes to `x0` are ignored.
+	pub fn set(&mut self, register: u8, value: usize) {
+		if register != 0 {
+			self.x[usize::from(register) - 1] = value;
+		}
+	}
 }
 impl Default for RegisterState {
 	fn default() -> Self {
@@ -90,6 +121,8 @@ mod trap {
 	global_asm!("__RISCV64__:");
 	#[cfg(target_arch = "riscv32")]
 	global_asm!("__RISCV32__:");
+	#[cfg(feature = "emulate-misaligned")]
+	global_asm!("__EMULATE_MISALIGNED__:");
 
 	global_asm!(include_str!("types.s"));
 	global_asm!(include_str!("registers.s"));
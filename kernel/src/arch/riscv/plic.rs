@@ -96,6 +96,26 @@ pub fn reserve(source: u16, address: Address) -> Result<(), ReserveError> {
 	Ok(())
 }
 
+/// Release all interrupt sources reserved by the given task.
+pub fn release_all(address: Address) {
+	let context = 1; // TODO ditto
+	let reservations = &RESERVATIONS[..usize::from(*TOTAL_SOURCES)];
+	for (source, entry) in reservations.iter().enumerate() {
+		if entry
+			.compare_exchange(
+				address.into(),
+				usize::MAX,
+				Ordering::Relaxed,
+				Ordering::Relaxed,
+			)
+			.is_ok()
+		{
+			let source = NonZeroU16::new(source as u16 + 1).unwrap();
+			PLIC.enable(context, source, false).unwrap();
+		}
+	}
+}
+
 /// A RISC-V Platform Level Interrupt Controller. This must be set up to receive
 /// interrupts at all.
 pub struct PLIC {
@@ -9,19 +9,7 @@ external_interrupt_handler:
 	# This isn't strictly necessary when the interrupt is addressed at the
 	# active task, but a branch to compare the TID is likely more expensive
 	# anyways.
-	csrrw			x31, sscratch, x31
-	save_gp_regs	1, 30, x31
-	# Save the remaining x31 register and restore sscratch as well.
-	csrrw			x30, sscratch, x31
-	gp_store		x30, 31 * GP_REGBYTES, x31
-	# Save pc
-	csrr			x30, sepc
-	gp_store		x30, 0 * GP_REGBYTES, x31
-
-	# Fix kernel stack, needed for call later
-	# FIXME this causes UB with the pseudo task, as it has no valid stack
-	# pointer
-	gp_load			sp, TASK_STACK, x31
+	save_task_regs
 
 	# Claim the interrupt.
 	# We need to do this now because we can't return to userspace otherwise.
@@ -80,10 +68,13 @@ external_interrupt_handler:
 	j		notification_enter
 
 
-# a0: type
+# a0: type, which must be less than NOTIFY_TYPE_COUNT
 # a1: value
 # a7: address
 # x31: task pointer
+#
+# The handler is called with the context in a2. The original a[0127] and pc
+# are pushed to the stack of the task.
 notification_enter:
 
 	# Switch to U-mode when executing sret.
@@ -101,21 +92,31 @@ notification_enter:
 	csrw	satp, t0
 	sfence.vma
 
+	# Remember the type in case the handler defers the notification
+	sh		a0, TASK_NOTIFY_TYPE (x31)
+
+	# Get the handler for this type of notification
+	li		t0, NOTIFY_HANDLER_SIZE
+	mul		t0, t0, a0
+	add		t0, t0, x31
+
 	# Set sepc to that of the notification handler
-	gp_load		t0, 2 * GP_REGBYTES + REGSTATE_SIZE, x31
-	csrw		sepc, t0
+	gp_load		t1, TASK_NOTIFY_HANDLERS, t0
+	csrw		sepc, t1
 
 	# Restore stack
 	load_gp_regs 2, 2, x31
 
 	# Enable SUM
-	li		t0, 1 << 18
-	csrs	sstatus, t0
+	li		t1, 1 << 18
+	csrs	sstatus, t1
 
-	# Push original a[017] and pc to stack
+	# Push original a[0127] and pc to stack
 	gp_load		x30, 10 * GP_REGBYTES, x31
-	gp_store	x30, -4 * GP_REGBYTES, sp
+	gp_store	x30, -5 * GP_REGBYTES, sp
 	gp_load		x30, 11 * GP_REGBYTES, x31
+	gp_store	x30, -4 * GP_REGBYTES, sp
+	gp_load		x30, 12 * GP_REGBYTES, x31
 	gp_store	x30, -3 * GP_REGBYTES, sp
 	gp_load		x30, 17 * GP_REGBYTES, x31
 	gp_store	x30, -2 * GP_REGBYTES, sp
@@ -123,7 +124,10 @@ notification_enter:
 	gp_store	x30, -1 * GP_REGBYTES, sp
 
 	# Disable SUM and SPP to ensure we will enter usermode
-	csrc	sstatus, t0
+	csrc	sstatus, t1
+
+	# Pass the context of the handler
+	gp_load		a2, TASK_NOTIFY_HANDLERS + GP_REGBYTES, t0
 
 	# == FIXME save the FP registers
 	li		t0, 1 << 13
@@ -133,10 +137,10 @@ notification_enter:
 	# ==
 
 	# Load all registers except the stack pointer (x2), since
-	# the stack pointer is already loaded, and a[017] (x10/11/17).
+	# the stack pointer is already loaded, and a[0127] (x10/11/12/17).
 	load_gp_regs 1, 1, x31
 	load_gp_regs 3, 9, x31
-	load_gp_regs 12, 16, x31
+	load_gp_regs 13, 16, x31
 	load_gp_regs 18, 31, x31
 
 	# Jump to notification handler
@@ -28,6 +28,27 @@
 	.endif
 .endm
 
+# Save the state of the interrupted task and switch to its kernel stack.
+#
+# sscratch must point to the task's register state. Afterwards, x31 points to
+# the register state and sscratch is restored.
+.macro save_task_regs
+	# Save all the general purpose registers.
+	csrrw			x31, sscratch, x31
+	save_gp_regs	1, 30, x31
+	# Save the remaining x31 register and restore sscratch as well.
+	csrrw			x30, sscratch, x31
+	gp_store		x30, 31 * GP_REGBYTES, x31
+	# Save pc
+	csrr			x30, sepc
+	gp_store		x30, 0 * GP_REGBYTES, x31
+
+	# Fix kernel stack, needed for call later
+	# FIXME this causes UB with the pseudo task, as it has no valid stack
+	# pointer
+	gp_load			sp, TASK_STACK, x31
+.endm
+
 # Clear the given range of general purpose registers
 .macro clear_gp_regs	from, to
 	.altmacro
@@ -25,3 +25,17 @@ pub fn set_timer(value: u64) {
 	}
 	unsafe { asm!("csrs sie, {0}", in(reg) (1 << 5) | (1 << 9)) };
 }
+
+/// The ID of the system suspend extension.
+pub const EXTENSION_SUSPEND: usize = 0x5355_5350;
+
+// TODO ditto
+#[inline(never)]
+pub fn probe_extension(id: usize) -> bool {
+	let available: usize;
+	// SAFETY: probing an extension has no side effects.
+	unsafe {
+		asm!("ecall", in("a7") 0x10, in("a6") 3, inout("a0") id => _, lateout("a1") available);
+	}
+	available != 0
+}
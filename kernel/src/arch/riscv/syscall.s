@@ -59,12 +59,13 @@ syscall_io_notify_return:
 	li		t0, 1 << 18
 	csrs	sstatus, t0
 
-	# Pop a[017] and pc from the stack
-	gp_load		a0, -4 * GP_REGBYTES, sp
-	gp_load		a1, -3 * GP_REGBYTES, sp
+	# Pop a[0127] and pc from the stack
+	gp_load		a0, -5 * GP_REGBYTES, sp
+	gp_load		a1, -4 * GP_REGBYTES, sp
+	gp_load		a2, -3 * GP_REGBYTES, sp
 	gp_load		a7, -2 * GP_REGBYTES, sp
-	gp_load		t0, -1 * GP_REGBYTES, sp
-	csrw		sepc, t0
+	gp_load		t1, -1 * GP_REGBYTES, sp
+	csrw		sepc, t1
 
 	# Disable SUM
 	csrc	sstatus, t0
@@ -76,9 +77,9 @@ syscall_io_notify_return:
 	csrs	sstatus, t0
 	# ==
 
-	# Restore all registers except a[017] and sp
+	# Restore all registers except a[0127] and sp
 	load_gp_regs	1, 9, x31
-	load_gp_regs	12, 16, x31
+	load_gp_regs	13, 16, x31
 	load_gp_regs	18, 31, x31
 
 	# Begin running the task
@@ -99,13 +100,18 @@ syscall_io_notify_defer:
 	j		mini_panic
 66:
 
-	# Get the a[01] from the notification handler that just ran
-	# FIXME find a way to properly pass the type between defers
-	# the task's a0 is being used to indicate deferment.
-	# Perhaps require the task to store the original value on 0(sp)?
-	gp_load		s0, 10 * GP_REGBYTES, a0
+	# Get the type and a1 of the notification handler that just ran.
+	# The task's a0 is used to indicate deferment, so the type is taken
+	# from the field set by notification_enter instead.
+	lhu		s0, TASK_NOTIFY_TYPE (a0)
 	gp_load		s1, 11 * GP_REGBYTES, a0
 
+	# Drop the notification if the type is somehow invalid
+	li		t0, NOTIFY_TYPE_COUNT
+	bltu	s0, t0, 67f
+	j		syscall_io_notify_return
+67:
+
 	# Restore sp (x2)
 	gp_load		t0, 2 * GP_REGBYTES, a0
 
@@ -113,11 +119,13 @@ syscall_io_notify_defer:
 	li		t1, 1 << 18
 	csrs	sstatus, t1
 
-	# Pop a[017] and pc from the stack
-	gp_load		t2, -4 * GP_REGBYTES, t0
+	# Pop a[0127] and pc from the stack
+	gp_load		t2, -5 * GP_REGBYTES, t0
 	gp_store	t2, 10 * GP_REGBYTES, a0
-	gp_load		t2, -3 * GP_REGBYTES, t0 
+	gp_load		t2, -4 * GP_REGBYTES, t0
 	gp_store	t2, 11 * GP_REGBYTES, a0
+	gp_load		t2, -3 * GP_REGBYTES, t0
+	gp_store	t2, 12 * GP_REGBYTES, a0
 	gp_load		t2, -2 * GP_REGBYTES, t0
 	gp_store	t2, 17 * GP_REGBYTES, a0
 	gp_load		t2, -1 * GP_REGBYTES, t0
@@ -1,5 +1,7 @@
 # Trap handling routines
 
+# stvec is set to vectored mode, so each interrupt jumps straight to its own
+# handler without decoding scause. All exceptions go through entry 0.
 .section .text.hot
 	.balign 4	# 0
 interrupt_table:
@@ -30,21 +32,30 @@ interrupt_table:
 
 	.balign 4	# 0
 sync_trap_table:
-	j	mini_panic	
+	j	fault_trap_handler	# Instruction address misaligned
 	.balign 4	# 1
-	j	mini_panic	
+	j	fault_trap_handler	# Instruction access fault
 	.balign 4	# 2
-	j	mini_panic	
+	j	fault_trap_handler	# Illegal instruction
 	.balign 4	# 3
 	j	mini_panic	
+.ifdef __EMULATE_MISALIGNED__
 	.balign 4	# 4
-	j	mini_panic	
+	j	misaligned_trap_handler	# Load address misaligned
 	.balign 4	# 5
-	j	mini_panic	
+	j	fault_trap_handler	# Load access fault
 	.balign 4	# 6
-	j	mini_panic
+	j	misaligned_trap_handler	# Store address misaligned
+.else
+	.balign 4	# 4
+	j	fault_trap_handler	# Load address misaligned
+	.balign 4	# 5
+	j	fault_trap_handler	# Load access fault
+	.balign 4	# 6
+	j	fault_trap_handler	# Store address misaligned
+.endif
 	.balign 4	# 7
-	j	mini_panic	
+	j	fault_trap_handler	# Store access fault
 	.balign 4	# 8
 	jal		trap_syscall
 	.balign 4	# 9
@@ -54,14 +65,13 @@ sync_trap_table:
 	.balign 4	# 11
 	j	mini_panic # We shouldn't be able to catch M-mode syscalls
 	.balign 4	# 12
-	j	mini_panic
-	#ret
+	j	fault_trap_handler	# Instruction page fault
 	.balign 4	# 13
-	j	mini_panic
+	j	fault_trap_handler	# Load page fault
 	.balign 4	# 14
 	j	mini_panic
 	.balign 4	# 15
-	j	mini_panic
+	j	fault_trap_handler	# Store page fault
 
 ## Default handler for traps
 trap_handler:
@@ -133,7 +143,8 @@ trap_syscall:
 	csrw	sepc, t0
 
 	# Check if the syscall exists, otherwise return the 'no syscall' error code
-	li		t1, SYSCALL_MAX
+	la		t1, syscall_table_len
+	gp_load	t1, 0, t1
 	bgeu	a7, t1, 1f
 
 	# Look up the entry in the call table
@@ -170,7 +181,7 @@ trap_syscall:
 ## Initialize the trap CSR and the interrupt table
 trap_init:
 	la		t0, interrupt_table
-	ori		t0, t0, 1
+	ori		t0, t0, 1	# Vectored mode
 	csrw	stvec, t0
 	ret
 
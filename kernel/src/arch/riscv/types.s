@@ -89,27 +89,30 @@
 .equ		REGSTATE_SIZE, (GP_REGSTATE_SIZE + FP_REGSTATE_SIZE)
 
 
+# A notification handler is a function pointer followed by a context.
+.equ		NOTIFY_HANDLER_SIZE, (2 * GP_REGBYTES)
+.equ		NOTIFY_TYPE_COUNT, 3
+
+
 # The offset of the tasks' fields.
 .equ		TASK_STACK, REGSTATE_SIZE
 .equ		TASK_VMS, (TASK_STACK + GP_REGBYTES)
-.equ		TASK_NOTIFY_HANDLER, (TASK_VMS + GP_REGBYTES)
-.equ		TASK_IRQ, (TASK_NOTIFY_HANDLER + GP_REGBYTES)
+.equ		TASK_NOTIFY_HANDLERS, (TASK_VMS + GP_REGBYTES)
+.equ		TASK_IRQ, (TASK_NOTIFY_HANDLERS + NOTIFY_TYPE_COUNT * NOTIFY_HANDLER_SIZE)
 .equ		TASK_FLAGS, (TASK_IRQ + 4)
 .equ		TASK_EXECUTOR_ID, (TASK_FLAGS + 2)
 .equ		TASK_PRIORITY, (TASK_EXECUTOR_ID + 2)
 .equ		TASK_PRIORITY_FACTOR, (TASK_PRIORITY + 2)
+.equ		TASK_NOTIFY_TYPE, (TASK_PRIORITY_FACTOR + 2)
 .ifdef	__RISCV64__
-	.equ		_TASK_PADDING_0, (TASK_PRIORITY_FACTOR + 4)
+	.equ		_TASK_PADDING_0, (TASK_NOTIFY_TYPE + 2)
 .else
-	.equ		_TASK_PADDING_0, (TASK_PRIORITY_FACTOR + 0)
+	.equ		_TASK_PADDING_0, (TASK_NOTIFY_TYPE + 0)
 .endif
 .equ		TASK_WAIT_UNTIL, (_TASK_PADDING_0 + 2)
 
 .equ		TASK_FLAG_NOTIFYING, 0x1
 .equ		TASK_FLAG_NOTIFIED, 0x2
 
-# The total amount of system calls, including placeholders
-.equ		SYSCALL_MAX,			20
-
 # The error code for when a syscall was not found.
 .equ		SYSCALL_ERR_NOCALL, 	1
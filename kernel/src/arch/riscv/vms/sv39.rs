@@ -23,6 +23,10 @@ const GLOBAL_KERNEL_TABLE_START_INDEX: usize =
 /// The root table (level 2).
 const ROOT: NonNull<[Entry; 512]> = VMM_ROOT.start.as_non_null_ptr().cast();
 
+/// The end of the userland part of the address space, which is the lower half of the canonical
+/// addresses.
+const USER_END: u64 = 1 << 38;
+
 /// HIGHMEM_A
 const HIGHMEM_A: Page = reserved::HIGHMEM_A.start;
 
@@ -324,6 +328,40 @@ impl VirtualAddress {
 }
 
 impl Sv39 {
+	/// Return a copy of the entry of a page in the active VMS if it is valid & accessible by
+	/// userland.
+	///
+	/// Uses HIGHMEM_A
+	fn user_entry(address: Page) -> Option<Entry> {
+		let va = VirtualAddress(address.as_ptr() as u64);
+		if va.0 >= USER_END {
+			return None;
+		}
+
+		// VPN[2]
+		let mut pte = &unsafe { ROOT.as_ref() }[va.ppn_2()];
+
+		// VPN[1] & VPN[0]
+		for &index in &[va.ppn_1(), va.ppn_0()] {
+			if !pte.is_valid() || !pte.is_table() {
+				break;
+			}
+			let ppn = unsafe { PPN::from_raw((pte.0 >> 10) as u32) };
+			unsafe { Self::map_highmem_a(Some(ppn.as_raw())) };
+			Self::flush_highmem_a();
+			let tbl = unsafe {
+				Self::translate_highmem_a(ppn.as_raw())
+					.as_non_null_ptr()
+					.cast::<[Entry; 512]>()
+					.as_ref()
+			};
+			pte = &tbl[index];
+		}
+
+		let user = pte.0 & Entry::USERMODE_MASK > 0;
+		(pte.is_valid() && user).then(|| Entry(pte.0))
+	}
+
 	/// Uses HIGHMEM_A
 	fn get_pte(address: Page) -> Result<NonNull<Leaf>, AddError> {
 		let va = VirtualAddress(address.as_ptr() as u64);
@@ -802,6 +840,66 @@ impl VirtualMemorySystem for Sv39 {
 		Ok(())
 	}
 
+	fn user_rwx(address: Page) -> Option<RWX> {
+		Self::user_entry(address).and_then(|pte| pte.rwx())
+	}
+
+	fn user_shared(address: Page) -> Option<usize> {
+		let pte = Leaf(Self::user_entry(address)?.0);
+		pte.is_shared().then(|| ((pte.0 & !0x3ff) << 2) as usize)
+	}
+
+	fn next_user_page(address: Page) -> Option<(Page, RWX)> {
+		const MEGA_MASK: u64 = (1 << 21) - 1;
+		let mut va = VirtualAddress(address.as_ptr() as u64);
+		while va.0 < USER_END {
+			// VPN[2]
+			// Mega- and gigapages are only used for direct mappings, so they're skipped.
+			let pte = &unsafe { ROOT.as_ref() }[va.ppn_2()];
+			if !pte.is_valid() || !pte.is_table() {
+				va.0 = (va.0 | ((1 << 30) - 1)) + 1;
+				continue;
+			}
+
+			// VPN[1]
+			let ppn = unsafe { PPN::from_raw((pte.0 >> 10) as u32) };
+			unsafe { Self::map_highmem_a(Some(ppn.as_raw())) };
+			Self::flush_highmem_a();
+			let tbl = unsafe {
+				Self::translate_highmem_a(ppn.as_raw())
+					.as_non_null_ptr()
+					.cast::<[Entry; 512]>()
+					.as_ref()
+			};
+			let pte = &tbl[va.ppn_1()];
+			if !pte.is_valid() || !pte.is_table() {
+				va.0 = (va.0 | MEGA_MASK) + 1;
+				continue;
+			}
+
+			// VPN[0]
+			let ppn = unsafe { PPN::from_raw((pte.0 >> 10) as u32) };
+			unsafe { Self::map_highmem_a(Some(ppn.as_raw())) };
+			Self::flush_highmem_a();
+			let tbl = unsafe {
+				Self::translate_highmem_a(ppn.as_raw())
+					.as_non_null_ptr()
+					.cast::<[Entry; 512]>()
+					.as_ref()
+			};
+			for (i, pte) in tbl.iter().enumerate().skip(va.ppn_0()) {
+				let user = pte.0 & Entry::USERMODE_MASK > 0;
+				let direct = pte.0 & Leaf::TYPE_MASK == Leaf::TYPE_DIRECT;
+				if pte.is_valid() && user && !direct {
+					let a = (va.0 & !MEGA_MASK) as usize | (i << VirtualAddress::PPN_0_OFFSET);
+					return Some((Page::from_usize(a).ok()?, pte.rwx()?));
+				}
+			}
+			va.0 = (va.0 | MEGA_MASK) + 1;
+		}
+		None
+	}
+
 	/// Begin mapping a range of pages with PPNs passed from a function. Some of the PPNs may be
 	/// used as tables.
 	///
@@ -116,6 +116,19 @@ where
 	/// Write the physical *addresses* from the start of the virtual address into the given slice.
 	fn physical_addresses(address: Page, store: &mut [usize]) -> Result<(), ()>;
 
+	/// Return the RWX flags of a page in the active VMS if it is mapped and accessible by
+	/// userland. Addresses outside the userland part of the address space always return `None`.
+	fn user_rwx(address: Page) -> Option<RWX>;
+
+	/// Return the physical address of a page in the active VMS if it is a shared mapping
+	/// accessible by userland, e.g. a page received with IPC.
+	fn user_shared(address: Page) -> Option<usize>;
+
+	/// Return the first page at or after the given address in the active VMS that is
+	/// accessible by userland and backed by memory, i.e. it isn't a direct mapping, along with
+	/// its RWX flags.
+	fn next_user_page(address: Page) -> Option<(Page, RWX)>;
+
 	/// Begin mapping a range of pages with PPNs passed from a function. Some of the PPNs may be
 	/// used as tables.
 	///
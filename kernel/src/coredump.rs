@@ -0,0 +1,273 @@
+//! # Core dumps
+//!
+//! If enabled with the `coredump` boot argument, the registers & memory of a task that faulted
+//! are handed to the task registered as `coredump`, which writes them to a file. The kernel
+//! itself doesn't know about files.
+//!
+//! The service pulls the dump one segment at a time:
+//!
+//! - The kernel sends a `COREDUMP` packet. The data is a page holding a [`Header`] with the
+//!   registers of the task & the amount of segments that follow.
+//! - Each time the service sends a `COREDUMP_SEGMENT` packet to [`Address::KERNEL`], the kernel
+//!   sends a `COREDUMP_SEGMENT` packet with the next segment back. The offset is the address of
+//!   the segment, the flags hold its RWX flags and the data is the memory, mapped read-only.
+//!   The service acknowledges the last segment too, which ends the dump.
+//!
+//! A segment is a range of at most [`SEGMENT_PAGES`] pages with the same flags. Direct
+//! mappings are left out as reading them may have side effects. Pages beyond the size limit are
+//! left out too, which is recorded in the header.
+//!
+//! Only one dump is in progress at any time. Tasks that fault in the meantime aren't dumped.
+//! A dump is abandoned if the service doesn't request the next segment within [`TIMEOUT`], so
+//! a stuck service can't prevent later dumps.
+//!
+//! The memory of the dumped task stays valid until the dump is done as the virtual memory of
+//! destroyed tasks is leaked.
+
+use crate::arch::vms::{Accessibility, VirtualMemorySystem, RWX};
+use crate::arch::{self, Page};
+use crate::sync::Mutex;
+use crate::task::{ipc, registry, Address, Group, Task};
+use core::mem;
+use core::ptr;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+/// The maximum amount of pages of a single segment.
+const SEGMENT_PAGES: usize = 8;
+
+/// The maximum amount of ranges of pages with the same flags in a dump.
+const MAX_RANGES: usize = 64;
+
+/// The size limit of a dump in bytes if none is given.
+pub const DEFAULT_LIMIT: usize = 4 << 20;
+
+/// The time in microseconds the service has to request the next segment.
+const TIMEOUT: u64 = 5_000_000;
+
+/// The name of the task in the registry that receives the dumps.
+const SERVICE: &[u8] = b"coredump";
+
+/// The first packet of a dump.
+#[repr(C)]
+struct Header {
+	/// The address of the task that faulted.
+	task: usize,
+	/// The cause of the fault, i.e. `scause`.
+	cause: usize,
+	/// The faulting address or instruction, i.e. `stval`.
+	value: usize,
+	/// The address of the instruction that faulted.
+	pc: usize,
+	/// All integer registers except `x0`.
+	x: [usize; 31],
+	/// The amount of segments that follow.
+	segments: usize,
+	/// The amount of pages that were left out because of the size limit.
+	omitted: usize,
+}
+
+/// A range of pages with the same flags.
+#[derive(Clone, Copy)]
+struct Range {
+	address: usize,
+	pages: usize,
+	rwx: RWX,
+}
+
+/// A dump that is waiting for the service to request the next segment.
+struct Dump {
+	/// The virtual memory of the dumped task.
+	vms: arch::VMS,
+	/// The address of the service.
+	service: Address,
+	ranges: [Option<Range>; MAX_RANGES],
+	/// The range & the index of the page in it of the next segment.
+	next: (usize, usize),
+	/// The time after which the dump is abandoned.
+	deadline: u64,
+}
+
+impl Dump {
+	/// Whether the service is still alive & requested a segment in time.
+	fn is_active(&self) -> bool {
+		get(self.service).is_some() && arch::current_time() < self.deadline
+	}
+}
+
+static DUMP: Mutex<Option<Dump>> = Mutex::new(None);
+
+/// The size limit of a dump in pages or `0` if dumps are disabled.
+static LIMIT: AtomicUsize = AtomicUsize::new(0);
+
+/// Enable core dumps with the given size limit in bytes.
+pub fn enable(limit: usize) {
+	LIMIT.store(limit / Page::SIZE, Ordering::Relaxed);
+}
+
+/// Take a core dump of the task at the given address after it faulted. The registers of the
+/// task must have been saved and its virtual memory must be active.
+///
+/// This changes the active virtual memory.
+pub fn take(task: &Task, address: Address, cause: usize, pc: usize, value: usize) {
+	let limit = LIMIT.load(Ordering::Relaxed);
+	if limit == 0 {
+		return;
+	}
+	let (service, service_task) = match registry::get(SERVICE) {
+		Some(a) if a != address => match get(a) {
+			Some(t) => (a, t),
+			None => return,
+		},
+		_ => return,
+	};
+
+	let mut dump = DUMP.lock();
+	// The service may have died or gotten stuck in the middle of a dump.
+	if dump.as_ref().map_or(false, Dump::is_active) {
+		log!("coredump: a dump is in progress, not dumping {:?}", address);
+		return;
+	}
+
+	let mut d = Dump {
+		vms: arch::VMS::current(),
+		service,
+		ranges: [None; MAX_RANGES],
+		next: (0, 0),
+		deadline: arch::current_time().saturating_add(TIMEOUT),
+	};
+	let omitted = collect(&mut d.ranges, limit);
+	let segments = d
+		.ranges
+		.iter()
+		.flatten()
+		.map(|r| (r.pages + SEGMENT_PAGES - 1) / SEGMENT_PAGES)
+		.sum();
+	let header = Header {
+		task: address.into(),
+		cause,
+		value,
+		pc,
+		x: task.register_state().x,
+		segments,
+		omitted,
+	};
+
+	let length = mem::size_of::<Header>();
+	let sent =
+		service_task.send_kernel_data(ipc::OP_COREDUMP, 0, length, RWX::RW, |vm, page, _| {
+			arch::VMS::allocate(page, 1, RWX::RW, Accessibility::UserLocal).unwrap();
+			// Flush the TLB.
+			vm.activate();
+			arch::set_supervisor_userpage_access(true);
+			unsafe {
+				ptr::write_bytes(page.as_ptr(), 0, 1);
+				ptr::copy_nonoverlapping(&header, page.as_ptr().cast(), 1);
+			}
+			arch::set_supervisor_userpage_access(false);
+		});
+	if sent {
+		log!(
+			"coredump: dumping {:?}, {} segments, {} pages left out",
+			address,
+			segments,
+			omitted
+		);
+		*dump = Some(d);
+	} else {
+		log!("coredump: failed to send the header for {:?}", address);
+	}
+}
+
+/// Send the next segment of the dump to the service at the given address.
+///
+/// This changes the active virtual memory.
+pub fn next(service: Address) {
+	let mut dump = DUMP.lock();
+	let d = match dump.as_mut() {
+		Some(d) if d.service == service => d,
+		_ => return,
+	};
+	if !d.is_active() {
+		log!("coredump: {:?} timed out, abandoning the dump", service);
+		*dump = None;
+		return;
+	}
+	d.deadline = arch::current_time().saturating_add(TIMEOUT);
+	let (range, offset) = d.next;
+	let (r, task) = match (d.ranges.get(range).copied().flatten(), get(service)) {
+		(Some(r), Some(task)) => (r, task),
+		_ => {
+			*dump = None;
+			return;
+		}
+	};
+	let pages = (r.pages - offset).min(SEGMENT_PAGES);
+	d.next = if offset + pages < r.pages {
+		(range, offset + pages)
+	} else {
+		(range + 1, 0)
+	};
+
+	let address = r.address + offset * Page::SIZE;
+	let vms = &d.vms;
+	let length = pages * Page::SIZE;
+	let sent = task.send_kernel_data(
+		ipc::OP_COREDUMP_SEGMENT,
+		address as u64,
+		length,
+		r.rwx,
+		|vm, page, i| {
+			vms.activate();
+			let from = Page::from_usize(address + i * Page::SIZE).unwrap();
+			if let Err(e) = vm.share(page, from, RWX::R, Accessibility::UserLocal) {
+				log!("coredump: failed to share {:p}: {:?}", from.as_ptr(), e);
+			}
+		},
+	);
+	if !sent {
+		log!("coredump: failed to send segment {:x}", address);
+		*dump = None;
+	}
+}
+
+/// Collect the ranges of pages accessible by userland in the active virtual memory. Returns
+/// the amount of pages that were left out.
+fn collect(ranges: &mut [Option<Range>], limit: usize) -> usize {
+	let mut count: usize = 0;
+	let (mut total, mut omitted) = (0, 0);
+	let mut address = Page::from_usize(Page::SIZE).ok();
+	while let Some((page, rwx)) = address.and_then(arch::VMS::next_user_page) {
+		address = page.next();
+		let page = page.as_ptr() as usize;
+		if total >= limit {
+			omitted += 1;
+			continue;
+		}
+		// Extend the last range if the page is adjacent to it.
+		if let Some(Some(r)) = count.checked_sub(1).and_then(|i| ranges.get_mut(i)) {
+			if r.rwx == rwx && r.address + r.pages * Page::SIZE == page {
+				r.pages += 1;
+				total += 1;
+				continue;
+			}
+		}
+		match ranges.get_mut(count) {
+			Some(r) => {
+				*r = Some(Range {
+					address: page,
+					pages: 1,
+					rwx,
+				});
+				count += 1;
+				total += 1;
+			}
+			None => omitted += 1,
+		}
+	}
+	omitted
+}
+
+/// Return the task at the given address if it exists.
+fn get(address: Address) -> Option<Task> {
+	Group::get(address.group().into()).and_then(|g| g.task(address.task().into()).ok())
+}
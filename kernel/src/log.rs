@@ -1,16 +1,141 @@
 //! Basic logging facilities
 //!
 //! These are all globally accessible for ease of use
+//!
+//! All messages are stored in a ring buffer, which is the source of truth. Userspace reads it
+//! with `sys_log_read` and can forward it to any device, e.g. a virtio console, as the kernel
+//! doesn't drive such devices itself.
+//!
+//! Messages are also written to the primary backend, which is the SBI console (usually a UART)
+//! unless disabled with the `console=none` boot argument, and to a secondary backend if one is
+//! registered. A panic forces the SBI console on so the panic message is never lost.
 
+use crate::sync::Mutex;
 use core::fmt;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+/// A device log messages are written to.
+pub trait Backend: Sync {
+	/// Write bytes to the device.
+	fn write_bytes(&self, bytes: &[u8]);
+
+	/// Whether the device can accept bytes. Messages are dropped if not.
+	fn is_ready(&self) -> bool;
+}
+
+/// The console provided by the SBI, which is usually a UART.
+pub struct Sbi;
+
+impl Backend for Sbi {
+	fn write_bytes(&self, bytes: &[u8]) {
+		for b in bytes.iter().copied() {
+			crate::arch::riscv::sbi::console_putchar(b);
+		}
+	}
+
+	fn is_ready(&self) -> bool {
+		true
+	}
+}
+
+pub static SBI: Sbi = Sbi;
+
+/// The size of the ring buffer in bytes. Must be a power of two.
+const RING_SIZE: usize = 1 << 14;
+
+struct Ring {
+	buffer: [u8; RING_SIZE],
+	/// The total amount of bytes ever written.
+	head: u64,
+}
+
+impl Ring {
+	fn push(&mut self, bytes: &[u8]) {
+		for b in bytes.iter().copied() {
+			self.buffer[self.head as usize & (RING_SIZE - 1)] = b;
+			self.head += 1;
+		}
+	}
+
+	/// Copy the bytes starting at `position` to `store`. If the bytes at `position` have been
+	/// overwritten, the oldest bytes are copied instead. Returns the position of the first byte
+	/// that was copied & the amount of bytes copied.
+	fn read(&self, position: u64, store: &mut [u8]) -> (u64, usize) {
+		let oldest = self.head.saturating_sub(RING_SIZE as u64);
+		let start = position.max(oldest).min(self.head);
+		let count = (self.head - start).min(store.len() as u64) as usize;
+		for (i, w) in store[..count].iter_mut().enumerate() {
+			*w = self.buffer[(start as usize + i) & (RING_SIZE - 1)];
+		}
+		(start, count)
+	}
+}
+
+static RING: Mutex<Ring> = Mutex::new(Ring {
+	buffer: [0; RING_SIZE],
+	head: 0,
+});
+
+/// The primary & secondary backend.
+static BACKENDS: Mutex<[Option<&'static dyn Backend>; 2]> = Mutex::new([Some(&SBI), None]);
+
+/// Set when the kernel panics, after which messages go straight to the SBI console.
+static PANICKING: AtomicBool = AtomicBool::new(false);
+
+/// A secondary backend is already registered.
+#[derive(Debug)]
+pub struct Occupied;
+
+/// Set the primary backend. If `None`, messages are only stored in the ring buffer.
+pub fn set_primary(backend: Option<&'static dyn Backend>) {
+	BACKENDS.lock()[0] = backend;
+}
+
+/// Register a secondary backend, which receives all messages besides the primary backend.
+// No kernel backend besides the SBI console exists yet.
+#[allow(dead_code)]
+pub fn register(backend: &'static dyn Backend) -> Result<(), Occupied> {
+	let mut backends = BACKENDS.lock();
+	if backends[1].is_some() {
+		return Err(Occupied);
+	}
+	backends[1] = Some(backend);
+	Ok(())
+}
+
+/// Write all further messages to the SBI console without taking any locks that may be held
+/// by the panicking code.
+pub fn force_sbi() {
+	PANICKING.store(true, Ordering::Relaxed);
+}
+
+/// Copy messages from the ring buffer. See [`Ring::read`].
+pub fn read(position: u64, store: &mut [u8]) -> (u64, usize) {
+	RING.lock().read(position, store)
+}
+
+/// Store bytes in the ring buffer & write them to the backends.
+pub fn write_bytes(bytes: &[u8]) {
+	if PANICKING.load(Ordering::Relaxed) {
+		if let Some(mut ring) = RING.try_lock() {
+			ring.push(bytes);
+		}
+		SBI.write_bytes(bytes);
+		return;
+	}
+	RING.lock().push(bytes);
+	for backend in BACKENDS.lock().iter().flatten() {
+		if backend.is_ready() {
+			backend.write_bytes(bytes);
+		}
+	}
+}
 
 pub struct Log;
 
 impl fmt::Write for Log {
 	fn write_str(&mut self, string: &str) -> fmt::Result {
-		for b in string.bytes() {
-			crate::arch::riscv::sbi::console_putchar(b);
-		}
+		write_bytes(string.as_bytes());
 		Ok(())
 	}
 }
@@ -40,6 +40,7 @@ mod util;
 
 mod allocator;
 mod arch;
+mod coredump;
 mod driver;
 mod elf;
 mod memory;
@@ -57,6 +58,8 @@ static PLATFORM_INFO_PHYS_PTR: OnceCell<usize> = OnceCell::new(0);
 
 #[panic_handler]
 fn panic(info: &panic::PanicInfo) -> ! {
+	// Make sure the message ends up somewhere, even if the console is disabled.
+	log::force_sbi();
 	log!("Kernel panicked!");
 	if let Some(msg) = info.message() {
 		log!("  Message:  {:?}", msg);
@@ -407,7 +410,27 @@ extern "C" fn main(
 
 	task::Group::new(init).expect("failed to create init task group");
 
-	let _ = (boot_args, stdout, model);
+	for arg in boot_args.trim_end_matches('\0').split_whitespace() {
+		match arg {
+			"console=uart" => log::set_primary(Some(&log::SBI)),
+			"console=none" => log::set_primary(None),
+			a if a.starts_with("console=") => log!("Unknown console '{}'", a),
+			a if a.starts_with("dma_quarantine=") => {
+				match a["dma_quarantine=".len()..].parse::<u64>() {
+					Ok(ms) => memory::pin::set_timeout(ms.saturating_mul(1000)),
+					Err(_) => log!("Invalid DMA quarantine timeout '{}'", a),
+				}
+			}
+			"coredump" => coredump::enable(coredump::DEFAULT_LIMIT),
+			a if a.starts_with("coredump=") => match a["coredump=".len()..].parse::<usize>() {
+				Ok(kib) => coredump::enable(kib.saturating_mul(1024)),
+				Err(_) => log!("Invalid core dump size limit '{}'", a),
+			},
+			_ => (),
+		}
+	}
+
+	let _ = (stdout, model);
 
 	arch::enable_interrupts(true);
 	task::Executor::init(hart_id.try_into().expect("hart id higher than supported"));
@@ -107,6 +107,56 @@ impl Stacks {
 		top_base.1 = top_base.1.wrapping_add(1);
 		unsafe { Some(PPN::from_raw(ppn)) }
 	}
+
+	/// Returns the amount of PPNs on the stack.
+	fn len(&self, stack_index: usize) -> usize {
+		// SAFETY: the pointers point to arrays at least as large as stacks, and if the index
+		// was OOB we'd have paniced already.
+		let top_base = unsafe { &*self.top_base.add(stack_index) };
+		top_base.0.wrapping_sub(top_base.1).into()
+	}
+
+	/// Removes the longest run of contiguous PPNs from the stack, up to `max` pages. Returns
+	/// `None` if the stack is empty.
+	///
+	/// This sorts the stack, so it is much slower than `pop`.
+	#[must_use]
+	fn pop_run(&mut self, stack_index: usize, max: usize) -> Option<(PPNBox, u32)> {
+		let len = self.len(stack_index);
+		if len == 0 || max == 0 {
+			return None;
+		}
+		let stack = &mut self.stacks[stack_index];
+		// SAFETY: the pointers point to arrays at least as large as stacks, and if the index
+		// was OOB we'd have paniced already.
+		let top_base = unsafe { &mut *self.top_base.add(stack_index) };
+
+		// Move the base to the start of the array so the entries form a single slice.
+		stack.rotate_left((top_base.1 & (Self::STACK_SIZE - 1)).into());
+		let entries = &mut stack[..len];
+		entries.sort_unstable();
+
+		// Find the longest run.
+		let (mut start, mut count) = (0, 1);
+		let mut run_start = 0;
+		for i in 1..len {
+			if entries[i - 1] + 1 != entries[i] {
+				run_start = i;
+			} else if i + 1 - run_start > count {
+				start = run_start;
+				count = i + 1 - run_start;
+			}
+		}
+		let count = count.min(max);
+		let ppn = entries[start];
+
+		// Remove the run from the stack.
+		entries.copy_within(start + count.., start);
+		top_base.1 = 0;
+		top_base.0 = (len - count) as u16;
+
+		Some((ppn, count as u32))
+	}
 }
 
 #[cfg(fals)]
@@ -202,6 +252,20 @@ impl Allocator {
 		}
 	}
 
+	/// Allocate the longest run of contiguous pages available, up to `max` pages.
+	pub fn alloc_run(&mut self, max: usize) -> Result<PPNRange, ()> {
+		// FIXME use hart IDs.
+		let (start, count) = self.stacks.pop_run(0, max).ok_or(())?;
+		// SAFETY: the PPNs came from the stack, so they are valid.
+		Ok(unsafe { PPNRange::from_raw(start, count) })
+	}
+
+	/// Return the amount of free pages.
+	pub fn free_count(&self) -> usize {
+		// FIXME use hart IDs.
+		self.stacks.len(0)
+	}
+
 	/// Inserts an untracked page.
 	pub fn insert(&mut self, page: PPN) {
 		self.free(page)
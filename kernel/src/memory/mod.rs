@@ -7,6 +7,7 @@
 
 pub use crate::arch::Page;
 
+pub mod pin;
 pub mod ppn;
 pub mod reserved;
 
@@ -23,6 +24,16 @@ use allocator::Allocator;
 #[derive(Debug)]
 pub struct AllocateError;
 
+/// Statistics of the global memory manager.
+#[derive(Clone, Copy, Debug, Default)]
+#[repr(C)]
+pub struct Stats {
+	/// The amount of pages that can be allocated.
+	pub free: usize,
+	/// The amount of pages of destroyed tasks that are waiting to be unpinned.
+	pub quarantined: usize,
+}
+
 /// The global memory allocator.
 ///
 /// The maximum area order varies for each architecture depending on hugepage support and practical
@@ -116,13 +127,39 @@ where
 	Ok(())
 }
 
+/// Allocate a number of pages as the fewest contiguous runs possible, largest runs first. The
+/// closure is called once for each run.
+///
+/// This only fails if there are not enough free pages. Like [`mem_allocate_range`], it may
+/// still fail after some runs have been handed out if the pages are taken concurrently. It is
+/// up to the caller to deallocate them.
+pub fn mem_allocate_best_effort<F>(count: usize, mut f: F) -> Result<(), AllocateError>
+where
+	F: FnMut(PPNRange),
+{
+	// SAFETY: the allocator is initialized before any task runs.
+	let allocator = || unsafe { ALLOCATOR.as_ref().expect("No initialized PMM").lock() };
+	if allocator().free_count() < count {
+		return Err(AllocateError);
+	}
+	let mut remaining = count;
+	while remaining > 0 {
+		// The lock must be released before calling f as it may need to allocate pages too.
+		let run = allocator()
+			.alloc_run(remaining)
+			.map_err(|()| AllocateError)?;
+		remaining -= run.len();
+		f(run);
+	}
+	Ok(())
+}
+
 /// Deallocate a page
 ///
 /// ## Safety
 ///
 /// The page is no longer in use and hasn't been freed yet.
 #[optimize(speed)]
-#[allow(dead_code)]
 pub unsafe fn deallocate(page: PPN) {
 	#[cfg(debug_assertions)]
 	ALLOCATOR
@@ -133,3 +170,12 @@ pub unsafe fn deallocate(page: PPN) {
 	#[cfg(not(debug_assertions))]
 	ALLOCATOR.as_ref().unwrap_unchecked().lock().free(page);
 }
+
+/// Return statistics of the global memory manager.
+pub fn stats() -> Stats {
+	// Reap the quarantine first so the pages that were released are counted as free.
+	let quarantined = pin::quarantined();
+	// SAFETY: the allocator is initialized before any task runs.
+	let free = unsafe { ALLOCATOR.as_ref().expect("No initialized PMM").lock() }.free_count();
+	Stats { free, quarantined }
+}
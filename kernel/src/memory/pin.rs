@@ -0,0 +1,297 @@
+//! # DMA pins
+//!
+//! A driver pins the pages of a client while a device may access them, e.g. for the duration of
+//! a block request. Each pin is recorded in the pin table of the task owning the pages along with
+//! the driver that made it.
+//!
+//! When a task is destroyed, its pinned pages are not freed as the device may still write to
+//! them. They are moved to the *quarantine* instead. A quarantined page is only returned to the
+//! allocator once the driver that pinned it unpins it, which it does when it completes or
+//! cancels the request after learning about the death of the client, or once it has been
+//! quarantined for longer than the timeout. The latter is logged loudly as it means the driver
+//! leaked the pin and the device may still be using the page.
+//!
+//! Expired pages are only reaped when the quarantine is accessed.
+
+use super::{PPNBox, PPN};
+use crate::sync::Mutex;
+use crate::task::Address;
+use core::sync::atomic::{AtomicU64, Ordering};
+
+/// The maximum amount of pins a task can have.
+pub const TABLE_SIZE: usize = 32;
+
+/// The maximum amount of pages that can be in quarantine.
+const QUARANTINE_SIZE: usize = 256;
+
+/// The time in microseconds after which quarantined pages are released by default.
+pub const DEFAULT_TIMEOUT: u64 = 5_000_000;
+
+/// A page pinned by a driver.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Pin {
+	/// The pinned page.
+	pub ppn: PPNBox,
+	/// The address of the driver that pinned the page.
+	pub driver: Address,
+}
+
+/// The pins on the pages of a task.
+pub struct Table(Mutex<[Option<Pin>; TABLE_SIZE]>);
+
+/// The table has no room for another pin.
+#[derive(Debug)]
+pub struct Full;
+
+impl Table {
+	pub const fn new() -> Self {
+		Self(Mutex::new([None; TABLE_SIZE]))
+	}
+
+	/// Record a pin. A page may be pinned more than once.
+	pub fn pin(&self, pin: Pin) -> Result<(), Full> {
+		let mut table = self.0.lock();
+		let e = table.iter_mut().find(|e| e.is_none()).ok_or(Full)?;
+		*e = Some(pin);
+		Ok(())
+	}
+
+	/// Remove a pin. Returns `false` if there is no such pin.
+	pub fn unpin(&self, pin: Pin) -> bool {
+		let mut table = self.0.lock();
+		table
+			.iter_mut()
+			.find(|e| **e == Some(pin))
+			.map(|e| *e = None)
+			.is_some()
+	}
+
+	/// Remove all pins.
+	fn drain(&self, mut f: impl FnMut(Pin)) {
+		self.0
+			.lock()
+			.iter_mut()
+			.filter_map(Option::take)
+			.for_each(&mut f);
+	}
+}
+
+/// A page that was pinned when its owner was destroyed.
+#[derive(Clone, Copy)]
+struct Entry {
+	pin: Pin,
+	/// The time after which the page is released even if it is still pinned.
+	deadline: u64,
+}
+
+/// Pages that are waiting to be unpinned before they can be freed.
+struct Quarantine {
+	entries: [Option<Entry>; QUARANTINE_SIZE],
+	count: usize,
+}
+
+impl Quarantine {
+	const fn new() -> Self {
+		Self {
+			entries: [None; QUARANTINE_SIZE],
+			count: 0,
+		}
+	}
+
+	/// Move all pins of a table into the quarantine.
+	///
+	/// If the quarantine is full the page is leaked, as freeing it while a device may write to
+	/// it is worse.
+	fn take(&mut self, table: &Table, deadline: u64) {
+		table.drain(|pin| match self.entries.iter_mut().find(|e| e.is_none()) {
+			Some(e) => {
+				*e = Some(Entry { pin, deadline });
+				self.count += 1;
+			}
+			None => log!("quarantine is full, leaking pinned page {:x}", pin.ppn),
+		});
+	}
+
+	/// Remove a pin. Returns `true` and releases the page if it was quarantined.
+	fn unpin(&mut self, pin: Pin, release: impl FnOnce(PPNBox)) -> bool {
+		let e = self
+			.entries
+			.iter_mut()
+			.find(|e| e.map_or(false, |e| e.pin == pin));
+		match e {
+			Some(e) => {
+				*e = None;
+				self.count -= 1;
+				// Another pin from another driver may still be quarantined.
+				if !self.is_pinned(pin.ppn) {
+					release(pin.ppn);
+				}
+				true
+			}
+			None => false,
+		}
+	}
+
+	/// Release all pages that have been quarantined for too long.
+	fn reap(&mut self, now: u64, mut release: impl FnMut(PPNBox)) {
+		for i in 0..self.entries.len() {
+			let entry = match self.entries[i] {
+				Some(e) if e.deadline <= now => e,
+				_ => continue,
+			};
+			log!(
+				"WARNING: page {:x} pinned by {:?} is still pinned after its owner died, releasing it",
+				entry.pin.ppn,
+				entry.pin.driver
+			);
+			self.entries[i] = None;
+			self.count -= 1;
+			if !self.is_pinned(entry.pin.ppn) {
+				release(entry.pin.ppn);
+			}
+		}
+	}
+
+	fn is_pinned(&self, ppn: PPNBox) -> bool {
+		self.entries.iter().flatten().any(|e| e.pin.ppn == ppn)
+	}
+}
+
+static QUARANTINE: Mutex<Quarantine> = Mutex::new(Quarantine::new());
+
+static TIMEOUT: AtomicU64 = AtomicU64::new(DEFAULT_TIMEOUT);
+
+/// Set the time in microseconds after which quarantined pages are released.
+pub fn set_timeout(timeout: u64) {
+	TIMEOUT.store(timeout, Ordering::Relaxed);
+}
+
+/// Return a quarantined page to the allocator.
+fn release(ppn: PPNBox) {
+	// SAFETY: the owner of the page is destroyed and it isn't pinned anymore.
+	unsafe { super::deallocate(PPN::from_raw(ppn)) };
+}
+
+/// Move the pinned pages of a destroyed task into the quarantine.
+pub fn quarantine(table: &Table) {
+	let now = crate::arch::current_time();
+	let mut q = QUARANTINE.lock();
+	q.reap(now, release);
+	q.take(table, now.saturating_add(TIMEOUT.load(Ordering::Relaxed)));
+}
+
+/// Remove a pin. If the owner of the page is still alive, its table must be given.
+///
+/// Returns `false` if there is no such pin.
+pub fn unpin(owner: Option<&Table>, pin: Pin) -> bool {
+	let mut q = QUARANTINE.lock();
+	q.reap(crate::arch::current_time(), release);
+	q.unpin(pin, release) || owner.map_or(false, |t| t.unpin(pin))
+}
+
+/// Return the amount of quarantined pages.
+pub fn quarantined() -> usize {
+	let mut q = QUARANTINE.lock();
+	q.reap(crate::arch::current_time(), release);
+	q.count
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	/// A stand-in for the page allocator.
+	#[derive(Default)]
+	struct Allocator(Vec<PPNBox>);
+
+	impl Allocator {
+		fn alloc(&mut self) -> Option<PPNBox> {
+			self.0.pop()
+		}
+
+		fn free(&mut self, ppn: PPNBox) {
+			assert!(!self.0.contains(&ppn), "double free of {:x}", ppn);
+			self.0.push(ppn);
+		}
+	}
+
+	fn pin(ppn: PPNBox, driver: usize) -> Pin {
+		Pin {
+			ppn,
+			driver: Address::from(driver),
+		}
+	}
+
+	/// Destroy a task owning the given pages. Pinned pages are quarantined, the others are freed.
+	fn destroy(pages: &[PPNBox], table: &Table, q: &mut Quarantine, a: &mut Allocator) {
+		let pinned: Vec<_> = table.0.lock().iter().flatten().map(|p| p.ppn).collect();
+		q.take(table, 100);
+		pages
+			.iter()
+			.filter(|p| !pinned.contains(p))
+			.for_each(|p| a.free(*p));
+	}
+
+	#[test]
+	fn unpin_after_death() {
+		let (table, mut q, mut a) = (Table::new(), Quarantine::new(), Allocator::default());
+		table.pin(pin(7, 1)).unwrap();
+		destroy(&[6, 7], &table, &mut q, &mut a);
+		assert_eq!(q.count, 1);
+		assert!(table.0.lock().iter().all(Option::is_none));
+
+		// The pinned page isn't handed out while the device may still write to it.
+		assert_eq!(a.alloc(), Some(6));
+		assert_eq!(a.alloc(), None);
+
+		// Only the driver that pinned the page can unpin it.
+		assert!(!q.unpin(pin(7, 2), |p| a.free(p)));
+		assert!(q.unpin(pin(7, 1), |p| a.free(p)));
+		assert_eq!(q.count, 0);
+		assert_eq!(a.alloc(), Some(7));
+	}
+
+	#[test]
+	fn pinned_twice() {
+		let (table, mut q, mut a) = (Table::new(), Quarantine::new(), Allocator::default());
+		table.pin(pin(7, 1)).unwrap();
+		table.pin(pin(7, 2)).unwrap();
+		destroy(&[7], &table, &mut q, &mut a);
+		assert!(q.unpin(pin(7, 1), |p| a.free(p)));
+		assert_eq!(a.alloc(), None);
+		assert!(q.unpin(pin(7, 2), |p| a.free(p)));
+		assert_eq!(a.alloc(), Some(7));
+	}
+
+	#[test]
+	fn timeout() {
+		let (table, mut q, mut a) = (Table::new(), Quarantine::new(), Allocator::default());
+		table.pin(pin(7, 1)).unwrap();
+		destroy(&[7], &table, &mut q, &mut a);
+		q.reap(99, |p| a.free(p));
+		assert_eq!(a.alloc(), None);
+		q.reap(100, |p| a.free(p));
+		assert_eq!(q.count, 0);
+		assert_eq!(a.alloc(), Some(7));
+		// A late unpin doesn't free the page again.
+		assert!(!q.unpin(pin(7, 1), |p| a.free(p)));
+	}
+
+	#[test]
+	fn unpin_alive() {
+		let table = Table::new();
+		table.pin(pin(7, 1)).unwrap();
+		assert!(!table.unpin(pin(8, 1)));
+		assert!(table.unpin(pin(7, 1)));
+		assert!(!table.unpin(pin(7, 1)));
+	}
+
+	#[test]
+	fn full() {
+		let table = Table::new();
+		for i in 0..TABLE_SIZE {
+			table.pin(pin(i as PPNBox, 1)).unwrap();
+		}
+		assert!(table.pin(pin(0, 1)).is_err());
+	}
+}
@@ -85,6 +85,15 @@ impl PPNRange {
 		Self { start, count }
 	}
 
+	/// Creates a new range from a raw PPN and a count.
+	///
+	/// ## Safety:
+	///
+	/// The PPNs are valid and not in use by anything else.
+	pub unsafe fn from_raw(start: PPNBox, count: u32) -> Self {
+		Self { start, count }
+	}
+
 	/// Return the top PPN and decrement the count.
 	pub fn pop(&mut self) -> Option<PPN> {
 		self.count.checked_sub(1).map(|c| {
@@ -1,3 +1,32 @@
+//! # Power states
+//!
+//! Before the system is suspended every task in the registry is sent a `SUSPEND` packet so it
+//! can put its devices in a low power state. The system is suspended once all of them sent a
+//! packet with the same opcode back to the kernel or when the timeout expires, whichever comes
+//! first. Afterwards, the tasks & the caller are sent a `RESUME` packet.
+
+use crate::arch;
+use crate::sync::Mutex;
+use crate::task::{ipc, registry, Address, Group, Task};
+
+/// A suspend that is waiting for acknowledgements.
+struct Suspend {
+	/// The task that requested the suspend.
+	caller: Address,
+	/// The time after which the system is suspended regardless of missing acknowledgements.
+	deadline: u64,
+	/// The tasks that haven't acknowledged yet.
+	pending: [Option<Address>; registry::REGISTRY_CAPACITY],
+}
+
+static SUSPEND: Mutex<Option<Suspend>> = Mutex::new(None);
+
+#[derive(Debug)]
+pub enum SuspendError {
+	/// Another suspend is in progress.
+	InProgress,
+}
+
 /// Halts the CPU until an interrupt is received
 #[inline]
 pub fn halt() {
@@ -5,3 +34,81 @@ pub fn halt() {
 		asm!("wfi");
 	}
 }
+
+/// Send a `SUSPEND` packet to every task in the registry. Returns the amount of tasks that
+/// were sent a packet.
+///
+/// This changes the active virtual memory.
+pub fn suspend(caller: Address, timeout: u64) -> Result<usize, SuspendError> {
+	let mut suspend = SUSPEND.lock();
+	if suspend.is_some() {
+		return Err(SuspendError::InProgress);
+	}
+	let mut pending = [None; registry::REGISTRY_CAPACITY];
+	let mut count = 0;
+	registry::for_each_address(|address| {
+		if address != caller && send(address, ipc::OP_SUSPEND, 0) {
+			pending[count] = Some(address);
+			count += 1;
+		}
+	});
+	*suspend = Some(Suspend {
+		caller,
+		deadline: arch::current_time().saturating_add(timeout),
+		pending,
+	});
+	Ok(count)
+}
+
+/// Mark the task at the given address as ready to be suspended.
+///
+/// The acknowledgement may come from another thread than the one the registry refers to, so
+/// every address of the same task is marked.
+pub fn acknowledge(address: Address) {
+	if let Some(suspend) = SUSPEND.lock().as_mut() {
+		suspend
+			.pending
+			.iter_mut()
+			.filter(|a| a.map_or(false, |a| a == address || Task::same_owner(a, address)))
+			.for_each(|a| *a = None);
+	}
+}
+
+/// Suspend the system if all tasks acknowledged or the deadline passed. Returns the deadline
+/// if the suspend is still waiting for tasks.
+///
+/// This may change the active virtual memory.
+pub fn poll(time: u64) -> Option<u64> {
+	let mut suspend = SUSPEND.lock();
+	let s = suspend.as_ref()?;
+	let missing = s.pending.iter().flatten().count();
+	if missing > 0 && time < s.deadline {
+		return Some(s.deadline);
+	}
+	let s = suspend.take().unwrap();
+	drop(suspend);
+	if missing > 0 {
+		log!("suspend: {} tasks didn't acknowledge in time", missing);
+	}
+	if arch::riscv::sbi::probe_extension(arch::riscv::sbi::EXTENSION_SUSPEND) {
+		// FIXME there is no entry point to resume from yet.
+		log!("suspend: no resume path, staying awake");
+	} else {
+		log!("suspend: not supported by SBI, staying awake");
+	}
+	registry::for_each_address(|address| {
+		if address != s.caller {
+			let _ = send(address, ipc::OP_RESUME, 0);
+		}
+	});
+	// The caller is told how many tasks didn't acknowledge.
+	let _ = send(s.caller, ipc::OP_RESUME, missing as u64);
+	None
+}
+
+/// Send a packet from the kernel to the task at the given address.
+fn send(address: Address, opcode: u8, offset: u64) -> bool {
+	Group::get(address.group().into())
+		.and_then(|g| g.task(address.task().into()).ok())
+		.map_or(false, |task| task.send_kernel_packet(opcode, offset))
+}
@@ -26,6 +26,15 @@ impl<T> Mutex<T> {
 		self.lock.set(true);
 		MutexGuard { mutex: self }
 	}
+
+	/// Lock the mutex if it isn't locked already.
+	pub fn try_lock(&self) -> Option<MutexGuard<'_, T>> {
+		if self.lock.get() {
+			return None;
+		}
+		self.lock.set(true);
+		Some(MutexGuard { mutex: self })
+	}
 }
 
 impl<T> Drop for MutexGuard<'_, T> {
@@ -29,7 +29,11 @@ pub type Syscall = extern "C" fn(
 pub struct Return(Status, usize);
 
 /// The length of the table as a separate constant because Rust is a little dum dum.
-pub const TABLE_LEN: usize = 20;
+pub const TABLE_LEN: usize = 34;
+
+/// The length of the table for the trap handler, so it doesn't need a copy of [`TABLE_LEN`].
+#[export_name = "syscall_table_len"]
+static TABLE_LEN_EXPORT: usize = TABLE_LEN;
 
 /// Table with all syscalls.
 #[export_name = "syscall_table"]
@@ -52,8 +56,22 @@ pub static TABLE: [Syscall; TABLE_LEN] = [
 	sys::sys_log,                      // 15
 	sys::sys_registry_add,             // 16
 	sys::sys_registry_get,             // 17
-	sys::placeholder,                  // 18
-	sys::placeholder,                  // 19
+	sys::sys_time,                     // 18
+	sys::sys_watch_task,               // 19
+	sys::task_exit,                    // 20
+	sys::dev_dma_alloc_scatter,        // 21
+	sys::task_stats,                   // 22
+	sys::task_emulate_misaligned,      // 23
+	sys::thread_spawn,                 // 24
+	sys::thread_exit,                  // 25
+	sys::sys_system_suspend,           // 26
+	sys::task_strict_endpoints,        // 27
+	sys::sys_log_read,                 // 28
+	sys::dev_dma_pin,                  // 29
+	sys::dev_dma_unpin,                // 30
+	sys::mem_stats,                    // 31
+	sys::task_close_endpoint,          // 32
+	sys::placeholder,                  // 33
 ];
 
 /// Enum representing whether a syscall was successfull or failed.
@@ -82,6 +100,8 @@ pub enum Status {
 	TooLong = 10,
 	Occupied = 11,
 	Unavailable = 12,
+	/// The task isn't allowed to perform the call.
+	PermissionDenied = 13,
 }
 
 impl From<Status> for u8 {
@@ -101,6 +121,15 @@ pub struct Mapping {
 	self_address: *mut PageData,
 }
 
+/// An endpoint granted to a new task.
+#[repr(C)]
+pub struct Grant {
+	/// A handle or raw address of the spawning task.
+	address: usize,
+	/// The only UUID the new task may send to the endpoint, or 0 for any UUID.
+	uuid: [u64; 2],
+}
+
 /// Module containing all the actual syscalls.
 mod sys {
 	use super::*;
@@ -129,6 +158,29 @@ mod sys {
 		})
 	}
 
+	/// Check whether the pages covering the given range are mapped in the current task with at
+	/// least the given permissions.
+	fn is_user_range(address: usize, length: usize, permissions: RWX) -> bool {
+		let end = match address.checked_add(length) {
+			Some(end) => end,
+			None => return false,
+		};
+		let required = permissions as u8;
+		let mut page = address & !arch::PAGE_MASK;
+		while page < end {
+			// Pages above user space aren't user accessible, so this can't overflow.
+			let allowed = Page::from_usize(page)
+				.ok()
+				.and_then(arch::VMS::user_rwx)
+				.map_or(false, |rwx| rwx as u8 & required == required);
+			if !allowed {
+				return false;
+			}
+			page += Page::SIZE;
+		}
+		true
+	}
+
 	macro_rules! logcall {
 		($($args:expr),+ $(,)?) => {
 			#[cfg(feature = "log-syscalls")]
@@ -245,14 +297,31 @@ mod sys {
 	}
 
 	sys! {
-		/// Set a handler for receiving notifications.
-		[task] io_set_notify_handler(function) {
-			logcall!("io_set_notify_handler 0x{:x}", function);
-			let handler = NonNull::new(function as *mut _)
-				.map(task::notification::Handler::new)
-				.map(Result::unwrap);
-			let prev = task.set_notification_handler(handler);
-			Return(Status::Ok, prev.map(|p| p.as_ptr() as usize).unwrap_or(0))
+		/// Set a handler for receiving notifications of the given type. The context is passed to
+		/// the handler as is. The previous handler is written to `previous` if it isn't null.
+		[task] io_set_notify_handler(function, context, typ, previous) {
+			logcall!("io_set_notify_handler 0x{:x}, 0x{:x}, {}, 0x{:x}", function, context, typ, previous);
+			let previous = NonNull::new(previous as *mut [usize; 2]);
+			if let Some(previous) = previous {
+				if previous.as_ptr() as usize % mem::align_of::<[usize; 2]>() != 0 {
+					return Return(Status::BadAlignment, 0);
+				}
+				if !is_user_range(previous.as_ptr() as usize, mem::size_of::<[usize; 2]>(), RWX::RW) {
+					return Return(Status::MemoryNotAllocated, 0);
+				}
+			}
+			let handler = task::notification::Handler::new(NonNull::new(function as *mut _), context)
+				.unwrap();
+			let prev = match task.set_notification_handler(typ, handler) {
+				Ok(prev) => prev,
+				Err(task::notification::InvalidType) => return Return(Status::NotFound, 0),
+			};
+			if let Some(previous) = previous {
+				arch::set_supervisor_userpage_access(true);
+				unsafe { previous.as_ptr().write([prev.as_ptr() as usize, prev.context()]) };
+				arch::set_supervisor_userpage_access(false);
+			}
+			Return(Status::Ok, prev.as_ptr() as usize)
 		}
 	}
 
@@ -272,7 +341,10 @@ mod sys {
 					syscall_io_notify_return(task.clone());
 				} else {
 					let from_addr = task::Executor::current_address();
-					let to_addr = task::Address::from(defer_to);
+					let to_addr = match task.resolve(defer_to, None) {
+						Ok(addr) => addr,
+						Err(_) => syscall_io_notify_return(task.clone()),
+					};
 					syscall_io_notify_defer(task.clone(), from_addr, to_addr);
 				}
 			}
@@ -312,14 +384,18 @@ mod sys {
 	}
 
 	sys! {
+		/// Not implemented yet, so it fails as if it doesn't exist instead of panicking.
 		[_] mem_get_flags() {
-			todo!()
+			logcall!("mem_get_flags");
+			Return(Status::InvalidCall, 0)
 		}
 	}
 
 	sys! {
+		/// Not implemented yet, so it fails as if it doesn't exist instead of panicking.
 		[_] mem_set_flags() {
-			todo!()
+			logcall!("mem_set_flags");
+			Return(Status::InvalidCall, 0)
 		}
 	}
 
@@ -339,12 +415,42 @@ mod sys {
 	}
 
 	sys! {
-		[_] task_spawn(mappings, mappings_count, program_counter, stack_pointer) {
-			logcall!("task_spawn 0x{:x}, {}, 0x{:x}, 0x{:x}", mappings, mappings_count, program_counter, stack_pointer);
+		/// Spawn a new task. The granted endpoints are available to the new task as handles
+		/// `0` up to `grants_count`, in order.
+		[task] task_spawn(mappings, mappings_count, program_counter, stack_pointer, grants, grants_count) {
+			logcall!("task_spawn 0x{:x}, {}, 0x{:x}, 0x{:x}, 0x{:x}, {}", mappings, mappings_count, program_counter, stack_pointer, grants, grants_count);
 			let mappings = unsafe { core::slice::from_raw_parts(mappings as *const Mapping, mappings_count) };
 			use crate::task::*;
-			let vms = arch::VMS::new().unwrap();
+			let parent = task;
+
+			// Check the grants before creating anything so nothing leaks if one is invalid.
+			if grants_count > endpoint::TABLE_SIZE {
+				return Return(Status::TooLong, 0);
+			}
+			let mut endpoints = [None; endpoint::TABLE_SIZE];
 			arch::set_supervisor_userpage_access(true);
+			let grants = unsafe { core::slice::from_raw_parts(grants as *const Grant, grants_count) };
+			for (w, g) in endpoints.iter_mut().zip(grants) {
+				let uuid = (g.uuid != [0; 2]).then(|| g.uuid);
+				// A grant can't give access to more UUIDs than the spawning task has itself.
+				let inherited = endpoint::handle_index(g.address)
+					.and_then(|_| parent.endpoints().get(g.address))
+					.and_then(|e| e.uuid);
+				let e = match parent.resolve(g.address, uuid).map(endpoint::of) {
+					Ok(Some(e)) => e,
+					Ok(None) | Err(endpoint::ResolveError::Dead) => {
+						arch::set_supervisor_userpage_access(false);
+						return Return(Status::NotFound, 0);
+					}
+					Err(endpoint::ResolveError::InvalidEndpoint) => {
+						arch::set_supervisor_userpage_access(false);
+						return Return(Status::PermissionDenied, 0);
+					}
+				};
+				*w = Some(endpoint::Endpoint { uuid: uuid.or(inherited), ..e });
+			}
+
+			let vms = arch::VMS::new().unwrap();
 			for map in mappings {
 				match map.typ {
 					// Share mapping from current process.
@@ -363,14 +469,22 @@ mod sys {
 				}
 			}
 			arch::set_supervisor_userpage_access(false);
-			let task = Task::new(vms).unwrap();
+			let child = Task::new(vms).unwrap();
 			logcall!("  pc  {:p}", program_counter as *const ());
 			logcall!("  sp  {:p}", stack_pointer as *const ());
-			task.set_pc(program_counter as *const ());
-			task.set_stack_pointer(stack_pointer as *const ());
+			child.set_pc(program_counter as *const ());
+			child.set_stack_pointer(stack_pointer as *const ());
+			for e in endpoints.iter().flatten() {
+				child.endpoints().push(*e).expect("table is empty");
+			}
 			let group = Group::get(0).unwrap();
-			let id = group.insert(task).unwrap();
-			Return(Status::Ok, id)
+			let id = group.insert(child).unwrap();
+			let child = endpoint::of(Address::from(id)).expect("task was just inserted");
+			match parent.handle_for(child) {
+				Ok(handle) => Return(Status::Ok, handle),
+				// FIXME the task is still running.
+				Err(endpoint::Full) => Return(Status::MemoryUnavailable, 0),
+			}
 		}
 	}
 
@@ -534,11 +648,17 @@ mod sys {
 	sys! {
 		/// Add an entry to the registry. If the address is `usize::MAX`, it will be replaced by
 		/// the calling tasks' address.
-		[_] sys_registry_add(name, name_len, address) {
+		[task] sys_registry_add(name, name_len, address) {
 			use task::registry;
-			let address = (address == usize::MAX)
-				.then(task::Executor::current_address)
-				.unwrap_or(task::Address::todo(address));
+			let address = if address == usize::MAX {
+				task::Executor::current_address()
+			} else {
+				match task.resolve(address, None) {
+					Ok(address) => address,
+					Err(task::endpoint::ResolveError::InvalidEndpoint) => return Return(Status::PermissionDenied, 0),
+					Err(task::endpoint::ResolveError::Dead) => return Return(Status::NotFound, 0),
+				}
+			};
 			arch::set_supervisor_userpage_access(true);
 			let name = unsafe { core::slice::from_raw_parts(name as *const u8, name_len.into()) };
 			let ret = match registry::add(name, address) {
@@ -553,18 +673,392 @@ mod sys {
 	}
 
 	sys! {
-		/// Get an entry in the registry and return the address if found.
-		[_] sys_registry_get(name, name_len) {
+		/// Get an entry in the registry and return a handle to it if found.
+		[task] sys_registry_get(name, name_len) {
 			arch::set_supervisor_userpage_access(true);
 			let name = unsafe { core::slice::from_raw_parts(name as *const u8, name_len.into()) };
-			let ret = task::registry::get(name)
-				.map(|addr| Return(Status::Ok, addr.into()))
-				.unwrap_or(Return(Status::NotFound, 0));
+			let ret = match task::registry::get(name).and_then(task::endpoint::of) {
+				Some(e) => match task.handle_for(e) {
+					Ok(handle) => Return(Status::Ok, handle),
+					Err(task::endpoint::Full) => Return(Status::MemoryUnavailable, 0),
+				},
+				None => Return(Status::NotFound, 0),
+			};
 			arch::set_supervisor_userpage_access(false);
 			ret
 		}
 	}
 
+	sys! {
+		/// Return the current time in microseconds.
+		[_] sys_time() {
+			logcall!("sys_time");
+			Return(Status::Ok, arch::current_time() as usize)
+		}
+	}
+
+	sys! {
+		/// Get notified when the task with the given address dies.
+		[task] sys_watch_task(address) {
+			logcall!("sys_watch_task {}", address);
+			let address = match task.resolve(address, None) {
+				Ok(address) => address,
+				Err(task::endpoint::ResolveError::InvalidEndpoint) => return Return(Status::PermissionDenied, 0),
+				Err(task::endpoint::ResolveError::Dead) => return Return(Status::NotFound, 0),
+			};
+			let (g, t) = (address.group(), address.task());
+			if task::Group::get(g.into()).and_then(|g| g.task(t.into()).ok()).is_none() {
+				return Return(Status::NotFound, 0);
+			}
+			match task::watch::add(task::Executor::current_address(), address) {
+				Ok(()) => Return(Status::Ok, 0),
+				Err(task::watch::AddError::ListFull) => Return(Status::MemoryUnavailable, 0),
+			}
+		}
+	}
+
+	sys! {
+		/// Destroy the calling task along with all its threads.
+		[_] task_exit() {
+			logcall!("task_exit");
+			let address = task::Executor::current_address();
+			task::Task::destroy_all(address).expect("current task doesn't exist");
+			task::Executor::next()
+		}
+	}
+
+	sys! {
+		/// Allocate DMA memory as a list of physically contiguous runs, largest first.
+		[_] dev_dma_alloc_scatter(address, size, store, max_count) {
+			logcall!("dev_dma_alloc_scatter 0x{:x}, {}, 0x{:x}, {}", address, size, store, max_count);
+			let address = match Page::from_usize(address) {
+				Ok(a) => a,
+				Err(arch::page::FromPointerError::Null) => return Return(Status::NullArgument, 0),
+				Err(arch::page::FromPointerError::BadAlignment) => return Return(Status::BadAlignment, 0),
+			};
+			let count = match size.checked_add(arch::Page::SIZE - 1) {
+				Some(s) => s / arch::Page::SIZE,
+				None => return Return(Status::MemoryUnavailable, 0),
+			};
+			if store == 0 {
+				return Return(Status::NullArgument, 0);
+			}
+			if store % mem::align_of::<(usize, usize)>() != 0 {
+				return Return(Status::BadAlignment, 0);
+			}
+			let valid = max_count
+				.checked_mul(mem::size_of::<(usize, usize)>())
+				.map_or(false, |len| is_user_range(store, len, RWX::RW));
+			if !valid {
+				return Return(Status::MemoryNotAllocated, 0);
+			}
+			let store = unsafe { core::slice::from_raw_parts_mut(store as *mut (usize, usize), max_count) };
+			let (mut runs, mut address, mut error) = (0, Some(address), None);
+			let ret = crate::memory::mem_allocate_best_effort(count, |mut run| {
+				if let Some(s) = store.get_mut(runs) {
+					arch::set_supervisor_userpage_access(true);
+					*s = ((run.start() as usize) << arch::PAGE_BITS, run.len());
+					arch::set_supervisor_userpage_access(false);
+				}
+				runs += 1;
+				while let Some(ppn) = run.pop_base() {
+					let raw = ppn.into_raw();
+					let res = address.ok_or(vms::AddError::OutOfRange).and_then(|a| {
+						let map = Map::Private(unsafe { PPN::from_raw(raw) });
+						arch::VMS::add(a, map, RWX::RW, vms::Accessibility::UserLocal)
+					});
+					match res {
+						Ok(()) => address = address.and_then(|a| a.next()),
+						Err(e) => {
+							// SAFETY: the page didn't get mapped, so it is still unused.
+							unsafe { crate::memory::deallocate(PPN::from_raw(raw)) };
+							error = error.or(Some(e));
+						}
+					}
+				}
+			});
+			match (ret, error) {
+				(Err(_), _) => Return(Status::MemoryUnavailable, runs),
+				(Ok(()), Some(vms::AddError::Overlaps)) => Return(Status::MemoryOverlap, runs),
+				(Ok(()), Some(_)) => Return(Status::MemoryUnavailable, runs),
+				(Ok(()), None) if runs > max_count => Return(Status::TooLong, runs),
+				(Ok(()), None) => Return(Status::Ok, runs),
+			}
+		}
+	}
+
+	sys! {
+		/// Copy the statistics of the calling task. Only as many bytes as fit in the buffer are
+		/// copied.
+		[task] task_stats(store, size) {
+			logcall!("task_stats 0x{:x}, {}", store, size);
+			if store == 0 {
+				return Return(Status::NullArgument, 0);
+			}
+			if store % mem::align_of::<task::Stats>() != 0 {
+				return Return(Status::BadAlignment, 0);
+			}
+			let size = size.min(mem::size_of::<task::Stats>());
+			if !is_user_range(store, size, RWX::RW) {
+				return Return(Status::MemoryNotAllocated, 0);
+			}
+			let stats = task.stats();
+			let stats = unsafe {
+				core::slice::from_raw_parts(&stats as *const _ as *const u8, size)
+			};
+			arch::set_supervisor_userpage_access(true);
+			unsafe { core::slice::from_raw_parts_mut(store as *mut u8, size) }
+				.copy_from_slice(stats);
+			arch::set_supervisor_userpage_access(false);
+			Return(Status::Ok, size)
+		}
+	}
+
+	sys! {
+		/// Enable or disable the emulation of misaligned loads & stores for the calling task.
+		[task] task_emulate_misaligned(enable) {
+			logcall!("task_emulate_misaligned {}", enable);
+			if cfg!(feature = "emulate-misaligned") {
+				Return(Status::Ok, task.set_emulate_misaligned(enable != 0).into())
+			} else {
+				Return(Status::Unavailable, 0)
+			}
+		}
+	}
+
+	sys! {
+		/// Create a thread sharing the VMS & IPC queues of the calling task. The argument is
+		/// passed in the first argument register.
+		[task] thread_spawn(program_counter, stack_pointer, argument) {
+			logcall!("thread_spawn 0x{:x}, 0x{:x}, 0x{:x}", program_counter, stack_pointer, argument);
+			let thread = match task.new_thread() {
+				Ok(thread) => thread,
+				Err(_) => return Return(Status::MemoryUnavailable, 0),
+			};
+			thread.set_pc(program_counter as *const ());
+			thread.set_stack_pointer(stack_pointer as *const ());
+			thread.set_argument(argument);
+			let address = task::Executor::current_address();
+			let group = task::Group::get(address.group().into())
+				.expect("current group doesn't exist");
+			match group.insert(thread.clone()) {
+				Ok(id) => Return(Status::Ok, address.with_task(id).into()),
+				Err(_) => {
+					// The thread never ran, so nothing else refers to it.
+					thread.discard();
+					Return(Status::MemoryUnavailable, 0)
+				}
+			}
+		}
+	}
+
+	sys! {
+		/// Destroy the calling thread. If it is the thread the task started with, all other
+		/// threads are destroyed too.
+		[_] thread_exit() {
+			logcall!("thread_exit");
+			let address = task::Executor::current_address();
+			task::Task::destroy(address).expect("current task doesn't exist");
+			task::Executor::next()
+		}
+	}
+
+	sys! {
+		/// Ask all tasks in the registry to suspend their devices, then suspend the system once
+		/// they acknowledged or the timeout expired. The caller is sent a `RESUME` packet
+		/// afterwards.
+		[task] sys_system_suspend(timeout) {
+			logcall!("sys_system_suspend {}", timeout);
+			let address = task::Executor::current_address();
+			// FIXME use capabilities once they exist.
+			if usize::from(address) != 0 {
+				return Return(Status::PermissionDenied, 0);
+			}
+			match crate::powerstate::suspend(address, timeout as u64) {
+				Ok(count) => {
+					// Sending the packets changed the active virtual memory.
+					task.set_return(Status::Ok as usize, count);
+					task::Executor::next()
+				}
+				Err(crate::powerstate::SuspendError::InProgress) => Return(Status::Occupied, 0),
+			}
+		}
+	}
+
+	sys! {
+		/// Only accept endpoint handles from the calling task & its threads and only give it
+		/// handles. Returns whether this was already enabled.
+		[task] task_strict_endpoints(enable) {
+			logcall!("task_strict_endpoints {}", enable);
+			Return(Status::Ok, task.set_strict_endpoints(enable != 0).into())
+		}
+	}
+
+	sys! {
+		/// Copy messages from the kernel log, starting at the byte position stored in
+		/// `position`. If those bytes were already overwritten, the oldest bytes are copied
+		/// instead. `position` is set to the position after the last copied byte & the amount
+		/// of copied bytes is returned.
+		[_] sys_log_read(store, length, position) {
+			logcall!("sys_log_read 0x{:x}, {}, 0x{:x}", store, length, position);
+			// The slice of an empty store must still be non-null.
+			if store == 0
+				|| position % mem::align_of::<u64>() != 0
+				|| !is_user_range(position, mem::size_of::<u64>(), RWX::RW)
+				|| !is_user_range(store, length, RWX::RW)
+			{
+				return Return(Status::MemoryNotAllocated, 0);
+			}
+			arch::set_supervisor_userpage_access(true);
+			let store = unsafe { core::slice::from_raw_parts_mut(store as *mut u8, length) };
+			let position = unsafe { &mut *(position as *mut u64) };
+			let (start, count) = crate::log::read(*position, store);
+			*position = start + count as u64;
+			arch::set_supervisor_userpage_access(false);
+			Return(Status::Ok, count)
+		}
+	}
+
+	sys! {
+		/// Pin pages of the calling task that were shared by a client, so they aren't reused
+		/// while a device may still access them. The physical addresses of the pages are written
+		/// to `store`.
+		[task] dev_dma_pin(client, address, store, count) {
+			logcall!("dev_dma_pin {}, 0x{:x}, 0x{:x}, {}", client, address, store, count);
+			let client = match task.resolve(client, None) {
+				Ok(address) => address,
+				Err(task::endpoint::ResolveError::InvalidEndpoint) => return Return(Status::PermissionDenied, 0),
+				Err(task::endpoint::ResolveError::Dead) => return Return(Status::NotFound, 0),
+			};
+			let (g, t) = (client.group(), client.task());
+			let client = match task::Group::get(g.into()).and_then(|g| g.task(t.into()).ok()) {
+				Some(client) => client,
+				None => return Return(Status::NotFound, 0),
+			};
+			let address = match Page::from_usize(address) {
+				Ok(a) => a,
+				Err(arch::page::FromPointerError::Null) => return Return(Status::NullArgument, 0),
+				Err(arch::page::FromPointerError::BadAlignment) => return Return(Status::BadAlignment, 0),
+			};
+			// More pages can't be pinned anyway.
+			if count > crate::memory::pin::TABLE_SIZE {
+				return Return(Status::MemoryUnavailable, 0);
+			}
+			if store % mem::align_of::<usize>() != 0 {
+				return Return(Status::BadAlignment, 0);
+			}
+			if store == 0 || !is_user_range(store, count * mem::size_of::<usize>(), RWX::RW) {
+				return Return(Status::MemoryNotAllocated, 0);
+			}
+			// Only pages the client shared with the caller may be pinned. Any other page would
+			// be freed by the quarantine while its owner still maps it.
+			let mut physical = [0; crate::memory::pin::TABLE_SIZE];
+			let physical = &mut physical[..count];
+			for (i, p) in physical.iter_mut().enumerate() {
+				match address.skip(i).and_then(arch::VMS::user_shared) {
+					Some(a) => *p = a,
+					None => return Return(Status::MemoryNotAllocated, 0),
+				}
+			}
+			if !client.maps_shared(physical) {
+				return Return(Status::MemoryNotAllocated, 0);
+			}
+			let driver = task::Executor::current_address();
+			let pin = |phys: &usize| crate::memory::pin::Pin {
+				ppn: (phys >> arch::PAGE_BITS) as PPNBox,
+				driver,
+			};
+			let pinned = physical.iter().take_while(|p| client.pins().pin(pin(p)).is_ok()).count();
+			if pinned < count {
+				// Don't leave a partial pin behind.
+				physical[..pinned].iter().for_each(|p| { client.pins().unpin(pin(p)); });
+				return Return(Status::MemoryUnavailable, 0);
+			}
+			arch::set_supervisor_userpage_access(true);
+			unsafe { core::slice::from_raw_parts_mut(store as *mut usize, count) }
+				.copy_from_slice(physical);
+			arch::set_supervisor_userpage_access(false);
+			Return(Status::Ok, 0)
+		}
+	}
+
+	sys! {
+		/// Unpin pages pinned with `dev_dma_pin`, given by their physical addresses. If the
+		/// client died, its quarantined pages are freed. Returns the amount of pages that were
+		/// pinned.
+		[task] dev_dma_unpin(client, store, count) {
+			logcall!("dev_dma_unpin {}, 0x{:x}, {}", client, store, count);
+			let client = match task.resolve(client, None) {
+				Ok(address) => {
+					let (g, t) = (address.group(), address.task());
+					task::Group::get(g.into()).and_then(|g| g.task(t.into()).ok())
+				}
+				Err(task::endpoint::ResolveError::InvalidEndpoint) => return Return(Status::PermissionDenied, 0),
+				Err(task::endpoint::ResolveError::Dead) => None,
+			};
+			if store % mem::align_of::<usize>() != 0 {
+				return Return(Status::BadAlignment, 0);
+			}
+			let valid = count
+				.checked_mul(mem::size_of::<usize>())
+				.map_or(false, |size| is_user_range(store, size, RWX::R));
+			if store == 0 || !valid {
+				return Return(Status::MemoryNotAllocated, 0);
+			}
+			let driver = task::Executor::current_address();
+			let store = unsafe { core::slice::from_raw_parts(store as *const usize, count) };
+			arch::set_supervisor_userpage_access(true);
+			let unpinned = store
+				.iter()
+				.filter(|phys| {
+					let pin = crate::memory::pin::Pin {
+						ppn: (**phys >> arch::PAGE_BITS) as PPNBox,
+						driver,
+					};
+					crate::memory::pin::unpin(client.as_ref().map(|c| c.pins()), pin)
+				})
+				.count();
+			arch::set_supervisor_userpage_access(false);
+			Return(Status::Ok, unpinned)
+		}
+	}
+
+	sys! {
+		/// Copy the statistics of the global memory manager. Only as many bytes as fit in the
+		/// buffer are copied.
+		[_] mem_stats(store, size) {
+			logcall!("mem_stats 0x{:x}, {}", store, size);
+			if store == 0 {
+				return Return(Status::NullArgument, 0);
+			}
+			let size = size.min(mem::size_of::<crate::memory::Stats>());
+			if !is_user_range(store, size, RWX::RW) {
+				return Return(Status::MemoryNotAllocated, 0);
+			}
+			let stats = crate::memory::stats();
+			let stats = unsafe {
+				core::slice::from_raw_parts(&stats as *const _ as *const u8, size)
+			};
+			arch::set_supervisor_userpage_access(true);
+			unsafe { core::slice::from_raw_parts_mut(store as *mut u8, size) }
+				.copy_from_slice(stats);
+			arch::set_supervisor_userpage_access(false);
+			Return(Status::Ok, size)
+		}
+	}
+
+	sys! {
+		/// Remove an endpoint handle of the calling task & its threads so the entry can be
+		/// reused.
+		[task] task_close_endpoint(handle) {
+			logcall!("task_close_endpoint 0x{:x}", handle);
+			match task.endpoints().close(handle) {
+				Ok(()) => Return(Status::Ok, 0),
+				Err(endpoint::InvalidHandle) => Return(Status::NotFound, 0),
+			}
+		}
+	}
+
 	sys! {
 		/// Placeholder so that I don't need to update TABLE_LEN constantly.
 		[_] placeholder() {
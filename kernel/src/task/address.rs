@@ -51,6 +51,10 @@ pub struct GroupID(u64);
 pub struct Address(usize);
 
 impl Address {
+	/// The address used by the kernel as sender of packets & as destination of packets
+	/// answering them.
+	pub const KERNEL: Self = Self(usize::MAX);
+
 	#[allow(dead_code)]
 	pub fn new(task: TaskID, group: GroupID) -> Self {
 		Self(task.0 as usize | (group.0 << (mem::size_of::<usize>() * 4)) as usize)
@@ -72,6 +76,13 @@ impl Address {
 		)
 	}
 
+	/// Return the address of the task with the given ID in the same group.
+	pub fn with_task(&self, task: usize) -> Self {
+		let shift = mem::size_of::<usize>() * 4;
+		debug_assert!(task < 1 << shift, "task ID out of range");
+		Self(self.0 >> shift << shift | task)
+	}
+
 	pub const fn todo(n: usize) -> Self {
 		Self(n)
 	}
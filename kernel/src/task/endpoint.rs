@@ -0,0 +1,233 @@
+//! # IPC endpoints
+//!
+//! Tasks refer to other tasks with *endpoint handles* instead of raw task addresses. Each task
+//! has a small table that maps handles to a task & the generation of the slot it occupies,
+//! optionally restricted to a single UUID. A handle is only meaningful to the task owning the
+//! table, so a task can only talk to the tasks it was given a handle to. Since the generation
+//! of a slot changes when its task is destroyed, a handle never refers to a task that reused
+//! the slot.
+//!
+//! Handles are populated by the parent when a task is spawned, by registry lookups and by the
+//! kernel when a packet is received, in which case the address of the packet is a handle to
+//! reply to the sender.
+//!
+//! ## Compatibility mode
+//!
+//! Tasks are in compatibility mode until they opt out. Such tasks may still send packets to
+//! raw addresses and receive raw addresses from the kernel. Handles work in either mode.
+
+use super::Address;
+use crate::sync::Mutex;
+use core::mem;
+
+/// Set on values that are handles. Other values are raw addresses.
+pub const HANDLE_FLAG: usize = 1 << (mem::size_of::<usize>() * 8 - 1);
+
+/// The maximum amount of handles a task can have.
+pub const TABLE_SIZE: usize = 32;
+
+/// The bits of a handle holding the index in the table. The bits above hold the amount of times
+/// the entry was reused, so a handle that was closed doesn't refer to the next endpoint put in
+/// the same entry.
+const INDEX_BITS: u32 = 8;
+
+/// A task another task can send packets to.
+#[derive(Clone, Copy, PartialEq)]
+pub struct Endpoint {
+	/// The address of the task.
+	pub address: Address,
+	/// The generation of the slot of the task.
+	pub generation: u32,
+	/// The only UUID that may be sent to the task, if any.
+	pub uuid: Option<[u64; 2]>,
+}
+
+impl Endpoint {
+	/// Whether the task this endpoint refers to was destroyed.
+	fn is_dead(&self) -> bool {
+		of(self.address).map_or(true, |e| e.generation != self.generation)
+	}
+}
+
+/// An entry of a table of endpoints.
+#[derive(Clone, Copy)]
+struct Entry {
+	endpoint: Option<Endpoint>,
+	/// The amount of times the entry was reused.
+	reuse: u16,
+}
+
+impl Entry {
+	fn handle(&self, index: usize) -> usize {
+		HANDLE_FLAG | usize::from(self.reuse) << INDEX_BITS | index
+	}
+}
+
+/// A table of endpoints, indexed by handle.
+pub struct Table(Mutex<[Entry; TABLE_SIZE]>);
+
+/// The table has no room for another endpoint.
+#[derive(Debug)]
+pub struct Full;
+
+/// The handle doesn't refer to an endpoint.
+#[derive(Debug)]
+pub struct InvalidHandle;
+
+impl Table {
+	pub const fn new() -> Self {
+		Self(Mutex::new(
+			[Entry {
+				endpoint: None,
+				reuse: 0,
+			}; TABLE_SIZE],
+		))
+	}
+
+	/// Add an endpoint and return its handle. If an equal endpoint is already present, its
+	/// handle is returned instead.
+	pub fn insert(&self, endpoint: Endpoint) -> Result<usize, Full> {
+		let mut table = self.0.lock();
+		match table.iter().position(|e| e.endpoint == Some(endpoint)) {
+			Some(i) => Ok(table[i].handle(i)),
+			None => Self::push_locked(&mut table[..], endpoint),
+		}
+	}
+
+	/// Add an endpoint and return its handle, even if an equal endpoint is already present.
+	pub fn push(&self, endpoint: Endpoint) -> Result<usize, Full> {
+		Self::push_locked(&mut self.0.lock()[..], endpoint)
+	}
+
+	/// Put an endpoint in a free entry. If there is none, the entry of an endpoint whose task
+	/// was destroyed is reused. Sending to the old handle of that entry fails as if it never
+	/// existed.
+	fn push_locked(table: &mut [Entry], endpoint: Endpoint) -> Result<usize, Full> {
+		if let Some(i) = table.iter().position(|e| e.endpoint.is_none()) {
+			table[i].endpoint = Some(endpoint);
+			return Ok(table[i].handle(i));
+		}
+		let i = table
+			.iter()
+			.position(|e| e.endpoint.map_or(false, |e| e.is_dead()))
+			.ok_or(Full)?;
+		table[i] = Entry {
+			endpoint: Some(endpoint),
+			reuse: table[i].reuse.wrapping_add(1),
+		};
+		Ok(table[i].handle(i))
+	}
+
+	/// Return the endpoint a handle refers to.
+	pub fn get(&self, handle: usize) -> Option<Endpoint> {
+		let i = handle_index(handle)?;
+		self.0
+			.lock()
+			.get(i)
+			.filter(|e| e.handle(i) == handle)
+			.and_then(|e| e.endpoint)
+	}
+
+	/// Remove the endpoint a handle refers to. The handle and any copies of it become invalid,
+	/// even if the same endpoint is added again later.
+	pub fn close(&self, handle: usize) -> Result<(), InvalidHandle> {
+		let i = handle_index(handle).ok_or(InvalidHandle)?;
+		let mut table = self.0.lock();
+		let e = table
+			.get_mut(i)
+			.filter(|e| e.handle(i) == handle && e.endpoint.is_some())
+			.ok_or(InvalidHandle)?;
+		*e = Entry {
+			endpoint: None,
+			reuse: e.reuse.wrapping_add(1),
+		};
+		Ok(())
+	}
+}
+
+/// Return the index of a handle, or `None` if the value is a raw address.
+pub fn handle_index(value: usize) -> Option<usize> {
+	(value & HANDLE_FLAG > 0 && Address::from(value) != Address::KERNEL)
+		.then(|| value & ((1 << INDEX_BITS) - 1))
+}
+
+/// Why a value given by a task couldn't be translated to an address.
+#[derive(Debug)]
+pub enum ResolveError {
+	/// The task has no such handle, the handle doesn't allow the UUID or it's a raw address
+	/// while the task isn't in compatibility mode.
+	InvalidEndpoint,
+	/// The task the handle refers to was destroyed.
+	Dead,
+}
+
+/// Return the endpoint of the task with the given address, if it exists.
+pub fn of(address: Address) -> Option<Endpoint> {
+	let group = super::Group::get(address.group().into())?;
+	group.task(address.task().into()).ok()?;
+	let generation = group.generation(address.task().into()).ok()?;
+	Some(Endpoint {
+		address,
+		generation,
+		uuid: None,
+	})
+}
+
+impl super::Task {
+	/// Return the endpoint table of this task, which is shared with its threads.
+	pub fn endpoints(&self) -> &Table {
+		&self.owner().inner().endpoints
+	}
+
+	/// Check whether this task only accepts handles.
+	pub fn strict_endpoints(&self) -> bool {
+		self.owner().inner().flags.0 & super::Flags::STRICT_ENDPOINTS > 0
+	}
+
+	/// Enable or disable compatibility mode for this task & its threads. Returns the old value.
+	pub fn set_strict_endpoints(&self, enable: bool) -> bool {
+		let old = self.strict_endpoints();
+		let flags = &mut self.owner().inner().flags.0;
+		if enable {
+			*flags |= super::Flags::STRICT_ENDPOINTS;
+		} else {
+			*flags &= !super::Flags::STRICT_ENDPOINTS;
+		}
+		old
+	}
+
+	/// Translate a handle or raw address given by this task to the address of a task.
+	///
+	/// If a UUID is given, the handle must allow it. Raw addresses are accepted as is if this
+	/// task is in compatibility mode. The caller must check whether the task exists.
+	pub fn resolve(&self, value: usize, uuid: Option<[u64; 2]>) -> Result<Address, ResolveError> {
+		if handle_index(value).is_none() {
+			return (!self.strict_endpoints())
+				.then(|| Address::from(value))
+				.ok_or(ResolveError::InvalidEndpoint);
+		}
+		let e = self
+			.endpoints()
+			.get(value)
+			.ok_or(ResolveError::InvalidEndpoint)?;
+		if let (Some(allowed), Some(uuid)) = (e.uuid, uuid) {
+			if allowed != uuid {
+				return Err(ResolveError::InvalidEndpoint);
+			}
+		}
+		match of(e.address) {
+			Some(current) if current.generation == e.generation => Ok(e.address),
+			_ => Err(ResolveError::Dead),
+		}
+	}
+
+	/// Return the value this task should use to refer to the given endpoint, i.e. a handle or
+	/// the raw address if this task is in compatibility mode.
+	pub fn handle_for(&self, endpoint: Endpoint) -> Result<usize, Full> {
+		if self.strict_endpoints() {
+			self.endpoints().insert(endpoint)
+		} else {
+			Ok(endpoint.address.into())
+		}
+	}
+}
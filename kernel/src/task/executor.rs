@@ -56,8 +56,8 @@ impl Executor<'_> {
 		// Incrementing by prime numbers because I'm a genius hacker hmmm yes yes
 		let mut id = (prev_id + 7) & 0xf;
 
-		let mut min_time = u64::MAX;
 		let curr_time = arch::current_time();
+		let mut min_time = crate::powerstate::poll(curr_time).unwrap_or(u64::MAX);
 		let mut stop_next = false;
 
 		loop {
@@ -10,7 +10,7 @@ use crate::memory::reserved;
 use core::ops::Deref;
 use core::ptr;
 use core::ptr::NonNull;
-use core::sync::atomic::{AtomicPtr, Ordering};
+use core::sync::atomic::{AtomicPtr, AtomicU32, Ordering};
 
 /// The start of the task group list.
 static GROUPS: arena::Arena<GroupData> = unsafe {
@@ -26,6 +26,9 @@ pub struct GroupData {
 	///
 	/// Increment as needed until it's no longer manageable.
 	tasks: [AtomicPtr<super::TaskData>; 16],
+	/// The generation of each slot, which is incremented when its task is removed. Used to
+	/// detect [`Endpoint`](super::endpoint::Endpoint)s referring to a destroyed task.
+	generations: [AtomicU32; 16],
 }
 
 // FIXME Task is not sync yet. We also need to ensure tasks can't be removed/freed while referenced.
@@ -73,6 +76,7 @@ impl Group<'_> {
 				AtomicPtr::default(),
 				AtomicPtr::default(),
 			],
+			generations: Default::default(),
 		})
 	}
 
@@ -88,16 +92,33 @@ impl Group<'_> {
 			.ok_or(NoTask)
 	}
 
+	/// Return the generation of the slot with the given ID.
+	pub fn generation(&self, id: usize) -> Result<u32, NoTask> {
+		self.data
+			.generations
+			.get(id)
+			.map(|g| g.load(Ordering::Relaxed))
+			.ok_or(NoTask)
+	}
+
+	/// Return an iterator over the IDs of the tasks in this group & the tasks themselves.
+	pub fn tasks(&self) -> impl Iterator<Item = (usize, Task)> + '_ {
+		(0..self.data.tasks.len()).filter_map(move |id| self.task(id).ok().map(|t| (id, t)))
+	}
+
 	/// Remove a task. This frees the group if no task are left.
 	///
 	/// If any tasks are left, the group itself is returned.
 	// FIXME this isn't thread-safe
-	#[allow(dead_code)]
 	pub fn remove_task(self, id: usize) -> Result<Option<Self>, NoTask> {
 		let tasks = &self.data.tasks;
 		tasks
 			.get(id)
 			.map(|t| t.store(ptr::null_mut(), Ordering::Relaxed));
+		self.data
+			.generations
+			.get(id)
+			.map(|g| g.fetch_add(1, Ordering::Relaxed));
 		// FIXME this is not sound
 		if tasks
 			.iter()
@@ -1,13 +1,15 @@
 //! Structures & functions to facilitate inter-task communication
 
+use super::endpoint::{self, ResolveError};
 use super::group::Group;
 use super::Address;
+use crate::arch::vms::RWX;
 use crate::arch::{self, Page, PageData};
 use core::cell::Cell;
 use core::num::NonZeroU8;
 use core::ptr::NonNull;
 use core::slice;
-use core::sync::atomic::{AtomicU16, Ordering};
+use core::sync::atomic::{AtomicBool, AtomicU16, Ordering};
 
 /// An IPC packet.
 #[derive(Clone, Copy, Debug)]
@@ -35,6 +37,15 @@ impl Flags {
 	const WRITEABLE: u16 = 0x2;
 	const EXECUTABLE: u16 = 0x4;
 	const LOCK: u16 = 0x8;
+	/// Set on responses to requests that failed. The offset holds the error code.
+	const ERROR: u16 = 0x20;
+	/// Set on packets that couldn't be delivered because the destination doesn't exist anymore
+	/// and on notifications sent to tasks watching a dead task.
+	const DEAD_PEER: u16 = 0x200;
+	/// Unmap the data pages from the sender once they're shared with the receiver.
+	const MOVE: u16 = 0x400;
+	/// Flags only the kernel may set. These are cleared on packets sent by tasks.
+	const KERNEL_ONLY: u16 = Self::DEAD_PEER;
 
 	#[must_use]
 	#[allow(dead_code)]
@@ -59,6 +70,85 @@ impl Flags {
 	pub fn lock(&self) -> bool {
 		self.0 & Self::LOCK > 0
 	}
+
+	#[must_use]
+	#[allow(dead_code)]
+	pub fn dead_peer(&self) -> bool {
+		self.0 & Self::DEAD_PEER > 0
+	}
+
+	#[must_use]
+	pub fn moves(&self) -> bool {
+		self.0 & Self::MOVE > 0
+	}
+}
+
+impl From<RWX> for Flags {
+	fn from(rwx: RWX) -> Self {
+		let (r, w, x) = match rwx {
+			RWX::R => (true, false, false),
+			RWX::RW => (true, true, false),
+			RWX::X => (false, false, true),
+			RWX::RX => (true, false, true),
+			RWX::RWX => (true, true, true),
+		};
+		Self(
+			u16::from(r) * Self::READABLE
+				| u16::from(w) * Self::WRITEABLE
+				| u16::from(x) * Self::EXECUTABLE,
+		)
+	}
+}
+
+/// The data or name of a packet isn't accessible by the sender.
+const ERROR_INVALID_POINTER: u64 = 3;
+/// The sender has no such handle, the handle doesn't allow the UUID, the handle refers to the
+/// sender itself or the receiver can't hold a handle to reply to the sender.
+const ERROR_INVALID_ENDPOINT: u64 = 4;
+
+/// Sent by the kernel before the system is suspended. Tasks acknowledge it by sending a packet
+/// with the same opcode to [`Address::KERNEL`].
+pub const OP_SUSPEND: u8 = 13;
+/// Sent by the kernel after the system resumed.
+pub const OP_RESUME: u8 = 14;
+/// Sent by the kernel to the core dump service with the header of a dump.
+pub const OP_COREDUMP: u8 = 15;
+/// Sent by the kernel to the core dump service with a segment of a dump. The service requests
+/// the next segment by sending a packet with the same opcode to [`Address::KERNEL`].
+pub const OP_COREDUMP_SEGMENT: u8 = 16;
+
+impl Packet {
+	/// Create a packet notifying a task that the task at the given address died.
+	fn death_notification(address: Address) -> Self {
+		Self {
+			uuid: [0; 2],
+			data: None,
+			name: None,
+			data_offset: 0,
+			data_length: 0,
+			address,
+			flags: Flags(Flags::DEAD_PEER),
+			name_length: 0,
+			id: 0,
+			opcode: None,
+		}
+	}
+
+	/// Create a packet sent by the kernel.
+	fn kernel(opcode: u8, offset: u64) -> Self {
+		Self {
+			uuid: [0; 2],
+			data: None,
+			name: None,
+			data_offset: offset,
+			data_length: 0,
+			address: Address::KERNEL,
+			flags: Flags(0),
+			name_length: 0,
+			id: 0,
+			opcode: NonZeroU8::new(opcode),
+		}
+	}
 }
 
 #[derive(Debug)]
@@ -79,7 +169,37 @@ enum PushFreeSlotError {
 	Full,
 }
 
+/// A spinlock for state that is only accessed by the kernel. It is never held while a task
+/// is running, so waiting for it can't deadlock.
+struct Lock(AtomicBool);
+
+struct LockGuard<'a>(&'a Lock);
+
+impl Lock {
+	const fn new() -> Self {
+		Self(AtomicBool::new(false))
+	}
+
+	fn lock(&self) -> LockGuard<'_> {
+		while self
+			.0
+			.compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed)
+			.is_err()
+		{}
+		LockGuard(self)
+	}
+}
+
+impl Drop for LockGuard<'_> {
+	fn drop(&mut self) {
+		(self.0).0.store(false, Ordering::Release);
+	}
+}
+
 /// A structure used for handling IPC.
+///
+/// It is shared by all threads of a task, which may access it from different harts at the
+/// same time.
 pub struct IPC {
 	/// The address of the packets buffer.
 	packets: NonNull<Packet>,
@@ -91,6 +211,13 @@ pub struct IPC {
 	free_pages: NonNull<FreePage>,
 	/// The maximum amount of free pages.
 	max_free_pages: usize,
+	/// Held while processing the transmit ring.
+	transmit_lock: Lock,
+	/// Held while adding packets to the received ring.
+	received_lock: Lock,
+	/// A processed transmit slot that couldn't be pushed to the free stack yet because the
+	/// stack was locked by the task.
+	unfreed_slot: Cell<Option<u16>>,
 }
 
 impl IPC {
@@ -117,27 +244,108 @@ impl IPC {
 				ring_mask: (1 << mask_bits) - 1,
 				free_pages,
 				max_free_pages,
+				transmit_lock: Lock::new(),
+				received_lock: Lock::new(),
+				unfreed_slot: Cell::new(None),
 			})
 			.ok_or(TooLarge)
 	}
 
 	/// Process IPC packets to be transmitted.
-	pub fn process_packets(&mut self, slf_task: &super::Task, slf_address: Address) {
+	pub fn process_packets(&self, slf_task: &super::Task, slf_address: Address) {
 		use crate::arch::vms::VirtualMemorySystem;
+		let _transmit_lock = self.transmit_lock.lock();
 		slf_task.inner().shared_state.virtual_memory.activate(); // TODO do this beforehand and once only
 		arch::set_supervisor_userpage_access(true);
+		if let Some(slot) = self.unfreed_slot.take() {
+			if !self.free_transmit_slot(slf_task, slot) {
+				arch::set_supervisor_userpage_access(false);
+				return;
+			}
+		}
 		let (tx_index, tx_slots) = self.transmit_ring();
 		let mut last_transmit_index = self.last_transmit_index.get();
 		while last_transmit_index != tx_index {
 			let tx_pkt_slot = last_transmit_index & self.ring_mask;
-			let tx_pkt = unsafe { *self.packet(tx_slots[usize::from(tx_pkt_slot)]).unwrap() };
+			let mut tx_pkt = unsafe { *self.packet(tx_slots[usize::from(tx_pkt_slot)]).unwrap() };
+			tx_pkt.flags.0 &= !Flags::KERNEL_ONLY;
+
+			// Packets to the kernel are acknowledgements of packets the kernel sent.
+			if tx_pkt.address == Address::KERNEL {
+				match tx_pkt.opcode.map(NonZeroU8::get) {
+					Some(OP_SUSPEND) => crate::powerstate::acknowledge(slf_address),
+					Some(OP_COREDUMP_SEGMENT) => {
+						crate::coredump::next(slf_address);
+						// The segment is sent to this task, which may change the active virtual
+						// memory.
+						slf_task.inner().shared_state.virtual_memory.activate();
+						arch::set_supervisor_userpage_access(true);
+					}
+					_ => (),
+				}
+				last_transmit_index = last_transmit_index.wrapping_add(1);
+				if !self.free_transmit_slot(slf_task, tx_pkt_slot) {
+					break;
+				}
+				continue;
+			}
 
-			// Disallow sending packets to self since it's pointless + leads to potential aliasing
-			// bugs.
-			assert_ne!(tx_pkt.address, slf_address, "can't transmit to self");
+			// Make sure the sender can't share pages it doesn't own, such as kernel mappings or
+			// pages it may only read. The VMS of the sender is still active.
+			let data_valid = tx_pkt
+				.data
+				.map_or(true, |d| Self::is_user_range(d, tx_pkt.data_length, true));
+			let name_valid = tx_pkt.name.map_or(true, |n| {
+				Self::is_user_range(n, usize::from(tx_pkt.name_length), false)
+			});
+			if !data_valid || !name_valid {
+				let mut rx_pkt = tx_pkt;
+				rx_pkt.flags.0 |= Flags::ERROR;
+				rx_pkt.data_offset = ERROR_INVALID_POINTER;
+				last_transmit_index = last_transmit_index.wrapping_add(1);
+				if !self.bounce(slf_task, tx_pkt_slot, rx_pkt) {
+					break;
+				}
+				continue;
+			}
 
-			let (group, task) = (tx_pkt.address.group(), tx_pkt.address.task());
-			let task = Group::get(group.into()).unwrap().task(task.into()).unwrap();
+			// The sender may only send to tasks it has a handle to, unless it is in compatibility
+			// mode. Sending packets to self is disallowed since it's pointless & leads to
+			// potential aliasing bugs.
+			let address = match slf_task.resolve(tx_pkt.address.into(), Some(tx_pkt.uuid)) {
+				Ok(address) if address != slf_address => Some(address),
+				Err(ResolveError::Dead) => None,
+				Ok(_) | Err(ResolveError::InvalidEndpoint) => {
+					let mut rx_pkt = tx_pkt;
+					rx_pkt.flags.0 |= Flags::ERROR;
+					rx_pkt.data_offset = ERROR_INVALID_ENDPOINT;
+					last_transmit_index = last_transmit_index.wrapping_add(1);
+					if !self.bounce(slf_task, tx_pkt_slot, rx_pkt) {
+						break;
+					}
+					continue;
+				}
+			};
+
+			let task = address.and_then(|a| {
+				let (group, task) = (a.group(), a.task());
+				Group::get(group.into()).and_then(|g| g.task(task.into()).ok())
+			});
+			let task = match task {
+				Some(task) => task,
+				None => {
+					// The destination doesn't exist (anymore), so return the packet to the
+					// sender. The data & name pages were never shared and are still owned by
+					// the sender.
+					let mut rx_pkt = tx_pkt;
+					rx_pkt.flags.0 |= Flags::DEAD_PEER;
+					last_transmit_index = last_transmit_index.wrapping_add(1);
+					if !self.bounce(slf_task, tx_pkt_slot, rx_pkt) {
+						break;
+					}
+					continue;
+				}
+			};
 
 			// TODO this is potentially terribly inefficient
 			//
@@ -147,7 +355,7 @@ impl IPC {
 			// It may be worth mapping the packet tables into kernel space.
 			task.inner().shared_state.virtual_memory.activate();
 
-			let task_ipc = match task.inner().ipc.as_mut() {
+			let task_ipc = match task.ipc().as_ref() {
 				Some(ipc) => ipc,
 				None => {
 					// TODO instead of waking up the task, we should just process it's entries
@@ -156,8 +364,35 @@ impl IPC {
 					break;
 				}
 			};
+
+			// Give the receiver a handle to reply to the sender.
+			let sender = endpoint::of(slf_address).expect("sender doesn't exist");
+			let reply_address = match task.handle_for(sender) {
+				Ok(a) => Address::from(a),
+				Err(endpoint::Full) => {
+					// The receiver can't hold any more handles.
+					slf_task.inner().shared_state.virtual_memory.activate();
+					let mut rx_pkt = tx_pkt;
+					rx_pkt.flags.0 |= Flags::ERROR;
+					rx_pkt.data_offset = ERROR_INVALID_ENDPOINT;
+					last_transmit_index = last_transmit_index.wrapping_add(1);
+					if !self.bounce(slf_task, tx_pkt_slot, rx_pkt) {
+						break;
+					}
+					continue;
+				}
+			};
+			let received_lock = task_ipc.received_lock.lock();
 			let (rx_index, rx_slots) = task_ipc.received_ring();
-			let rx_pkt_slot = task_ipc.pop_free_slot().unwrap();
+			let rx_pkt_slot = match task_ipc.pop_free_slot() {
+				Ok(slot) => slot,
+				Err(_) => {
+					// The free stack is full or locked by the receiving task. Leave the packet
+					// in the transmit ring and try again later.
+					slf_task.inner().wait_time = 0;
+					break;
+				}
+			};
 
 			// Get address range to map the data
 			let tx_rx_data = tx_pkt.data.map(|data| {
@@ -185,13 +420,14 @@ impl IPC {
 				data_length: tx_pkt.data_length,
 				data_offset: tx_pkt.data_offset,
 				name_length: tx_pkt.name_length,
-				address: slf_address,
+				address: reply_address,
 				flags: tx_pkt.flags,
 				opcode: tx_pkt.opcode,
 				id: tx_pkt.id,
 			};
 
 			rx_index.fetch_add(1, Ordering::Release);
+			drop(received_lock);
 
 			// Clear the tasks wait time so it will be rescheduled
 			task.inner().wait_time = 0;
@@ -211,6 +447,11 @@ impl IPC {
 					)
 					.unwrap();
 				}
+				// The receiver holds the only mapping from now on. The VMS of the sender is
+				// active.
+				if tx_pkt.flags.moves() {
+					arch::VMS::deallocate(tx_data, count).unwrap();
+				}
 			}
 			if let Some((tx_name, rx_name, count)) = tx_rx_name {
 				for i in 0..count {
@@ -224,14 +465,83 @@ impl IPC {
 				}
 			}
 
-			self.push_free_slot(tx_pkt_slot).unwrap();
-
 			last_transmit_index = last_transmit_index.wrapping_add(1);
+			if !self.free_transmit_slot(slf_task, tx_pkt_slot) {
+				break;
+			}
 		}
 		self.last_transmit_index.set(last_transmit_index);
 		arch::set_supervisor_userpage_access(false);
 	}
 
+	/// Return a packet that couldn't be delivered to the sender, i.e. the task owning this
+	/// structure.
+	///
+	/// Returns `false` if processing must stop, see [`Self::free_transmit_slot`].
+	///
+	/// The virtual memory of the task owning this structure must be active.
+	fn bounce(&self, slf_task: &super::Task, slot: u16, packet: Packet) -> bool {
+		let freed = self.free_transmit_slot(slf_task, slot);
+		// FIXME the packet is lost if the received ring is full.
+		let _ = self.push_received(packet);
+		slf_task.inner().wait_time = 0;
+		freed
+	}
+
+	/// Push a processed transmit slot to the free stack.
+	///
+	/// If the stack is locked by a thread of the task the slot is kept aside and pushed the next
+	/// time packets are processed, in which case `false` is returned and processing must stop.
+	///
+	/// The virtual memory of the task owning this structure must be active.
+	fn free_transmit_slot(&self, slf_task: &super::Task, slot: u16) -> bool {
+		match self.push_free_slot(slot) {
+			Ok(()) => true,
+			Err(PushFreeSlotError::LockTimeout) => {
+				self.unfreed_slot.set(Some(slot));
+				slf_task.inner().wait_time = 0;
+				false
+			}
+			Err(PushFreeSlotError::Full) => panic!("free stack is full"),
+		}
+	}
+
+	/// Check whether the pages covering the given range are all mapped and accessible by
+	/// userland in the active VMS. If `write` is set the pages must also be writeable.
+	fn is_user_range(address: NonNull<PageData>, length: usize, write: bool) -> bool {
+		use crate::arch::vms::{VirtualMemorySystem, RWX};
+		let page = match Page::new(address) {
+			Ok(page) => page,
+			Err(_) => return false,
+		};
+		let count = match length.checked_add(Page::OFFSET_MASK) {
+			Some(n) => n / Page::SIZE,
+			None => return false,
+		};
+		(0..count).all(|i| match page.skip(i).and_then(arch::VMS::user_rwx) {
+			Some(RWX::RW) | Some(RWX::RWX) => true,
+			Some(RWX::R) | Some(RWX::RX) => !write,
+			Some(RWX::X) | None => false,
+		})
+	}
+
+	/// Put a packet in the received ring.
+	///
+	/// The entry & packet are written before the index is incremented with `Release`, which
+	/// pairs with the `Acquire` load of the task.
+	///
+	/// The virtual memory of the task owning this structure must be active.
+	fn push_received(&self, packet: Packet) -> Result<(), PopFreeSlotError> {
+		let _received_lock = self.received_lock.lock();
+		let (rx_index, rx_slots) = self.received_ring();
+		let slot = self.pop_free_slot()?;
+		rx_slots[usize::from(rx_index.load(Ordering::Acquire) & self.ring_mask)].set(slot);
+		// SAFETY: the slot was free, so nothing else is referencing it.
+		unsafe { *self.packet(slot).unwrap() = packet };
+		rx_index.fetch_add(1, Ordering::Release);
+		Ok(())
+	}
+
 	/// Pop an address range from the free ranges list.
 	fn pop_free_range(&self, size: usize) -> Option<Page> {
 		let free_pages =
@@ -258,19 +568,23 @@ impl IPC {
 	}
 
 	/// Return the transmit ring buffer list.
+	///
+	/// The index is loaded with `Acquire` so the entries & packets before it are visible. It
+	/// pairs with the `Release` store of the task.
 	#[must_use]
 	fn transmit_ring(&self) -> (u16, &[u16]) {
 		unsafe {
 			let count = usize::from(self.len());
-			let addr = self.packets.as_ptr().add(count).cast::<u16>();
-			let index = *addr;
-			let slice = slice::from_raw_parts(addr.add(1), count);
+			let addr = self.packets.as_ptr().add(count).cast::<AtomicU16>();
+			let index = (*addr).load(Ordering::Acquire);
+			let slice = slice::from_raw_parts(addr.add(1).cast::<u16>(), count);
 			(index, slice)
 		}
 	}
 
 	/// Return the received ring buffer list.
-	// FIXME lock the ring.
+	///
+	/// `received_lock` must be held while adding packets.
 	#[must_use]
 	fn received_ring(&self) -> (&AtomicU16, &[Cell<u16>]) {
 		unsafe {
@@ -369,9 +683,109 @@ pub struct FreePage {
 impl super::Task {
 	/// Process IPC packets to be transmitted.
 	pub fn process_io(&self, slf_address: Address) {
-		self.inner()
-			.ipc
-			.as_mut()
+		self.ipc()
+			.as_ref()
 			.map(|ipc| ipc.process_packets(self, slf_address));
+		// The task may have made room for notifications that didn't fit before.
+		self.deliver_deaths();
+	}
+
+	/// Notify this task that the given task died. If the received ring is full or the task
+	/// can't hold any more handles, the notification is delivered once the task processes its
+	/// packets again.
+	///
+	/// This changes the active virtual memory.
+	pub fn notify_death(&self, endpoint: endpoint::Endpoint) {
+		let mut deaths = self.owner().inner().deaths.lock();
+		// A task can't watch more tasks than there are watches, so this can't fail.
+		match deaths.iter_mut().find(|e| e.is_none()) {
+			Some(e) => *e = Some(endpoint),
+			None => unreachable!("more deaths than watches"),
+		}
+		drop(deaths);
+		self.deliver_deaths();
+	}
+
+	/// Deliver pending death notifications in order until one doesn't fit.
+	///
+	/// This changes the active virtual memory.
+	fn deliver_deaths(&self) {
+		let mut deaths = self.owner().inner().deaths.lock();
+		let delivered = deaths
+			.iter()
+			.take_while(|e| match e {
+				Some(endpoint) => self.handle_for(*endpoint).map_or(false, |address| {
+					self.push_kernel_packet(Packet::death_notification(address.into()))
+				}),
+				None => false,
+			})
+			.count();
+		deaths.rotate_left(delivered);
+		let len = deaths.len();
+		deaths[len - delivered..].iter_mut().for_each(|e| *e = None);
+	}
+
+	/// Send a packet from the kernel with the given opcode & offset to this task. Returns
+	/// `false` if the task has no IPC queues or the received ring is full.
+	///
+	/// This changes the active virtual memory.
+	pub fn send_kernel_packet(&self, opcode: u8, offset: u64) -> bool {
+		self.push_kernel_packet(Packet::kernel(opcode, offset))
+	}
+
+	/// Send a packet from the kernel with data to this task. A range of pages is taken from
+	/// the free ranges of this task and `map` is called with the virtual memory of this task,
+	/// the address of each page in it & the index of the page, which must map the page.
+	///
+	/// Returns `false` if the task has no IPC queues, no free range that is large enough or if
+	/// the received ring is full.
+	///
+	/// This changes the active virtual memory.
+	pub fn send_kernel_data(
+		&self,
+		opcode: u8,
+		offset: u64,
+		length: usize,
+		rwx: RWX,
+		mut map: impl FnMut(&arch::VMS, Page, usize),
+	) -> bool {
+		use crate::arch::vms::VirtualMemorySystem;
+		let ipc = match self.ipc().as_ref() {
+			Some(ipc) => ipc,
+			None => return false,
+		};
+		let vm = &self.inner().shared_state.virtual_memory;
+		let count = Page::min_pages_for_byte_count(length);
+		vm.activate();
+		arch::set_supervisor_userpage_access(true);
+		let data = ipc.pop_free_range(count);
+		arch::set_supervisor_userpage_access(false);
+		let data = match data {
+			Some(data) => data,
+			None => return false,
+		};
+		// FIXME the pages stay mapped if the received ring is full.
+		for i in 0..count {
+			map(vm, data.skip(i).unwrap(), i);
+		}
+		let mut packet = Packet::kernel(opcode, offset);
+		packet.data = Some(data.as_non_null_ptr());
+		packet.data_length = length;
+		packet.flags = Flags::from(rwx);
+		self.push_kernel_packet(packet)
+	}
+
+	fn push_kernel_packet(&self, packet: Packet) -> bool {
+		use crate::arch::vms::VirtualMemorySystem;
+		if let Some(ipc) = self.ipc().as_ref() {
+			self.inner().shared_state.virtual_memory.activate();
+			arch::set_supervisor_userpage_access(true);
+			let ret = ipc.push_received(packet);
+			arch::set_supervisor_userpage_access(false);
+			self.inner().wait_time = 0;
+			ret.is_ok()
+		} else {
+			false
+		}
 	}
 }
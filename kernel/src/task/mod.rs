@@ -17,9 +17,11 @@
 //! highest level. This does sacrifice some security but there is not much that can be done about
 //! it.
 
+pub mod endpoint;
 pub mod ipc;
 pub mod notification;
 pub mod registry;
+pub mod watch;
 
 mod address;
 mod executor;
@@ -32,6 +34,7 @@ pub use group::Group;
 use crate::arch::vms::{self, VirtualMemorySystem, RWX};
 use crate::arch::{self, Map, Page};
 use crate::memory::{self, AllocateError};
+use crate::sync::Mutex;
 use core::ptr::NonNull;
 use core::sync::atomic::{AtomicU16, AtomicU32, Ordering};
 
@@ -46,6 +49,22 @@ impl Flags {
 	#[allow(dead_code)]
 	const NOTIFYING: u16 = 0x1;
 	const NOTIFIED: u16 = 0x2;
+	/// The task opted out of the emulation of misaligned loads & stores.
+	const NO_EMULATE_MISALIGNED: u16 = 0x4;
+	/// The task only accepts endpoint handles, i.e. it isn't in compatibility mode. Only set on
+	/// the owner.
+	const STRICT_ENDPOINTS: u16 = 0x8;
+}
+
+/// Statistics of a single task.
+///
+/// The layout of this structure is part of the ABI of `task_stats`. New fields must be added
+/// at the end.
+#[derive(Clone, Copy, Default)]
+#[repr(C)]
+pub struct Stats {
+	/// The amount of misaligned loads & stores that were emulated.
+	pub misaligned_emulations: u64,
 }
 
 /// An IRQ source / identifier
@@ -70,6 +89,9 @@ pub struct Task {
 }
 
 /// State that can be shared between multiple tasks.
+///
+/// Each thread has its own copy. The copies are kept alive by the reference count of the
+/// owner.
 #[repr(C)]
 struct SharedState {
 	/// Mapping of virtual memory.
@@ -84,10 +106,9 @@ pub struct TaskData {
 	/// A pointer to some stack space for use with syscalls.
 	stack: Page,
 	/// The shared state of this task.
-	// TODO should be reference counted.
 	shared_state: SharedState,
-	/// The address of a notification handler.
-	notification_handler: Option<notification::Handler>,
+	/// The notification handlers of this task, indexed by the type of notification.
+	notification_handlers: [notification::Handler; notification::TYPE_COUNT],
 	/// The IRQ this task is currently handling, if any.
 	///
 	/// Only relevant for drivers.
@@ -102,10 +123,26 @@ pub struct TaskData {
 	priority: u16,
 	/// A factor that scales the value of the priority.
 	priority_factor: u16,
+	/// The type of the notification that is being handled. It is passed on if the handler
+	/// defers the notification.
+	notification_type: u16,
 	/// The time a task will wait for an event until it is rescheduled.
 	wait_time: u64,
 	/// IPC state to communicate with other tasks.
 	ipc: Option<ipc::IPC>,
+	/// The tasks this task can send packets to. Only used by the owner.
+	endpoints: endpoint::Table,
+	/// The pages of this task that are pinned by drivers. Only used by the owner.
+	pins: memory::pin::Table,
+	/// Tasks whose death couldn't be notified yet, oldest first. Only used by the owner.
+	deaths: Mutex<[Option<endpoint::Endpoint>; watch::MAX_WATCHES]>,
+	/// Statistics of this task.
+	stats: Stats,
+	/// The task owning the shared state & IPC queues. This is the task itself unless it is
+	/// a thread.
+	owner: Task,
+	/// The amount of tasks using the shared state of this task. Only used by the owner.
+	references: AtomicU16,
 }
 
 const STACK_ADDRESS: Page = memory::reserved::HART_STACKS.start;
@@ -114,6 +151,39 @@ static mut TASK_DATA_ADDRESS: Page = memory::reserved::TASK_DATA.start;
 impl Task {
 	/// Create a new empty task with the given VMS.
 	pub fn new(vms: arch::VMS) -> Result<Self, AllocateError> {
+		Self::with_owner(vms, None)
+	}
+
+	/// Create a new thread of this task. The thread shares the VMS & IPC queues of this task
+	/// and starts with a copy of its notification handlers, but has its own register state.
+	///
+	/// The VMS of this task must be active.
+	pub fn new_thread(&self) -> Result<Self, AllocateError> {
+		let owner = self.owner();
+		let thread = Self::with_owner(arch::VMS::current(), Some(owner.clone()))?;
+		thread.inner().notification_handlers = self.inner().notification_handlers;
+		owner.inner().references.fetch_add(1, Ordering::Relaxed);
+		Ok(thread)
+	}
+
+	/// Free a task that was never inserted in a group, e.g. because the group is full.
+	///
+	/// The task must not have run and there may be no other references to it.
+	pub fn discard(self) {
+		let owner = self.owner();
+		if owner.ptr != self.ptr {
+			owner.inner().references.fetch_sub(1, Ordering::Relaxed);
+		}
+		let page = Page::new(self.ptr.cast()).unwrap();
+		match arch::VMS::remove(page) {
+			Ok(vms::PrivateOrShared::Private(ppn)) => unsafe { memory::deallocate(ppn) },
+			_ => unreachable!("task data isn't a private mapping"),
+		}
+	}
+
+	/// Create a new empty task with the given VMS. If an owner is given, the task will use the
+	/// IPC queues of the owner.
+	fn with_owner(vms: arch::VMS, owner: Option<Task>) -> Result<Self, AllocateError> {
 		// FIXME may leak memory on alloc error.
 		let task_data = Map::Private(memory::allocate()?);
 		unsafe {
@@ -136,14 +206,21 @@ impl Task {
 				shared_state: SharedState {
 					virtual_memory: vms,
 				},
-				notification_handler: None,
+				notification_handlers: Default::default(),
 				current_irq: IRQ::default(),
 				flags: Flags(0),
 				executor_id: AtomicU16::new(u16::MAX),
 				priority: 0,
 				priority_factor: 0,
+				notification_type: 0,
 				wait_time: 0,
 				ipc: None,
+				endpoints: endpoint::Table::new(),
+				pins: memory::pin::Table::new(),
+				deaths: Mutex::new([None; watch::MAX_WATCHES]),
+				stats: Stats::default(),
+				owner: owner.unwrap_or_else(|| task.clone()),
+				references: AtomicU16::new(1),
 			});
 		}
 		unsafe { TASK_DATA_ADDRESS = TASK_DATA_ADDRESS.next().unwrap() };
@@ -160,6 +237,17 @@ impl Task {
 		self.inner().register_state.set_stack_pointer(address);
 	}
 
+	/// Set the first argument passed to this task to the given value.
+	pub fn set_argument(&self, value: usize) {
+		self.inner().register_state.set_argument(value);
+	}
+
+	/// Set the values returned by the system call the task is blocked on. This is useful for
+	/// calls that end with [`Executor::next`].
+	pub fn set_return(&self, status: usize, value: usize) {
+		self.inner().register_state.set_return(status, value);
+	}
+
 	/// Begin executing this task.
 	fn execute(&self, executor_id: u16) -> Result<!, Claimed> {
 		self.inner().shared_state.virtual_memory.activate();
@@ -191,9 +279,54 @@ impl Task {
 		arch::VMS::deallocate(address, count)
 	}
 
-	/// Set the task transmit & receive queue pointers and sizes.
+	/// Set the task transmit & receive queue pointers and sizes. The queues are shared with
+	/// all threads of the task.
 	pub fn set_queues(&self, buffers: Option<ipc::IPC>) {
-		self.inner().ipc = buffers;
+		*self.ipc() = buffers;
+	}
+
+	/// Return the IPC state of this task, which is owned by the owner of the task.
+	fn ipc(&self) -> &mut Option<ipc::IPC> {
+		&mut self.owner().inner().ipc
+	}
+
+	/// Return the pin table of this task, which is shared with its threads.
+	pub fn pins(&self) -> &memory::pin::Table {
+		&self.owner().inner().pins
+	}
+
+	/// Check whether each of the given physical page addresses is mapped as a shared page in
+	/// this task, i.e. the task shared it with IPC or received it from another task. At most
+	/// 64 pages can be checked at once.
+	pub fn maps_shared(&self, physical: &[usize]) -> bool {
+		assert!(physical.len() <= 64, "too many pages");
+		let all = u64::MAX
+			.checked_shr((64 - physical.len()) as u32)
+			.unwrap_or(0);
+		let mut found = 0u64;
+		let current = arch::VMS::current();
+		self.inner().shared_state.virtual_memory.activate();
+		let mut address = Page::from_usize(Page::SIZE).ok();
+		while let Some((page, _)) = address.and_then(arch::VMS::next_user_page) {
+			if found == all {
+				break;
+			}
+			address = page.next();
+			if let Some(p) = arch::VMS::user_shared(page) {
+				physical
+					.iter()
+					.enumerate()
+					.filter(|(_, q)| **q == p)
+					.for_each(|(i, _)| found |= 1 << i);
+			}
+		}
+		current.activate();
+		found == all
+	}
+
+	/// Return the task owning the shared state of this task.
+	fn owner(&self) -> &Task {
+		&self.inner().owner
 	}
 
 	/// Check if the task recently ran its notification handler.
@@ -206,6 +339,116 @@ impl Task {
 		self.inner().flags.0 &= !Flags::NOTIFIED;
 	}
 
+	/// Return the statistics of this task.
+	pub fn stats(&self) -> Stats {
+		self.inner().stats
+	}
+
+	/// Return the statistics of this task for updating.
+	#[allow(dead_code)]
+	pub fn stats_mut(&self) -> &mut Stats {
+		&mut self.inner().stats
+	}
+
+	/// Return the saved register state of this task.
+	#[allow(dead_code)]
+	pub fn register_state(&self) -> &mut arch::RegisterState {
+		&mut self.inner().register_state
+	}
+
+	/// Check whether misaligned loads & stores of this task should be emulated.
+	pub fn emulate_misaligned(&self) -> bool {
+		self.inner().flags.0 & Flags::NO_EMULATE_MISALIGNED == 0
+	}
+
+	/// Enable or disable the emulation of misaligned loads & stores. Returns the old value.
+	pub fn set_emulate_misaligned(&self, enable: bool) -> bool {
+		let old = self.emulate_misaligned();
+		if enable {
+			self.inner().flags.0 &= !Flags::NO_EMULATE_MISALIGNED;
+		} else {
+			self.inner().flags.0 |= Flags::NO_EMULATE_MISALIGNED;
+		}
+		old
+	}
+
+	/// Destroy the task with the given address.
+	///
+	/// Registry entries & interrupts owned by the task are released and tasks watching it are
+	/// notified. Pages pinned by drivers are quarantined until they are unpinned. Packets sent
+	/// to it afterwards are returned to the sender.
+	///
+	/// If the task owns state shared with threads, the threads are destroyed first. The state
+	/// is only released after the last thread is gone, so no thread can use the IPC queues,
+	/// registry entries, interrupts or watches of a dead task.
+	///
+	/// This changes the active virtual memory.
+	// FIXME the task data, stack & virtual memory are leaked.
+	pub fn destroy(address: Address) -> Result<(), group::NoTask> {
+		let group = Group::get(address.group().into()).ok_or(group::NoTask)?;
+		let task = group.task(address.task().into())?;
+		if task.owner().ptr == task.ptr {
+			// The owner is removed last, so the group can't be freed while iterating it.
+			group
+				.tasks()
+				.filter(|(_, t)| t.ptr != task.ptr && t.owner().ptr == task.ptr)
+				.try_for_each(|(id, _)| Self::remove(address.with_task(id)))?;
+		}
+		Self::remove(address)
+	}
+
+	/// Remove a single task from its group and release everything that refers to its address.
+	fn remove(address: Address) -> Result<(), group::NoTask> {
+		let group = Group::get(address.group().into()).ok_or(group::NoTask)?;
+		let task = group.task(address.task().into())?;
+		// Get the endpoint before the generation changes so watchers get the same handle they
+		// already have.
+		let endpoint = endpoint::of(address).ok_or(group::NoTask)?;
+		group.remove_task(address.task().into())?;
+		let owner = task.owner();
+		if owner.inner().references.fetch_sub(1, Ordering::AcqRel) == 1 {
+			// This was the last task using the VMS.
+			// FIXME free the VMS. There is no way to destroy one yet.
+			owner.set_queues(None);
+			// Pinned pages may still be written to by a device, so they must outlive the VMS.
+			memory::pin::quarantine(owner.pins());
+		}
+		registry::remove_address(address);
+		arch::interrupts::release_all(address);
+		watch::remove(address, |watcher| {
+			let (g, t) = (watcher.group(), watcher.task());
+			if let Some(task) = Group::get(g.into()).and_then(|g| g.task(t.into()).ok()) {
+				task.notify_death(endpoint);
+			}
+		});
+		Ok(())
+	}
+
+	/// Destroy the task with the given address along with all threads it shares its state
+	/// with.
+	///
+	/// This changes the active virtual memory.
+	pub fn destroy_all(address: Address) -> Result<(), group::NoTask> {
+		let group = Group::get(address.group().into()).ok_or(group::NoTask)?;
+		let owner = group.task(address.task().into())?.owner().ptr;
+		let id = group
+			.tasks()
+			.find(|(_, task)| task.ptr == owner)
+			.map(|(id, _)| id)
+			.ok_or(group::NoTask)?;
+		Self::destroy(address.with_task(id))
+	}
+
+	/// Check whether the tasks at both addresses are the same task or threads of the same task.
+	pub fn same_owner(a: Address, b: Address) -> bool {
+		let get =
+			|a: Address| Group::get(a.group().into()).and_then(|g| g.task(a.task().into()).ok());
+		match (get(a), get(b)) {
+			(Some(a), Some(b)) => a.owner().ptr == b.owner().ptr,
+			_ => false,
+		}
+	}
+
 	fn inner<'a>(&'a self) -> &'a mut TaskData {
 		// SAFETY: The task has been safely initialized.
 		unsafe { self.ptr.clone().as_mut() }
@@ -2,20 +2,44 @@
 //!
 //! While notifications are a form of IPC, they also behave significantly differently from the
 //! "regular" IPC, hence why notifications are treated as a separate thing.
+//!
+//! Each task has a small table with one handler per type of notification. A handler consists
+//! of the function to jump to and an opaque context value which is passed in `a2`.
 
 use core::mem;
 use core::ptr::NonNull;
 
-/// A pointer to a userspace notification handler
-pub struct Handler(NonNull<()>);
+/// The amount of notification types, i.e. the size of the handler table of each task.
+pub const TYPE_COUNT: usize = 3;
+
+/// A notification sent by an external interrupt.
+#[allow(dead_code)]
+pub const TYPE_INTERRUPT: usize = 0;
+/// A notification sent by another task.
+#[allow(dead_code)]
+pub const TYPE_IPC: usize = 1;
+/// A notification sent by a timer.
+#[allow(dead_code)]
+pub const TYPE_TIMER: usize = 2;
+
+/// A pointer to a userspace notification handler & the context to pass to it.
+///
+/// The layout is used by the notification entry code and must stay in sync with
+/// `NOTIFY_HANDLER_SIZE`.
+#[derive(Clone, Copy, Default)]
+#[repr(C)]
+pub struct Handler {
+	function: Option<NonNull<()>>,
+	context: usize,
+}
 
 #[derive(Debug)]
 pub enum NewHandlerError {}
 
 impl Handler {
 	/// Create a new handler.
-	pub fn new(function: NonNull<()>) -> Result<Self, NewHandlerError> {
-		Ok(Self(function))
+	pub fn new(function: Option<NonNull<()>>, context: usize) -> Result<Self, NewHandlerError> {
+		Ok(Self { function, context })
 	}
 
 	/// Call the handler. This causes a context switch.
@@ -24,9 +48,14 @@ impl Handler {
 		todo!();
 	}
 
-	/// Return the inner pointer.
+	/// Return the pointer to the function.
 	pub fn as_ptr(&self) -> *const () {
-		self.0.as_ptr()
+		self.function.map_or(core::ptr::null(), |f| f.as_ptr())
+	}
+
+	/// Return the context that is passed to the function.
+	pub fn context(&self) -> usize {
+		self.context
 	}
 }
 
@@ -39,22 +68,34 @@ pub enum SendError {
 	Busy,
 }
 
+#[derive(Debug)]
+pub struct InvalidType;
+
 impl super::Task {
-	/// Send a notification to this task.
+	/// Send a notification of the given type to this task.
 	#[allow(dead_code)]
-	pub fn send_notification(&self) -> Result<(), SendError> {
+	pub fn send_notification(&self, typ: usize) -> Result<(), SendError> {
 		self.inner()
-			.notification_handler
-			.as_ref()
+			.notification_handlers
+			.get(typ)
+			.filter(|handler| handler.function.is_some())
 			.map(|handler| {
 				handler.call();
 			})
 			.ok_or(SendError::NoHandler)
 	}
 
-	/// Set the notification handler of this task, returning the previous
-	/// one, if any.
-	pub fn set_notification_handler(&self, handler: Option<Handler>) -> Option<Handler> {
-		mem::replace(&mut self.inner().notification_handler, handler)
+	/// Set the notification handler for the given type of notification, returning the previous
+	/// one.
+	pub fn set_notification_handler(
+		&self,
+		typ: usize,
+		handler: Handler,
+	) -> Result<Handler, InvalidType> {
+		self.inner()
+			.notification_handlers
+			.get_mut(typ)
+			.map(|h| mem::replace(h, handler))
+			.ok_or(InvalidType)
 	}
 }
@@ -29,7 +29,10 @@ static REGISTRY: LOL = LOL([
 	UnsafeCell::new(None),
 ]);
 
-struct LOL([UnsafeCell<Option<Entry>>; 16]);
+/// The maximum amount of entries in the registry.
+pub const REGISTRY_CAPACITY: usize = 16;
+
+struct LOL([UnsafeCell<Option<Entry>>; REGISTRY_CAPACITY]);
 
 unsafe impl Sync for LOL {}
 
@@ -81,6 +84,44 @@ pub fn get(name: &[u8]) -> Option<Address> {
 	e
 }
 
+/// Call `f` once for every address in the registry. The registry isn't locked while `f` runs.
+pub fn for_each_address(mut f: impl FnMut(Address)) {
+	let mut addresses = [None; REGISTRY_CAPACITY];
+	let len = lock();
+	for (w, e) in addresses.iter_mut().zip(REGISTRY.0[..len].iter()) {
+		// SAFETY: we hold the lock.
+		*w = unsafe { &*e.get() }.as_ref().map(|e| e.address);
+	}
+	unlock(len);
+	for (i, a) in addresses.iter().enumerate() {
+		// Multiple names may point to the same task.
+		if let Some(a) = a.filter(|a| !addresses[..i].contains(&Some(*a))) {
+			f(a);
+		}
+	}
+}
+
+/// Remove all entries pointing to the given address.
+pub fn remove_address(address: Address) {
+	let mut len = lock();
+	let mut i = 0;
+	while i < len {
+		// SAFETY: we hold the lock.
+		let e = unsafe { &*REGISTRY.0[i].get() };
+		if e.as_ref().map_or(false, |e| e.address == address) {
+			// Move the last entry into the hole so the list stays contiguous.
+			len -= 1;
+			unsafe {
+				let last = (&mut *REGISTRY.0[len].get()).take();
+				REGISTRY.0[i].get().write(last);
+			}
+		} else {
+			i += 1;
+		}
+	}
+	unlock(len);
+}
+
 fn lock() -> usize {
 	let mut len = REGISTRY_ENTRY_COUNT.load(Ordering::Relaxed);
 	loop {
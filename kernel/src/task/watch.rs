@@ -0,0 +1,62 @@
+//! # Task watches
+//!
+//! Tasks can subscribe to the death of other tasks. When a watched task is destroyed, every
+//! watcher receives a packet with the address of the dead task and the `DEAD_PEER` flag set.
+
+use super::Address;
+use crate::sync::Mutex;
+
+/// The maximum amount of watches, which is also the most deaths a single task can be notified
+/// of at once.
+pub const MAX_WATCHES: usize = 32;
+
+// TODO use a proper map instead of a dumb list.
+static WATCHES: Mutex<[Option<Watch>; MAX_WATCHES]> = Mutex::new([None; MAX_WATCHES]);
+
+#[derive(Clone, Copy)]
+struct Watch {
+	/// The task that wants to be notified.
+	watcher: Address,
+	/// The task being watched.
+	watched: Address,
+}
+
+#[derive(Debug)]
+pub enum AddError {
+	ListFull,
+}
+
+/// Subscribe `watcher` to the death of `watched`.
+///
+/// Adding the same watch twice has no effect.
+pub fn add(watcher: Address, watched: Address) -> Result<(), AddError> {
+	let mut watches = WATCHES.lock();
+	if watches
+		.iter()
+		.flatten()
+		.any(|w| w.watcher == watcher && w.watched == watched)
+	{
+		return Ok(());
+	}
+	watches
+		.iter_mut()
+		.find(|w| w.is_none())
+		.map(|w| *w = Some(Watch { watcher, watched }))
+		.ok_or(AddError::ListFull)
+}
+
+/// Remove all watches involving the given task and call `f` with the address of every task
+/// that was watching it.
+pub fn remove(address: Address, mut f: impl FnMut(Address)) {
+	let mut watches = WATCHES.lock();
+	for e in watches.iter_mut() {
+		match e {
+			Some(w) if w.watched == address => {
+				f(w.watcher);
+				*e = None;
+			}
+			Some(w) if w.watcher == address => *e = None,
+			_ => (),
+		}
+	}
+}
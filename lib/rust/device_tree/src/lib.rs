@@ -186,6 +186,22 @@ impl<'a> DeviceTree<'a> {
 		.map(|n| n.0)
 	}
 
+	/// Return an iterator over the CPUs in the `/cpus` node. CPUs are not filtered on their
+	/// status.
+	pub fn cpus(&self) -> Cpus<'_, '_> {
+		let cpus = self
+			.root()
+			.ok()
+			.and_then(|root| root.children().find(|n| n.name == b"cpus"));
+		Cpus {
+			timebase_frequency: cpus.as_ref().and_then(|cpus| {
+				cpus.property(b"timebase-frequency")
+					.and_then(|p| read_cells(p.value, (p.value.len() / 4) as u32))
+			}),
+			children: cpus.map(|cpus| cpus.children_iter()),
+		}
+	}
+
 	/// Return the total size of the FDT
 	pub fn total_size(&self) -> usize {
 		u32::from(self.header().total_size) as usize
@@ -368,57 +384,18 @@ impl<'a, 'b> Node<'a, 'b> {
 		}
 	}
 
+	/// Return the property with the given name, if any.
+	pub fn property(&self, name: &[u8]) -> Option<Property<'b>> {
+		self.properties().find(|p| p.name == name)
+	}
+
 	/// Return an iterator over all the children of this node
 	pub fn children(&self) -> impl Iterator<Item = Node<'a, 'b>> + fmt::Debug + '_ {
-		struct Iter<'a, 'b: 'a> {
-			dtb: &'a DeviceTree<'b>,
-			offset: u32,
-			address_cells: u32,
-			size_cells: u32,
-			interrupt_cells: u32,
-		}
-
-		impl<'a, 'b> Iterator for Iter<'a, 'b> {
-			type Item = Node<'a, 'b>;
-
-			fn next(&mut self) -> Option<Self::Item> {
-				#[cfg(debug_assertions)]
-				Node::is_token_valid(self.dtb, self.offset).expect("invalid token");
-				(self.dtb.get(self.offset) == Some(Node::TOKEN_BEGIN_NODE)).then(|| {
-					let (node, offt) = Node::new(
-						self.dtb,
-						self.offset,
-						self.address_cells,
-						self.size_cells,
-						self.interrupt_cells,
-					)
-					.unwrap();
-					self.offset = offt;
-					node
-				})
-			}
-		}
-
-		impl fmt::Debug for Iter<'_, '_> {
-			fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-				let &Iter {
-					dtb,
-					offset,
-					address_cells,
-					size_cells,
-					interrupt_cells,
-				} = self;
-				let iter = Self {
-					dtb,
-					offset,
-					address_cells,
-					size_cells,
-					interrupt_cells,
-				};
-				f.debug_list().entries(iter).finish()
-			}
-		}
+		self.children_iter()
+	}
 
+	/// Return an iterator over all the children of this node that doesn't borrow the node.
+	fn children_iter(&self) -> Children<'a, 'b> {
 		// Properties the direct descendants inherit
 		let (mut address_cells, mut size_cells, mut interrupt_cells) = (2, 1, 0);
 
@@ -437,7 +414,7 @@ impl<'a, 'b> Node<'a, 'b> {
 			}
 		}
 
-		Iter {
+		Children {
 			dtb: self.dtb,
 			offset: self.children,
 			address_cells,
@@ -461,6 +438,120 @@ impl<'a, 'b> Node<'a, 'b> {
 	}
 }
 
+/// An iterator over the children of a node.
+struct Children<'a, 'b: 'a> {
+	dtb: &'a DeviceTree<'b>,
+	offset: u32,
+	address_cells: u32,
+	size_cells: u32,
+	interrupt_cells: u32,
+}
+
+impl<'a, 'b> Iterator for Children<'a, 'b> {
+	type Item = Node<'a, 'b>;
+
+	fn next(&mut self) -> Option<Self::Item> {
+		#[cfg(debug_assertions)]
+		Node::is_token_valid(self.dtb, self.offset).expect("invalid token");
+		(self.dtb.get(self.offset) == Some(Node::TOKEN_BEGIN_NODE)).then(|| {
+			let (node, offt) = Node::new(
+				self.dtb,
+				self.offset,
+				self.address_cells,
+				self.size_cells,
+				self.interrupt_cells,
+			)
+			.unwrap();
+			self.offset = offt;
+			node
+		})
+	}
+}
+
+impl fmt::Debug for Children<'_, '_> {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		let &Children {
+			dtb,
+			offset,
+			address_cells,
+			size_cells,
+			interrupt_cells,
+		} = self;
+		let iter = Self {
+			dtb,
+			offset,
+			address_cells,
+			size_cells,
+			interrupt_cells,
+		};
+		f.debug_list().entries(iter).finish()
+	}
+}
+
+/// A `cpu` node in the `/cpus` node.
+pub struct CpuNode<'a, 'b: 'a> {
+	/// The node describing the CPU.
+	pub node: Node<'a, 'b>,
+	/// The `timebase-frequency` of the `/cpus` node, which applies if the CPU doesn't have one.
+	cpus_timebase_frequency: Option<u64>,
+}
+
+impl<'a, 'b> CpuNode<'a, 'b> {
+	/// Return the hart ID, which is stored in the `reg` property.
+	pub fn hartid(&self) -> Option<u64> {
+		self.node
+			.property(b"reg")
+			.and_then(|p| read_cells(p.value, self.node.address_cells))
+	}
+
+	/// Whether the CPU is usable. CPUs without a `status` property are enabled.
+	pub fn enabled(&self) -> bool {
+		self.node.property(b"status").map_or(true, |p| {
+			matches!(cstr_to_str(p.value), Some(b"okay") | Some(b"ok"))
+		})
+	}
+
+	/// Return an iterator over the strings in the `compatible` property.
+	pub fn compatible(&self) -> impl Iterator<Item = &'b [u8]> {
+		self.node
+			.property(b"compatible")
+			.map_or(&[][..], |p| p.value)
+			.split(|c| *c == 0)
+			.filter(|s| !s.is_empty())
+	}
+
+	/// Return the frequency of the timer in Hz. The property of the CPU takes precedence over
+	/// the property of the `/cpus` node.
+	pub fn timebase_frequency(&self) -> Option<u64> {
+		self.node
+			.property(b"timebase-frequency")
+			.and_then(|p| read_cells(p.value, (p.value.len() / 4) as u32))
+			.or(self.cpus_timebase_frequency)
+	}
+}
+
+/// An iterator over the CPUs in the `/cpus` node.
+pub struct Cpus<'a, 'b: 'a> {
+	children: Option<Children<'a, 'b>>,
+	timebase_frequency: Option<u64>,
+}
+
+impl<'a, 'b> Iterator for Cpus<'a, 'b> {
+	type Item = CpuNode<'a, 'b>;
+
+	fn next(&mut self) -> Option<Self::Item> {
+		let timebase_frequency = self.timebase_frequency;
+		// Skip the cpu-map & any other nodes that don't describe a CPU.
+		self.children
+			.as_mut()?
+			.find(|n| n.name == b"cpu" || n.name.starts_with(b"cpu@"))
+			.map(|node| CpuNode {
+				node,
+				cpus_timebase_frequency: timebase_frequency,
+			})
+	}
+}
+
 impl fmt::Debug for Node<'_, '_> {
 	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
 		let mut map = f.debug_map();
@@ -492,6 +583,19 @@ impl fmt::Debug for Property<'_> {
 	}
 }
 
+/// Read a big-endian number made of one or two cells.
+fn read_cells(value: &[u8], cells: u32) -> Option<u64> {
+	match cells {
+		1 => value
+			.get(..4)
+			.map(|v| u32::from_be_bytes(v.try_into().unwrap()).into()),
+		2 => value
+			.get(..8)
+			.map(|v| u64::from_be_bytes(v.try_into().unwrap())),
+		_ => None,
+	}
+}
+
 /// Converts a null-terminated C string to a Rust `[u8]`.
 fn cstr_to_str<T>(s: &[T]) -> Option<&[u8]> {
 	let len = s.len() * mem::size_of::<T>();
@@ -522,9 +626,164 @@ mod test {
 		}
 	}
 
+	/// Writer for small DTBs.
+	#[derive(Default)]
+	struct Builder {
+		structure: Vec<u8>,
+		strings: Vec<u8>,
+	}
+
+	impl Builder {
+		fn begin_node(&mut self, name: &str) -> &mut Self {
+			self.structure.extend(&Node::TOKEN_BEGIN_NODE.to_be_bytes());
+			self.structure.extend(name.as_bytes());
+			self.structure.push(0);
+			self.pad()
+		}
+
+		fn property(&mut self, name: &str, value: &[u8]) -> &mut Self {
+			let offset = self.strings.len() as u32;
+			self.strings.extend(name.as_bytes());
+			self.strings.push(0);
+			self.structure.extend(&Node::TOKEN_PROP.to_be_bytes());
+			self.structure.extend(&(value.len() as u32).to_be_bytes());
+			self.structure.extend(&offset.to_be_bytes());
+			self.structure.extend(value);
+			self.pad()
+		}
+
+		fn end_node(&mut self) -> &mut Self {
+			self.structure.extend(&Node::TOKEN_END_NODE.to_be_bytes());
+			self
+		}
+
+		fn pad(&mut self) -> &mut Self {
+			while self.structure.len() % 4 != 0 {
+				self.structure.push(0);
+			}
+			self
+		}
+
+		fn finish(&mut self) -> Vec<u32> {
+			self.structure.extend(&Node::TOKEN_END.to_be_bytes());
+			let header = mem::size_of::<Header>() as u32;
+			// The memory reservation block only has the terminating entry.
+			let structure = header + mem::size_of::<ReservedMemoryRegion>() as u32;
+			let strings = structure + self.structure.len() as u32;
+			let total = strings + self.strings.len() as u32;
+			let mut dtb = Vec::new();
+			for v in &[
+				DeviceTree::MAGIC,
+				total,
+				structure,
+				strings,
+				header,
+				17,
+				16,
+				0,
+				self.strings.len() as u32,
+				self.structure.len() as u32,
+			] {
+				dtb.extend(&v.to_be_bytes());
+			}
+			dtb.extend(&[0; mem::size_of::<ReservedMemoryRegion>()]);
+			dtb.extend(&self.structure);
+			dtb.extend(&self.strings);
+			while dtb.len() % 4 != 0 {
+				dtb.push(0);
+			}
+			dtb.chunks(4)
+				.map(|c| u32::from_ne_bytes(c.try_into().unwrap()))
+				.collect()
+		}
+	}
+
 	#[test]
 	fn qemu_system_riscv64() {
 		let data = Align(*include_bytes!("../test/qemu_system_riscv64.dtb"));
 		DeviceTree::parse(data.as_u32()).unwrap().root().unwrap();
 	}
+
+	#[test]
+	fn qemu_system_riscv64_cpus() {
+		let data = Align(*include_bytes!("../test/qemu_system_riscv64.dtb"));
+		let dtb = DeviceTree::parse(data.as_u32()).unwrap();
+		let cpus = dtb.cpus().collect::<Vec<_>>();
+		assert_eq!(cpus.len(), 1);
+		assert_eq!(cpus[0].node.name, b"cpu@0");
+		assert_eq!(cpus[0].hartid(), Some(0));
+		assert!(cpus[0].enabled());
+		assert_eq!(cpus[0].compatible().collect::<Vec<_>>(), [b"riscv"]);
+		assert_eq!(cpus[0].timebase_frequency(), Some(10_000_000));
+	}
+
+	#[test]
+	fn multiple_cpus() {
+		let data = Builder::default()
+			.begin_node("")
+			.begin_node("cpus")
+			.property("#address-cells", &1u32.to_be_bytes())
+			.property("#size-cells", &0u32.to_be_bytes())
+			.property("timebase-frequency", &1_000_000u32.to_be_bytes())
+			.begin_node("cpu@0")
+			.property("reg", &0u32.to_be_bytes())
+			.property("status", b"okay\0")
+			.property("compatible", b"sifive,u54\0riscv\0")
+			.end_node()
+			.begin_node("cpu@1")
+			.property("reg", &1u32.to_be_bytes())
+			.property("status", b"disabled\0")
+			.property("timebase-frequency", &4_000_000u64.to_be_bytes())
+			.end_node()
+			.begin_node("cpu-map")
+			.begin_node("cluster0")
+			.end_node()
+			.end_node()
+			.begin_node("cpu@3")
+			.property("reg", &3u32.to_be_bytes())
+			.end_node()
+			.end_node()
+			.end_node()
+			.finish();
+		let dtb = DeviceTree::parse(&data).unwrap();
+		let cpus = dtb.cpus().collect::<Vec<_>>();
+		assert_eq!(cpus.len(), 3);
+		let ids = cpus.iter().map(CpuNode::hartid).collect::<Vec<_>>();
+		assert_eq!(ids, [Some(0), Some(1), Some(3)]);
+		let enabled = cpus.iter().map(CpuNode::enabled).collect::<Vec<_>>();
+		assert_eq!(enabled, [true, false, true]);
+		let compatible = cpus[0].compatible().collect::<Vec<_>>();
+		assert_eq!(compatible, [&b"sifive,u54"[..], b"riscv"]);
+		assert_eq!(cpus[1].compatible().count(), 0);
+		let timebase = cpus.iter().map(CpuNode::timebase_frequency);
+		assert_eq!(
+			timebase.collect::<Vec<_>>(),
+			[Some(1_000_000), Some(4_000_000), Some(1_000_000)]
+		);
+	}
+
+	#[test]
+	fn wide_hartid() {
+		let data = Builder::default()
+			.begin_node("")
+			.begin_node("cpus")
+			.property("#address-cells", &2u32.to_be_bytes())
+			.begin_node("cpu@100000000")
+			.property("reg", &0x1_0000_0000u64.to_be_bytes())
+			.end_node()
+			.end_node()
+			.end_node()
+			.finish();
+		let dtb = DeviceTree::parse(&data).unwrap();
+		let cpu = dtb.cpus().next().unwrap();
+		assert_eq!(cpu.hartid(), Some(0x1_0000_0000));
+		assert_eq!(cpu.timebase_frequency(), None);
+	}
+
+	#[test]
+	fn no_cpus() {
+		let data = Builder::default().begin_node("").end_node().finish();
+		let dtb = DeviceTree::parse(&data).unwrap();
+		assert_eq!(dtb.cpus().count(), 0);
+	}
 }
@@ -1,12 +1,42 @@
 //! # Helper library for drivers
-
-#![no_std]
+//!
+//! ## Argument protocol
+//!
+//! Parents pass information about a device to a driver as command line arguments. Each group of
+//! arguments is a key with the amount of values it takes, followed by the values as hexadecimal
+//! numbers, e.g. `--reg/2 10000000 1000`.
+//!
+//! The first argument must be `--argv-version/1 <version>`. The form of this argument will never
+//! change. The compatibility rules are:
+//!
+//! * The version is only bumped if the encoding of the arguments changes in a way that an
+//!   older driver can't make sense of. Drivers refuse versions outside the range they support
+//!   with [`ParseError::UnsupportedVersion`].
+//!
+//! * Adding a new group of arguments does **not** bump the version. Drivers skip groups they
+//!   don't know, which is possible because each key encodes the amount of values.
+//!
+//! * The amount of values of an existing group never changes. A group with a different amount
+//!   of values is a new group with a different key.
+//!
+//! * Arguments that aren't in the `--key/count` form are passed as [`Arg::Other`].
+
+#![cfg_attr(not(test), no_std)]
 #![feature(optimize_attribute)]
 
+use core::convert::TryFrom;
 use core::fmt;
+use core::iter::Peekable;
 use core::num;
+use core::ops::RangeInclusive;
 use core::str;
 
+/// The version of the argument protocol parents use.
+pub const ARGV_VERSION: u32 = 1;
+
+/// The oldest version of the argument protocol that can be parsed.
+pub const ARGV_VERSION_MIN: u32 = 1;
+
 macro_rules! derive {
 	(@INTERNAL impl to_args($self:ident, $buf:ident, $alloc:ident, $add_arg:ident) for $name:ident $code:tt) => {
 		impl $name {
@@ -47,6 +77,7 @@ macro_rules! derive {
 
 		impl $name {
 			pub const CMD_ARG: &'static str = $arg;
+			pub const ARITY: usize = 1;
 
 			#[inline(always)]
 			pub const fn new($a: u128) -> Self {
@@ -55,7 +86,7 @@ macro_rules! derive {
 		}
 
 		derive!(@INTERNAL impl to_args(self, buffer, alloc, add_argument) for $name {
-			to(concat!("--", $arg), buffer, alloc, add_argument, &[self.$a])
+			to(concat!("--", $arg, "/1"), buffer, alloc, add_argument, &[self.$a])
 		});
 
 		derive!(@INTERNAL impl from_args(buffer[1]) for $name {
@@ -75,6 +106,7 @@ macro_rules! derive {
 
 		impl $name {
 			pub const CMD_ARG: &'static str = $arg;
+			pub const ARITY: usize = 2;
 
 			#[inline(always)]
 			pub const fn new($a: u128, $b: u128) -> Self {
@@ -83,7 +115,7 @@ macro_rules! derive {
 		}
 
 		derive!(@INTERNAL impl to_args(self, buffer, alloc, add_argument) for $name {
-			to(concat!("--", $arg), buffer, alloc, add_argument, &[self.$a, self.$b])
+			to(concat!("--", $arg, "/2"), buffer, alloc, add_argument, &[self.$a, self.$b])
 		});
 
 		derive!(@INTERNAL impl from_args(buffer[2]) for $name {
@@ -104,6 +136,7 @@ macro_rules! derive {
 
 		impl $name {
 			pub const CMD_ARG: &'static str = $arg;
+			pub const ARITY: usize = 3;
 
 			#[inline(always)]
 			pub const fn new($a: u128, $b: u128, $c: u128) -> Self {
@@ -112,7 +145,7 @@ macro_rules! derive {
 		}
 
 		derive!(@INTERNAL impl to_args(self, buffer, alloc, add_argument) for $name {
-			to(concat!("--", $arg), buffer, alloc, add_argument, &[self.$a, self.$b, self.$c])
+			to(concat!("--", $arg, "/3"), buffer, alloc, add_argument, &[self.$a, self.$b, self.$c])
 		});
 
 		derive!(@INTERNAL impl from_args(buffer[3]) for $name {
@@ -135,6 +168,7 @@ macro_rules! derive {
 
 		impl $name {
 			pub const CMD_ARG: &'static str = $arg;
+			pub const ARITY: usize = 5;
 
 			#[inline(always)]
 			pub const fn new($a: u128, $b: u128, $c: u128, $d: u128, $e: u128) -> Self {
@@ -143,7 +177,7 @@ macro_rules! derive {
 		}
 
 		derive!(@INTERNAL impl to_args(self, buffer, alloc, add_argument) for $name {
-			to(concat!("--", $arg), buffer, alloc, add_argument, &[self.$a, self.$b, self.$c, self.$d, self.$e])
+			to(concat!("--", $arg, "/5"), buffer, alloc, add_argument, &[self.$a, self.$b, self.$c, self.$d, self.$e])
 		});
 
 		derive!(@INTERNAL impl from_args(buffer[5]) for $name {
@@ -203,12 +237,12 @@ where
 	}
 
 	fn fmt_hex(buf: &mut [u8], mut num: u128) -> &str {
-		let mut i = buf.len() - 1;
+		let mut i = buf.len();
 		while {
+			i -= 1;
 			let d = (num % 16) as u8;
 			buf[i] = (d < 10).then(|| b'0').unwrap_or(b'a' - 10) + d;
 			num /= 16;
-			i -= 1;
 			num != 0
 		} {}
 		core::str::from_utf8(buf).unwrap()
@@ -244,6 +278,7 @@ where
 	Ok(())
 }
 
+derive!(ArgvVersion "argv-version" version);
 derive!(Reg "reg" address size);
 derive!(Range "range" child_address address size);
 derive!(InterruptMap "interrupt-map" child_address child_interrupt parent parent_address parent_interrupt);
@@ -278,6 +313,11 @@ pub enum Arg<'a> {
 	Other(&'a [u8]),
 }
 
+impl ArgvVersion {
+	/// The version parents should pass.
+	pub const CURRENT: Self = Self::new(ARGV_VERSION as u128);
+}
+
 impl Arg<'_> {
 	#[optimize(size)]
 	pub fn cmd_arg(&self) -> Result<&str, &[u8]> {
@@ -306,31 +346,95 @@ impl Arg<'_> {
 	}
 }
 
+/// Split an argument in the `--key/count` form.
+fn split_key(arg: &[u8]) -> Option<(&[u8], usize)> {
+	let arg = arg.strip_prefix(b"--")?;
+	let i = arg.iter().rposition(|&c| c == b'/')?;
+	let count = str::from_utf8(&arg[i + 1..]).ok()?;
+	let count = usize::from_str_radix(count, 16).ok()?;
+	Some((&arg[..i], count))
+}
+
 /// Parse arguments from the given iterator
-pub fn parse_args<'a, I, F>(mut args: I, mut f: F) -> Result<(), ParseError<'a>>
+pub fn parse_args<'a, I, F>(args: I, f: F) -> Result<(), ParseError<'a>>
 where
 	I: Iterator<Item = &'a [u8]> + 'a,
-	F: FnMut(Arg<'a>, &mut I),
+	F: FnMut(Arg<'a>, &mut Peekable<I>),
 {
+	parse_args_with_versions(args, ARGV_VERSION_MIN..=ARGV_VERSION, f)
+}
+
+/// Parse arguments from the given iterator, accepting only the given protocol versions.
+fn parse_args_with_versions<'a, I, F>(
+	args: I,
+	supported: RangeInclusive<u32>,
+	mut f: F,
+) -> Result<(), ParseError<'a>>
+where
+	I: Iterator<Item = &'a [u8]> + 'a,
+	F: FnMut(Arg<'a>, &mut Peekable<I>),
+{
+	let mut args = args.peekable();
+
+	// Parents that predate the handshake don't pass a version, which is treated as version 0.
+	// The first argument is only consumed if it is the version marker.
+	let marker = args.peek().and_then(|a| split_key(a));
+	let got = match marker {
+		Some((key, count)) if key == ArgvVersion::CMD_ARG.as_bytes() => {
+			if count != ArgvVersion::ARITY {
+				return Err(ParseError::WrongArity(ArgvVersion::CMD_ARG));
+			}
+			args.next();
+			ArgvVersion::from_args(&mut args)?.version
+		}
+		_ => 0,
+	};
+	let got = u32::try_from(got).unwrap_or(u32::MAX);
+	if !supported.contains(&got) {
+		return Err(ParseError::UnsupportedVersion { got, supported });
+	}
+
+	macro_rules! parse {
+		($ty:ident, $count:ident) => {{
+			if $count != $ty::ARITY {
+				return Err(ParseError::WrongArity($ty::CMD_ARG));
+			}
+			Arg::$ty($ty::from_args(&mut args)?)
+		}};
+	}
+
 	while let Some(ty) = args.next() {
-		let a = match ty {
+		let (key, count) = match split_key(ty) {
+			Some(k) => k,
+			None => {
+				f(Arg::Other(ty), &mut args);
+				continue;
+			}
+		};
+		let a = match key {
 			#[cfg(feature = "parse-reg")]
-			b"--reg" => Arg::Reg(Reg::from_args(&mut args)?),
+			b"reg" => parse!(Reg, count),
 			#[cfg(feature = "parse-range")]
-			b"--range" => Arg::Range(Range::from_args(&mut args)?),
+			b"range" => parse!(Range, count),
 			#[cfg(feature = "parse-interrupt-map")]
-			b"--interrupt-map" => Arg::InterruptMap(InterruptMap::from_args(&mut args)?),
+			b"interrupt-map" => parse!(InterruptMap, count),
 			#[cfg(feature = "parse-interrupt-map-mask")]
-			b"--interrupt-map-mask" => Arg::InterruptMapMask(InterruptMapMask::from_args(&mut args)?),
+			b"interrupt-map-mask" => parse!(InterruptMapMask, count),
 			#[cfg(feature = "parse-pci")]
-			b"--pci" => Arg::Pci(Pci::from_args(&mut args)?),
+			b"pci" => parse!(Pci, count),
 			#[cfg(feature = "parse-pci-interrupt")]
-			b"--pci-interrupt" => Arg::PciInterrupt(PciInterrupt::from_args(&mut args)?),
-			#[cfg(feature = "parse-bar-mmio")]
-			b"--bar-io" => Arg::BarIo(BarIo::from_args(&mut args)?),
+			b"pci-interrupt" => parse!(PciInterrupt, count),
 			#[cfg(feature = "parse-bar-io")]
-			b"--bar-mmio" => Arg::BarMmio(BarMmio::from_args(&mut args)?),
-			arg => Arg::Other(arg),
+			b"bar-io" => parse!(BarIo, count),
+			#[cfg(feature = "parse-bar-mmio")]
+			b"bar-mmio" => parse!(BarMmio, count),
+			_ => {
+				// Skip groups we don't know about.
+				for _ in 0..count {
+					args.next().ok_or(ParseError::MissingArgument("unknown"))?;
+				}
+				continue;
+			}
 		};
 		f(a, &mut args)
 	}
@@ -347,6 +451,13 @@ pub enum ParseError<'a> {
 	ParseIntError(num::ParseIntError),
 	UnknownArgument(&'a [u8]),
 	OutOfMemory,
+	/// The parent uses a version of the argument protocol that isn't supported.
+	UnsupportedVersion {
+		got: u32,
+		supported: RangeInclusive<u32>,
+	},
+	/// A known group of arguments has the wrong amount of values.
+	WrongArity(&'static str),
 }
 
 impl<'a> ParseError<'a> {
@@ -360,6 +471,8 @@ impl<'a> ParseError<'a> {
 			Self::ParseIntError(_) => "failed to parse integer",
 			Self::UnknownArgument(_) => "unknown argument",
 			Self::OutOfMemory => "out of memory",
+			Self::UnsupportedVersion { .. } => "unsupported argument protocol version",
+			Self::WrongArity(_) => "wrong amount of values",
 		}
 	}
 }
@@ -378,6 +491,14 @@ impl fmt::Debug for ParseError<'_> {
 				Err(_) => write!(f, "argument is not valid UTF-8"),
 			},
 			Self::OutOfMemory => fmt::Display::fmt("out of memory", f),
+			Self::UnsupportedVersion { got, supported } => write!(
+				f,
+				"unsupported argument protocol version {} (supported: {}-{})",
+				got,
+				supported.start(),
+				supported.end()
+			),
+			Self::WrongArity(r) => write!(f, "wrong amount of values for {:?}", r),
 		}
 	}
 }
@@ -388,3 +509,122 @@ impl From<OutOfMemory> for ParseError<'_> {
 		Self::OutOfMemory
 	}
 }
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	fn alloc(buf: &mut [u8], size: usize) -> Result<(&mut [u8], &mut [u8]), OutOfMemory> {
+		if size <= buf.len() {
+			Ok(buf.split_at_mut(size))
+		} else {
+			Err(OutOfMemory)
+		}
+	}
+
+	/// Parse the arguments and return the amount of `Reg`s and `Other`s.
+	fn parse<'a>(
+		args: &'a [&'a [u8]],
+		supported: RangeInclusive<u32>,
+	) -> Result<(Vec<(u128, u128)>, usize), ParseError<'a>> {
+		let (mut regs, mut other) = (Vec::new(), 0);
+		parse_args_with_versions(args.iter().copied(), supported, |arg, _| match arg {
+			Arg::Reg(r) => regs.push((r.address, r.size)),
+			Arg::Other(_) => other += 1,
+			arg => panic!("unexpected argument {:?}", arg),
+		})?;
+		Ok((regs, other))
+	}
+
+	#[test]
+	fn to_from_args() {
+		let mut buf = [0; 64];
+		let mut args = Vec::new();
+		let mut add_arg = |arg| {
+			args.push(str::as_bytes(arg));
+			Ok(())
+		};
+		let buf = ArgvVersion::CURRENT
+			.to_args(&mut buf, alloc, &mut add_arg)
+			.unwrap();
+		Reg::new(0x1000, 0x20)
+			.to_args(buf, alloc, &mut add_arg)
+			.unwrap();
+		assert_eq!(
+			&args[..],
+			&[&b"--argv-version/1"[..], b"1", b"--reg/2", b"1000", b"20"]
+		);
+		let (regs, other) = parse(&args, ARGV_VERSION_MIN..=ARGV_VERSION).unwrap();
+		assert_eq!(&regs[..], &[(0x1000, 0x20)]);
+		assert_eq!(other, 0);
+	}
+
+	#[test]
+	fn old_parent_new_child() {
+		let args: &[&[u8]] = &[b"--reg", b"1000", b"20"];
+		match parse(args, 1..=1) {
+			Err(ParseError::UnsupportedVersion { got: 0, supported }) => {
+				assert_eq!(supported, 1..=1)
+			}
+			r => panic!("expected unsupported version, got {:?}", r.map(|_| ())),
+		}
+	}
+
+	#[test]
+	fn unversioned() {
+		let args: &[&[u8]] = &[b"--reg/2", b"1000", b"20", b"--no-count"];
+		let (regs, other) = parse(args, 0..=1).unwrap();
+		assert_eq!(&regs[..], &[(0x1000, 0x20)]);
+		assert_eq!(other, 1);
+	}
+
+	#[test]
+	fn new_parent_old_child_incompatible() {
+		let args: &[&[u8]] = &[b"--argv-version/1", b"2", b"--reg/2", b"1000", b"20"];
+		match parse(args, 1..=1) {
+			Err(ParseError::UnsupportedVersion { got: 2, supported }) => {
+				assert_eq!(supported, 1..=1)
+			}
+			r => panic!("expected unsupported version, got {:?}", r.map(|_| ())),
+		}
+	}
+
+	#[test]
+	fn new_parent_old_child_skip_unknown() {
+		let args: &[&[u8]] = &[
+			b"--argv-version/1",
+			b"1",
+			b"--name/1",
+			b"abc",
+			b"--quirks/3",
+			b"1",
+			b"2",
+			b"3",
+			b"--reg/2",
+			b"1000",
+			b"20",
+			b"--no-count",
+		];
+		let (regs, other) = parse(args, 1..=1).unwrap();
+		assert_eq!(&regs[..], &[(0x1000, 0x20)]);
+		assert_eq!(other, 1);
+	}
+
+	#[test]
+	fn unknown_missing_values() {
+		let args: &[&[u8]] = &[b"--argv-version/1", b"1", b"--name/2", b"abc"];
+		assert!(matches!(
+			parse(args, 1..=1),
+			Err(ParseError::MissingArgument(_))
+		));
+	}
+
+	#[test]
+	fn wrong_arity() {
+		let args: &[&[u8]] = &[b"--argv-version/1", b"1", b"--reg/3", b"1", b"2", b"3"];
+		assert!(matches!(
+			parse(args, 1..=1),
+			Err(ParseError::WrongArity("reg"))
+		));
+	}
+}
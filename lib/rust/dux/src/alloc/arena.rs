@@ -0,0 +1,231 @@
+use super::*;
+
+/// A bump allocator.
+///
+/// Deallocating only reclaims memory if it is the most recent allocation. All other memory is
+/// reclaimed at once with [`Self::reset`].
+pub struct Arena<P: Pages = System> {
+	chunks: Chunks<P>,
+	/// The next free byte in the current chunk.
+	next: Cell<usize>,
+	/// The end of the current chunk.
+	end: Cell<usize>,
+	/// The start of the most recent allocation, which can be grown or shrunk in place.
+	last: Cell<usize>,
+}
+
+/// An allocator handle for use with collections, e.g. `Vec<T, ArenaAlloc>`.
+pub type ArenaAlloc<'a, P = System> = &'a Arena<P>;
+
+impl Arena {
+	/// Create an arena that uses at most `max_pages` pages.
+	pub const fn new(max_pages: usize) -> Self {
+		Self::with_pages(System, max_pages)
+	}
+}
+
+impl<P: Pages> Arena<P> {
+	/// Create an arena that gets at most `max_pages` pages from the given source.
+	pub const fn with_pages(source: P, max_pages: usize) -> Self {
+		Self {
+			chunks: Chunks::new(source, max_pages),
+			next: Cell::new(0),
+			end: Cell::new(0),
+			last: Cell::new(usize::MAX),
+		}
+	}
+
+	/// Free all allocations at once. The most recent chunk of pages is kept for reuse.
+	pub fn reset(&mut self) {
+		// SAFETY: we have a mutable reference, so nothing can be borrowing the arena.
+		unsafe { self.chunks.release(true) };
+		let (next, end) = self
+			.chunks
+			.last()
+			.map_or((0, 0), |(s, e)| (s.as_ptr() as usize, e.as_ptr() as usize));
+		self.next.set(next);
+		self.end.set(end);
+		self.last.set(usize::MAX);
+	}
+
+	/// The amount of pages currently in use by the arena.
+	pub fn pages(&self) -> usize {
+		self.chunks.pages()
+	}
+
+	/// Try to allocate in the current chunk.
+	fn bump(&self, layout: Layout) -> Option<NonNull<[u8]>> {
+		let start = align_up(self.next.get(), layout.align())?;
+		let end = start.checked_add(layout.size())?;
+		(end <= self.end.get()).then(|| {
+			self.next.set(end);
+			self.last.set(start);
+			// SAFETY: the allocation isn't empty, so start is in a chunk and isn't null.
+			let ptr = unsafe { NonNull::new_unchecked(start as *mut u8) };
+			NonNull::slice_from_raw_parts(ptr, layout.size())
+		})
+	}
+}
+
+unsafe impl<P: Pages> Allocator for Arena<P> {
+	fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+		// Zero-sized allocations don't need any memory but the pointer must still be aligned.
+		// The bump pointer may be null if no chunk has been allocated yet.
+		if layout.size() == 0 {
+			// SAFETY: the alignment is never 0.
+			let ptr = unsafe { NonNull::new_unchecked(layout.align() as *mut u8) };
+			return Ok(NonNull::slice_from_raw_parts(ptr, 0));
+		}
+		if let Some(ptr) = self.bump(layout) {
+			return Ok(ptr);
+		}
+		let size = layout
+			.size()
+			.checked_add(layout.align())
+			.ok_or(AllocError)?;
+		let (start, end) = self.chunks.grow(size).ok_or(AllocError)?;
+		self.next.set(start.as_ptr() as usize);
+		self.end.set(end.as_ptr() as usize);
+		self.bump(layout).ok_or(AllocError)
+	}
+
+	unsafe fn deallocate(&self, ptr: NonNull<u8>, _: Layout) {
+		if ptr.as_ptr() as usize == self.last.get() {
+			self.next.set(self.last.get());
+			self.last.set(usize::MAX);
+		}
+	}
+
+	unsafe fn grow(
+		&self,
+		ptr: NonNull<u8>,
+		old: Layout,
+		new: Layout,
+	) -> Result<NonNull<[u8]>, AllocError> {
+		let start = ptr.as_ptr() as usize;
+		if start == self.last.get() && start & (new.align() - 1) == 0 {
+			if let Some(end) = start.checked_add(new.size()) {
+				if end <= self.end.get() {
+					self.next.set(end);
+					return Ok(NonNull::slice_from_raw_parts(ptr, new.size()));
+				}
+			}
+		}
+		let new_ptr = self.allocate(new)?;
+		new_ptr
+			.cast::<u8>()
+			.as_ptr()
+			.copy_from_nonoverlapping(ptr.as_ptr(), old.size());
+		Ok(new_ptr)
+	}
+
+	unsafe fn shrink(
+		&self,
+		ptr: NonNull<u8>,
+		_: Layout,
+		new: Layout,
+	) -> Result<NonNull<[u8]>, AllocError> {
+		let start = ptr.as_ptr() as usize;
+		if start & (new.align() - 1) != 0 {
+			let new_ptr = self.allocate(new)?;
+			new_ptr
+				.cast::<u8>()
+				.as_ptr()
+				.copy_from_nonoverlapping(ptr.as_ptr(), new.size());
+			return Ok(new_ptr);
+		}
+		if start == self.last.get() {
+			self.next.set(start + new.size());
+		}
+		Ok(NonNull::slice_from_raw_parts(ptr, new.size()))
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::super::test::Host;
+	use super::*;
+
+	#[test]
+	fn vec() {
+		let host = Host::default();
+		let arena = Arena::with_pages(&host, 16);
+		let mut v = Vec::new_in(&arena);
+		for i in 0..500u32 {
+			v.push(i);
+		}
+		assert!(v.iter().copied().eq(0..500));
+		// The vector is always the last allocation, so it should be grown in place.
+		assert_eq!(arena.pages(), 1);
+		drop(v);
+		drop(arena);
+		assert_eq!(host.allocated.get(), 0);
+	}
+
+	#[test]
+	fn alignment() {
+		let host = Host::default();
+		let arena = Arena::with_pages(&host, 16);
+		let _a = Box::new_in(1u8, &arena);
+		let b = Box::new_in(2u64, &arena);
+		assert_eq!(&*b as *const u64 as usize % 8, 0);
+		let c = arena.allocate(Layout::from_size_align(64, 64).unwrap());
+		assert_eq!(c.unwrap().cast::<u8>().as_ptr() as usize % 64, 0);
+	}
+
+	#[test]
+	fn growth() {
+		let host = Host::default();
+		let arena = Arena::with_pages(&host, 7);
+		let layout = Layout::from_size_align(Page::SIZE / 2, 8).unwrap();
+		// 1 page, then 2, then 4.
+		for _ in 0..1 + 3 + 7 {
+			arena.allocate(layout).unwrap();
+		}
+		assert_eq!(arena.pages(), 7);
+		assert_eq!(arena.allocate(layout), Err(AllocError));
+	}
+
+	#[test]
+	fn too_large() {
+		let host = Host::default();
+		let arena = Arena::with_pages(&host, 4);
+		let layout = Layout::from_size_align(Page::SIZE * 4, 8).unwrap();
+		assert_eq!(arena.allocate(layout), Err(AllocError));
+		let layout = Layout::from_size_align(Page::SIZE * 3, 8).unwrap();
+		assert!(arena.allocate(layout).is_ok());
+		assert_eq!(arena.pages(), 4);
+	}
+
+	#[test]
+	fn zero_size() {
+		let host = Host::default();
+		let arena = Arena::with_pages(&host, 4);
+		let layout = Layout::from_size_align(0, 16).unwrap();
+		let ptr = arena.allocate(layout).unwrap().cast::<u8>();
+		assert_eq!(ptr.as_ptr() as usize % 16, 0);
+		assert_eq!(arena.pages(), 0);
+		unsafe { arena.deallocate(ptr, layout) };
+		let mut v = Vec::<u64, _>::new_in(&arena);
+		v.push(1);
+		assert_eq!(v, [1]);
+	}
+
+	#[test]
+	fn reset() {
+		let host = Host::default();
+		let mut arena = Arena::with_pages(&host, 16);
+		let layout = Layout::from_size_align(Page::SIZE, 8).unwrap();
+		arena.allocate(layout).unwrap();
+		let second = arena.allocate(layout).unwrap();
+		assert_eq!(arena.pages(), 2 + 4);
+		arena.reset();
+		assert_eq!(arena.pages(), 4);
+		assert_eq!(host.allocated.get(), 4);
+		// The memory of the last chunk is reused.
+		assert_eq!(arena.allocate(layout).unwrap(), second);
+		assert_eq!(arena.pages(), 4);
+		drop(arena);
+		assert_eq!(host.allocated.get(), 0);
+	}
+}
@@ -0,0 +1,249 @@
+use super::*;
+use core::mem;
+
+/// A first-fit free list allocator.
+///
+/// Free blocks are kept sorted by address so that adjacent blocks can be merged when memory
+/// is deallocated.
+pub struct Heap<P: Pages = System> {
+	chunks: Chunks<P>,
+	/// The free block with the lowest address.
+	free: Cell<Option<NonNull<Block>>>,
+}
+
+/// A free block, which is stored in the block itself.
+struct Block {
+	next: Option<NonNull<Block>>,
+	size: usize,
+}
+
+/// The granularity of all blocks, which ensures a free block can always hold a [`Block`].
+const UNIT: usize = mem::size_of::<Block>();
+
+const _UNIT_IS_POWER_OF_TWO: usize = 0 - (UNIT & (UNIT - 1));
+const _UNIT_IS_ALIGNED: usize = 0 - (UNIT % mem::align_of::<Block>());
+
+impl Heap {
+	/// Create a heap that uses at most `max_pages` pages.
+	pub const fn new(max_pages: usize) -> Self {
+		Self::with_pages(System, max_pages)
+	}
+}
+
+impl<P: Pages> Heap<P> {
+	/// Create a heap that gets at most `max_pages` pages from the given source.
+	pub const fn with_pages(source: P, max_pages: usize) -> Self {
+		Self {
+			chunks: Chunks::new(source, max_pages),
+			free: Cell::new(None),
+		}
+	}
+
+	/// The amount of pages currently in use by the heap.
+	pub fn pages(&self) -> usize {
+		self.chunks.pages()
+	}
+
+	/// The size of the block used for the given layout.
+	fn size(layout: Layout) -> Option<usize> {
+		align_up(layout.size().max(1), UNIT)
+	}
+
+	/// Take a block of `size` bytes aligned to `align` from the free list.
+	fn take(&self, size: usize, align: usize) -> Option<NonNull<u8>> {
+		let mut prev = None::<NonNull<Block>>;
+		let mut cur = self.free.get();
+		while let Some(block) = cur {
+			// SAFETY: all blocks in the list are valid.
+			let Block {
+				next,
+				size: block_size,
+			} = unsafe { block.as_ptr().read() };
+			let start = block.as_ptr() as usize;
+			let end = start + block_size;
+			let ptr = align_up(start, align)?;
+			if ptr.checked_add(size).map_or(false, |e| e <= end) {
+				match prev {
+					// SAFETY: prev is a valid block in the list.
+					Some(prev) => unsafe { (*prev.as_ptr()).next = next },
+					None => self.free.set(next),
+				}
+				// SAFETY: the remaining regions are unused and part of a chunk.
+				unsafe {
+					self.insert(start, ptr - start);
+					self.insert(ptr + size, end - (ptr + size));
+					return Some(NonNull::new_unchecked(ptr as *mut u8));
+				}
+			}
+			prev = cur;
+			cur = next;
+		}
+		None
+	}
+
+	/// Add a region to the free list, merging it with adjacent blocks.
+	///
+	/// # Safety
+	///
+	/// The region must be part of a chunk and not be in use. `start` and `size` must be a
+	/// multiple of [`UNIT`].
+	unsafe fn insert(&self, start: usize, size: usize) {
+		if size == 0 {
+			return;
+		}
+		let mut prev = None::<NonNull<Block>>;
+		let mut next = self.free.get();
+		while let Some(block) = next {
+			if block.as_ptr() as usize > start {
+				break;
+			}
+			prev = next;
+			next = block.as_ref().next;
+		}
+
+		// Merge with the next block.
+		let (size, next) = match next {
+			Some(n) if start + size == n.as_ptr() as usize => {
+				(size + n.as_ref().size, n.as_ref().next)
+			}
+			n => (size, n),
+		};
+
+		// Merge with the previous block.
+		match prev {
+			Some(mut p) if p.as_ptr() as usize + p.as_ref().size == start => {
+				let p = p.as_mut();
+				p.size += size;
+				p.next = next;
+			}
+			p => {
+				let block = NonNull::new_unchecked(start as *mut Block);
+				block.as_ptr().write(Block { next, size });
+				match p {
+					Some(mut p) => p.as_mut().next = Some(block),
+					None => self.free.set(Some(block)),
+				}
+			}
+		}
+	}
+}
+
+unsafe impl<P: Pages> Allocator for Heap<P> {
+	fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+		let size = Self::size(layout).ok_or(AllocError)?;
+		let align = layout.align().max(UNIT);
+		let ptr = match self.take(size, align) {
+			Some(ptr) => ptr,
+			None => {
+				let (start, end) = self
+					.chunks
+					.grow(size.checked_add(align).ok_or(AllocError)?)
+					.ok_or(AllocError)?;
+				let (start, end) = (start.as_ptr() as usize, end.as_ptr() as usize);
+				// SAFETY: the chunk was just allocated. The header of a chunk is as large as
+				// a Block and chunks are page aligned, so the region is aligned to UNIT.
+				unsafe { self.insert(start, end - start) };
+				self.take(size, align).ok_or(AllocError)?
+			}
+		};
+		Ok(NonNull::slice_from_raw_parts(ptr, size))
+	}
+
+	unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+		let size = Self::size(layout).expect("size was valid when allocating");
+		self.insert(ptr.as_ptr() as usize, size);
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::super::test::Host;
+	use super::*;
+
+	/// Return the sizes of all free blocks.
+	fn free_list(heap: &Heap<&Host>) -> Vec<usize> {
+		let mut v = Vec::new();
+		let mut cur = heap.free.get();
+		while let Some(b) = cur {
+			unsafe {
+				v.push(b.as_ref().size);
+				cur = b.as_ref().next;
+			}
+		}
+		v
+	}
+
+	#[test]
+	fn reuse() {
+		let host = Host::default();
+		let heap = Heap::with_pages(&host, 16);
+		let a = Box::new_in([0u8; 100], &heap);
+		let a_ptr = &*a as *const _ as usize;
+		drop(a);
+		let b = Box::new_in([1u8; 100], &heap);
+		assert_eq!(&*b as *const _ as usize, a_ptr);
+		assert_eq!(heap.pages(), 1);
+	}
+
+	#[test]
+	fn merge() {
+		let host = Host::default();
+		let heap = Heap::with_pages(&host, 16);
+		let boxes = (0..10).map(|i| Box::new_in([i; 64], &heap));
+		let mut boxes = boxes.collect::<Vec<_>>();
+		assert_eq!(free_list(&heap).len(), 1);
+		// Free every other box, then the rest.
+		let mut i = 0;
+		boxes.retain(|_| {
+			i += 1;
+			i % 2 == 0
+		});
+		assert_eq!(free_list(&heap).len(), 6);
+		drop(boxes);
+		assert_eq!(free_list(&heap), [Page::SIZE - UNIT]);
+	}
+
+	#[test]
+	fn alignment() {
+		let host = Host::default();
+		let heap = Heap::with_pages(&host, 16);
+		let a = Box::new_in(1u8, &heap);
+		for align in [8, 64, 256, 1024] {
+			let layout = Layout::from_size_align(24, align).unwrap();
+			let ptr = heap.allocate(layout).unwrap().cast::<u8>();
+			assert_eq!(ptr.as_ptr() as usize % align, 0);
+			unsafe { heap.deallocate(ptr, layout) };
+		}
+		drop(a);
+		assert_eq!(free_list(&heap), [Page::SIZE - UNIT]);
+	}
+
+	#[test]
+	fn vec() {
+		let host = Host::default();
+		let heap = Heap::with_pages(&host, 16);
+		let mut a = Vec::new_in(&heap);
+		let mut b = Vec::new_in(&heap);
+		for i in 0..1000u32 {
+			a.push(i);
+			b.push(u64::from(i) * 3);
+		}
+		assert!(a.iter().copied().eq(0..1000));
+		assert!(b.iter().copied().eq((0..3000).step_by(3)));
+		drop((a, b));
+		drop(heap);
+		assert_eq!(host.allocated.get(), 0);
+	}
+
+	#[test]
+	fn limit() {
+		let host = Host::default();
+		let heap = Heap::with_pages(&host, 3);
+		let layout = Layout::from_size_align(Page::SIZE * 2, 8).unwrap();
+		let ptr = heap.allocate(layout).unwrap().cast::<u8>();
+		assert_eq!(heap.allocate(layout), Err(AllocError));
+		unsafe { heap.deallocate(ptr, layout) };
+		assert!(heap.allocate(layout).is_ok());
+		assert_eq!(heap.pages(), 3);
+	}
+}
@@ -0,0 +1,234 @@
+//! # Local allocators
+//!
+//! There is no global heap. Instead, services create an allocator for the data that needs one
+//! and pass it to collections explicitly, e.g. `Vec::new_in(&arena)`.
+//!
+//! * [`Arena`] is a bump allocator for short-lived or bounded data. Memory is only reclaimed
+//!   with [`Arena::reset`] or when the arena is dropped.
+//! * [`Heap`] is a first-fit free list allocator for long-lived data of varying size.
+//!
+//! Neither allocator is thread-safe, which is why they are `!Sync`.
+//!
+//! ## Growth policy
+//!
+//! Both allocators start without any memory. When an allocation doesn't fit, a new chunk of
+//! pages is requested from [`Pages`]. Each chunk is twice as large as the previous one, or
+//! larger if the allocation requires it. The total amount of pages is limited by the cap given
+//! on creation: if a new chunk would exceed it, a smaller chunk that still fits the allocation
+//! is tried and if that doesn't fit either the allocation fails with [`AllocError`].
+//!
+//! Pages are only returned to the system when the allocator is dropped, or by
+//! [`Arena::reset`] for all but the most recent chunk.
+//!
+//! ## Global allocator
+//!
+//! Linking the `alloc` crate requires a global allocator. Services that only use local
+//! allocators can use [`NoGlobalAlloc`], which makes any use of the global heap fail.
+
+mod arena;
+mod heap;
+
+pub use arena::*;
+pub use core::alloc::{AllocError, Allocator, Layout};
+pub use heap::*;
+
+use crate::{Page, RWX};
+use core::alloc::GlobalAlloc;
+use core::cell::Cell;
+use core::ptr::{self, NonNull};
+
+/// A source of pages for the allocators.
+pub trait Pages {
+	/// Allocate a contiguous range of pages.
+	fn allocate(&self, count: usize) -> Option<NonNull<u8>>;
+
+	/// Deallocate a range of pages.
+	///
+	/// # Safety
+	///
+	/// The range must have been allocated with [`Self::allocate`] with the same `count` and may
+	/// not be in use anymore.
+	unsafe fn deallocate(&self, start: NonNull<u8>, count: usize);
+}
+
+/// Pages allocated with [`crate::mem::allocate_range`].
+#[derive(Clone, Copy, Debug, Default)]
+pub struct System;
+
+impl Pages for System {
+	fn allocate(&self, count: usize) -> Option<NonNull<u8>> {
+		crate::mem::allocate_range(None, count, RWX::RW)
+			.ok()
+			.map(|p| p.as_non_null_ptr().cast())
+	}
+
+	unsafe fn deallocate(&self, start: NonNull<u8>, count: usize) {
+		crate::mem::deallocate_range(Page::new_unchecked(start.as_ptr().cast()), count)
+	}
+}
+
+/// A global allocator that never returns any memory.
+pub struct NoGlobalAlloc;
+
+unsafe impl GlobalAlloc for NoGlobalAlloc {
+	unsafe fn alloc(&self, _: Layout) -> *mut u8 {
+		ptr::null_mut()
+	}
+
+	unsafe fn dealloc(&self, _: *mut u8, _: Layout) {
+		unreachable!("memory was never allocated");
+	}
+}
+
+/// The header at the start of every chunk of pages, which links all chunks of an allocator.
+struct Chunk {
+	next: Option<NonNull<Chunk>>,
+	pages: usize,
+}
+
+/// A list of chunks of pages and the limit on the total amount of pages.
+struct Chunks<P: Pages> {
+	source: P,
+	head: Cell<Option<NonNull<Chunk>>>,
+	/// The amount of pages of the most recent chunk.
+	last_pages: Cell<usize>,
+	/// The total amount of pages of all chunks.
+	pages: Cell<usize>,
+	max_pages: usize,
+}
+
+impl<P: Pages> Chunks<P> {
+	const fn new(source: P, max_pages: usize) -> Self {
+		Self {
+			source,
+			head: Cell::new(None),
+			last_pages: Cell::new(0),
+			pages: Cell::new(0),
+			max_pages,
+		}
+	}
+
+	/// Allocate a new chunk with room for at least `size` bytes after the header. Returns the
+	/// start and end of the usable region.
+	fn grow(&self, size: usize) -> Option<(NonNull<u8>, NonNull<u8>)> {
+		let header = core::mem::size_of::<Chunk>();
+		let min = Page::min_pages_for_range(size.checked_add(header)?);
+		let left = self.max_pages - self.pages.get();
+		let count = min.max(self.last_pages.get() * 2).min(left);
+		if count < min {
+			return None;
+		}
+		let start = self.source.allocate(count)?;
+		let chunk = start.cast::<Chunk>();
+		// SAFETY: the chunk was just allocated and pages are always aligned enough for Chunk.
+		unsafe {
+			chunk.as_ptr().write(Chunk {
+				next: self.head.get(),
+				pages: count,
+			})
+		};
+		self.head.set(Some(chunk));
+		self.last_pages.set(count);
+		self.pages.set(self.pages.get() + count);
+		// SAFETY: both pointers are within or one past the end of the chunk.
+		unsafe {
+			let end = start.as_ptr().add(count * Page::SIZE);
+			Some((
+				NonNull::new_unchecked(start.as_ptr().add(header)),
+				NonNull::new_unchecked(end),
+			))
+		}
+	}
+
+	/// Deallocate all chunks except the most recent one if `keep_last` is set.
+	///
+	/// # Safety
+	///
+	/// None of the memory in the deallocated chunks may be in use.
+	unsafe fn release(&mut self, keep_last: bool) {
+		let mut next = self.head.get();
+		if keep_last {
+			if let Some(mut head) = next {
+				next = head.as_ref().next;
+				head.as_mut().next = None;
+				self.pages.set(head.as_ref().pages);
+			}
+		} else {
+			self.head.set(None);
+			self.last_pages.set(0);
+			self.pages.set(0);
+		}
+		while let Some(chunk) = next {
+			let Chunk { next: n, pages } = chunk.as_ptr().read();
+			self.source.deallocate(chunk.cast(), pages);
+			next = n;
+		}
+	}
+
+	/// Return the usable region of the most recent chunk.
+	fn last(&self) -> Option<(NonNull<u8>, NonNull<u8>)> {
+		self.head.get().map(|chunk| unsafe {
+			let pages = chunk.as_ref().pages;
+			let start = chunk.as_ptr().cast::<u8>();
+			(
+				NonNull::new_unchecked(start.add(core::mem::size_of::<Chunk>())),
+				NonNull::new_unchecked(start.add(pages * Page::SIZE)),
+			)
+		})
+	}
+
+	/// The total amount of pages in use.
+	fn pages(&self) -> usize {
+		self.pages.get()
+	}
+}
+
+impl<P: Pages> Drop for Chunks<P> {
+	fn drop(&mut self) {
+		// SAFETY: the owning allocator is being dropped so nothing can be using the memory.
+		unsafe { self.release(false) }
+	}
+}
+
+/// Round `value` up to a multiple of `align`, which must be a power of two.
+fn align_up(value: usize, align: usize) -> Option<usize> {
+	Some(value.checked_add(align - 1)? & !(align - 1))
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+	use super::*;
+	use std::alloc::{alloc, dealloc};
+
+	/// Pages allocated from the host heap, which also checks for leaks.
+	#[derive(Default)]
+	pub struct Host {
+		pub allocated: Cell<usize>,
+	}
+
+	fn layout(count: usize) -> Layout {
+		Layout::from_size_align(count * Page::SIZE, Page::SIZE).unwrap()
+	}
+
+	impl Pages for Host {
+		fn allocate(&self, count: usize) -> Option<NonNull<u8>> {
+			self.allocated.set(self.allocated.get() + count);
+			NonNull::new(unsafe { alloc(layout(count)) })
+		}
+
+		unsafe fn deallocate(&self, start: NonNull<u8>, count: usize) {
+			self.allocated.set(self.allocated.get() - count);
+			dealloc(start.as_ptr(), layout(count))
+		}
+	}
+
+	impl Pages for &Host {
+		fn allocate(&self, count: usize) -> Option<NonNull<u8>> {
+			(*self).allocate(count)
+		}
+
+		unsafe fn deallocate(&self, start: NonNull<u8>, count: usize) {
+			(*self).deallocate(start, count)
+		}
+	}
+}
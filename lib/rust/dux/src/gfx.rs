@@ -0,0 +1,354 @@
+//! # Graphics types shared by the GPU driver & its clients
+//!
+//! The GPU driver hands out draw buffers of [`Pixel`]s. Clients learn the size & layout of each
+//! scanout from the [`ScanoutInfo`] entries returned when opening the metadata and access the
+//! draw buffer through a [`FrameBuffer`], which checks all accesses against its bounds.
+
+use core::convert::TryFrom;
+use core::mem;
+use core::ptr::NonNull;
+use core::slice;
+
+/// A pixel in a draw buffer.
+///
+/// The layout matches the `R8G8B8A8_UNORM` format of virtio-gpu, i.e. red is the first byte in
+/// memory.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+#[repr(C)]
+pub struct Pixel {
+	pub r: u8,
+	pub g: u8,
+	pub b: u8,
+	pub a: u8,
+}
+
+impl Pixel {
+	/// The virtio-gpu format that matches the layout of a pixel.
+	pub const FORMAT: u32 = 67;
+
+	pub const fn rgb(r: u8, g: u8, b: u8) -> Self {
+		Self::rgba(r, g, b, 255)
+	}
+
+	pub const fn rgba(r: u8, g: u8, b: u8, a: u8) -> Self {
+		Self { r, g, b, a }
+	}
+}
+
+/// A rectangle of pixels.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Rect {
+	pub x: u32,
+	pub y: u32,
+	pub width: u32,
+	pub height: u32,
+}
+
+impl Rect {
+	pub const fn new(x: u32, y: u32, width: u32, height: u32) -> Self {
+		Self {
+			x,
+			y,
+			width,
+			height,
+		}
+	}
+
+	/// Whether the rect has no pixels.
+	pub fn is_empty(&self) -> bool {
+		self.width == 0 || self.height == 0
+	}
+
+	/// Return the part of this rect that overlaps with another rect, if any.
+	pub fn intersect(&self, other: &Self) -> Option<Self> {
+		let (x, y) = (self.x.max(other.x), self.y.max(other.y));
+		let x1 = self.x.saturating_add(self.width);
+		let y1 = self.y.saturating_add(self.height);
+		let x1 = x1.min(other.x.saturating_add(other.width));
+		let y1 = y1.min(other.y.saturating_add(other.height));
+		let r = Self::new(x, y, x1.saturating_sub(x), y1.saturating_sub(y));
+		(!r.is_empty()).then(|| r)
+	}
+
+	/// Pack the rect in the offset of a flush request, i.e. x, y, width & height with 16 bits
+	/// each. Values that don't fit are saturated.
+	pub fn pack(&self) -> u64 {
+		[self.x, self.y, self.width, self.height]
+			.iter()
+			.rev()
+			.fold(0, |o, v| o << 16 | u64::from((*v).min(0xffff)))
+	}
+
+	/// Unpack a rect from the offset of a flush request.
+	pub fn unpack(offset: u64) -> Self {
+		let f = |shift: u32| u32::from((offset >> shift) as u16);
+		Self::new(f(0), f(16), f(32), f(48))
+	}
+}
+
+/// An entry in the metadata returned when opening the metadata of the GPU driver. There is one
+/// entry per scanout, including disabled ones.
+#[derive(Clone, Copy, Debug, Default)]
+#[repr(C)]
+pub struct ScanoutInfo {
+	/// The scanout ID, which is used as the `id` of flush & screenshot requests.
+	pub id: u32,
+	/// Whether a display is connected to the scanout. The other fields are 0 if not.
+	pub enabled: u32,
+	pub width: u32,
+	pub height: u32,
+	/// The amount of bytes between the start of each row.
+	pub stride: u32,
+	/// The virtio-gpu format of the pixels.
+	pub format: u32,
+}
+
+/// Metadata at the start of a screenshot. The pixels start at the offset given in the reply.
+#[derive(Clone, Copy, Debug, Default)]
+#[repr(C)]
+pub struct ScreenshotInfo {
+	pub width: u32,
+	pub height: u32,
+	/// The amount of bytes between the start of each row.
+	pub stride: u32,
+	/// The virtio-gpu format of the pixels.
+	pub format: u32,
+}
+
+/// The format of a scanout isn't supported by [`FrameBuffer`].
+#[derive(Debug)]
+pub enum LayoutError {
+	/// The pixels aren't in the layout of [`Pixel`].
+	Format,
+	/// The stride is not a multiple of the pixel size or is less than a row.
+	Stride,
+	/// The buffer is too small for the given size.
+	TooSmall,
+}
+
+/// A coordinate is outside the frame buffer.
+#[derive(Debug)]
+pub struct OutOfBounds;
+
+/// A draw buffer with bounds checked access.
+pub struct FrameBuffer<'a> {
+	pixels: &'a mut [Pixel],
+	width: usize,
+	height: usize,
+	/// The amount of pixels between the start of each row.
+	stride: usize,
+}
+
+impl<'a> FrameBuffer<'a> {
+	/// Wrap the draw buffer of a scanout.
+	///
+	/// # Safety
+	///
+	/// `ptr` must point to at least `info.stride * info.height` bytes that are not accessed by
+	/// anything else but the device for the lifetime of the frame buffer.
+	pub unsafe fn new(ptr: NonNull<Pixel>, info: &ScanoutInfo) -> Result<Self, LayoutError> {
+		if info.format != Pixel::FORMAT {
+			return Err(LayoutError::Format);
+		}
+		let stride = usize::try_from(info.stride).unwrap();
+		if stride % mem::size_of::<Pixel>() != 0 {
+			return Err(LayoutError::Stride);
+		}
+		let stride = stride / mem::size_of::<Pixel>();
+		let width = usize::try_from(info.width).unwrap();
+		let height = usize::try_from(info.height).unwrap();
+		let pixels = slice::from_raw_parts_mut(ptr.as_ptr(), stride * height);
+		Self::from_slice(pixels, width, height, stride)
+	}
+
+	/// Wrap a slice of pixels. `stride` is the amount of pixels between the start of each row.
+	pub fn from_slice(
+		pixels: &'a mut [Pixel],
+		width: usize,
+		height: usize,
+		stride: usize,
+	) -> Result<Self, LayoutError> {
+		if stride < width {
+			return Err(LayoutError::Stride);
+		}
+		if stride
+			.checked_mul(height)
+			.map_or(true, |l| l > pixels.len())
+		{
+			return Err(LayoutError::TooSmall);
+		}
+		Ok(Self {
+			pixels,
+			width,
+			height,
+			stride,
+		})
+	}
+
+	pub fn width(&self) -> usize {
+		self.width
+	}
+
+	pub fn height(&self) -> usize {
+		self.height
+	}
+
+	/// Return the rect covering the whole frame buffer.
+	pub fn rect(&self) -> Rect {
+		let f = |v| u32::try_from(v).unwrap_or(u32::MAX);
+		Rect::new(0, 0, f(self.width), f(self.height))
+	}
+
+	/// Return a pixel.
+	pub fn get(&self, x: usize, y: usize) -> Option<Pixel> {
+		self.index(x, y).map(|i| self.pixels[i])
+	}
+
+	/// Set a pixel.
+	pub fn set(&mut self, x: usize, y: usize, pixel: Pixel) -> Result<(), OutOfBounds> {
+		let i = self.index(x, y).ok_or(OutOfBounds)?;
+		self.pixels[i] = pixel;
+		Ok(())
+	}
+
+	/// Return a row of pixels.
+	pub fn row(&mut self, y: usize) -> Option<&mut [Pixel]> {
+		let start = self.index(0, y)?;
+		Some(&mut self.pixels[start..start + self.width])
+	}
+
+	/// Fill a rect with a single color. The rect is clipped to the frame buffer.
+	pub fn fill_rect(&mut self, rect: Rect, pixel: Pixel) {
+		if let Some(r) = rect.intersect(&self.rect()) {
+			let (x, w) = (r.x as usize, r.width as usize);
+			for y in r.y as usize..(r.y + r.height) as usize {
+				self.row(y).unwrap()[x..x + w].fill(pixel);
+			}
+		}
+	}
+
+	/// Copy pixels to the given position. `src` has rows of `src_width` pixels. The pixels that
+	/// fall outside the frame buffer are skipped.
+	pub fn blit(&mut self, x: usize, y: usize, src: &[Pixel], src_width: usize) {
+		if src_width == 0 || x >= self.width {
+			return;
+		}
+		let w = src_width.min(self.width - x);
+		for (sy, row) in src.chunks_exact(src_width).enumerate() {
+			match y.checked_add(sy).and_then(|y| self.row(y)) {
+				Some(dst) => dst[x..x + w].copy_from_slice(&row[..w]),
+				None => break,
+			}
+		}
+	}
+
+	fn index(&self, x: usize, y: usize) -> Option<usize> {
+		(x < self.width && y < self.height).then(|| x + y * self.stride)
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	const BLACK: Pixel = Pixel::rgb(0, 0, 0);
+	const WHITE: Pixel = Pixel::rgb(255, 255, 255);
+
+	#[test]
+	fn layout() {
+		let p = Pixel::rgba(1, 2, 3, 4);
+		let bytes: [u8; 4] = unsafe { mem::transmute(p) };
+		assert_eq!(bytes, [1, 2, 3, 4]);
+		assert_eq!(mem::size_of::<ScanoutInfo>(), 24);
+		assert_eq!(mem::size_of::<ScreenshotInfo>(), 16);
+	}
+
+	#[test]
+	fn pack() {
+		let r = Rect::new(1, 2, 3, 4);
+		assert_eq!(r.pack(), 1 | 2 << 16 | 3 << 32 | 4 << 48);
+		assert_eq!(Rect::unpack(r.pack()), r);
+		assert_eq!(
+			Rect::new(0x1_0000, 0, 1, 1).pack(),
+			0xffff | 1 << 32 | 1 << 48
+		);
+	}
+
+	#[test]
+	fn intersect() {
+		let a = Rect::new(0, 0, 10, 10);
+		assert_eq!(
+			a.intersect(&Rect::new(5, 8, 10, 10)),
+			Some(Rect::new(5, 8, 5, 2))
+		);
+		assert_eq!(a.intersect(&Rect::new(10, 0, 1, 1)), None);
+		assert_eq!(
+			a.intersect(&Rect::new(2, 2, u32::MAX, u32::MAX)),
+			Some(Rect::new(2, 2, 8, 8))
+		);
+	}
+
+	#[test]
+	fn bounds() {
+		let mut pixels = [BLACK; 4 * 3];
+		let mut fb = FrameBuffer::from_slice(&mut pixels, 3, 3, 4).unwrap();
+		assert!(fb.set(2, 2, WHITE).is_ok());
+		// This is inside the slice because of the stride, but not inside the frame buffer.
+		assert!(fb.set(3, 0, WHITE).is_err());
+		assert!(fb.set(0, 3, WHITE).is_err());
+		assert_eq!(fb.get(2, 2), Some(WHITE));
+		assert_eq!(fb.get(3, 0), None);
+		assert_eq!(pixels[3], BLACK);
+		assert_eq!(pixels[2 + 2 * 4], WHITE);
+
+		assert!(FrameBuffer::from_slice(&mut pixels, 4, 4, 4).is_err());
+		assert!(FrameBuffer::from_slice(&mut pixels, 5, 1, 4).is_err());
+	}
+
+	#[test]
+	fn fill_rect() {
+		let mut pixels = [BLACK; 4 * 4];
+		let mut fb = FrameBuffer::from_slice(&mut pixels, 4, 4, 4).unwrap();
+		fb.fill_rect(Rect::new(2, 3, 10, 10), WHITE);
+		let white = pixels.iter().enumerate().filter(|(_, p)| **p == WHITE);
+		assert_eq!(white.map(|(i, _)| i).collect::<Vec<_>>(), [14, 15]);
+	}
+
+	#[test]
+	fn blit() {
+		let mut pixels = [BLACK; 4 * 4];
+		let mut fb = FrameBuffer::from_slice(&mut pixels, 4, 4, 4).unwrap();
+		let src = [WHITE; 3 * 2];
+		fb.blit(2, 3, &src, 3);
+		fb.blit(4, 0, &src, 3);
+		let white = pixels.iter().enumerate().filter(|(_, p)| **p == WHITE);
+		assert_eq!(white.map(|(i, _)| i).collect::<Vec<_>>(), [14, 15]);
+	}
+
+	#[test]
+	fn from_scanout() {
+		let mut pixels = [BLACK; 4 * 2];
+		let mut info = ScanoutInfo {
+			id: 0,
+			enabled: 1,
+			width: 3,
+			height: 2,
+			stride: 16,
+			format: Pixel::FORMAT,
+		};
+		let ptr = NonNull::new(pixels.as_mut_ptr()).unwrap();
+		let fb = unsafe { FrameBuffer::new(ptr, &info) }.unwrap();
+		assert_eq!((fb.width(), fb.height()), (3, 2));
+		info.stride = 10;
+		assert!(matches!(
+			unsafe { FrameBuffer::new(ptr, &info) },
+			Err(LayoutError::Stride)
+		));
+		info.stride = 16;
+		info.format = 1;
+		assert!(matches!(
+			unsafe { FrameBuffer::new(ptr, &info) },
+			Err(LayoutError::Format)
+		));
+	}
+}
@@ -1,4 +1,47 @@
+use core::convert::TryFrom;
+
 pub mod list;
+pub(crate) mod ring;
 
 // Re-export the transmit & receive functions in the "right" module.
 pub use crate::mem::ipc::*;
+
+/// The opcode of the packets the input driver sends to the console before handing a burst of
+/// text, e.g. a paste, to a reader. The offset is the amount of bytes in the burst, so the
+/// console can draw the echo of all of it before flushing.
+pub const OP_TEXT_BURST: u8 = 131;
+
+/// Release a packet that is a death notification or a reply the kernel returned because the
+/// client died before it could be delivered. The pages of such a reply are still ours and
+/// nobody else will free them. Returns `false` if the packet is neither, in which case it is
+/// left alone.
+pub fn release_dead(packet: &kernel::ipc::Packet) -> bool {
+	let dead = crate::task::dead_peer(packet).is_some();
+	if dead {
+		release(packet);
+	}
+	dead
+}
+
+/// Acknowledge `SUSPEND` packets of the kernel on behalf of tasks that have no device state to
+/// save. Returns whether the packet was a `SUSPEND` or `RESUME` packet of the kernel, which
+/// such tasks don't need to handle any further.
+pub fn acknowledge_power(packet: &kernel::ipc::Packet) -> bool {
+	use kernel::ipc::Op;
+	if packet.address != kernel::ipc::KERNEL_ADDRESS {
+		return false;
+	}
+	match packet.opcode.map(Op::try_from) {
+		Some(Ok(Op::Suspend)) => {
+			*transmit() = kernel::ipc::Packet {
+				opcode: packet.opcode,
+				id: packet.id,
+				address: kernel::ipc::KERNEL_ADDRESS,
+				..Default::default()
+			};
+			true
+		}
+		Some(Ok(Op::Resume)) => true,
+		_ => false,
+	}
+}
@@ -0,0 +1,147 @@
+//! # Transmit & received ring buffers
+//!
+//! Both rings have a single producer and a single consumer. The producer owns the entries
+//! between the consumer's position and the index; the consumer owns the entries before it.
+//!
+//! The producer writes the packet & the entry first and only then stores the incremented index
+//! with `Release`. The consumer loads the index with `Acquire` before reading the entry &
+//! packet, so it is guaranteed to see everything written before the index was stored.
+//!
+//! The producer never overruns the consumer as there are as many entries as there are slots.
+
+use core::cell::Cell;
+use core::sync::atomic::{AtomicU16, Ordering};
+
+/// A view of a ring buffer shared with the kernel.
+pub(crate) struct Ring<'a> {
+	/// The index the producer will write the next entry to.
+	pub index: &'a AtomicU16,
+	/// The slots of the packets in the ring.
+	pub entries: &'a [Cell<u16>],
+	/// The amount of entries minus one.
+	pub mask: u16,
+}
+
+impl Ring<'_> {
+	/// Add a slot to the ring and make it visible to the consumer.
+	///
+	/// The packet in the slot must be fully written out before calling this. The caller must
+	/// be the only producer, i.e. hold the lock on the ring.
+	pub fn push(&self, slot: u16) {
+		// Only we modify the index, so there is nothing to synchronize with.
+		let i = self.index.load(Ordering::Relaxed);
+		self.entries[usize::from(i & self.mask)].set(slot);
+		self.index.store(i.wrapping_add(1), Ordering::Release);
+	}
+
+	/// Return the slot at position `last` if the producer has already pushed it.
+	pub fn peek(&self, last: u16) -> Option<u16> {
+		let i = self.index.load(Ordering::Acquire);
+		(i != last).then(|| self.entries[usize::from(last & self.mask)].get())
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+	use std::sync::Arc;
+	use std::thread;
+
+	const LEN: usize = 8;
+	const ROUNDS: u64 = 20_000;
+
+	/// A packet with enough fields to detect torn or stale reads.
+	type Packet = [Cell<u64>; 4];
+
+	/// Memory shared between two tasks. The rings are only accessed according to the protocol
+	/// above, like the memory shared with the kernel.
+	#[derive(Default)]
+	struct Shared {
+		packets: [Packet; LEN],
+		ping_index: AtomicU16,
+		ping: [Cell<u16>; LEN],
+		pong_index: AtomicU16,
+		pong: [Cell<u16>; LEN],
+	}
+
+	unsafe impl Sync for Shared {}
+
+	impl Shared {
+		fn ping(&self) -> Ring<'_> {
+			Ring {
+				index: &self.ping_index,
+				entries: &self.ping,
+				mask: LEN as u16 - 1,
+			}
+		}
+
+		fn pong(&self) -> Ring<'_> {
+			Ring {
+				index: &self.pong_index,
+				entries: &self.pong,
+				mask: LEN as u16 - 1,
+			}
+		}
+
+		fn write(&self, slot: u16, value: u64) {
+			for (i, f) in self.packets[usize::from(slot)].iter().enumerate() {
+				f.set(value.wrapping_mul(i as u64 + 1));
+			}
+		}
+
+		fn read(&self, slot: u16) -> u64 {
+			let p = &self.packets[usize::from(slot)];
+			let value = p[0].get();
+			for (i, f) in p.iter().enumerate() {
+				assert_eq!(f.get(), value.wrapping_mul(i as u64 + 1), "torn packet");
+			}
+			value
+		}
+	}
+
+	/// Receive the next slot, spinning until there is one.
+	fn receive(ring: &Ring, last: &mut u16) -> u16 {
+		loop {
+			if let Some(slot) = ring.peek(*last) {
+				*last = last.wrapping_add(1);
+				return slot;
+			}
+			thread::yield_now();
+		}
+	}
+
+	#[test]
+	fn ping_pong() {
+		let shared = Arc::<Shared>::default();
+
+		let s = shared.clone();
+		let echo = thread::spawn(move || {
+			let mut last = 0;
+			for _ in 0..ROUNDS {
+				let slot = receive(&s.ping(), &mut last);
+				s.write(slot, !s.read(slot));
+				s.pong().push(slot);
+			}
+		});
+
+		// Keep all slots in flight so the index wraps around often.
+		for slot in 0..LEN as u16 {
+			shared.write(slot, u64::from(slot));
+			shared.ping().push(slot);
+		}
+		let mut last = 0;
+		let mut expected = 0;
+		for value in LEN as u64..ROUNDS + LEN as u64 {
+			let slot = receive(&shared.pong(), &mut last);
+			// Packets are returned in the order they were sent.
+			assert_eq!(shared.read(slot), !expected, "stale or reordered packet");
+			expected += 1;
+			if value < ROUNDS {
+				shared.write(slot, value);
+				shared.ping().push(slot);
+			}
+		}
+		echo.join().unwrap();
+		assert_eq!(expected, ROUNDS);
+	}
+}
@@ -2,17 +2,24 @@
 //!
 //! This library defines common types used in the Dux operating system.
 
-#![no_std]
+#![cfg_attr(not(test), no_std)]
+#![feature(allocator_api)]
+#![feature(const_fn_fn_ptr_basics)]
 #![feature(const_option)]
 #![feature(const_ptr_is_null)]
 #![feature(const_ptr_offset)]
 #![feature(const_raw_ptr_deref)]
 #![feature(global_asm)]
 
+pub mod alloc;
+pub mod gfx;
 pub mod ipc;
 pub mod mem;
+pub mod notification;
 pub mod page;
 pub mod task;
+pub mod thread;
+pub mod time;
 
 mod util;
 
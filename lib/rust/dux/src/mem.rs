@@ -325,6 +325,10 @@ pub fn allocate_range(
 	let ret = unsafe { kernel::mem_alloc(address.as_ptr(), count, flags.into()) };
 	match ret.status {
 		kernel::Return::OK => Ok(address),
+		kernel::Return::MEMORY_UNAVAILABLE => {
+			unreserve_range(address, count).expect("failed to unreserve range");
+			Err(ReserveError::NoMemory)
+		}
 		r => unreachable!("{}", r),
 	}
 }
@@ -355,6 +359,7 @@ pub unsafe fn deallocate_range(address: Page, count: usize) {
 pub(crate) mod ipc {
 
 	use super::*;
+	use crate::ipc::ring::Ring;
 
 	/// Error returned when no free slots are available in a queue.
 	#[derive(Debug)]
@@ -368,6 +373,7 @@ pub(crate) mod ipc {
 		loop {
 			match pop_free_slot() {
 				Ok(slot) => return TransmitLock { slot },
+				// io_wait may return before the kernel freed any slots, so try again.
 				Err(NoFreeSlots) => unsafe {
 					let _ = kernel::io_wait(u64::MAX);
 				},
@@ -416,11 +422,8 @@ pub(crate) mod ipc {
 
 	impl Drop for TransmitLock {
 		fn drop(&mut self) {
-			let (index, entries) = unsafe { transmit_ring() };
-			let mask = GLOBAL.part.ring_mask.get();
-
-			entries[usize::from(*index & mask)] = self.slot;
-			*index = index.wrapping_add(1);
+			// The packet is written out, so hand it to the kernel.
+			unsafe { transmit_ring() }.push(self.slot);
 
 			unsafe { util::SpinLockGuard::from_raw(&GLOBAL.part.transmit_lock, false) };
 		}
@@ -431,15 +434,13 @@ pub(crate) mod ipc {
 	/// This will yield the task if no packets have been received yet.
 	pub fn receive() -> ReceivedLock {
 		let _ = util::SpinLockGuard::new(&GLOBAL.part.received_lock, true).into_raw();
-		let mask = GLOBAL.part.ring_mask.get();
 		loop {
-			let (index, entries) = unsafe { received_ring() };
 			let i = GLOBAL.part.last_received_index.get();
-			if i != index {
-				return ReceivedLock {
-					slot: entries[usize::from(i & mask)].get(),
-				};
+			if let Some(slot) = unsafe { received_ring() }.peek(i) {
+				return ReceivedLock { slot };
 			}
+			// io_wait may return without a packet having arrived, e.g. because of a
+			// notification or a timeout, so check the ring again.
 			unsafe { kernel::io_wait(u64::MAX) };
 		}
 	}
@@ -448,14 +449,10 @@ pub(crate) mod ipc {
 	pub fn try_receive() -> Option<ReceivedLock> {
 		let guard = util::SpinLockGuard::new(&GLOBAL.part.received_lock, true);
 
-		let (index, entries) = unsafe { received_ring() };
-		let mask = GLOBAL.part.ring_mask.get();
 		let i = GLOBAL.part.last_received_index.get();
-		(i != index).then(|| {
+		unsafe { received_ring() }.peek(i).map(|slot| {
 			let _ = guard.into_raw();
-			ReceivedLock {
-				slot: entries[usize::from(i & mask)].get(),
-			}
+			ReceivedLock { slot }
 		})
 	}
 
@@ -478,11 +475,14 @@ pub(crate) mod ipc {
 		/// Release the lock but don't discard the packet. Instead, swap the packet with the last
 		/// available entry in the ring buffer.
 		pub fn defer(self) {
-			let (index, entries) = unsafe { received_ring() };
+			let Ring {
+				index,
+				entries,
+				mask,
+			} = unsafe { received_ring() };
 			let last_index = GLOBAL.part.last_received_index.get();
-			let mask = GLOBAL.part.ring_mask.get();
 
-			let prev_index = index.wrapping_sub(1);
+			let prev_index = index.load(Ordering::Acquire).wrapping_sub(1);
 			let a = entries[usize::from(last_index & mask)].get();
 			let b = entries[usize::from(prev_index & mask)].get();
 			debug_assert_eq!(a, self.slot, "current received entry mutated while locked");
@@ -536,6 +536,22 @@ pub(crate) mod ipc {
 		})
 	}
 
+	/// Unmap the data & name pages of a received packet and give their address range back to
+	/// the kernel, which maps the pages of new packets in it.
+	///
+	/// Packets that were returned because the receiver died carry the pages they were sent
+	/// with. Only release these if those pages came from a received packet as well.
+	pub fn release(packet: &kernel::ipc::Packet) {
+		let data = packet.data.map(|d| (d, packet.length));
+		let name = packet.name.map(|n| (n, usize::from(packet.name_len)));
+		for (ptr, length) in data.into_iter().chain(name) {
+			let count = Page::min_pages_for_range(length);
+			let ret = unsafe { kernel::mem_dealloc(ptr.as_ptr(), count) };
+			assert_eq!(ret.status, 0, "failed to unmap packet pages");
+			add_free_range(Page::new(ptr).unwrap(), count).unwrap();
+		}
+	}
+
 	/// Return the IPC packet at a given slot.
 	///
 	/// # Safety
@@ -548,32 +564,33 @@ pub(crate) mod ipc {
 			.then(|| &mut *GLOBAL.part.ipc_packets.get().add(usize::from(index)))
 	}
 
-	/// Return the transmit index & buffer.
+	/// Return the transmit ring.
 	///
 	/// # Safety
 	///
-	/// There may be no other references to this buffer.
-	///
-	/// The ring may not be resized while there is a reference to the slice.
+	/// The ring may not be resized while there is a reference to it.
 	///
-	/// The ring must be locked during this call.
-	unsafe fn transmit_ring<'a>() -> (&'a mut u16, &'a mut [u16]) {
+	/// The ring must be locked while it is in use.
+	unsafe fn transmit_ring<'a>() -> Ring<'a> {
 		let len = usize::from(ring_len());
 		// Skip table
-		let addr = GLOBAL.part.ipc_packets.get().add(len).cast::<u16>();
-		let index = &mut *addr;
-		let slice = slice::from_raw_parts_mut(addr.cast::<u16>().add(1), len);
-		(index, slice)
+		// Use an AtomicU16 as the kernel may read it from another thread.
+		let addr = GLOBAL.part.ipc_packets.get().add(len).cast::<AtomicU16>();
+		Ring {
+			index: &*addr,
+			entries: slice::from_raw_parts(addr.cast::<Cell<u16>>().add(1), len),
+			mask: GLOBAL.part.ring_mask.get(),
+		}
 	}
 
-	/// Return the received index & buffer.
+	/// Return the received ring.
 	///
 	/// # Safety
 	///
-	/// The ring may not be resized while there is a reference to the slice.
+	/// The ring may not be resized while there is a reference to it.
 	///
-	/// The ring must be locked during this call.
-	unsafe fn received_ring<'a>() -> (u16, &'a [Cell<u16>]) {
+	/// The ring must be locked while it is in use.
+	unsafe fn received_ring<'a>() -> Ring<'a> {
 		let len = usize::from(ring_len());
 		// Skip table + transmit ring
 		// Use an AtomicU16 as the kernel may write to it from another thread.
@@ -584,9 +601,11 @@ pub(crate) mod ipc {
 			.add(len)
 			.cast::<AtomicU16>()
 			.add(1 + len);
-		let index = (&*addr).load(Ordering::Acquire);
-		let slice = slice::from_raw_parts(addr.cast::<Cell<u16>>().add(1), len);
-		(index, slice)
+		Ring {
+			index: &*addr,
+			entries: slice::from_raw_parts(addr.cast::<Cell<u16>>().add(1), len),
+			mask: GLOBAL.part.ring_mask.get(),
+		}
 	}
 
 	/// Try to get an unused slot from the free stack.
@@ -0,0 +1,175 @@
+//! # Notification handlers
+//!
+//! A notification interrupts the task and runs the handler for its type on the stack of the
+//! task. Each handler has its own state, which is passed by reference to the handler function
+//! so that no `static mut` is needed.
+//!
+//! Since a handler can interrupt the main routine at any point, the state must be [`Sync`],
+//! i.e. any data shared with the main routine needs atomics or some other form of
+//! synchronization.
+
+use crate::task::Address;
+pub use kernel::notification::Entry;
+
+/// The type of a notification. Each type has its own handler.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Type {
+	/// An external interrupt. The value is the interrupt source.
+	Interrupt,
+	/// A notification sent by another task.
+	Ipc,
+	/// A timer expired.
+	Timer,
+}
+
+impl From<Type> for usize {
+	fn from(typ: Type) -> Self {
+		match typ {
+			Type::Interrupt => kernel::notification::TYPE_INTERRUPT,
+			Type::Ipc => kernel::notification::TYPE_IPC,
+			Type::Timer => kernel::notification::TYPE_TIMER,
+		}
+	}
+}
+
+/// A notification as received by a handler.
+#[derive(Clone, Copy, Debug)]
+pub struct Notification {
+	/// The type of the notification, as passed by the kernel.
+	pub typ: usize,
+	/// A value whose meaning depends on the type.
+	pub value: usize,
+	/// The task that sent the notification. This is [`Address::KERNEL`] for interrupts.
+	pub address: Address,
+}
+
+/// Type-erased function that calls the handler function with the handler's state.
+type Call = unsafe fn(*const (), Notification) -> usize;
+
+/// A notification handler and its state.
+///
+/// The handler function returns the address of the task to defer the notification to, if any.
+#[repr(C)]
+pub struct Handler<T: 'static> {
+	/// This must be the first field as it is used to call the handler via a pointer to it.
+	call: Call,
+	function: fn(&'static T, Notification) -> Option<Address>,
+	state: T,
+}
+
+impl<T: 'static> Handler<T> {
+	/// Create a new handler with the given state.
+	pub const fn new(function: fn(&'static T, Notification) -> Option<Address>, state: T) -> Self {
+		Self {
+			call: Self::call,
+			function,
+			state,
+		}
+	}
+
+	/// Return the state of the handler.
+	pub fn state(&self) -> &T {
+		&self.state
+	}
+
+	/// # Safety
+	///
+	/// `slf` must point to a `Handler<T>` with a `'static` lifetime.
+	unsafe fn call(slf: *const (), notification: Notification) -> usize {
+		let slf = &*slf.cast::<Self>();
+		(slf.function)(&slf.state, notification).map_or(usize::MAX, usize::from)
+	}
+}
+
+/// Set the handler for the given type of notification. Returns the previous handler, which
+/// can be reinstalled with [`set_raw_handler`].
+pub fn set_handler<T: Sync>(typ: Type, handler: &'static Handler<T>) -> Entry {
+	extern "C" {
+		fn dux_notification_entry();
+	}
+	let entry = Entry {
+		function: dux_notification_entry as unsafe extern "C" fn() as usize,
+		context: handler as *const _ as usize,
+	};
+	// SAFETY: the entry points to a valid, 'static handler.
+	unsafe { set_raw_handler(typ, entry) }
+}
+
+/// Set the handler for the given type of notification as is. Returns the previous handler.
+///
+/// # Safety
+///
+/// The function must be a valid notification handler that is able to deal with the given
+/// context.
+pub unsafe fn set_raw_handler(typ: Type, entry: Entry) -> Entry {
+	let mut prev = Entry::default();
+	let ret = kernel::io_set_notify_handler(entry.function, entry.context, typ.into(), &mut prev);
+	assert_eq!(ret.status, kernel::Return::OK, "invalid notification type");
+	prev
+}
+
+#[export_name = "dux_notification_dispatch"]
+extern "C" fn dispatch(typ: usize, value: usize, context: *const (), address: usize) -> usize {
+	// SAFETY: the context was set by set_handler and hence points to a Handler, which starts
+	// with a Call.
+	unsafe {
+		let call = context.cast::<Call>().read();
+		call(
+			context,
+			Notification {
+				typ,
+				value,
+				address: Address::from(address),
+			},
+		)
+	}
+}
+
+#[cfg(target_arch = "riscv64")]
+global_asm!(
+	"
+	.globl	dux_notification_entry
+	# a0: type
+	# a1: value
+	# a2: context
+	# a7: address
+	#
+	# The original a[0127] & pc are stored below the stack pointer by the kernel. The remaining
+	# caller-saved registers are saved here. One slot is used as padding to keep the stack
+	# aligned.
+	.equ	GP_REGBYTES, 8
+	.equ	NOTIFY_RETURN, 9
+dux_notification_entry:
+	addi	sp, sp, -(13 + 5) * GP_REGBYTES
+	sd		t0, 0 * GP_REGBYTES (sp)
+	sd		t1, 1 * GP_REGBYTES (sp)
+	sd		t2, 2 * GP_REGBYTES (sp)
+	sd		t3, 3 * GP_REGBYTES (sp)
+	sd		t4, 4 * GP_REGBYTES (sp)
+	sd		t5, 5 * GP_REGBYTES (sp)
+	sd		t6, 6 * GP_REGBYTES (sp)
+	sd		a3, 7 * GP_REGBYTES (sp)
+	sd		a4, 8 * GP_REGBYTES (sp)
+	sd		a5, 9 * GP_REGBYTES (sp)
+	sd		a6, 10 * GP_REGBYTES (sp)
+	sd		ra, 11 * GP_REGBYTES (sp)
+	mv		a3, a7
+	call	dux_notification_dispatch
+	ld		t0, 0 * GP_REGBYTES (sp)
+	ld		t1, 1 * GP_REGBYTES (sp)
+	ld		t2, 2 * GP_REGBYTES (sp)
+	ld		t3, 3 * GP_REGBYTES (sp)
+	ld		t4, 4 * GP_REGBYTES (sp)
+	ld		t5, 5 * GP_REGBYTES (sp)
+	ld		t6, 6 * GP_REGBYTES (sp)
+	ld		a3, 7 * GP_REGBYTES (sp)
+	ld		a4, 8 * GP_REGBYTES (sp)
+	ld		a5, 9 * GP_REGBYTES (sp)
+	ld		a6, 10 * GP_REGBYTES (sp)
+	ld		ra, 11 * GP_REGBYTES (sp)
+	addi	sp, sp, (13 + 5) * GP_REGBYTES
+	# a0 holds the address to defer to or -1
+	li		a7, NOTIFY_RETURN
+	ecall
+"
+);
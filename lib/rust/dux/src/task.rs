@@ -2,7 +2,7 @@
 
 use crate::mem;
 use crate::{Page, RWX};
-use core::convert::TryInto;
+use core::convert::{TryFrom, TryInto};
 use core::fmt;
 use core::slice;
 
@@ -59,226 +59,497 @@ impl From<Address> for usize {
 	}
 }
 
+/// The maximum amount of loadable segments an ELF file may have.
+const MAX_SEGMENTS: usize = 8;
+
+/// The maximum amount of pages that can be mapped into a new task.
+const MAX_MAPPINGS: usize = 96;
+
+/// The amount of pages allocated for the stack of a new task.
+const STACK_PAGES: usize = 16;
+
+/// The maximum amount of objects a new task can be given.
+const MAX_GRANTS: usize = 32;
+
+/// The end address of the stack of a new task. This address is exclusive.
+const STACK_TOP: usize = 0x8000_0000;
+
+/// Errors that can occur while validating an ELF file.
 #[derive(Debug)]
-pub enum SpawnElfError {
-	ReserveError(mem::ReserveError),
+pub enum ElfError {
+	/// The data could not be parsed as an ELF file.
+	Malformed(&'static str),
+	/// The ELF file is not a 64 bit ELF file.
+	UnsupportedClass,
+	/// A segment has a combination of RWX flags that cannot be mapped.
 	BadRWXFlags,
+	/// The offset and virtual address of a segment are not aligned to each other.
+	BadAlignment,
+	/// The file data of a segment lies outside the file.
+	SegmentOutOfBounds,
+	/// The file size of a segment is larger than its memory size.
+	SegmentTooLarge,
+	/// There are more loadable segments than can be handled.
+	TooManySegments,
+	/// The segments and stack need more mappings than can be passed to the kernel.
+	TooManyPages,
 }
 
-/// Create a new task from an ELF file.
-pub fn spawn_elf(
-	data: &[kernel::Page],
-	object_entries: &mut dyn ExactSizeIterator<Item = (Address, kernel::ipc::UUID)>,
-	arguments: &[&[u8]],
-) -> Result<Address, SpawnElfError> {
-	use xmas_elf::ElfFile;
+#[derive(Debug)]
+pub enum SpawnElfError {
+	/// The ELF file is invalid.
+	BadElf(ElfError),
+	/// There is not enough memory to load the segments and create the stack.
+	OutOfMemory { needed_pages: usize },
+	/// Two segments or a segment and the stack are mapped to the same address.
+	MappingConflict { address: usize },
+	/// More objects were given than the new task can have handles to.
+	TooManyObjects { count: usize },
+	/// The kernel returned an unexpected status.
+	KernelError(usize),
+}
 
-	// SAFETY: the data is guaranteed to be properly aligned and have the proper size
-	let data =
-		unsafe { core::slice::from_raw_parts(data.as_ptr().cast::<u8>(), data.len() * Page::SIZE) };
+/// A single loadable segment of a validated ELF file.
+#[derive(Clone, Copy, Debug)]
+pub struct Segment {
+	/// The page-aligned offset of the segment in the file.
+	pub offset: usize,
+	/// The offset of the segment data inside the first page.
+	pub page_offset: usize,
+	/// The page-aligned address the segment will be mapped to.
+	pub address: usize,
+	/// The amount of bytes of the segment that are present in the file.
+	pub file_size: usize,
+	/// The amount of pages that will be mapped.
+	pub pages: usize,
+	/// The RWX flags of the pages.
+	pub flags: RWX,
+	/// Whether the pages must be copied. If `false`, the pages are shared with the parent.
+	pub copy: bool,
+}
 
-	// This struct ensures no memory is leaked.
-	struct DropRanges([Option<(Page, usize)>; 8], usize);
+/// A validated ELF file that is ready to be spawned.
+///
+/// Creating a plan does not allocate any memory, so a bad ELF file can't leak any pages.
+pub struct SpawnPlan<'a> {
+	data: &'a [u8],
+	segments: [Option<Segment>; MAX_SEGMENTS],
+	segment_count: usize,
+	entry: usize,
+}
+
+impl<'a> SpawnPlan<'a> {
+	/// Validate an ELF file and determine which pages need to be allocated & mapped.
+	pub fn new(data: &'a [kernel::Page]) -> Result<Self, SpawnElfError> {
+		use xmas_elf::program::{ProgramHeader, Type};
+		use xmas_elf::ElfFile;
+
+		// SAFETY: the data is guaranteed to be properly aligned and have the proper size
+		let data = unsafe {
+			core::slice::from_raw_parts(data.as_ptr().cast::<u8>(), data.len() * Page::SIZE)
+		};
 
-	impl DropRanges {
-		fn push(&mut self, addr: Page, count: usize) {
-			self.0[self.1] = Some((addr, count));
-			self.1 += 1;
+		let bad = SpawnElfError::BadElf;
+
+		let elf = ElfFile::new(data).map_err(|e| bad(ElfError::Malformed(e)))?;
+
+		let mut segments = [None; MAX_SEGMENTS];
+		let mut segment_count = 0;
+
+		for ph in elf.program_iter() {
+			let ph = match ph {
+				ProgramHeader::Ph64(ph) => ph,
+				_ => return Err(bad(ElfError::UnsupportedClass)),
+			};
+			if ph.get_type() != Ok(Type::Load) {
+				continue;
+			}
+
+			let file_offset =
+				usize::try_from(ph.offset).map_err(|_| bad(ElfError::SegmentOutOfBounds))?;
+			let file_size =
+				usize::try_from(ph.file_size).map_err(|_| bad(ElfError::SegmentOutOfBounds))?;
+			let mem_size =
+				usize::try_from(ph.mem_size).map_err(|_| bad(ElfError::SegmentTooLarge))?;
+			let virt_a =
+				usize::try_from(ph.virtual_addr).map_err(|_| bad(ElfError::SegmentTooLarge))?;
+
+			if file_offset & Page::OFFSET_MASK != virt_a & Page::OFFSET_MASK {
+				return Err(bad(ElfError::BadAlignment));
+			}
+			match file_offset.checked_add(file_size) {
+				Some(end) if end <= data.len() => (),
+				_ => return Err(bad(ElfError::SegmentOutOfBounds)),
+			}
+			if file_size > mem_size {
+				return Err(bad(ElfError::SegmentTooLarge));
+			}
+
+			let page_offset = file_offset & Page::OFFSET_MASK;
+			let flags = ph.flags;
+			let flags = match (flags.is_read(), flags.is_write(), flags.is_execute()) {
+				(true, false, false) => RWX::R,
+				(false, true, false) => RWX::W,
+				(false, false, true) => RWX::X,
+				(true, true, false) => RWX::RW,
+				(true, false, true) => RWX::RX,
+				(true, true, true) => RWX::RWX,
+				_ => return Err(bad(ElfError::BadRWXFlags)),
+			};
+
+			// Writeable pages must be copied, read-only pages can be shared.
+			let copy = ph.flags.is_write();
+			let size = if copy { mem_size } else { file_size };
+			if size > MAX_MAPPINGS * Page::SIZE {
+				return Err(bad(ElfError::TooManyPages));
+			}
+			let pages = Page::min_pages_for_range(page_offset + size);
+			if virt_a.checked_add(pages * Page::SIZE).is_none() {
+				return Err(bad(ElfError::SegmentTooLarge));
+			}
+
+			let segment = Segment {
+				offset: file_offset & !Page::OFFSET_MASK,
+				page_offset,
+				address: virt_a & !Page::OFFSET_MASK,
+				file_size,
+				pages,
+				flags,
+				copy,
+			};
+
+			*segments
+				.get_mut(segment_count)
+				.ok_or(bad(ElfError::TooManySegments))? = Some(segment);
+			segment_count += 1;
 		}
-	}
 
-	impl Drop for DropRanges {
-		fn drop(&mut self) {
-			for (addr, count) in self.0[..self.1].iter().copied().map(Option::unwrap) {
-				// SAFETY: Nothing can (should?) be using these ranges anymore.
-				unsafe { mem::deallocate_range(addr, count) };
+		let plan = Self {
+			data,
+			segments,
+			segment_count,
+			entry: elf.header.pt2.entry_point() as usize,
+		};
+
+		if plan.mapping_count() > MAX_MAPPINGS {
+			return Err(bad(ElfError::TooManyPages));
+		}
+
+		// Check that no two ranges overlap.
+		{
+			let stack = (STACK_TOP - STACK_PAGES * Page::SIZE, STACK_PAGES);
+			let ranges = plan.segments().map(|s| (s.address, s.pages));
+			for (i, (a_start, a_pages)) in ranges.clone().chain(Some(stack)).enumerate() {
+				let a_end = a_start + a_pages * Page::SIZE;
+				for (b_start, b_pages) in ranges.clone().chain(Some(stack)).skip(i + 1) {
+					let b_end = b_start + b_pages * Page::SIZE;
+					if a_start < b_end && b_start < a_end {
+						let address = a_start.max(b_start);
+						return Err(SpawnElfError::MappingConflict { address });
+					}
+				}
 			}
 		}
+
+		Ok(plan)
 	}
 
-	// SAFETY: all zeroes TaskSpawnMapping is valid.
-	let mut mappings =
-		unsafe { core::mem::MaybeUninit::<[kernel::TaskSpawnMapping; 96]>::zeroed().assume_init() };
-	let mut i = 0;
-
-	let mut reserved_ranges = DropRanges([None; 8], 0);
-
-	let elf = ElfFile::new(data).unwrap();
-	for ph in elf
-		.program_iter()
-		.filter(|ph| ph.get_type() == Ok(xmas_elf::program::Type::Load))
-	{
-		let mut offset = ph.offset() as usize & !Page::OFFSET_MASK;
-		let page_offset = ph.offset() as usize & Page::OFFSET_MASK;
-		let mut virt_a = ph.virtual_addr() as usize & !Page::OFFSET_MASK;
-
-		let file_pages = ((ph.file_size() as usize + page_offset + Page::OFFSET_MASK)
-			& !Page::OFFSET_MASK)
-			/ Page::SIZE;
-		let mem_pages = ((ph.mem_size() as usize + page_offset + Page::OFFSET_MASK)
-			& !Page::OFFSET_MASK)
-			/ Page::SIZE;
-		let flags = ph.flags();
-		let flags = match (flags.is_read(), flags.is_write(), flags.is_execute()) {
-			(true, false, false) => RWX::R,
-			(false, true, false) => RWX::W,
-			(false, false, true) => RWX::X,
-			(true, true, false) => RWX::RW,
-			(true, false, true) => RWX::RX,
-			(true, true, true) => RWX::RWX,
-			_ => Err(SpawnElfError::BadRWXFlags)?,
-		};
+	/// Returns an iterator over all loadable segments.
+	pub fn segments(&self) -> impl Iterator<Item = &Segment> + Clone {
+		self.segments[..self.segment_count]
+			.iter()
+			.map(|s| s.as_ref().unwrap())
+	}
 
-		if ph.flags().is_write() {
-			// We must copy the pages as they may be written to.
-			let addr =
-				mem::allocate_range(None, mem_pages, flags).map_err(SpawnElfError::ReserveError)?;
+	/// The amount of pages that need to be allocated in the current task, including the stack.
+	pub fn pages_needed(&self) -> usize {
+		self.segments()
+			.filter(|s| s.copy)
+			.map(|s| s.pages)
+			.sum::<usize>()
+			+ STACK_PAGES
+	}
 
-			// FIXME ensure there is no garbage in the pages.
-			//
-			// This should be done by the kernel...
-			unsafe {
-				let to_zero = core::slice::from_raw_parts_mut(
-					addr.as_ptr().cast::<kernel::Page>(),
-					mem_pages,
-				);
-				to_zero.iter_mut().for_each(kernel::Page::zeroize);
+	/// The amount of pages that will be mapped into the new task, including the stack.
+	pub fn mapping_count(&self) -> usize {
+		self.segments().map(|s| s.pages).sum::<usize>() + STACK_PAGES
+	}
+
+	/// The entry point of the program.
+	pub fn entry(&self) -> usize {
+		self.entry
+	}
+
+	/// Allocate all memory needed for the new task and spawn it.
+	///
+	/// All memory is allocated at once before anything is mapped, so if allocation fails no
+	/// memory is leaked.
+	pub fn spawn(
+		&self,
+		object_entries: &mut dyn ExactSizeIterator<Item = (Address, kernel::ipc::UUID)>,
+		arguments: &[&[u8]],
+	) -> Result<Address, SpawnElfError> {
+		// This struct ensures no memory is leaked.
+		struct DropRange(Page, usize);
+
+		impl Drop for DropRange {
+			fn drop(&mut self) {
+				// SAFETY: Nothing can (should?) be using this range anymore.
+				unsafe { mem::deallocate_range(self.0, self.1) };
 			}
+		}
 
-			reserved_ranges.push(addr, mem_pages);
+		let needed_pages = self.pages_needed();
+		let base = mem::allocate_range(None, needed_pages, RWX::RW)
+			.map_err(|_| SpawnElfError::OutOfMemory { needed_pages })?;
+		let reserved_range = DropRange(base, needed_pages);
 
-			let copy = unsafe {
-				let addr = addr.as_ptr().cast::<u8>().add(page_offset);
-				slice::from_raw_parts_mut(addr, ph.file_size().try_into().unwrap())
-			};
+		// FIXME ensure there is no garbage in the pages.
+		//
+		// This should be done by the kernel...
+		unsafe {
+			let to_zero = core::slice::from_raw_parts_mut(base.as_ptr(), needed_pages);
+			to_zero.iter_mut().for_each(kernel::Page::zeroize);
+		}
 
-			let data = match ph {
-				xmas_elf::program::ProgramHeader::Ph64(ph) => ph.raw_data(&elf),
-				_ => unreachable!(),
-			};
-			copy.copy_from_slice(&data);
-			for k in 0..mem_pages {
-				let self_address = addr.as_ptr().wrapping_add(k);
-				mappings[i] = kernel::TaskSpawnMapping {
-					typ: 0,
-					flags: flags.into(),
-					task_address: virt_a as *mut _,
-					self_address,
+		// SAFETY: all zeroes TaskSpawnMapping is valid.
+		let mut mappings = unsafe {
+			core::mem::MaybeUninit::<[kernel::TaskSpawnMapping; MAX_MAPPINGS]>::zeroed()
+				.assume_init()
+		};
+		let mut i = 0;
+		let mut next_page = base.as_ptr();
+
+		for s in self.segments() {
+			let mut virt_a = s.address;
+			if s.copy {
+				// We must copy the pages as they may be written to.
+				let start = s.offset + s.page_offset;
+				let copy = unsafe {
+					let addr = next_page.cast::<u8>().add(s.page_offset);
+					slice::from_raw_parts_mut(addr, s.file_size)
 				};
-				i += 1;
-				offset += Page::SIZE;
-				virt_a += Page::SIZE;
+				copy.copy_from_slice(&self.data[start..start + s.file_size]);
+				for k in 0..s.pages {
+					mappings[i] = kernel::TaskSpawnMapping {
+						typ: 0,
+						flags: s.flags.into(),
+						task_address: virt_a as *mut _,
+						self_address: next_page.wrapping_add(k),
+					};
+					i += 1;
+					virt_a += Page::SIZE;
+				}
+				next_page = next_page.wrapping_add(s.pages);
+			} else {
+				// It is safe to share the pages
+				let mut offset = s.offset;
+				for _ in 0..s.pages {
+					let self_address = self.data.as_ptr().wrapping_add(offset) as *mut _;
+					mappings[i] = kernel::TaskSpawnMapping {
+						typ: 0,
+						flags: s.flags.into(),
+						task_address: virt_a as *mut _,
+						self_address,
+					};
+					i += 1;
+					offset += Page::SIZE;
+					virt_a += Page::SIZE;
+				}
 			}
-		} else {
-			// It is safe to share the pages
-			for _ in 0..file_pages {
-				let self_address = data.as_ptr().wrapping_add(offset as usize) as *mut _;
+		}
+
+		// The objects are granted in order, so the new task refers to them with handles 0, 1, ...
+		if object_entries.len() > MAX_GRANTS {
+			return Err(SpawnElfError::TooManyObjects {
+				count: object_entries.len(),
+			});
+		}
+		let mut grants = [kernel::TaskSpawnGrant::default(); MAX_GRANTS];
+		let grants_count = object_entries.len();
+		for (w, (address, uuid)) in grants.iter_mut().zip(object_entries) {
+			*w = kernel::TaskSpawnGrant {
+				address: address.into(),
+				uuid,
+			};
+		}
+
+		let mut stack_offset = 0;
+
+		// Setup the stack
+		{
+			let addr = next_page;
+
+			unsafe {
+				let mut sp = addr.add(STACK_PAGES);
+
+				// Copy strings onto stack
+				for arg in arguments.iter().copied() {
+					let words = ((arg.len() + 1) / 2) + 1;
+					sp = sp.cast::<u16>().sub(words).cast();
+					// Write length
+					sp.cast::<u16>().write(arg.len().try_into().unwrap());
+					// let sp = ... prevents accidently mutating the original sp
+					let sp = sp.cast::<u16>().add(1);
+					// Copy characters
+					for (i, c) in arg.iter().copied().enumerate() {
+						sp.cast::<u8>().add(i).write(c);
+					}
+					// Adjust stack base address as appropriate
+					stack_offset += 2 * words;
+				}
+
+				// Align to usize boundary
+				let size = core::mem::size_of::<usize>();
+				let offt = (size - sp.cast::<u8>().align_offset(size)) & (size - 1);
+				sp = sp.cast::<u8>().sub(offt).cast();
+				stack_offset += offt;
+
+				let mut strings_base = (STACK_TOP - stack_offset + offt) as *const u8;
+
+				// Push arguments
+				sp = sp.cast::<usize>().sub(1).cast();
+				sp.cast::<usize>().write(arguments.len());
+				for arg in arguments.iter().rev().copied() {
+					sp = sp.cast::<*const u8>().sub(1).cast();
+					sp.cast::<*const u8>().write(strings_base);
+					let bytes = ((arg.len() + 1) & !1) + 2;
+					strings_base = strings_base.add(bytes);
+				}
+
+				// Push address + UUID entries on the stack
+				sp = sp.cast::<usize>().sub(1).cast();
+				sp.cast::<usize>().write(grants_count);
+				for (handle, g) in grants[..grants_count].iter().enumerate() {
+					sp = sp.cast::<Address>().sub(1).cast();
+					sp.cast::<Address>()
+						.write(Address(kernel::ipc::HANDLE_FLAG | handle));
+					sp = sp.cast::<kernel::ipc::UUID>().sub(1).cast();
+					sp.cast::<kernel::ipc::UUID>().write(g.uuid);
+				}
+			}
+
+			// Map
+			let mut virt_a = STACK_TOP - STACK_PAGES * Page::SIZE;
+
+			for k in 0..STACK_PAGES {
 				mappings[i] = kernel::TaskSpawnMapping {
 					typ: 0,
-					flags: flags.into(),
+					flags: RWX::RW.into(),
 					task_address: virt_a as *mut _,
-					self_address,
+					self_address: addr.wrapping_add(k),
 				};
 				i += 1;
-				offset += Page::SIZE;
 				virt_a += Page::SIZE;
 			}
 		}
-	}
 
-	let mut stack_offset = 0;
+		let ret = unsafe {
+			kernel::task_spawn(
+				mappings.as_ptr(),
+				i,
+				self.entry as *const _,
+				(STACK_TOP - stack_offset) as *const _,
+				grants.as_ptr(),
+				grants_count,
+			)
+		};
 
-	// Allocate a stack
-	{
-		// Allocate
-		let stack_pages = 16;
-		let addr =
-			mem::allocate_range(None, stack_pages, RWX::RW).map_err(SpawnElfError::ReserveError)?;
+		// The pages are shared with the new task, so they can be released here.
+		drop(reserved_range);
 
-		reserved_ranges.push(addr, stack_pages);
+		match ret.status {
+			kernel::Return::OK => Ok(Address(ret.value)),
+			r => Err(SpawnElfError::KernelError(r)),
+		}
+	}
+}
 
-		unsafe {
-			let mut sp = addr.as_ptr().add(stack_pages);
-
-			// Copy strings onto stack
-			for arg in arguments.iter().copied() {
-				let words = ((arg.len() + 1) / 2) + 1;
-				sp = sp.cast::<u16>().sub(words).cast();
-				// Write length
-				sp.cast::<u16>().write(arg.len().try_into().unwrap());
-				// let sp = ... prevents accidently mutating the original sp
-				let sp = sp.cast::<u16>().add(1);
-				// Copy characters
-				for (i, c) in arg.iter().copied().enumerate() {
-					sp.cast::<u8>().add(i).write(c);
-				}
-				// Adjust stack base address as appropriate
-				stack_offset += 2 * words;
-			}
+/// Create a new task from an ELF file.
+///
+/// The ELF file is fully validated before any memory is allocated. See [`SpawnPlan`] for more
+/// control.
+pub fn spawn_elf(
+	data: &[kernel::Page],
+	object_entries: &mut dyn ExactSizeIterator<Item = (Address, kernel::ipc::UUID)>,
+	arguments: &[&[u8]],
+) -> Result<Address, SpawnElfError> {
+	SpawnPlan::new(data)?.spawn(object_entries, arguments)
+}
 
-			// Align to usize boundary
-			let size = core::mem::size_of::<usize>();
-			let offt = (size - sp.cast::<u8>().align_offset(size)) & (size - 1);
-			sp = sp.cast::<u8>().sub(offt).cast();
-			stack_offset += offt;
-
-			let mut strings_base = (0x8000_0000 - stack_offset + offt) as *const u8;
-
-			// Push arguments
-			sp = sp.cast::<usize>().sub(1).cast();
-			sp.cast::<usize>().write(arguments.len());
-			for arg in arguments.iter().rev().copied() {
-				sp = sp.cast::<*const u8>().sub(1).cast();
-				sp.cast::<*const u8>().write(strings_base);
-				let bytes = ((arg.len() + 1) & !1) + 2;
-				strings_base = strings_base.add(bytes);
-			}
+/// A subscription to the death of a task.
+///
+/// When the task dies, a packet is received with the address of the dead task. Use
+/// [`dead_peer`] to recognize it.
+///
+/// The kernel has no way to cancel a watch yet, so dropping this does nothing.
+#[derive(Debug)]
+pub struct TaskWatch {
+	address: Address,
+}
 
-			// Push address + UUID entries on the stack
-			sp = sp.cast::<usize>().sub(1).cast();
-			sp.cast::<usize>().write(object_entries.len());
-			for (addr, uuid) in object_entries {
-				sp = sp.cast::<Address>().sub(1).cast();
-				sp.cast::<Address>().write(addr);
-				sp = sp.cast::<kernel::ipc::UUID>().sub(1).cast();
-				sp.cast::<kernel::ipc::UUID>().write(uuid);
-			}
-		}
+#[derive(Debug)]
+pub enum WatchError {
+	NotFound,
+	Unavailable,
+}
 
-		// Map
-		let mut virt_a = 0x7fff_0000;
-		let mut offset = 0x0;
-
-		for i in i..i + 16 {
-			let self_address = addr.as_ptr().wrapping_add(offset as usize) as *mut _;
-			mappings[i] = kernel::TaskSpawnMapping {
-				typ: 0,
-				flags: RWX::RW.into(),
-				task_address: virt_a as *mut _,
-				self_address,
-			};
-			offset += 1;
-			virt_a += Page::SIZE;
+impl TaskWatch {
+	/// Get notified when the task with the given address dies.
+	pub fn new(address: Address) -> Result<Self, WatchError> {
+		let ret = unsafe { kernel::sys_watch_task(address.0) };
+		match ret.status {
+			kernel::Return::OK => Ok(Self { address }),
+			kernel::Return::NOT_FOUND => Err(WatchError::NotFound),
+			kernel::Return::MEMORY_UNAVAILABLE => Err(WatchError::Unavailable),
+			r => unreachable!("{}", r),
 		}
-		i += 16;
 	}
 
-	let pc = elf.header.pt2.entry_point() as usize;
-
-	let ret = unsafe {
-		kernel::task_spawn(
-			mappings.as_ptr(),
-			i,
-			pc as *const _,
-			(0x8000_0000 - stack_offset) as *const _,
-		)
-	};
-	match ret.status {
-		kernel::Return::OK => Ok(Address(ret.value)),
-		r => unreachable!("{}", r),
+	/// The address of the watched task.
+	#[inline(always)]
+	pub fn address(&self) -> Address {
+		self.address
+	}
+
+	/// Check if the given packet indicates the watched task died.
+	#[inline]
+	pub fn is_dead(&self, packet: &kernel::ipc::Packet) -> bool {
+		dead_peer(packet) == Some(self.address)
 	}
 }
 
+/// Return the address of a dead task if the packet is a death notification or a packet that
+/// couldn't be delivered.
+///
+/// Death notifications have no opcode. Undelivered packets are returned as they were sent,
+/// i.e. any data & name pages are still owned by the caller.
+#[inline]
+pub fn dead_peer(packet: &kernel::ipc::Packet) -> Option<Address> {
+	(packet.flags & kernel::ipc::FLAG_DEAD_PEER > 0).then(|| Address(packet.address))
+}
+
+/// Only accept endpoint handles from this task & its threads and only receive handles from the
+/// kernel, i.e. leave compatibility mode. Returns whether this was already enabled.
+pub fn set_strict_endpoints(enable: bool) -> bool {
+	let ret = unsafe { kernel::task_strict_endpoints(enable.into()) };
+	debug_assert_eq!(ret.status, kernel::Return::OK);
+	ret.value != 0
+}
+
+/// Remove an endpoint handle so the table has room for others. The handle may not be used
+/// afterwards. Returns `false` if the address isn't a handle of this task.
+pub fn close_endpoint(address: Address) -> bool {
+	let ret = unsafe { kernel::task_close_endpoint(address.0) };
+	ret.status == kernel::Return::OK
+}
+
+/// Destroy the current task along with all its threads.
+pub fn exit() -> ! {
+	let _ = unsafe { kernel::task_exit() };
+	unreachable!("task_exit returned");
+}
+
 pub mod registry {
 
 	use super::Address;
@@ -288,11 +559,17 @@ pub mod registry {
 		Unavailable,
 		NameTooLong,
 		Occupied,
+		/// The address isn't an endpoint handle of this task.
+		InvalidEndpoint,
+		/// The task the address refers to is dead.
+		NotFound,
 	}
 
 	#[derive(Debug)]
 	pub enum GetError {
 		NotFound,
+		/// There is no room for another endpoint handle.
+		Unavailable,
 	}
 
 	/// Try to add a task to the kernel's registry.
@@ -303,6 +580,8 @@ pub mod registry {
 			kernel::Return::MEMORY_UNAVAILABLE => Err(AddError::Unavailable),
 			kernel::Return::TOO_LONG => Err(AddError::NameTooLong),
 			kernel::Return::OCCUPIED => Err(AddError::Occupied),
+			kernel::Return::PERMISSION_DENIED => Err(AddError::InvalidEndpoint),
+			kernel::Return::NOT_FOUND => Err(AddError::NotFound),
 			r => unreachable!("{}", r),
 		}
 	}
@@ -314,6 +593,7 @@ pub mod registry {
 		match ret.status {
 			kernel::Return::OK => Ok(Address(ret.value)),
 			kernel::Return::NOT_FOUND => Err(GetError::NotFound),
+			kernel::Return::MEMORY_UNAVAILABLE => Err(GetError::Unavailable),
 			r => unreachable!("{}", r),
 		}
 	}
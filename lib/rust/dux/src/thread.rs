@@ -0,0 +1,70 @@
+//! # Threads
+//!
+//! A thread shares the memory & IPC queues of the task that spawned it, i.e. packets sent to
+//! any thread end up in the same received ring. The IPC functions in [`crate::ipc`] can be used
+//! by multiple threads at the same time.
+
+use crate::task::Address;
+use crate::{mem, RWX};
+
+/// The amount of pages allocated for the stack of a thread.
+pub const STACK_PAGES: usize = 4;
+
+#[derive(Debug)]
+pub enum SpawnError {
+	/// There is not enough memory for the stack or the thread itself.
+	NoMemory,
+}
+
+/// The function & argument of a new thread. It is stored at the top of the stack of the thread.
+#[repr(C)]
+struct Start {
+	function: fn(usize),
+	argument: usize,
+}
+
+/// Spawn a thread that calls the function with the given argument. The thread exits when the
+/// function returns.
+// FIXME the stack is leaked when the thread exits.
+pub fn spawn(function: fn(usize), argument: usize) -> Result<Address, SpawnError> {
+	let stack =
+		mem::allocate_range(None, STACK_PAGES, RWX::RW).map_err(|_| SpawnError::NoMemory)?;
+	// SAFETY: the stack was just allocated and is large enough to hold Start.
+	let start = unsafe {
+		let start = stack.as_ptr().add(STACK_PAGES).cast::<Start>().sub(1);
+		start.write(Start { function, argument });
+		start
+	};
+	// The stack pointer must be aligned to 16 bytes.
+	let stack_pointer = start as usize & !0xf;
+	let ret = unsafe {
+		kernel::thread_spawn(
+			entry as extern "C" fn(_) -> ! as *const _,
+			stack_pointer as *const _,
+			start as usize,
+		)
+	};
+	match ret.status {
+		kernel::Return::OK => Ok(Address::from(ret.value)),
+		kernel::Return::MEMORY_UNAVAILABLE => {
+			// SAFETY: the thread doesn't exist, so nothing uses the stack.
+			unsafe { mem::deallocate_range(stack, STACK_PAGES) };
+			Err(SpawnError::NoMemory)
+		}
+		r => unreachable!("{}", r),
+	}
+}
+
+/// Destroy the current thread. If it is the thread the task started with, the whole task is
+/// destroyed.
+pub fn exit() -> ! {
+	let _ = unsafe { kernel::thread_exit() };
+	unreachable!("thread_exit returned");
+}
+
+extern "C" fn entry(start: *const Start) -> ! {
+	// SAFETY: spawn passes a pointer to the Start at the top of the stack of this thread.
+	let Start { function, argument } = unsafe { start.read() };
+	function(argument);
+	exit()
+}
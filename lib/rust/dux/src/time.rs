@@ -0,0 +1,74 @@
+//! # Helper functions & structures to measure time
+
+use core::fmt;
+
+/// The opcode of the packets the input driver sends to the console if both are given
+/// `--latency-debug`. The offset is the time at which the oldest event handed to a reader was
+/// collected, so the console can measure the time from the event until its glyph is flushed.
+pub const OP_LATENCY_MARK: u8 = 130;
+
+/// Return the current time in microseconds.
+pub fn now() -> u64 {
+	// SAFETY: sys_time has no side effects.
+	unsafe { kernel::sys_time() }.value as u64
+}
+
+/// Rolling statistics of a series of durations, e.g. latencies.
+///
+/// The statistics are reset every time a summary is produced, so no storage is needed for the
+/// individual samples.
+pub struct Stats {
+	min: u64,
+	max: u64,
+	sum: u64,
+	count: u32,
+	interval: u32,
+}
+
+impl Stats {
+	/// Create a new `Stats` that produces a summary every `interval` samples.
+	pub const fn new(interval: u32) -> Self {
+		Self {
+			min: u64::MAX,
+			max: 0,
+			sum: 0,
+			count: 0,
+			interval,
+		}
+	}
+
+	/// Add a sample. Once every `interval` samples a summary is returned.
+	pub fn add(&mut self, sample: u64) -> Option<Summary> {
+		self.min = self.min.min(sample);
+		self.max = self.max.max(sample);
+		self.sum = self.sum.saturating_add(sample);
+		self.count += 1;
+		(self.count >= self.interval).then(|| {
+			let summary = Summary {
+				min: self.min,
+				avg: self.sum / u64::from(self.count),
+				max: self.max,
+			};
+			*self = Self::new(self.interval);
+			summary
+		})
+	}
+}
+
+/// A summary of a series of durations.
+#[derive(Clone, Copy, Debug)]
+pub struct Summary {
+	pub min: u64,
+	pub avg: u64,
+	pub max: u64,
+}
+
+impl fmt::Display for Summary {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(
+			f,
+			"min {}us, avg {}us, max {}us",
+			self.min, self.avg, self.max
+		)
+	}
+}
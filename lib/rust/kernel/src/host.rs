@@ -0,0 +1,20 @@
+//! Stand-ins for the system calls on other architectures than RISC-V.
+//!
+//! There is no kernel to call, so every system call panics. This only exists so that crates
+//! using these bindings can be built & tested on the host.
+
+macro_rules! syscall {
+	(saveall $name:ident, $code:literal $(, $arg:ident:$argt:ty)*) => {
+		pub unsafe fn $name($($arg: $argt),*) {
+			let _ = ($($arg,)*);
+			unimplemented!(concat!(stringify!($name), " is only available on RISC-V"))
+		}
+	};
+	($name:ident, $code:literal $(, $arg:ident:$argt:ty)*) => {
+		#[must_use]
+		pub unsafe fn $name($($arg: $argt),*) -> Return {
+			let _ = ($($arg,)*);
+			unimplemented!(concat!(stringify!($name), " is only available on RISC-V"))
+		}
+	};
+}
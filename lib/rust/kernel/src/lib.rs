@@ -7,6 +7,7 @@
 use core::convert::TryFrom;
 use core::ffi;
 use core::fmt;
+use core::mem;
 
 pub const IO_NONE: u8 = 0;
 pub const IO_READ: u8 = 1;
@@ -39,6 +40,8 @@ impl Return {
 	pub const NOT_FOUND: usize = 9;
 	pub const TOO_LONG: usize = 10;
 	pub const OCCUPIED: usize = 11;
+	pub const UNAVAILABLE: usize = 12;
+	pub const PERMISSION_DENIED: usize = 13;
 }
 
 pub mod ipc {
@@ -104,6 +107,52 @@ pub mod ipc {
 		}
 	}
 
+	/// The data of the packet is readable.
+	pub const FLAG_READABLE: u16 = 0x1;
+	/// The data of the packet is writeable.
+	pub const FLAG_WRITEABLE: u16 = 0x2;
+	/// The data of the packet is executable.
+	pub const FLAG_EXECUTABLE: u16 = 0x4;
+
+	/// Set on responses to requests that failed. The `offset` field holds one of the `ERROR_*`
+	/// codes.
+	pub const FLAG_ERROR: u16 = 0x20;
+
+	/// Set by the kernel on packets that couldn't be delivered because the destination died
+	/// and on notifications sent to tasks watching a dead task. The kernel clears it on packets
+	/// sent by tasks.
+	pub const FLAG_DEAD_PEER: u16 = 0x200;
+
+	/// Unmap the data pages from the sender once the kernel shared them with the receiver.
+	/// The pages are kept if the packet is returned to the sender.
+	pub const FLAG_MOVE: u16 = 0x400;
+
+	/// One of the arguments of the request, e.g. the name, is invalid.
+	pub const ERROR_INVALID_ARG: u64 = 1;
+	/// The object the request refers to doesn't exist.
+	pub const ERROR_NOT_FOUND: u64 = 2;
+	/// The data or name of the packet isn't mapped in the sender or may not be shared, e.g.
+	/// because it points to kernel memory. Set by the kernel, which returns the packet to the
+	/// sender.
+	pub const ERROR_INVALID_POINTER: u64 = 3;
+	/// The address of the packet isn't a valid endpoint handle of the sender, the handle
+	/// doesn't allow the UUID of the packet or the receiver has no room for a handle to the
+	/// sender. Set by the kernel, which returns the packet to the sender.
+	pub const ERROR_INVALID_ENDPOINT: u64 = 4;
+	/// The request can't be handled right now but may succeed if it is sent again later.
+	pub const ERROR_BUSY: u64 = 5;
+	/// The request failed because of an I/O error or an inconsistency in the stored data, e.g.
+	/// a corrupted filesystem or a full disk.
+	pub const ERROR_IO: u64 = 6;
+
+	/// Set on addresses that are endpoint handles. Other addresses are raw task addresses,
+	/// which are only accepted from & given to tasks in compatibility mode.
+	pub const HANDLE_FLAG: usize = 1 << (mem::size_of::<usize>() * 8 - 1);
+
+	/// Hint that a request should be handled before requests without this flag, e.g. because it
+	/// accesses filesystem metadata. The kernel doesn't look at this flag.
+	pub const FLAG_PRIORITY_HIGH: u16 = 0x100;
+
 	/// Structure used to communicate with other tasks.
 	#[derive(Clone, Debug, Default)]
 	#[repr(C)]
@@ -135,6 +184,44 @@ pub mod ipc {
 		MapReadCow = 10,
 		MapExecCow = 11,
 		MapReadExecCow = 12,
+		/// Sent by the kernel before the system is suspended. It must be answered with a
+		/// packet with the same opcode addressed to the kernel.
+		Suspend = 13,
+		/// Sent by the kernel after the system resumed.
+		Resume = 14,
+		/// Sent by the kernel to the task registered as `coredump` when a task faulted. The
+		/// data holds a [`CoreDumpHeader`].
+		CoreDump = 15,
+		/// Sent by the kernel with a segment of a core dump. The offset is the address of the
+		/// segment and the `FLAG_READABLE`, `FLAG_WRITEABLE` & `FLAG_EXECUTABLE` flags are
+		/// set as they were for the dumped task. The next segment is requested by sending a
+		/// packet with the same opcode to [`KERNEL_ADDRESS`], which must also be done after
+		/// the last segment.
+		CoreDumpSegment = 16,
+	}
+
+	/// The address of packets sent by the kernel. Packets sent to it answer packets of the
+	/// kernel.
+	pub const KERNEL_ADDRESS: usize = usize::MAX;
+
+	/// The header of a core dump.
+	#[derive(Clone, Copy, Debug)]
+	#[repr(C)]
+	pub struct CoreDumpHeader {
+		/// The address of the task that faulted.
+		pub task: usize,
+		/// The cause of the fault, i.e. `scause`.
+		pub cause: usize,
+		/// The faulting address or instruction, i.e. `stval`.
+		pub value: usize,
+		/// The address of the instruction that faulted.
+		pub pc: usize,
+		/// All integer registers except `x0`.
+		pub x: [usize; 31],
+		/// The amount of segments that follow.
+		pub segments: usize,
+		/// The amount of pages that were left out because the dump would be too large.
+		pub omitted: usize,
 	}
 
 	impl From<Op> for NonZeroU8 {
@@ -186,6 +273,25 @@ pub mod ipc {
 pub mod notification {
 	/// The handler function type
 	pub type Handler = extern "C" fn();
+
+	/// A notification sent by an external interrupt.
+	pub const TYPE_INTERRUPT: usize = 0;
+	/// A notification sent by another task.
+	pub const TYPE_IPC: usize = 1;
+	/// A notification sent by a timer.
+	pub const TYPE_TIMER: usize = 2;
+
+	/// A handler as stored by the kernel, which is returned by [`io_set_notify_handler`].
+	///
+	/// [`io_set_notify_handler`]: super::io_set_notify_handler
+	#[derive(Clone, Copy, Debug, Default)]
+	#[repr(C)]
+	pub struct Entry {
+		/// The address of the handler or `0` if there is none.
+		pub function: usize,
+		/// The context passed to the handler in `a2`.
+		pub context: usize,
+	}
 }
 
 #[repr(C)]
@@ -196,12 +302,90 @@ pub struct TaskSpawnMapping {
 	pub self_address: *mut Page,
 }
 
+/// An endpoint granted to a task spawned with [`task_spawn`].
+#[derive(Clone, Copy, Default, Debug)]
+#[repr(C)]
+pub struct TaskSpawnGrant {
+	/// A handle or raw address of the calling task.
+	pub address: usize,
+	/// The only UUID the new task may send to the endpoint, or [`ipc::UUID::INVALID`] to
+	/// allow the same UUIDs as the calling task.
+	pub uuid: ipc::UUID,
+}
+
+/// A physically contiguous run of pages returned by [`dev_dma_alloc_scatter`].
+#[derive(Clone, Copy, Default, Debug)]
+#[repr(C)]
+pub struct DMARun {
+	/// The physical address of the first page.
+	pub address: usize,
+	/// The amount of pages in the run.
+	pub count: usize,
+}
+
+/// Statistics of a task returned by [`task_stats`]. Fields are only ever appended.
+#[derive(Clone, Copy, Default, Debug)]
+#[repr(C)]
+pub struct TaskStats {
+	/// The amount of misaligned loads & stores emulated by the kernel.
+	pub misaligned_emulations: u64,
+}
+
+/// Statistics of the memory manager returned by [`mem_stats`]. Fields are only ever appended.
+#[derive(Clone, Copy, Default, Debug)]
+#[repr(C)]
+pub struct MemoryStats {
+	/// The amount of free pages.
+	pub free: usize,
+	/// The amount of pages of dead tasks that are waiting to be unpinned.
+	pub quarantined: usize,
+}
+
 #[macro_use]
 #[cfg(any(target_arch = "riscv32", target_arch = "riscv64"))]
 mod riscv;
 #[cfg(any(target_arch = "riscv32", target_arch = "riscv64"))]
 pub use riscv::*;
 
+// Lets crates that use these bindings build & run their tests on the host.
+#[macro_use]
+#[cfg(not(any(target_arch = "riscv32", target_arch = "riscv64")))]
+mod host;
+
+/// Representation of a single memory page.
+#[repr(align(4096))]
+#[repr(C)]
+pub struct Page([u128; Self::SIZE / mem::size_of::<u128>()]);
+
+impl Page {
+	pub const OFFSET_BITS: u8 = 12;
+	pub const SIZE: usize = 1 << Self::OFFSET_BITS;
+	pub const ALIGN: usize = Self::SIZE;
+	pub const MASK: usize = Self::SIZE - 1;
+
+	pub fn zeroize(&mut self) {
+		self.0.iter_mut().for_each(|e| *e = 0);
+	}
+
+	pub const fn zeroed() -> Self {
+		Self([0; Self::SIZE / mem::size_of::<u128>()])
+	}
+}
+
+impl AsRef<[u8; Self::SIZE]> for Page {
+	fn as_ref(&self) -> &[u8; 4096] {
+		unsafe { &*(self as *const Self as *const [u8; Self::SIZE]) }
+	}
+}
+
+impl AsMut<[u8; Self::SIZE]> for Page {
+	fn as_mut(&mut self) -> &mut [u8; 4096] {
+		unsafe { &mut *(self as *mut Self as *mut [u8; Self::SIZE]) }
+	}
+}
+
+const _: usize = Page::SIZE - mem::size_of::<Page>();
+
 syscall!(saveall io_wait, 0, time: u64);
 syscall!(
 	io_set_queues,
@@ -211,7 +395,14 @@ syscall!(
 	free_pages: *mut ipc::FreePage,
 	free_pages_size: usize
 );
-syscall!(io_set_notify_handler, 2, function: notification::Handler);
+syscall!(
+	io_set_notify_handler,
+	2,
+	function: usize,
+	context: usize,
+	typ: usize,
+	previous: *mut notification::Entry
+);
 
 syscall!(mem_alloc, 3, address: *mut Page, size: usize, flags: u8);
 syscall!(mem_dealloc, 4, address: *mut Page, size: usize);
@@ -241,7 +432,9 @@ syscall!(
 	mappings: *const TaskSpawnMapping,
 	mappings_count: usize,
 	program_counter: *const ffi::c_void,
-	stack_pointer: *const ffi::c_void
+	stack_pointer: *const ffi::c_void,
+	grants: *const TaskSpawnGrant,
+	grants_count: usize
 );
 
 syscall!(
@@ -271,6 +464,41 @@ syscall!(
 	address: usize
 );
 syscall!(sys_registry_get, 17, name: *const u8, name_length: usize);
+syscall!(sys_time, 18);
+syscall!(sys_watch_task, 19, address: usize);
+syscall!(task_exit, 20);
+syscall!(
+	dev_dma_alloc_scatter,
+	21,
+	address: *mut Page,
+	size: usize,
+	store: *mut DMARun,
+	max_count: usize
+);
+syscall!(task_stats, 22, store: *mut TaskStats, size: usize);
+syscall!(task_emulate_misaligned, 23, enable: usize);
+syscall!(
+	thread_spawn,
+	24,
+	program_counter: *const ffi::c_void,
+	stack_pointer: *const ffi::c_void,
+	argument: usize
+);
+syscall!(thread_exit, 25);
+syscall!(sys_system_suspend, 26, timeout: u64);
+syscall!(task_strict_endpoints, 27, enable: usize);
+syscall!(sys_log_read, 28, store: *mut u8, length: usize, position: *mut u64);
+syscall!(
+	dev_dma_pin,
+	29,
+	client: usize,
+	address: *const Page,
+	store: *mut usize,
+	count: usize
+);
+syscall!(dev_dma_unpin, 30, client: usize, store: *const usize, count: usize);
+syscall!(mem_stats, 31, store: *mut MemoryStats, size: usize);
+syscall!(task_close_endpoint, 32, handle: usize);
 
 /// Interface for sending messages to the kernel log.
 pub struct SysLog;
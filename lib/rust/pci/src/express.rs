@@ -0,0 +1,330 @@
+//! # PCI Express capability
+//!
+//! Only function level resets & the link registers are exposed for now. The latter are useful
+//! to diagnose cards that trained at a lower speed or width than they support.
+//!
+//! ## References
+//!
+//! PCI Express Base Specification, section 7.5.3 "PCI Express Capability Structure".
+
+use crate::{ConfigSnapshot, Header0};
+use core::fmt;
+use simple_endian::{u16le, u32le};
+use vcell::VolatileCell;
+
+/// The PCI Express capability structure, up to and including the link registers.
+#[repr(C)]
+pub struct Express {
+	id: VolatileCell<u8>,
+	next: VolatileCell<u8>,
+	capabilities: VolatileCell<u16le>,
+	device_capabilities: VolatileCell<u32le>,
+	device_control: VolatileCell<u16le>,
+	device_status: VolatileCell<u16le>,
+	link_capabilities: VolatileCell<u32le>,
+	link_control: VolatileCell<u16le>,
+	link_status: VolatileCell<u16le>,
+}
+
+/// The time a function needs to complete a function level reset, in microseconds. Software may
+/// not access the function during this time.
+pub const FLR_DELAY: u64 = 100_000;
+
+impl Express {
+	/// The ID of the PCI Express capability.
+	pub const ID: u8 = 0x10;
+
+	const CAPABILITY_FLR: u32 = 1 << 28;
+	const CONTROL_INITIATE_FLR: u16 = 1 << 15;
+
+	/// Whether the function supports function level resets.
+	pub fn supports_flr(&self) -> bool {
+		u32::from(self.device_capabilities.get()) & Self::CAPABILITY_FLR > 0
+	}
+
+	/// Reset the function, waiting with `delay` until it's done. The snapshot is restored
+	/// afterwards if one is given.
+	///
+	/// The caller must ensure the function has no outstanding requests, e.g. by clearing bus
+	/// mastering and waiting for in-flight DMA to finish.
+	pub fn function_level_reset(
+		&self,
+		header: &Header0,
+		snapshot: Option<&ConfigSnapshot>,
+		delay: impl FnOnce(u64),
+	) -> Result<(), FlrUnsupported> {
+		if !self.supports_flr() {
+			return Err(FlrUnsupported);
+		}
+		let control = u16::from(self.device_control.get());
+		self.device_control
+			.set((control | Self::CONTROL_INITIATE_FLR).into());
+		delay(FLR_DELAY);
+		if let Some(snapshot) = snapshot {
+			snapshot.restore(header);
+		}
+		Ok(())
+	}
+
+	pub fn link_capability(&self) -> LinkCapability {
+		LinkCapability(self.link_capabilities.get().into())
+	}
+
+	pub fn link_status(&self) -> LinkStatus {
+		LinkStatus(self.link_status.get().into())
+	}
+
+	pub fn link_summary(&self) -> LinkSummary {
+		let (cap, status) = (self.link_capability(), self.link_status());
+		LinkSummary {
+			max_speed: cap.max_speed(),
+			max_width: cap.max_width(),
+			speed: status.speed(),
+			width: status.width(),
+			training: status.training(),
+		}
+	}
+}
+
+/// The function doesn't support function level resets.
+#[derive(Debug)]
+pub struct FlrUnsupported;
+
+/// The Link Capabilities register.
+#[derive(Clone, Copy, Debug)]
+pub struct LinkCapability(pub u32);
+
+impl LinkCapability {
+	pub fn max_speed(&self) -> LinkSpeed {
+		LinkSpeed::from_raw((self.0 & 0xf) as u8)
+	}
+
+	pub fn max_width(&self) -> LinkWidth {
+		LinkWidth::from_raw(((self.0 >> 4) & 0x3f) as u8)
+	}
+}
+
+/// The Link Status register.
+#[derive(Clone, Copy, Debug)]
+pub struct LinkStatus(pub u16);
+
+impl LinkStatus {
+	pub fn speed(&self) -> LinkSpeed {
+		LinkSpeed::from_raw((self.0 & 0xf) as u8)
+	}
+
+	pub fn width(&self) -> LinkWidth {
+		LinkWidth::from_raw(((self.0 >> 4) & 0x3f) as u8)
+	}
+
+	/// Whether the link is being trained.
+	pub fn training(&self) -> bool {
+		self.0 & (1 << 11) > 0
+	}
+}
+
+/// The speed of a link.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LinkSpeed {
+	/// 2.5 GT/s
+	Gen1,
+	/// 5 GT/s
+	Gen2,
+	/// 8 GT/s
+	Gen3,
+	/// 16 GT/s
+	Gen4,
+	/// 32 GT/s
+	Gen5,
+	/// A reserved encoding.
+	Unknown(u8),
+}
+
+impl LinkSpeed {
+	fn from_raw(raw: u8) -> Self {
+		match raw {
+			1 => Self::Gen1,
+			2 => Self::Gen2,
+			3 => Self::Gen3,
+			4 => Self::Gen4,
+			5 => Self::Gen5,
+			r => Self::Unknown(r),
+		}
+	}
+}
+
+impl fmt::Display for LinkSpeed {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			Self::Gen1 => f.write_str("2.5 GT/s"),
+			Self::Gen2 => f.write_str("5 GT/s"),
+			Self::Gen3 => f.write_str("8 GT/s"),
+			Self::Gen4 => f.write_str("16 GT/s"),
+			Self::Gen5 => f.write_str("32 GT/s"),
+			Self::Unknown(r) => write!(f, "unknown speed ({})", r),
+		}
+	}
+}
+
+/// The amount of lanes of a link.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LinkWidth {
+	X(u8),
+	/// A reserved encoding.
+	Unknown(u8),
+}
+
+impl LinkWidth {
+	fn from_raw(raw: u8) -> Self {
+		match raw {
+			1 | 2 | 4 | 8 | 12 | 16 | 32 => Self::X(raw),
+			r => Self::Unknown(r),
+		}
+	}
+}
+
+impl fmt::Display for LinkWidth {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			Self::X(n) => write!(f, "x{}", n),
+			Self::Unknown(r) => write!(f, "unknown width ({})", r),
+		}
+	}
+}
+
+/// The current & maximum speed and width of a link.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LinkSummary {
+	pub max_speed: LinkSpeed,
+	pub max_width: LinkWidth,
+	pub speed: LinkSpeed,
+	pub width: LinkWidth,
+	pub training: bool,
+}
+
+impl LinkSummary {
+	/// Whether the link runs slower or with fewer lanes than it supports.
+	pub fn is_degraded(&self) -> bool {
+		self.speed != self.max_speed || self.width != self.max_width
+	}
+}
+
+impl fmt::Display for LinkSummary {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(
+			f,
+			"{} {} (max {} {})",
+			self.width, self.speed, self.max_width, self.max_speed
+		)?;
+		if self.training {
+			f.write_str(", training")?;
+		}
+		Ok(())
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	/// Create a capability structure with the given link registers.
+	fn express(link_capabilities: u32, link_status: u16) -> [u8; 0x14] {
+		let mut b = [0; 0x14];
+		b[0] = Express::ID;
+		b[0xc..0x10].copy_from_slice(&link_capabilities.to_le_bytes());
+		b[0x12..0x14].copy_from_slice(&link_status.to_le_bytes());
+		b
+	}
+
+	fn summary(link_capabilities: u32, link_status: u16) -> LinkSummary {
+		#[repr(align(4))]
+		struct Align([u8; 0x14]);
+		let b = Align(express(link_capabilities, link_status));
+		let e = unsafe { &*(&b as *const Align).cast::<Express>() };
+		e.link_summary()
+	}
+
+	#[test]
+	fn full_speed() {
+		// x4 gen3 card in a x4 gen3 slot.
+		let s = summary(0x43, 0x43);
+		assert_eq!(s.max_speed, LinkSpeed::Gen3);
+		assert_eq!(s.max_width, LinkWidth::X(4));
+		assert_eq!(s.speed, LinkSpeed::Gen3);
+		assert_eq!(s.width, LinkWidth::X(4));
+		assert!(!s.training);
+		assert!(!s.is_degraded());
+	}
+
+	#[test]
+	fn degraded() {
+		// x16 gen4 card that trained at x1 gen1.
+		let s = summary(0x104, 0x11);
+		assert_eq!(s.max_speed, LinkSpeed::Gen4);
+		assert_eq!(s.max_width, LinkWidth::X(16));
+		assert_eq!(s.speed, LinkSpeed::Gen1);
+		assert_eq!(s.width, LinkWidth::X(1));
+		assert!(s.is_degraded());
+	}
+
+	#[test]
+	fn training() {
+		let s = summary(0x82, 0x800 | 0x42);
+		assert_eq!(s.speed, LinkSpeed::Gen2);
+		assert_eq!(s.width, LinkWidth::X(4));
+		assert!(s.training);
+	}
+
+	#[test]
+	fn reserved() {
+		// Speed 0 and width 0 are what root complex integrated endpoints report.
+		let s = summary(0, 0);
+		assert_eq!(s.max_speed, LinkSpeed::Unknown(0));
+		assert_eq!(s.max_width, LinkWidth::Unknown(0));
+		let s = summary(0x3f << 4 | 0xf, 3 << 4 | 7);
+		assert_eq!(s.max_speed, LinkSpeed::Unknown(0xf));
+		assert_eq!(s.max_width, LinkWidth::Unknown(0x3f));
+		assert_eq!(s.speed, LinkSpeed::Unknown(7));
+		assert_eq!(s.width, LinkWidth::Unknown(3));
+	}
+
+	#[test]
+	fn flr() {
+		/// A configuration space with a PCI Express capability at 0x40.
+		#[repr(align(256))]
+		struct Config([u8; 256]);
+		let mut c = Config([0; 256]);
+		c.0[0x34] = 0x40;
+		c.0[0x40..0x54].copy_from_slice(&express(0, 0));
+		let h = unsafe { &*(&mut c as *mut Config).cast::<Header0>() };
+		let e = h.express().unwrap();
+		assert!(e.function_level_reset(h, None, |_| ()).is_err());
+
+		c.0[0x44..0x48].copy_from_slice(&(1u32 << 28).to_le_bytes());
+		let h = unsafe { &*(&mut c as *mut Config).cast::<Header0>() };
+		h.set_base_address(0, 0x4000_0000);
+		h.set_command(0x2);
+		let snapshot = ConfigSnapshot::save(h);
+		// The function lost its configuration.
+		h.set_base_address(0, 0);
+		h.set_command(0);
+		let e = h.express().unwrap();
+		let mut waited = 0;
+		e.function_level_reset(h, Some(&snapshot), |us| waited += us)
+			.unwrap();
+		assert_eq!(waited, FLR_DELAY);
+		assert_eq!(h.base_address(0), 0x4000_0000);
+		assert_eq!(u16::from(h.common.command.get()), 0x2);
+		assert_eq!(u16::from_le_bytes([c.0[0x48], c.0[0x49]]), 1 << 15);
+	}
+
+	#[test]
+	fn other_bits_ignored() {
+		// Port number, ASPM support etc. are set in the upper bits.
+		let s = summary(0xff00_0000 | 0xc00 | 0x83, 0xf000 | 0x83);
+		assert_eq!(s.max_speed, LinkSpeed::Gen3);
+		assert_eq!(s.max_width, LinkWidth::X(8));
+		assert_eq!(s.speed, LinkSpeed::Gen3);
+		assert_eq!(s.width, LinkWidth::X(8));
+	}
+}
@@ -6,9 +6,16 @@
 //!
 //! [osdev pci]: https://wiki.osdev.org/PCI
 
-#![no_std]
+#![cfg_attr(not(test), no_std)]
 #![feature(ptr_metadata)]
 
+pub mod express;
+pub mod msi;
+pub mod power;
+mod snapshot;
+
+pub use snapshot::ConfigSnapshot;
+
 use core::cell::Cell;
 use core::convert::TryInto;
 use core::fmt;
@@ -24,7 +31,7 @@ pub const BAR_TYPE_MASK: u32 = 0x6;
 ///
 /// I/O bar layout:
 ///
-/// ```
+/// ```text
 /// +------------------------+----------+----------+
 /// | 31 - 2                 | 1        | 0        |
 /// +------------------------+----------+----------+
@@ -34,7 +41,7 @@ pub const BAR_TYPE_MASK: u32 = 0x6;
 ///
 /// MMIO bar layout:
 ///
-/// ```
+/// ```text
 /// +-------------------------+--------------+-------+----------+
 /// | 31 - 4                  | 3            | 1 - 2 | 0        |
 /// +-------------------------+--------------+-------+----------+
@@ -122,6 +129,8 @@ pub struct HeaderCommon {
 }
 
 impl HeaderCommon {
+	/// Flag used to enable I/O space accesses.
+	pub const COMMAND_IO_MASK: u16 = 0x1;
 	/// Flag used to enable MMIO
 	pub const COMMAND_MMIO_MASK: u16 = 0x2;
 	/// Flag used to toggle bus mastering.
@@ -164,16 +173,51 @@ impl Header0 {
 
 	/// Return the capability structures attached to this header.
 	pub fn capabilities<'a>(&'a self) -> CapabilityIter<'a> {
-		unsafe {
-			let next = (self as *const _ as *const u8).add(self.capabilities_pointer.get().into());
-			let next = Some(NonNull::new_unchecked(next as *mut Capability).cast());
-			CapabilityIter {
-				_header: self,
-				next,
-			}
+		// The lower two bits are reserved.
+		let offset = usize::from(self.capabilities_pointer.get() & !0x3);
+		let next = (offset != 0).then(|| unsafe {
+			let next = (self as *const _ as *const u8).add(offset);
+			NonNull::new_unchecked(next as *mut Capability)
+		});
+		CapabilityIter {
+			_header: self,
+			next,
 		}
 	}
 
+	/// Return the PCI Express capability structure, if any.
+	pub fn express(&self) -> Option<&express::Express> {
+		self.capabilities()
+			.find(|c| c.id() == express::Express::ID)
+			.map(|c| unsafe { c.data() })
+	}
+
+	/// Return the power management capability structure, if any.
+	pub fn power_management(&self) -> Option<&power::PowerManagement> {
+		self.capabilities()
+			.find(|c| c.id() == power::PowerManagement::ID)
+			.map(|c| unsafe { c.data() })
+	}
+
+	/// Return the MSI capability structure, if any.
+	pub fn msi(&self) -> Option<&msi::Msi> {
+		self.capabilities()
+			.find(|c| c.id() == msi::Msi::ID)
+			.map(|c| unsafe { c.data() })
+	}
+
+	/// Return the MSI-X capability structure, if any.
+	pub fn msix(&self) -> Option<&msi::MsiX> {
+		self.capabilities()
+			.find(|c| c.id() == msi::MsiX::ID)
+			.map(|c| unsafe { c.data() })
+	}
+
+	/// Return the current & maximum speed and width of the PCI Express link, if any.
+	pub fn pcie_link_summary(&self) -> Option<express::LinkSummary> {
+		self.express().map(express::Express::link_summary)
+	}
+
 	pub fn base_address(&self, index: usize) -> u32 {
 		self.base_address[usize::from(index)].get().into()
 	}
@@ -0,0 +1,150 @@
+//! # Message Signaled Interrupts
+//!
+//! Only the registers in the configuration space are exposed. The MSI-X table & pending bit
+//! array live in a BAR and are up to the driver.
+//!
+//! ## References
+//!
+//! PCI Local Bus Specification, revision 3.0, section 6.8 "Message Signaled Interrupts".
+
+use simple_endian::{u16le, u32le};
+use vcell::VolatileCell;
+
+/// The MSI capability structure.
+///
+/// The layout after the message address depends on whether the device supports 64 bit
+/// addresses, so the remaining registers are accessed by index.
+#[repr(C)]
+pub struct Msi {
+	id: VolatileCell<u8>,
+	next: VolatileCell<u8>,
+	control: VolatileCell<u16le>,
+	registers: [VolatileCell<u32le>; 5],
+}
+
+impl Msi {
+	/// The ID of the MSI capability.
+	pub const ID: u8 = 0x05;
+
+	/// Flag used to enable MSI.
+	pub const CONTROL_ENABLE: u16 = 1 << 0;
+	const CONTROL_ADDRESS_64: u16 = 1 << 7;
+	const CONTROL_PER_VECTOR_MASK: u16 = 1 << 8;
+
+	/// Return the Message Control register.
+	pub fn control(&self) -> u16 {
+		self.control.get().into()
+	}
+
+	/// Set the Message Control register.
+	pub fn set_control(&self, value: u16) {
+		self.control.set(value.into());
+	}
+
+	/// Whether the device supports 64 bit message addresses.
+	pub fn is_64bit(&self) -> bool {
+		self.control() & Self::CONTROL_ADDRESS_64 > 0
+	}
+
+	/// Whether the device supports masking individual vectors.
+	pub fn has_mask(&self) -> bool {
+		self.control() & Self::CONTROL_PER_VECTOR_MASK > 0
+	}
+
+	/// Return the message address. The upper half is always 0 for 32 bit devices.
+	pub fn message_address(&self) -> u64 {
+		let low = u64::from(u32::from(self.registers[0].get()));
+		let high = if self.is_64bit() {
+			u64::from(u32::from(self.registers[1].get()))
+		} else {
+			0
+		};
+		high << 32 | low
+	}
+
+	/// Set the message address. The upper half is ignored for 32 bit devices.
+	pub fn set_message_address(&self, address: u64) {
+		self.registers[0].set((address as u32).into());
+		if self.is_64bit() {
+			self.registers[1].set(((address >> 32) as u32).into());
+		}
+	}
+
+	/// Return the dword holding the message data. The data is in the lower 16 bits.
+	pub fn message_data(&self) -> u32 {
+		self.registers[self.data_index()].get().into()
+	}
+
+	/// Set the dword holding the message data.
+	pub fn set_message_data(&self, data: u32) {
+		self.registers[self.data_index()].set(data.into());
+	}
+
+	/// Return the Mask Bits register, if the device supports masking.
+	pub fn mask(&self) -> Option<u32> {
+		self.has_mask()
+			.then(|| self.registers[self.data_index() + 1].get().into())
+	}
+
+	/// Set the Mask Bits register. Does nothing if the device doesn't support masking.
+	pub fn set_mask(&self, mask: u32) {
+		if self.has_mask() {
+			self.registers[self.data_index() + 1].set(mask.into());
+		}
+	}
+
+	fn data_index(&self) -> usize {
+		if self.is_64bit() {
+			2
+		} else {
+			1
+		}
+	}
+}
+
+/// The MSI-X capability structure.
+#[repr(C)]
+pub struct MsiX {
+	id: VolatileCell<u8>,
+	next: VolatileCell<u8>,
+	control: VolatileCell<u16le>,
+	table: VolatileCell<u32le>,
+	pending: VolatileCell<u32le>,
+}
+
+impl MsiX {
+	/// The ID of the MSI-X capability.
+	pub const ID: u8 = 0x11;
+
+	/// Flag used to mask all vectors.
+	pub const CONTROL_FUNCTION_MASK: u16 = 1 << 14;
+	/// Flag used to enable MSI-X.
+	pub const CONTROL_ENABLE: u16 = 1 << 15;
+
+	/// Return the Message Control register.
+	pub fn control(&self) -> u16 {
+		self.control.get().into()
+	}
+
+	/// Set the Message Control register. Only the enable & function mask bits are writeable.
+	pub fn set_control(&self, value: u16) {
+		self.control.set(value.into());
+	}
+
+	/// Return the amount of entries in the MSI-X table.
+	pub fn table_size(&self) -> u16 {
+		(self.control() & 0x7ff) + 1
+	}
+
+	/// Return the index of the BAR & the offset inside it of the MSI-X table.
+	pub fn table(&self) -> (u8, u32) {
+		let t = u32::from(self.table.get());
+		((t & 0x7) as u8, t & !0x7)
+	}
+
+	/// Return the index of the BAR & the offset inside it of the pending bit array.
+	pub fn pending(&self) -> (u8, u32) {
+		let p = u32::from(self.pending.get());
+		((p & 0x7) as u8, p & !0x7)
+	}
+}
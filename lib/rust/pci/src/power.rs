@@ -0,0 +1,203 @@
+//! # Power management
+//!
+//! Devices may lose their configuration when they are put in D3hot, so drivers that suspend
+//! their device should take a [`ConfigSnapshot`] once it is programmed and pass it to
+//! [`PowerManagement::wake`].
+//!
+//! ## References
+//!
+//! PCI Bus Power Management Interface Specification, revision 1.2, chapter 3 "PCI Power
+//! Management Interface".
+
+use crate::{ConfigSnapshot, Header0};
+use simple_endian::u16le;
+use vcell::VolatileCell;
+
+/// The time a device needs to go from D3hot to D0, in microseconds. Software may not access
+/// the device during this time.
+pub const D3HOT_DELAY: u64 = 10_000;
+
+/// The time a device needs to go to or from D2, in microseconds.
+pub const D2_DELAY: u64 = 200;
+
+/// The power management capability structure.
+#[repr(C)]
+pub struct PowerManagement {
+	id: VolatileCell<u8>,
+	next: VolatileCell<u8>,
+	capabilities: VolatileCell<u16le>,
+	control_status: VolatileCell<u16le>,
+	bridge_support: VolatileCell<u8>,
+	data: VolatileCell<u8>,
+}
+
+impl PowerManagement {
+	/// The ID of the power management capability.
+	pub const ID: u8 = 0x01;
+
+	const CAPABILITY_D1: u16 = 1 << 9;
+	const CAPABILITY_D2: u16 = 1 << 10;
+
+	const STATE_MASK: u16 = 0x3;
+	const NO_SOFT_RESET: u16 = 1 << 3;
+	/// Writing 1 clears this bit, so it must be written as 0 to leave it alone.
+	const PME_STATUS: u16 = 1 << 15;
+
+	/// Return the current power state of the device.
+	pub fn state(&self) -> PowerState {
+		PowerState::from_raw(u16::from(self.control_status.get()) & Self::STATE_MASK)
+	}
+
+	/// Put the device in the given power state. The caller must wait for [`D3HOT_DELAY`] or
+	/// [`D2_DELAY`] before accessing the device if it moves out of D3hot or to or from D2.
+	pub fn set_state(&self, state: PowerState) {
+		let cs = u16::from(self.control_status.get());
+		let cs = cs & !(Self::STATE_MASK | Self::PME_STATUS) | state as u16;
+		self.control_status.set(cs.into());
+	}
+
+	/// Put the device in D0, waiting with `delay` as long as the current state requires.
+	///
+	/// If the device was reset, the snapshot is restored if one is given. Returns whether the
+	/// device was reset, in which case any state outside the configuration space (e.g. the
+	/// device registers in the BARs) must be set up again.
+	pub fn wake(
+		&self,
+		header: &Header0,
+		snapshot: Option<&ConfigSnapshot>,
+		delay: impl FnOnce(u64),
+	) -> bool {
+		let wait = match self.state() {
+			PowerState::D0 => return false,
+			PowerState::D1 => 0,
+			PowerState::D2 => D2_DELAY,
+			PowerState::D3Hot => D3HOT_DELAY,
+		};
+		let reset = self.state() == PowerState::D3Hot && !self.no_soft_reset();
+		self.set_state(PowerState::D0);
+		delay(wait);
+		if let (true, Some(snapshot)) = (reset, snapshot) {
+			snapshot.restore(header);
+		}
+		reset
+	}
+
+	/// Whether the device keeps its configuration when moving from D3hot to D0. If not, the
+	/// device is reset and must be set up again.
+	pub fn no_soft_reset(&self) -> bool {
+		u16::from(self.control_status.get()) & Self::NO_SOFT_RESET > 0
+	}
+
+	/// Whether the device supports the given power state. D0 & D3hot are always supported.
+	pub fn supports(&self, state: PowerState) -> bool {
+		let pmc = u16::from(self.capabilities.get());
+		match state {
+			PowerState::D0 | PowerState::D3Hot => true,
+			PowerState::D1 => pmc & Self::CAPABILITY_D1 > 0,
+			PowerState::D2 => pmc & Self::CAPABILITY_D2 > 0,
+		}
+	}
+}
+
+/// A power state of a device.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[repr(u16)]
+pub enum PowerState {
+	D0 = 0,
+	D1 = 1,
+	D2 = 2,
+	D3Hot = 3,
+}
+
+impl PowerState {
+	fn from_raw(raw: u16) -> Self {
+		match raw {
+			0 => Self::D0,
+			1 => Self::D1,
+			2 => Self::D2,
+			_ => Self::D3Hot,
+		}
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	/// A configuration space with a power management capability at 0x40.
+	#[repr(align(256))]
+	struct Config([u8; 256]);
+
+	impl Config {
+		fn new(capabilities: u16, control_status: u16) -> Self {
+			let mut c = Self([0; 256]);
+			c.0[0x34] = 0x40;
+			c.0[0x40] = PowerManagement::ID;
+			c.0[0x42..0x44].copy_from_slice(&capabilities.to_le_bytes());
+			c.0[0x44..0x46].copy_from_slice(&control_status.to_le_bytes());
+			c
+		}
+
+		fn header(&mut self) -> &Header0 {
+			unsafe { &*(self as *mut Self).cast() }
+		}
+
+		fn control_status(&self) -> u16 {
+			u16::from_le_bytes([self.0[0x44], self.0[0x45]])
+		}
+	}
+
+	#[test]
+	fn set_state() {
+		let mut c = Config::new(0, 0);
+		let pm = c.header().power_management().unwrap();
+		assert_eq!(pm.state(), PowerState::D0);
+		pm.set_state(PowerState::D3Hot);
+		assert_eq!(pm.state(), PowerState::D3Hot);
+		pm.set_state(PowerState::D0);
+		assert_eq!(pm.state(), PowerState::D0);
+	}
+
+	#[test]
+	fn set_state_preserves_bits() {
+		// PME_En & Data_Select must stay as is, PME_Status must not be cleared.
+		let mut c = Config::new(0, 0x8000 | 0x100 | 0x0a00 | 0x8);
+		let pm = c.header().power_management().unwrap();
+		assert!(pm.no_soft_reset());
+		pm.set_state(PowerState::D3Hot);
+		assert_eq!(c.control_status(), 0x100 | 0x0a00 | 0x8 | 0x3);
+	}
+
+	#[test]
+	fn supports() {
+		let mut c = Config::new(1 << 9, 0);
+		let pm = c.header().power_management().unwrap();
+		assert!(pm.supports(PowerState::D0));
+		assert!(pm.supports(PowerState::D1));
+		assert!(!pm.supports(PowerState::D2));
+		assert!(pm.supports(PowerState::D3Hot));
+		assert!(!pm.no_soft_reset());
+	}
+
+	#[test]
+	fn no_capability() {
+		let mut c = Config::new(0, 0);
+		c.0[0x40] = crate::express::Express::ID;
+		assert!(c.header().power_management().is_none());
+	}
+
+	#[test]
+	fn wake_without_reset() {
+		let mut c = Config::new(0, PowerManagement::NO_SOFT_RESET);
+		let h = c.header();
+		h.set_base_address(0, 0x4000_000c);
+		let snapshot = ConfigSnapshot::save(h);
+		let pm = h.power_management().unwrap();
+		pm.set_state(PowerState::D3Hot);
+		h.set_base_address(0, 0x8000_000c);
+		// The device kept its configuration, so the snapshot must not be restored.
+		assert!(!pm.wake(h, Some(&snapshot), |_| ()));
+		assert_eq!(pm.state(), PowerState::D0);
+		assert_eq!(h.base_address(0), 0x8000_000c);
+	}
+}
@@ -0,0 +1,265 @@
+//! # Configuration snapshots
+//!
+//! A function loses its configuration on a function level reset and may lose it when it is put
+//! in D3hot. A [`ConfigSnapshot`] holds everything a driver programs in the configuration space
+//! so it can be written back afterwards.
+//!
+//! The MSI-X table is in a BAR and isn't part of the snapshot. Drivers using MSI-X must program
+//! the table again after restoring.
+
+use crate::msi::{Msi, MsiX};
+use crate::{Header0, HeaderCommon};
+
+/// The registers of the MSI capability.
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct MsiState {
+	control: u16,
+	address: u64,
+	data: u32,
+	mask: Option<u32>,
+}
+
+/// A copy of the writeable registers of a type 0 header.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ConfigSnapshot {
+	command: u16,
+	cache_line_size: u8,
+	latency_timer: u8,
+	/// The raw values, so the upper half of 64 bit BARs is included.
+	base_address: [u32; Header0::BASE_ADDRESS_COUNT as usize],
+	interrupt_line: u8,
+	msi: Option<MsiState>,
+	msix_control: Option<u16>,
+}
+
+impl ConfigSnapshot {
+	/// The command flags that make the function respond to or initiate accesses.
+	const COMMAND_DECODE: u16 = HeaderCommon::COMMAND_IO_MASK
+		| HeaderCommon::COMMAND_MMIO_MASK
+		| HeaderCommon::COMMAND_BUS_MASTER_MASK;
+
+	/// Copy the current configuration of a function.
+	pub fn save(header: &Header0) -> Self {
+		let mut base_address = [0; Header0::BASE_ADDRESS_COUNT as usize];
+		for (i, w) in base_address.iter_mut().enumerate() {
+			*w = header.base_address(i);
+		}
+		Self {
+			command: header.common.command.get().into(),
+			cache_line_size: header.common.cache_line_size.get(),
+			latency_timer: header.common.latency_timer.get(),
+			base_address,
+			interrupt_line: header.interrupt_line.get(),
+			msi: header.msi().map(|msi| MsiState {
+				control: msi.control(),
+				address: msi.message_address(),
+				data: msi.message_data(),
+				mask: msi.mask(),
+			}),
+			msix_control: header.msix().map(MsiX::control),
+		}
+	}
+
+	/// Write the configuration back to a function.
+	///
+	/// Decoding is disabled until the BARs are written so the function never responds to stale
+	/// addresses. MSI is set up before bus mastering is enabled and MSI-X is enabled last.
+	pub fn restore(&self, header: &Header0) {
+		header.set_command(self.command & !Self::COMMAND_DECODE);
+		header.common.cache_line_size.set(self.cache_line_size);
+		header.common.latency_timer.set(self.latency_timer);
+		header.interrupt_line.set(self.interrupt_line);
+		for (i, bar) in self.base_address.iter().enumerate() {
+			header.set_base_address(i, *bar);
+		}
+		if let (Some(state), Some(msi)) = (self.msi, header.msi()) {
+			// Write the control register first as it determines the layout of the others.
+			msi.set_control(state.control & !Msi::CONTROL_ENABLE);
+			msi.set_message_address(state.address);
+			msi.set_message_data(state.data);
+			if let Some(mask) = state.mask {
+				msi.set_mask(mask);
+			}
+			msi.set_control(state.control);
+		}
+		header.set_command(self.command);
+		if let (Some(control), Some(msix)) = (self.msix_control, header.msix()) {
+			msix.set_control(control);
+		}
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+	use crate::power::{PowerManagement, PowerState};
+
+	/// A configuration space with a power management capability at 0x40, an MSI capability at
+	/// 0x50 and an MSI-X capability at 0x70.
+	#[repr(align(256))]
+	struct Config([u8; 256]);
+
+	impl Config {
+		fn new() -> Self {
+			let mut c = Self([0; 256]);
+			c.0[0x34] = 0x40;
+			c.0[0x40] = PowerManagement::ID;
+			c.0[0x41] = 0x50;
+			c.0[0x50] = Msi::ID;
+			c.0[0x51] = 0x70;
+			// 64 bit with per-vector masking.
+			c.0[0x52..0x54].copy_from_slice(&(1u16 << 7 | 1 << 8).to_le_bytes());
+			c.0[0x70] = MsiX::ID;
+			// 8 entries in BAR 0 at 0x2000, pending bits in BAR 0 at 0x3000.
+			c.0[0x72..0x74].copy_from_slice(&7u16.to_le_bytes());
+			c.0[0x74..0x78].copy_from_slice(&0x2000u32.to_le_bytes());
+			c.0[0x78..0x7c].copy_from_slice(&0x3000u32.to_le_bytes());
+			c
+		}
+
+		fn header(&mut self) -> &Header0 {
+			unsafe { &*(self as *mut Self).cast() }
+		}
+
+		fn u32(&self, offset: usize) -> u32 {
+			let mut b = [0; 4];
+			b.copy_from_slice(&self.0[offset..offset + 4]);
+			u32::from_le_bytes(b)
+		}
+	}
+
+	/// Program the function like a driver would.
+	fn program(h: &Header0) {
+		// BAR 0: 32 bit MMIO. BAR 1 & 2: 64 bit prefetchable MMIO. BAR 3: I/O.
+		h.set_base_address(0, 0x4000_0000);
+		h.set_base_address(1, 0x8000_000c);
+		h.set_base_address(2, 0x0000_0001);
+		h.set_base_address(3, 0x0000_c001);
+		h.common.cache_line_size.set(0x10);
+		h.common.latency_timer.set(0x40);
+		h.interrupt_line.set(0xb);
+		let msi = h.msi().unwrap();
+		msi.set_message_address(0x1_fee0_1000);
+		msi.set_message_data(0x4021);
+		msi.set_mask(0b1010);
+		msi.set_control(msi.control() | Msi::CONTROL_ENABLE);
+		h.set_command(
+			HeaderCommon::COMMAND_MMIO_MASK
+				| HeaderCommon::COMMAND_BUS_MASTER_MASK
+				| HeaderCommon::COMMAND_INTERRUPT_DISABLE,
+		);
+		let msix = h.msix().unwrap();
+		msix.set_control(msix.control() | MsiX::CONTROL_ENABLE);
+	}
+
+	/// Overwrite all registers, except the read-only ones that determine the layout of the
+	/// capabilities.
+	fn scramble(h: &Header0) {
+		for i in 0..6 {
+			h.set_base_address(i, 0xdead_beef);
+		}
+		h.set_command(0xffff);
+		h.common.cache_line_size.set(0xff);
+		h.common.latency_timer.set(0xff);
+		h.interrupt_line.set(0xff);
+		let msi = h.msi().unwrap();
+		msi.set_message_address(u64::MAX);
+		msi.set_message_data(u32::MAX);
+		msi.set_mask(u32::MAX);
+		msi.set_control(msi.control() & !Msi::CONTROL_ENABLE);
+		let msix = h.msix().unwrap();
+		msix.set_control(msix.control() & 0x7ff);
+	}
+
+	#[test]
+	fn round_trip() {
+		let mut c = Config::new();
+		let h = c.header();
+		program(h);
+		let snapshot = ConfigSnapshot::save(h);
+		let expect = c.0;
+
+		let h = c.header();
+		scramble(h);
+		assert_ne!(ConfigSnapshot::save(h), snapshot);
+
+		snapshot.restore(h);
+		assert_eq!(ConfigSnapshot::save(h), snapshot);
+		assert_eq!(&c.0[..], &expect[..]);
+		// Both halves of the 64 bit BAR are restored.
+		assert_eq!(c.u32(0x14), 0x8000_000c);
+		assert_eq!(c.u32(0x18), 0x0000_0001);
+	}
+
+	#[test]
+	fn msi_layout() {
+		let mut c = Config::new();
+		let h = c.header();
+		program(h);
+		assert_eq!(c.u32(0x54), 0xfee0_1000);
+		assert_eq!(c.u32(0x58), 0x1);
+		assert_eq!(c.u32(0x5c), 0x4021);
+		assert_eq!(c.u32(0x60), 0b1010);
+
+		// Without 64 bit addresses & masking the data directly follows the address.
+		let mut c = Config::new();
+		c.0[0x52] = 0;
+		c.0[0x53] = 0;
+		let h = c.header();
+		let msi = h.msi().unwrap();
+		msi.set_message_address(0x1_fee0_1000);
+		msi.set_message_data(0x4021);
+		msi.set_mask(0b1010);
+		assert_eq!(msi.mask(), None);
+		assert_eq!(msi.message_address(), 0xfee0_1000);
+		assert_eq!(c.u32(0x54), 0xfee0_1000);
+		assert_eq!(c.u32(0x58), 0x4021);
+		assert_eq!(c.u32(0x5c), 0);
+	}
+
+	#[test]
+	fn msix() {
+		let mut c = Config::new();
+		let msix = c.header().msix().unwrap();
+		assert_eq!(msix.table_size(), 8);
+		assert_eq!(msix.table(), (0, 0x2000));
+		assert_eq!(msix.pending(), (0, 0x3000));
+	}
+
+	#[test]
+	fn no_capabilities() {
+		let mut c = Config::new();
+		c.0[0x34] = 0;
+		let h = c.header();
+		h.set_base_address(0, 0x4000_0000);
+		h.set_command(HeaderCommon::COMMAND_MMIO_MASK);
+		let snapshot = ConfigSnapshot::save(h);
+		assert!(snapshot.msi.is_none() && snapshot.msix_control.is_none());
+		h.set_base_address(0, 0);
+		h.set_command(0);
+		snapshot.restore(h);
+		assert_eq!(h.base_address(0), 0x4000_0000);
+		assert_eq!(
+			u16::from(h.common.command.get()),
+			HeaderCommon::COMMAND_MMIO_MASK
+		);
+	}
+
+	#[test]
+	fn wake_restores_after_reset() {
+		let mut c = Config::new();
+		let h = c.header();
+		program(h);
+		let snapshot = ConfigSnapshot::save(h);
+		let pm = h.power_management().unwrap();
+		pm.set_state(PowerState::D3Hot);
+		scramble(h);
+
+		let mut waited = 0;
+		// NoSoft_Reset is clear, so the device is assumed to be reset.
+		assert!(pm.wake(h, Some(&snapshot), |us| waited += us));
+		assert_eq!(waited, crate::power::D3HOT_DELAY);
+		assert_eq!(pm.state(), PowerState::D0);
+		assert_eq!(ConfigSnapshot::save(h), snapshot);
+	}
+}
@@ -0,0 +1,42 @@
+//! # Legacy (pre-1.0) PCI transport
+//!
+//! Legacy devices expose a single register block in BAR 0, which is an I/O BAR, instead of
+//! the capability-based layout. Queues have a fixed size and the used ring must be aligned to
+//! [`Config::QUEUE_ALIGN`]. Only the lower 32 bits of the feature set are available.
+//!
+//! Anything that is "guest-endian" for legacy devices (e.g. the device-specific
+//! configuration) is treated as little endian.
+//!
+//! ## References
+//!
+//! https://docs.oasis-open.org/virtio/virtio/v1.1/cs01/virtio-v1.1-cs01.html#x1-1090002
+
+use core::mem;
+use simple_endian::{u16le, u32le};
+use vcell::VolatileCell;
+
+#[cfg(target_endian = "big")]
+compile_error!("legacy virtio devices are guest-endian, which is only handled for little endian");
+
+/// The register block of a legacy device, excluding the MSI-X fields.
+#[repr(C)]
+pub struct Config {
+	pub device_features: VolatileCell<u32le>,
+	pub driver_features: VolatileCell<u32le>,
+	/// The physical page number of the queue, in units of [`Self::QUEUE_ALIGN`].
+	pub queue_address: VolatileCell<u32le>,
+	pub queue_size: VolatileCell<u16le>,
+	pub queue_select: VolatileCell<u16le>,
+	pub queue_notify: VolatileCell<u16le>,
+	pub device_status: VolatileCell<u8>,
+	pub isr_status: VolatileCell<u8>,
+}
+
+impl Config {
+	/// The alignment of the used ring and the unit of `queue_address`.
+	pub const QUEUE_ALIGN: usize = 4096;
+	/// The offset of the device-specific configuration if MSI-X is disabled.
+	pub const DEVICE_CONFIG_OFFSET: usize = 0x14;
+}
+
+const _CONFIG_SIZE_CHECK: usize = 0 - (Config::DEVICE_CONFIG_OFFSET - mem::size_of::<Config>());
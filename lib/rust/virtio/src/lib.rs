@@ -2,5 +2,7 @@
 #![feature(asm)]
 #![feature(int_log)]
 
+#[cfg(feature = "legacy")]
+pub mod legacy;
 pub mod pci;
 pub mod queue;
@@ -1,3 +1,4 @@
+use crate::queue::Queue;
 use core::convert::TryFrom;
 use core::fmt;
 use core::marker::PhantomData;
@@ -144,14 +145,53 @@ pub struct Notify<'a> {
 }
 
 impl Notify<'_> {
-	pub fn send(&self, offset: u16) {
+	/// Notify the device of changes in a queue.
+	///
+	/// The index of the queue is written to its notify address, which is
+	/// `queue_notify_off * notify_off_multiplier` bytes past the base. The notify offset doesn't
+	/// have to be equal to the index. Legacy devices have a single register, i.e. a multiplier of
+	/// 0.
+	pub fn send(&self, queue: &Queue) {
 		unsafe {
-			let offt = usize::try_from(self.multiplier / 2).unwrap() * usize::from(offset);
-			(&*self.address.as_ptr().add(offt)).set(0.into())
+			let offt =
+				usize::try_from(self.multiplier / 2).unwrap() * usize::from(queue.notify_offset());
+			(&*self.address.as_ptr().add(offt)).set(queue.index().into())
 		};
 	}
 }
 
+/// The interface a device should be driven with.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Mode {
+	/// The capability-based interface introduced in virtio 1.0.
+	Modern,
+	/// The pre-1.0 interface. Using it requires the `legacy` feature.
+	Legacy,
+}
+
+/// Determine which interface to use for a device.
+///
+/// Transitional devices expose both interfaces, in which case the modern interface is preferred.
+pub fn mode(header: &pci::Header) -> Mode {
+	let header = match header {
+		pci::Header::H0(h) => h,
+		// TODO not actually unreachable, but meh.
+		_ => unreachable!(),
+	};
+	let modern = header
+		.capabilities()
+		.filter(|cap| cap.id() == 0x9)
+		.any(|cap| {
+			let cap = unsafe { cap.data::<Capability>() };
+			cap.config_type.get() == Capability::COMMON_CONFIGURATION
+		});
+	if modern {
+		Mode::Modern
+	} else {
+		Mode::Legacy
+	}
+}
+
 /// Setup a new virtio device on a PCI bus.
 pub fn new_device<'a, D, H, R>(
 	header: pci::Header<'a>,
@@ -266,7 +306,7 @@ where
 				.cast::<CommonConfig>()
 				.as_ref()
 		})
-		.expect("No common config map defined");
+		.expect("No common config map defined (legacy-only device?)");
 
 	let device_config = device_config
 		.map(|cfg| unsafe {
@@ -298,4 +338,56 @@ where
 	handler(common_config, device_config, notify_config, isr_config)
 }
 
+/// Setup a new legacy virtio device on a PCI bus.
+///
+/// The registers are located in BAR 0, which must be mapped. MSI-X is not supported.
+#[cfg(feature = "legacy")]
+pub fn new_legacy_device<'a, D, H, R>(
+	header: pci::Header<'a>,
+	base_address_regions: &[Option<NonNull<()>>],
+	handler: H,
+) -> Result<D, R>
+where
+	D: Device + 'a,
+	H: FnOnce(&'a super::legacy::Config, &'a DeviceConfig, Notify<'a>, &'a ISR) -> Result<D, R>,
+{
+	use super::legacy::Config;
+
+	let header = match header {
+		pci::Header::H0(h) => h,
+		// TODO not actually unreachable, but meh.
+		_ => unreachable!(),
+	};
+	assert!(
+		header.base_address(0) & pci::BAR_IO_SPACE > 0,
+		"BAR 0 of a legacy device isn't an I/O BAR"
+	);
+
+	let base = base_address_regions
+		.get(0)
+		.copied()
+		.flatten()
+		.expect("BAR not mapped to region")
+		.cast::<u8>();
+
+	// SAFETY: BAR 0 of a legacy device points to the legacy register block.
+	let (config, device_config, isr_config) = unsafe {
+		let config = base.cast::<Config>().as_ref();
+		let device_config = &*base
+			.as_ptr()
+			.add(Config::DEVICE_CONFIG_OFFSET)
+			.cast::<DeviceConfig>();
+		let isr_config = &*(&config.isr_status as *const VolatileCell<u8>).cast::<ISR>();
+		(config, device_config, isr_config)
+	};
+
+	let notify_config = Notify {
+		address: NonNull::from(&config.queue_notify),
+		multiplier: 0,
+		_marker: PhantomData,
+	};
+
+	handler(config, device_config, notify_config, isr_config)
+}
+
 pub trait Device {}
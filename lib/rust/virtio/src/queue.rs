@@ -2,6 +2,7 @@
 
 use core::convert::{TryFrom, TryInto};
 use core::fmt;
+use core::marker::PhantomData;
 use core::mem;
 use core::ptr::NonNull;
 use core::slice;
@@ -65,7 +66,7 @@ struct UsedTail {
 }
 
 pub struct Queue<'a> {
-	_config: &'a super::pci::CommonConfig,
+	_marker: PhantomData<&'a ()>,
 	mask: u16,
 	last_used: u16,
 	free_descriptors: [u16; 8],
@@ -73,9 +74,19 @@ pub struct Queue<'a> {
 	descriptors: NonNull<Descriptor>,
 	available: NonNull<Avail>,
 	used: NonNull<Used>,
+	index: u16,
 	notify_offset: u16,
 }
 
+/// The physical addresses of the parts of a queue.
+struct PhysicalAddresses {
+	descriptors: u64,
+	available: u64,
+	used: u64,
+	/// Whether the entire area is physically contiguous.
+	contiguous: bool,
+}
+
 /// Returns the available head & ring.
 ///
 /// This is implemented as a macro because Rust isn't quite advanced enough yet.
@@ -118,6 +129,20 @@ unsafe fn return_table<'s, T>(ptr: &'s mut NonNull<T>, mask: u16) -> &'s mut [T]
 	slice::from_raw_parts_mut(ptr.as_ptr(), size)
 }
 
+/// Return the physical address of a range in an area described by the given runs. Returns `None`
+/// if the range isn't physically contiguous.
+fn physical_address(runs: &[kernel::DMARun], offset: usize, size: usize) -> Option<u64> {
+	let mut start = 0;
+	for run in runs {
+		let end = start + run.count * 4096;
+		if offset < end {
+			return (offset + size <= end).then(|| (run.address + offset - start) as u64);
+		}
+		start = end;
+	}
+	None
+}
+
 static mut DMA_ADDR: usize = 0x300_0000; // FIXME get rid of this crap.
 
 impl<'a> Queue<'a> {
@@ -130,13 +155,69 @@ impl<'a> Queue<'a> {
 		max_size: u16,
 		msix: Option<u16>,
 	) -> Result<Self, OutOfMemory> {
+		config.queue_select.set(index.into());
+
+		// TODO ensure max_size is a power of 2
+		let size = u16::from(config.queue_size.get()).min(max_size);
+		let notify_offset = config.queue_notify_off.get().into();
+		let (queue, phys) = Self::allocate(size, index, notify_offset)?;
+
+		config.queue_descriptors.set(phys.descriptors.into());
+		config.queue_driver.set(phys.available.into());
+		config.queue_device.set(phys.used.into());
+		config.queue_size.set(size.into());
+		config.queue_enable.set(1.into());
+
+		msix.map(|msix| config.queue_msix_vector.set(msix.into()));
+
+		Ok(queue)
+	}
+
+	/// Create a new split virtqueue and attach it to a legacy device.
+	///
+	/// Legacy devices don't allow changing the size of a queue, so the size reported by the
+	/// device is used as is.
+	#[cfg(feature = "legacy")]
+	pub fn new_legacy(config: &'a super::legacy::Config, index: u16) -> Result<Self, OutOfMemory> {
+		use super::legacy::Config;
+
+		config.queue_select.set(index.into());
+
+		let size = u16::from(config.queue_size.get());
+		assert_ne!(size, 0, "queue doesn't exist");
+		// The notify "offset" of legacy devices is the queue index.
+		let (queue, phys) = Self::allocate(size, index, index)?;
+
+		// The descriptors are placed at the start of the area and the available ring follows
+		// immediately, which matches the legacy layout if the area is physically contiguous.
+		if !phys.contiguous {
+			return Err(OutOfMemory);
+		}
+		debug_assert_eq!(phys.descriptors % Config::QUEUE_ALIGN as u64, 0);
+		debug_assert_eq!(phys.used % Config::QUEUE_ALIGN as u64, 0);
+		let pfn = phys.descriptors / Config::QUEUE_ALIGN as u64;
+		let pfn = u32::try_from(pfn).expect("queue address out of range");
+		config.queue_address.set(pfn.into());
+
+		Ok(queue)
+	}
+
+	/// Allocate & initialize the memory for a queue.
+	///
+	/// The descriptor table and available ring are placed at the start of the area and the used
+	/// ring is placed right after it, aligned to a page boundary. The area itself doesn't need to
+	/// be physically contiguous, but each part of the queue must be.
+	fn allocate(
+		size: u16,
+		index: u16,
+		notify_offset: u16,
+	) -> Result<(Self, PhysicalAddresses), OutOfMemory> {
 		// FIXME something very, VERY bad is happening here...
 		if unsafe { DMA_ADDR } == 0 {
 			unsafe { DMA_ADDR = 0x300_0000 };
 		}
 
-		// TODO ensure max_size is a power of 2
-		let size = u16::from(config.queue_size.get()).min(max_size) as usize;
+		let size = usize::from(size);
 		let desc_size = mem::size_of::<Descriptor>() * size;
 		let avail_size = mem::size_of::<AvailHead>()
 			+ mem::size_of::<AvailElement>() * size
@@ -146,17 +227,25 @@ impl<'a> Queue<'a> {
 			+ mem::size_of::<UsedTail>();
 
 		let align = |s| (s + 0xfff) & !0xfff;
+		let total_size = align(desc_size + avail_size) + align(used_size);
 
+		let mut runs = [kernel::DMARun::default(); 8];
+		let mem = unsafe { DMA_ADDR as *mut u8 };
 		let ret = unsafe {
-			kernel::dev_dma_alloc(
-				DMA_ADDR as *mut kernel::Page,
-				align(desc_size + avail_size) + align(used_size),
-				0x2,
-			)
+			kernel::dev_dma_alloc_scatter(mem.cast(), total_size, runs.as_mut_ptr(), runs.len())
 		};
 		let kernel::Return { status, value } = ret;
-		assert_eq!(status, 0, "Failed DMA alloc");
-		let mem = value as *mut u8;
+		match status {
+			kernel::Return::OK => (),
+			// The memory is still mapped, so skip over it.
+			kernel::Return::TOO_LONG => {
+				unsafe { DMA_ADDR += total_size };
+				return Err(OutOfMemory);
+			}
+			kernel::Return::MEMORY_UNAVAILABLE => return Err(OutOfMemory),
+			s => panic!("Failed DMA alloc: {}", s),
+		}
+		let runs = &runs[..value];
 
 		let descriptors = unsafe { NonNull::new_unchecked(mem.cast()) };
 		let available = unsafe { NonNull::new_unchecked(mem.add(desc_size).cast()) };
@@ -177,29 +266,21 @@ impl<'a> Queue<'a> {
 		let free_descriptors = [5, 7, 6, 0, 1, 3, 2, 4];
 		let free_count = 8;
 
-		let mut phys = 0;
-		let ret = unsafe { kernel::mem_physical_address(mem.cast(), &mut phys as *mut _, 1) };
-		assert_eq!(ret.status, 0, "Failed DMA get phys address");
-
-		let d_phys = phys;
-		let a_phys = phys + desc_size;
-		let u_phys = phys + align(desc_size + avail_size);
-
-		config.queue_select.set(index.into());
-		config.queue_descriptors.set((d_phys as u64).into());
-		config.queue_driver.set((a_phys as u64).into());
-		config.queue_device.set((u_phys as u64).into());
-		config.queue_size.set((size as u16).into());
-		config.queue_enable.set(1.into());
-
-		let notify_offset = config.queue_notify_off.get().into();
-
-		unsafe { DMA_ADDR += 4096 * 0x2 };
+		// The runs are sorted from large to small, so the descriptors & available ring are
+		// almost always in one run.
+		let used_offset = align(desc_size + avail_size);
+		let phys = |offset, size| physical_address(runs, offset, size).ok_or(OutOfMemory);
+		let phys = PhysicalAddresses {
+			descriptors: phys(0, desc_size)?,
+			available: phys(desc_size, avail_size)?,
+			used: phys(used_offset, used_size)?,
+			contiguous: runs.len() == 1,
+		};
 
-		msix.map(|msix| config.queue_msix_vector.set(msix.into()));
+		unsafe { DMA_ADDR += total_size };
 
-		Ok(Queue {
-			_config: config,
+		let queue = Queue {
+			_marker: PhantomData,
 			mask: size as u16 - 1,
 			last_used: 0,
 			free_descriptors,
@@ -207,8 +288,10 @@ impl<'a> Queue<'a> {
 			descriptors,
 			available,
 			used,
+			index,
 			notify_offset,
-		})
+		};
+		Ok((queue, phys))
 	}
 
 	/// Convert an iterator of `(address, data)` into a linked list of descriptors and put it in the
@@ -322,6 +405,11 @@ impl<'a> Queue<'a> {
 		}
 	}
 
+	/// Return the index of this queue.
+	pub fn index(&self) -> u16 {
+		self.index
+	}
+
 	/// Return the offset relative to the notify address to flush this queue.
 	pub fn notify_offset(&self) -> u16 {
 		self.notify_offset
@@ -1,10 +1,12 @@
-#![no_std]
+#![cfg_attr(not(test), no_std)]
 
+mod metrics;
 mod sector;
 
+pub use metrics::*;
 pub use sector::Sector;
 
-use core::convert::TryInto;
+use core::convert::{TryFrom, TryInto};
 use core::fmt;
 use core::mem;
 use simple_endian::{u16le, u32le, u64le};
@@ -39,8 +41,12 @@ pub struct BlockDevice<'a> {
 	queue: queue::Queue<'a>,
 	notify: virtio::pci::Notify<'a>,
 	isr: &'a virtio::pci::ISR,
+	/// The interface used to drive the device.
+	mode: Mode,
 	/// The amount of sectors available
 	_capacity: u64,
+	/// Counters of completed requests.
+	metrics: Metrics,
 }
 
 #[repr(C)]
@@ -87,13 +93,33 @@ struct RequestHeader {
 impl RequestHeader {
 	const READ: u32 = 0;
 	const WRITE: u32 = 1;
+	const GET_ID: u32 = 8;
 }
 
+/// The buffer the device writes its ID to. The alignment ensures it doesn't cross a page.
+#[repr(C, align(32))]
+struct DeviceId([u8; DEVICE_ID_LEN]);
+
+/// The maximum length of a device ID.
+pub const DEVICE_ID_LEN: usize = 20;
+
 #[repr(C)]
 struct RequestStatus {
 	status: u8,
 }
 
+impl RequestStatus {
+	const OK: u8 = 0;
+	const IOERR: u8 = 1;
+	const UNSUPP: u8 = 2;
+
+	/// Read the status written by the device.
+	fn get(&self) -> u8 {
+		// SAFETY: the status is a valid u8 that may have been written by the device.
+		unsafe { core::ptr::read_volatile(&self.status) }
+	}
+}
+
 use virtio::pci::*;
 
 impl<'a> BlockDevice<'a> {
@@ -137,10 +163,72 @@ impl<'a> BlockDevice<'a> {
 			queue,
 			notify,
 			isr,
+			mode: Mode::Modern,
 			_capacity: blk_cfg.capacity.into(),
+			metrics: Metrics::default(),
 		})
 	}
 
+	/// Setup a legacy block device
+	///
+	/// This is meant to be used as a handler by the `virtio` crate.
+	///
+	/// The request header and configuration are guest-endian for legacy devices, which is the
+	/// same as the little endian layout used for modern devices on all supported targets.
+	#[cfg(feature = "legacy")]
+	pub fn new_legacy(
+		config: &'a virtio::legacy::Config,
+		device: &'a DeviceConfig,
+		notify: Notify<'a>,
+		isr: &'a virtio::pci::ISR,
+	) -> Result<Self, SetupError> {
+		config
+			.device_status
+			.set(CommonConfig::STATUS_ACKNOWLEDGE | CommonConfig::STATUS_DRIVER);
+
+		// Legacy devices only have 32 feature bits and don't use FEATURES_OK.
+		let features = SIZE_MAX | SEG_MAX | GEOMETRY | BLK_SIZE | TOPOLOGY;
+		let features = u32le::from(features) & config.device_features.get();
+		config.driver_features.set(features);
+
+		let blk_cfg = unsafe { device.cast::<Config>() };
+
+		// Set up queue.
+		let queue = queue::Queue::<'a>::new_legacy(config, 0).expect("OOM");
+
+		config.device_status.set(
+			CommonConfig::STATUS_ACKNOWLEDGE
+				| CommonConfig::STATUS_DRIVER
+				| CommonConfig::STATUS_DRIVER_OK,
+		);
+
+		Ok(Self {
+			queue,
+			notify,
+			isr,
+			mode: Mode::Legacy,
+			_capacity: blk_cfg.capacity.into(),
+			metrics: Metrics::default(),
+		})
+	}
+
+	/// The interface used to drive the device.
+	#[inline(always)]
+	pub fn mode(&self) -> Mode {
+		self.mode
+	}
+
+	/// Counters of the requests completed since creation or the last reset.
+	#[inline(always)]
+	pub fn metrics(&self) -> Metrics {
+		self.metrics
+	}
+
+	/// Reset all counters to zero.
+	pub fn reset_metrics(&mut self) {
+		self.metrics = Metrics::default();
+	}
+
 	/// Write out sectors
 	pub fn write<'s>(
 		&'s mut self,
@@ -156,6 +244,7 @@ impl<'a> BlockDevice<'a> {
 		let status = RequestStatus { status: 111 };
 		let (mut phys_header, mut phys_data, mut phys_status) = (0, 0, 0);
 		let h = &header as *const _ as usize;
+		let sectors = data.as_ref().len();
 		let d = data.as_ref() as *const _ as *const u8 as usize;
 		let s = &status as *const _ as usize;
 		let (hp, ho) = (h & !0xfff, h & 0xfff);
@@ -191,6 +280,7 @@ impl<'a> BlockDevice<'a> {
 			),
 		];
 
+		let start = now();
 		self.queue
 			.send(data.iter().copied(), None, None)
 			.expect("Failed to send data");
@@ -199,6 +289,9 @@ impl<'a> BlockDevice<'a> {
 
 		self.queue.wait_for_used(None, wait);
 
+		let latency = now().saturating_sub(start);
+		self.metrics.add(true, sectors, status.get(), latency);
+
 		Ok(())
 	}
 
@@ -217,6 +310,7 @@ impl<'a> BlockDevice<'a> {
 		let status = RequestStatus { status: 111 };
 		let (mut phys_header, mut phys_data, mut phys_status) = (0, 0, 0);
 		let h = &header as *const _ as usize;
+		let sectors = data.as_mut().len();
 		let d = data.as_mut() as *mut _ as *mut u8 as usize;
 		let s = &status as *const _ as usize;
 		let (hp, ho) = (h & !0xfff, h & 0xfff);
@@ -252,6 +346,7 @@ impl<'a> BlockDevice<'a> {
 			),
 		];
 
+		let start = now();
 		self.queue
 			.send(data.iter().copied(), None, None)
 			.expect("Failed to send data");
@@ -260,11 +355,78 @@ impl<'a> BlockDevice<'a> {
 
 		self.queue.wait_for_used(None, wait);
 
+		let latency = now().saturating_sub(start);
+		self.metrics.add(false, sectors, status.get(), latency);
+
 		Ok(())
 	}
 
+	/// Read the ID of the device, e.g. the serial set with `-device virtio-blk-pci,serial=...` in
+	/// QEMU, and return its length. The ID is not NUL terminated if it is exactly 20 bytes long.
+	///
+	/// Devices that don't support this request have an empty ID.
+	pub fn device_id(
+		&mut self,
+		buf: &mut [u8; DEVICE_ID_LEN],
+		wait: impl FnMut(),
+	) -> Result<usize, DeviceIdError> {
+		let header = RequestHeader {
+			typ: RequestHeader::GET_ID.into(),
+			reserved: 0.into(),
+			sector: 0.into(),
+		};
+		let id = DeviceId([0; DEVICE_ID_LEN]);
+		let status = RequestStatus { status: 111 };
+
+		let phys = |p: usize| {
+			let mut phys = 0;
+			let ret = unsafe {
+				kernel::mem_physical_address((p & !0xfff) as *const _, &mut phys as *mut _, 1)
+			};
+			assert_eq!(ret.status, 0, "Failed DMA get phys address");
+			u64::try_from(phys + (p & 0xfff)).unwrap()
+		};
+		let data = [
+			(
+				phys(&header as *const _ as usize),
+				mem::size_of::<RequestHeader>().try_into().unwrap(),
+				false,
+			),
+			(
+				phys(&id as *const _ as usize),
+				DEVICE_ID_LEN.try_into().unwrap(),
+				true,
+			),
+			(
+				phys(&status as *const _ as usize),
+				mem::size_of::<RequestStatus>().try_into().unwrap(),
+				true,
+			),
+		];
+
+		self.queue
+			.send(data.iter().copied(), None, None)
+			.expect("Failed to send data");
+
+		self.flush();
+
+		self.queue.wait_for_used(None, wait);
+
+		match status.get() {
+			RequestStatus::OK => {
+				// SAFETY: the device is done writing to the buffer.
+				let id = unsafe { core::ptr::read_volatile(&id.0) };
+				let len = id.iter().position(|c| *c == 0).unwrap_or(DEVICE_ID_LEN);
+				buf.copy_from_slice(&id);
+				Ok(len)
+			}
+			RequestStatus::UNSUPP => Ok(0),
+			_ => Err(DeviceIdError::Io),
+		}
+	}
+
 	pub fn flush(&self) {
-		self.notify.send(0);
+		self.notify.send(&self.queue);
 	}
 
 	#[inline]
@@ -273,6 +435,12 @@ impl<'a> BlockDevice<'a> {
 	}
 }
 
+/// Return the current time in microseconds.
+fn now() -> u64 {
+	// SAFETY: sys_time has no side effects.
+	unsafe { kernel::sys_time() }.value as u64
+}
+
 impl Drop for BlockDevice<'_> {
 	fn drop(&mut self) {
 		todo!("ensure the device doesn't read/write memory after being dropped");
@@ -291,6 +459,19 @@ impl fmt::Debug for SetupError {
 	}
 }
 
+pub enum DeviceIdError {
+	/// The device failed the request.
+	Io,
+}
+
+impl fmt::Debug for DeviceIdError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		f.write_str(match self {
+			Self::Io => "I/O error",
+		})
+	}
+}
+
 pub enum WriteError {}
 
 impl fmt::Debug for WriteError {
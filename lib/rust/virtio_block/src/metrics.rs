@@ -0,0 +1,107 @@
+//! # Request metrics
+
+/// The amount of buckets in the latency histogram.
+pub const LATENCY_BUCKETS: usize = 8;
+
+/// The first bucket counts latencies below `1 << LATENCY_MIN_SHIFT` microseconds.
+const LATENCY_MIN_SHIFT: u32 = 6;
+
+/// Counters of the requests completed by a [`BlockDevice`](crate::BlockDevice).
+///
+/// All counters wrap on overflow.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+#[repr(C)]
+pub struct Metrics {
+	/// The amount of read requests.
+	pub reads: u64,
+	/// The amount of write requests.
+	pub writes: u64,
+	/// The amount of sectors read by successful requests.
+	pub sectors_read: u64,
+	/// The amount of sectors written by successful requests.
+	pub sectors_written: u64,
+	/// Requests that failed with `VIRTIO_BLK_S_IOERR`.
+	pub io_errors: u64,
+	/// Requests that failed with `VIRTIO_BLK_S_UNSUPP`.
+	pub unsupported: u64,
+	/// Requests that completed with a status not defined by the specification.
+	pub unknown_status: u64,
+	/// Histogram of request latencies. Bucket 0 counts requests that took less than 64
+	/// microseconds and every next bucket covers twice the time of the previous one. The last
+	/// bucket counts everything from 4096 microseconds.
+	pub latency: [u64; LATENCY_BUCKETS],
+}
+
+impl Metrics {
+	/// Count a completed request.
+	pub(crate) fn add(&mut self, write: bool, sectors: usize, status: u8, latency: u64) {
+		let (requests, transferred) = if write {
+			(&mut self.writes, &mut self.sectors_written)
+		} else {
+			(&mut self.reads, &mut self.sectors_read)
+		};
+		*requests = requests.wrapping_add(1);
+		let errors = match status {
+			crate::RequestStatus::OK => {
+				*transferred = transferred.wrapping_add(sectors as u64);
+				None
+			}
+			crate::RequestStatus::IOERR => Some(&mut self.io_errors),
+			crate::RequestStatus::UNSUPP => Some(&mut self.unsupported),
+			_ => Some(&mut self.unknown_status),
+		};
+		if let Some(errors) = errors {
+			*errors = errors.wrapping_add(1);
+		}
+		let bucket = &mut self.latency[latency_bucket(latency)];
+		*bucket = bucket.wrapping_add(1);
+	}
+}
+
+/// Return the index of the histogram bucket for a latency in microseconds.
+pub fn latency_bucket(latency: u64) -> usize {
+	let bits = 64 - latency.leading_zeros();
+	(bits.saturating_sub(LATENCY_MIN_SHIFT) as usize).min(LATENCY_BUCKETS - 1)
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test]
+	fn bucket_bounds() {
+		assert_eq!(latency_bucket(0), 0);
+		assert_eq!(latency_bucket(1), 0);
+		assert_eq!(latency_bucket(63), 0);
+		assert_eq!(latency_bucket(64), 1);
+		assert_eq!(latency_bucket(127), 1);
+		assert_eq!(latency_bucket(128), 2);
+		assert_eq!(latency_bucket(2047), 5);
+		assert_eq!(latency_bucket(2048), 6);
+		assert_eq!(latency_bucket(4095), 6);
+		assert_eq!(latency_bucket(4096), 7);
+		assert_eq!(latency_bucket(u64::MAX), 7);
+	}
+
+	#[test]
+	fn add() {
+		let mut m = Metrics::default();
+		m.add(false, 8, crate::RequestStatus::OK, 10);
+		m.add(true, 8, crate::RequestStatus::IOERR, 100);
+		m.add(true, 1, crate::RequestStatus::OK, 1_000_000);
+		m.add(false, 8, 111, 100);
+		assert_eq!(
+			m,
+			Metrics {
+				reads: 2,
+				writes: 2,
+				sectors_read: 8,
+				sectors_written: 1,
+				io_errors: 1,
+				unsupported: 0,
+				unknown_status: 1,
+				latency: [1, 2, 0, 0, 0, 0, 0, 1],
+			}
+		);
+	}
+}
@@ -1,19 +1,61 @@
 use super::*;
+use crate::MAX_SCANOUTS;
+use core::mem;
 
-#[allow(dead_code)]
-const MAX_SCANOUTS: u32 = 16;
-
-#[allow(dead_code)]
+/// The response to a `GET_DISPLAY_INFO` command.
 #[repr(C)]
 pub struct DisplayInfo {
 	header: ControlHeader,
-	pmodes: [DisplayOne; MAX_SCANOUTS as usize],
+	pmodes: [DisplayOne; MAX_SCANOUTS],
 }
 
-#[allow(dead_code)]
+const _DISPLAY_INFO_SIZE_CHECK: usize = 0 - (408 - mem::size_of::<DisplayInfo>());
+
+impl DisplayInfo {
+	/// Create a zeroed response buffer.
+	pub(crate) fn new() -> Self {
+		Self {
+			header: ControlHeader::new(0, None),
+			pmodes: [DisplayOne {
+				rect: Rect::new(0, 0, 0, 0),
+				enabled: 0.into(),
+				flags: 0.into(),
+			}; MAX_SCANOUTS],
+		}
+	}
+
+	/// The type of the response.
+	#[inline(always)]
+	pub(crate) fn response(&self) -> u32 {
+		self.header.ty.into()
+	}
+
+	/// The preferred mode of each scanout.
+	#[inline(always)]
+	pub fn modes(&self) -> &[DisplayOne; MAX_SCANOUTS] {
+		&self.pmodes
+	}
+}
+
+/// The preferred mode of a single scanout.
+#[derive(Clone, Copy)]
 #[repr(C)]
 pub struct DisplayOne {
 	rect: Rect,
 	enabled: u32le,
 	flags: u32le,
 }
+
+impl DisplayOne {
+	/// The position & size of the scanout.
+	#[inline(always)]
+	pub fn rect(&self) -> Rect {
+		self.rect
+	}
+
+	/// Whether a display is connected to the scanout.
+	#[inline(always)]
+	pub fn enabled(&self) -> bool {
+		u32::from(self.enabled) != 0
+	}
+}
@@ -1,5 +1,10 @@
 use super::*;
+use core::fmt;
+use core::mem;
 
+/// Attach guest memory to a resource.
+///
+/// The command is followed by `count` [`MemoryEntry`]s in the same buffer.
 #[repr(C)]
 pub struct AttachBacking {
 	header: ControlHeader,
@@ -7,7 +12,15 @@ pub struct AttachBacking {
 	entities_count: u32le,
 }
 
+const _ATTACH_BACKING_SIZE_CHECK: usize = 0 - (32 - mem::size_of::<AttachBacking>());
+const _ATTACH_BACKING_ALIGN_CHECK: usize = 0 - (8 - mem::align_of::<AttachBacking>());
+
 impl AttachBacking {
+	/// Create a new `RESOURCE_ATTACH_BACKING` command.
+	///
+	/// * `resource_id` is the resource the memory will be attached to.
+	/// * `count` is the amount of [`MemoryEntry`]s that follow this command.
+	/// * `fence` is the fence ID to use, if any.
 	pub fn new(resource_id: u32, count: u32, fence: Option<u64>) -> Self {
 		Self {
 			header: ControlHeader::new(ControlHeader::CMD_RESOURCE_ATTACH_BACKING, fence),
@@ -15,8 +28,31 @@ impl AttachBacking {
 			entities_count: count.into(),
 		}
 	}
+
+	/// The resource the memory will be attached to.
+	#[inline(always)]
+	pub fn resource_id(&self) -> u32 {
+		self.resource_id.into()
+	}
+
+	/// The amount of [`MemoryEntry`]s that follow this command.
+	#[inline(always)]
+	pub fn count(&self) -> u32 {
+		self.entities_count.into()
+	}
+}
+
+impl fmt::Debug for AttachBacking {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		f.debug_struct("resource::AttachBacking")
+			.field("header", &self.header)
+			.field("resource_id", &self.resource_id())
+			.field("count", &self.count())
+			.finish()
+	}
 }
 
+/// A single range of guest memory to be used as backing storage.
 #[derive(Clone, Copy)] // Mainly so we can use it with arrays.
 #[repr(C)]
 pub struct MemoryEntry {
@@ -25,7 +61,18 @@ pub struct MemoryEntry {
 	_padding: u32le,
 }
 
+const _MEMORY_ENTRY_SIZE_CHECK: usize = 0 - (16 - mem::size_of::<MemoryEntry>());
+const _MEMORY_ENTRY_ALIGN_CHECK: usize = 0 - (8 - mem::align_of::<MemoryEntry>());
+
 impl MemoryEntry {
+	/// Create a new memory entry.
+	///
+	/// * `address` is the *physical* address of the start of the range.
+	/// * `length` is the size of the range in bytes.
+	///
+	/// The specification puts no alignment requirements on either the address or the length,
+	/// so neither is checked. The device may however be slower with ranges that don't cover
+	/// whole pages.
 	pub fn new(address: u64, length: u32) -> Self {
 		Self {
 			address: address.into(),
@@ -33,4 +80,25 @@ impl MemoryEntry {
 			_padding: 0.into(),
 		}
 	}
+
+	/// The physical address of the start of the range.
+	#[inline(always)]
+	pub fn address(&self) -> u64 {
+		self.address.into()
+	}
+
+	/// The size of the range in bytes.
+	#[inline(always)]
+	pub fn length(&self) -> u32 {
+		self.length.into()
+	}
+}
+
+impl fmt::Debug for MemoryEntry {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		f.debug_struct("resource::MemoryEntry")
+			.field("address", &format_args!("0x{:x}", self.address()))
+			.field("length", &self.length())
+			.finish()
+	}
 }
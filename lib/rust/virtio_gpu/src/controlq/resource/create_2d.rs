@@ -1,8 +1,10 @@
 use crate::ControlHeader;
 use core::convert::TryFrom;
 use core::fmt;
+use core::mem;
 use simple_endian::u32le;
 
+/// Create a 2D resource in host memory.
 #[repr(C)]
 pub struct Create2D {
 	header: ControlHeader,
@@ -12,21 +14,61 @@ pub struct Create2D {
 	height: u32le,
 }
 
+const _CREATE_2D_SIZE_CHECK: usize = 0 - (40 - mem::size_of::<Create2D>());
+const _CREATE_2D_ALIGN_CHECK: usize = 0 - (8 - mem::align_of::<Create2D>());
+
 impl Create2D {
+	/// Create a new `RESOURCE_CREATE_2D` command.
+	///
+	/// * `resource_id` is the ID the new resource will be known by.
+	/// * `format` is the pixel format of the resource.
+	/// * `width` and `height` are the dimensions of the resource in pixels. Neither may be 0.
+	/// * `fence` is the fence ID to use, if any.
 	pub fn new(
 		resource_id: u32,
 		format: Format,
 		width: u32,
 		height: u32,
 		fence: Option<u64>,
-	) -> Self {
-		Self {
+	) -> Result<Self, Create2DError> {
+		if width == 0 {
+			return Err(Create2DError::ZeroWidth);
+		}
+		if height == 0 {
+			return Err(Create2DError::ZeroHeight);
+		}
+		Ok(Self {
 			header: ControlHeader::new(ControlHeader::CMD_RESOURCE_CREATE_2D, fence),
 			resource_id: resource_id.into(),
 			format: u32::from(format).into(),
 			width: width.into(),
 			height: height.into(),
-		}
+		})
+	}
+
+	/// The ID the new resource will be known by.
+	#[inline(always)]
+	pub fn resource_id(&self) -> u32 {
+		self.resource_id.into()
+	}
+
+	/// The pixel format of the resource. If the format is unknown, the raw value is returned.
+	#[inline(always)]
+	pub fn format(&self) -> Result<Format, u32> {
+		let format = u32::from(self.format);
+		Format::try_from(format).map_err(|()| format)
+	}
+
+	/// The width of the resource in pixels.
+	#[inline(always)]
+	pub fn width(&self) -> u32 {
+		self.width.into()
+	}
+
+	/// The height of the resource in pixels.
+	#[inline(always)]
+	pub fn height(&self) -> u32 {
+		self.height.into()
 	}
 }
 
@@ -34,19 +76,28 @@ impl fmt::Debug for Create2D {
 	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
 		let mut d = f.debug_struct("resource::Create2D");
 		d.field("header", &self.header);
-		d.field("resource_id", &u32::from(self.resource_id));
+		d.field("resource_id", &self.resource_id());
 
-		match Format::try_from(u32::from(self.format)) {
+		match self.format() {
 			Ok(f) => d.field("format", &f),
-			Err(()) => d.field("format", &format_args!("0x{:x}", u32::from(self.format))),
+			Err(f) => d.field("format", &format_args!("0x{:x}", f)),
 		};
 
-		d.field("width", &u32::from(self.resource_id));
-		d.field("height", &u32::from(self.resource_id));
+		d.field("width", &self.width());
+		d.field("height", &self.height());
 		d.finish()
 	}
 }
 
+/// Errors that can occur when creating a [`Create2D`] command.
+#[derive(Debug)]
+pub enum Create2DError {
+	/// The width is 0.
+	ZeroWidth,
+	/// The height is 0.
+	ZeroHeight,
+}
+
 #[derive(Clone, Copy, Debug)]
 #[repr(u32)]
 #[non_exhaustive]
@@ -1,9 +1,43 @@
 use super::*;
+use core::fmt;
+use core::mem;
 
-#[allow(dead_code)]
+/// Detach the guest memory from a resource.
 #[repr(C)]
 pub struct DetachBacking {
 	header: ControlHeader,
 	resource_id: u32le,
 	_padding: u32le,
 }
+
+const _DETACH_BACKING_SIZE_CHECK: usize = 0 - (32 - mem::size_of::<DetachBacking>());
+const _DETACH_BACKING_ALIGN_CHECK: usize = 0 - (8 - mem::align_of::<DetachBacking>());
+
+impl DetachBacking {
+	/// Create a new `RESOURCE_DETACH_BACKING` command.
+	///
+	/// * `resource_id` is the resource to detach the memory from.
+	/// * `fence` is the fence ID to use, if any.
+	pub fn new(resource_id: u32, fence: Option<u64>) -> Self {
+		Self {
+			header: ControlHeader::new(ControlHeader::CMD_RESOURCE_DETACH_BACKING, fence),
+			resource_id: resource_id.into(),
+			_padding: 0.into(),
+		}
+	}
+
+	/// The resource to detach the memory from.
+	#[inline(always)]
+	pub fn resource_id(&self) -> u32 {
+		self.resource_id.into()
+	}
+}
+
+impl fmt::Debug for DetachBacking {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		f.debug_struct("resource::DetachBacking")
+			.field("header", &self.header)
+			.field("resource_id", &self.resource_id())
+			.finish()
+	}
+}
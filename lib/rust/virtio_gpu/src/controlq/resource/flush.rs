@@ -1,5 +1,8 @@
 use super::*;
+use core::fmt;
+use core::mem;
 
+/// Flush a region of a resource to any scanouts it is attached to.
 #[repr(C)]
 pub struct Flush {
 	header: ControlHeader,
@@ -8,7 +11,15 @@ pub struct Flush {
 	_padding: u32le,
 }
 
+const _FLUSH_SIZE_CHECK: usize = 0 - (48 - mem::size_of::<Flush>());
+const _FLUSH_ALIGN_CHECK: usize = 0 - (8 - mem::align_of::<Flush>());
+
 impl Flush {
+	/// Create a new `RESOURCE_FLUSH` command.
+	///
+	/// * `resource_id` is the resource to flush.
+	/// * `rect` is the region of the resource to flush.
+	/// * `fence` is the fence ID to use, if any.
 	pub fn new(resource_id: u32, rect: Rect, fence: Option<u64>) -> Self {
 		Self {
 			header: ControlHeader::new(ControlHeader::CMD_RESOURCE_FLUSH, fence),
@@ -17,4 +28,26 @@ impl Flush {
 			_padding: 0.into(),
 		}
 	}
+
+	/// The resource to flush.
+	#[inline(always)]
+	pub fn resource_id(&self) -> u32 {
+		self.resource_id.into()
+	}
+
+	/// The region of the resource to flush.
+	#[inline(always)]
+	pub fn rect(&self) -> Rect {
+		self.rect
+	}
+}
+
+impl fmt::Debug for Flush {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		f.debug_struct("resource::Flush")
+			.field("header", &self.header)
+			.field("rect", &self.rect)
+			.field("resource_id", &self.resource_id())
+			.finish()
+	}
 }
@@ -1,9 +1,43 @@
 use super::*;
+use core::fmt;
+use core::mem;
 
-#[allow(dead_code)]
+/// Destroy a resource.
 #[repr(C)]
 pub struct Unreference {
 	header: ControlHeader,
 	resource_id: u32le,
-	padding: u32le,
+	_padding: u32le,
+}
+
+const _UNREFERENCE_SIZE_CHECK: usize = 0 - (32 - mem::size_of::<Unreference>());
+const _UNREFERENCE_ALIGN_CHECK: usize = 0 - (8 - mem::align_of::<Unreference>());
+
+impl Unreference {
+	/// Create a new `RESOURCE_UNREF` command.
+	///
+	/// * `resource_id` is the resource to destroy.
+	/// * `fence` is the fence ID to use, if any.
+	pub fn new(resource_id: u32, fence: Option<u64>) -> Self {
+		Self {
+			header: ControlHeader::new(ControlHeader::CMD_RESOURCE_UNREF, fence),
+			resource_id: resource_id.into(),
+			_padding: 0.into(),
+		}
+	}
+
+	/// The resource to destroy.
+	#[inline(always)]
+	pub fn resource_id(&self) -> u32 {
+		self.resource_id.into()
+	}
+}
+
+impl fmt::Debug for Unreference {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		f.debug_struct("resource::Unreference")
+			.field("header", &self.header)
+			.field("resource_id", &self.resource_id())
+			.finish()
+	}
 }
@@ -3,8 +3,9 @@
 mod controlq;
 mod cursorq;
 
+pub use controlq::resource;
 pub use controlq::resource::create_2d::Format;
-pub use controlq::Rect;
+pub use controlq::{DisplayInfo, DisplayOne, Rect};
 
 use core::convert::TryInto;
 use core::fmt;
@@ -19,6 +20,15 @@ use vcell::VolatileCell;
 const FEATURE_VIRGL: u32 = 0x1;
 const FEATURE_EDID: u32 = 0x2;
 
+/// The maximum amount of scanouts a device can have.
+pub const MAX_SCANOUTS: usize = 16;
+
+/// The maximum amount of pages that can back a single resource.
+pub const MAX_BACKING_PAGES: usize = 1024;
+
+/// The size of a pixel in bytes. All supported formats use 4 bytes per pixel.
+const PIXEL_SIZE: u32 = 4;
+
 #[allow(dead_code)]
 #[repr(C)]
 struct Config {
@@ -29,7 +39,6 @@ struct Config {
 }
 
 impl Config {
-	#[allow(dead_code)]
 	const EVENT_DISPLAY: u32 = 0x1;
 }
 
@@ -145,12 +154,19 @@ impl fmt::Debug for ControlHeader {
 
 /// A handle to a resource
 #[derive(Clone, Copy)]
-pub struct Resource(NonZeroU32);
+pub struct Resource {
+	id: NonZeroU32,
+	/// The width in pixels, used to find the start of a region in the backing memory.
+	width: u32,
+}
 
 pub struct Device<'a> {
 	notify: virtio::pci::Notify<'a>,
 	controlq: virtio::queue::Queue<'a>,
 	cursorq: virtio::queue::Queue<'a>,
+	config: &'a Config,
+	/// The ID of the next resource to create. IDs are never reused.
+	next_resource_id: u32,
 }
 
 impl<'a> Device<'a> {
@@ -159,7 +175,7 @@ impl<'a> Device<'a> {
 	/// This is meant to be used as a handler by the `virtio` crate.
 	pub fn new(
 		common: &'a virtio::pci::CommonConfig,
-		_device: &'a virtio::pci::DeviceConfig,
+		device: &'a virtio::pci::DeviceConfig,
 		notify: virtio::pci::Notify<'a>,
 		_isr: &'a virtio::pci::ISR,
 	) -> Result<Self, SetupError> {
@@ -190,26 +206,70 @@ impl<'a> Device<'a> {
 			controlq,
 			cursorq,
 			notify,
+			// SAFETY: the device config of a GPU device is a Config.
+			config: unsafe { device.cast() },
+			next_resource_id: 1,
 		})
 	}
 
+	/// The amount of scanouts of the device.
+	pub fn scanout_count(&self) -> u32 {
+		u32::from(self.config.num_scanouts.get()).min(MAX_SCANOUTS as u32)
+	}
+
+	/// Check whether the display configuration changed since the last call, e.g. because a
+	/// display was connected. [`Self::display_info`] should be called again if so.
+	pub fn display_changed(&self) -> bool {
+		let events = u32::from(self.config.events_read.get()) & Config::EVENT_DISPLAY;
+		if events > 0 {
+			self.config.events_clear.set(events.into());
+		}
+		events > 0
+	}
+
+	/// Get the preferred mode of every scanout.
+	pub fn display_info(&mut self) -> Result<DisplayInfo, DisplayInfoError> {
+		let cmd = ControlHeader::new(ControlHeader::CMD_GET_DISPLAY_INFO, Some(0));
+		let mut info = DisplayInfo::new();
+		let data = [
+			Self::create_queue_entry(Pin::new(&cmd), None),
+			Self::create_queue_entry_mut(Pin::new(&mut info), None),
+		];
+		self.controlq
+			.send(data.iter().copied(), None, None)
+			.expect("failed to send data");
+		self.flush();
+		self.controlq.wait_for_used(None, || ());
+
+		match info.response() {
+			ControlHeader::RESP_OK_DISPLAY_INFO => Ok(info),
+			r => Err(DisplayInfoError::Response(r)),
+		}
+	}
+
+	/// Create a resource backed by the given memory and show it on a scanout.
+	///
+	/// # Safety
+	///
+	/// The memory must stay allocated until the resource is destroyed with
+	/// [`Self::disable_scanout`].
 	pub unsafe fn init_scanout(
 		&mut self,
+		scanout: u32,
 		format: Format,
 		rect: Rect,
 		backend: NonNull<kernel::Page>,
 		count: usize,
 	) -> Result<Resource, InitScanoutError> {
-		let res_id = 1;
-		let scan_id = 0;
+		if scanout >= self.scanout_count() {
+			return Err(InitScanoutError::InvalidScanout);
+		}
+		let id = self.allocate_resource_id();
+		let res_id = id.get();
+		let scan_id = scanout;
 
-		self.create_resource(
-			NonZeroU32::new(res_id).unwrap(),
-			rect,
-			format,
-			backend,
-			count,
-		);
+		self.create_resource(id, rect, format, backend, count)
+			.map_err(InitScanoutError::Create2D)?;
 
 		// Response buffer
 		let mut resp_buffer = ControlHeader::new(0, None);
@@ -228,7 +288,42 @@ impl<'a> Device<'a> {
 		self.flush();
 		self.controlq.wait_for_used(None, || ());
 
-		Ok(Resource(NonZeroU32::new(res_id).unwrap()))
+		Ok(Resource {
+			id,
+			width: rect.width(),
+		})
+	}
+
+	/// Stop showing a resource on a scanout and destroy the resource. The backing memory can
+	/// be freed afterwards.
+	pub fn disable_scanout(&mut self, scanout: u32, resource: Resource) {
+		let res_id = resource.id.get();
+
+		// Response buffer
+		let mut resp_buffer = ControlHeader::new(0, None);
+		let resp_buffer = Pin::new(&mut resp_buffer);
+		let resp_data = Self::create_queue_entry_mut(resp_buffer, None);
+
+		// A resource ID of 0 disables the scanout.
+		let rect = Rect::new(0, 0, 0, 0);
+		let scanout = controlq::SetScanout::new(scanout, 0, rect, Some(0));
+		let detach = controlq::resource::DetachBacking::new(res_id, Some(0));
+		let unref = controlq::resource::Unreference::new(res_id, Some(0));
+		for cmd in [
+			Self::create_queue_entry(Pin::new(&scanout), None),
+			Self::create_queue_entry(Pin::new(&detach), None),
+			Self::create_queue_entry(Pin::new(&unref), None),
+		]
+		.iter()
+		.copied()
+		{
+			let data = [cmd, resp_data];
+			self.controlq
+				.send(data.iter().copied(), None, None)
+				.expect("failed to send data");
+			self.flush();
+			self.controlq.wait_for_used(None, || ());
+		}
 	}
 
 	pub unsafe fn init_cursor(
@@ -240,17 +335,13 @@ impl<'a> Device<'a> {
 		count: usize,
 	) -> Result<Resource, InitCursorError> {
 		assert_eq!(count, 4);
-		let res_id = 2;
+		let id = self.allocate_resource_id();
+		let res_id = id.get();
 		let scan_id = 0;
 
 		let rect = Rect::new(0, 0, 64, 64);
-		self.create_resource(
-			NonZeroU32::new(res_id).unwrap(),
-			rect,
-			format,
-			backend,
-			count,
-		);
+		self.create_resource(id, rect, format, backend, count)
+			.expect("cursor rect is never empty");
 
 		// Response buffer
 		let mut resp_buffer = ControlHeader::new(0, None);
@@ -266,7 +357,10 @@ impl<'a> Device<'a> {
 		self.flush();
 		self.cursorq.wait_for_used(None, || ());
 
-		Ok(Resource(NonZeroU32::new(res_id).unwrap()))
+		Ok(Resource {
+			id,
+			width: rect.width(),
+		})
 	}
 
 	pub fn update_cursor(
@@ -275,7 +369,7 @@ impl<'a> Device<'a> {
 		hot_x: u32,
 		hot_y: u32,
 	) -> Result<Resource, UpdateCursorError> {
-		let res_id = resource.0.get();
+		let res_id = resource.id.get();
 		let scan_id = 0;
 
 		// Response buffer
@@ -292,7 +386,7 @@ impl<'a> Device<'a> {
 		self.flush();
 		self.cursorq.wait_for_used(None, || ());
 
-		Ok(Resource(NonZeroU32::new(res_id).unwrap()))
+		Ok(resource)
 	}
 
 	pub fn move_cursor(&mut self, x: u32, y: u32) -> Result<(), MoveCursorError> {
@@ -315,8 +409,13 @@ impl<'a> Device<'a> {
 		Ok(())
 	}
 
+	/// Copy a region of a resource to the host and flush it to the scanouts the resource is
+	/// attached to.
 	pub fn draw(&mut self, resource: Resource, rect: Rect) -> Result<(), DrawError> {
-		let res_id = resource.0.get();
+		let res_id = resource.id.get();
+		// The offset of the first pixel of the region in the backing memory.
+		let offset = (u64::from(rect.y()) * u64::from(resource.width) + u64::from(rect.x()))
+			* u64::from(PIXEL_SIZE);
 
 		// Response buffer
 		let mut resp_buffer = ControlHeader::new(0, None);
@@ -324,7 +423,7 @@ impl<'a> Device<'a> {
 		let resp_data = Self::create_queue_entry_mut(resp_buffer, None);
 
 		// Transfer to host
-		let res = controlq::TransferToHost2D::new(res_id, 0, rect, Some(0));
+		let res = controlq::TransferToHost2D::new(res_id, offset, rect, Some(0));
 		let res = Pin::new(&res);
 		let data = [Self::create_queue_entry(res, None), resp_data];
 		self.controlq
@@ -353,8 +452,8 @@ impl<'a> Device<'a> {
 		format: Format,
 		backend: NonNull<kernel::Page>,
 		count: usize,
-	) {
-		const MAX_PAGES: usize = 1024;
+	) -> Result<(), resource::Create2DError> {
+		const MAX_PAGES: usize = MAX_BACKING_PAGES;
 
 		// Response buffer
 		let mut resp_buffer = ControlHeader::new(0, None);
@@ -391,7 +490,7 @@ impl<'a> Device<'a> {
 			rect.width(),
 			rect.height(),
 			Some(0),
-		);
+		)?;
 		let res = Pin::new(&res);
 		let res_ptr = &*res as *const _ as usize;
 		let (ppn, offt) = (res_ptr & !kernel::Page::MASK, res_ptr & kernel::Page::MASK);
@@ -449,6 +548,14 @@ impl<'a> Device<'a> {
 			.expect("failed to send data");
 		self.flush();
 		self.controlq.wait_for_used(None, || ());
+
+		Ok(())
+	}
+
+	fn allocate_resource_id(&mut self) -> NonZeroU32 {
+		let id = NonZeroU32::new(self.next_resource_id).expect("out of resource IDs");
+		self.next_resource_id = self.next_resource_id.wrapping_add(1);
+		id
 	}
 
 	fn create_queue_entry<T>(buffer: Pin<&T>, size: Option<u32>) -> (u64, u32, bool) {
@@ -478,8 +585,8 @@ impl<'a> Device<'a> {
 	}
 
 	fn flush(&self) {
-		self.notify.send(0);
-		self.notify.send(1);
+		self.notify.send(&self.controlq);
+		self.notify.send(&self.cursorq);
 	}
 }
 
@@ -489,7 +596,17 @@ impl virtio::pci::Device for Device<'_> {}
 pub enum SetupError {}
 
 #[derive(Debug)]
-pub enum InitScanoutError {}
+pub enum DisplayInfoError {
+	/// The device returned an unexpected response.
+	Response(u32),
+}
+
+#[derive(Debug)]
+pub enum InitScanoutError {
+	/// The scanout doesn't exist.
+	InvalidScanout,
+	Create2D(resource::Create2DError),
+}
 
 #[derive(Debug)]
 pub enum InitCursorError {}
@@ -100,17 +100,24 @@ impl fmt::Debug for InputEvent {
 	}
 }
 
+/// The amount of event buffers given to the device.
+const MAX_EVENTS: u16 = 8;
+
 pub struct Device<'a> {
 	config: &'a Config,
 	notify: virtio::pci::Notify<'a>,
+	isr: &'a virtio::pci::ISR,
 	eventq: virtio::queue::Queue<'a>,
 	_statusq: virtio::queue::Queue<'a>,
 	events: NonNull<InputEvent>,
 	events_phys_addr: usize,
+	/// Event buffers that are kept from the device while it is paused.
+	held: [(u64, u32, bool); MAX_EVENTS as usize],
+	held_count: usize,
+	paused: bool,
 }
 
 impl<'a> Device<'a> {
-	const MAX_EVENTS: u16 = 8;
 	const MAX_STATUS: u16 = 8;
 
 	/// Setup an input device
@@ -120,7 +127,7 @@ impl<'a> Device<'a> {
 		common: &'a virtio::pci::CommonConfig,
 		device: &'a virtio::pci::DeviceConfig,
 		notify: virtio::pci::Notify<'a>,
-		_isr: &'a virtio::pci::ISR,
+		isr: &'a virtio::pci::ISR,
 	) -> Result<Self, SetupError> {
 		let features = 0;
 		common.device_feature_select.set(0.into());
@@ -137,8 +144,7 @@ impl<'a> Device<'a> {
 
 		let config = unsafe { device.cast::<Config>() };
 
-		let eventq =
-			virtio::queue::Queue::<'a>::new(common, 0, Self::MAX_EVENTS, None).expect("OOM");
+		let eventq = virtio::queue::Queue::<'a>::new(common, 0, MAX_EVENTS, None).expect("OOM");
 		let statusq =
 			virtio::queue::Queue::<'a>::new(common, 1, Self::MAX_STATUS, None).expect("OOM");
 
@@ -157,8 +163,12 @@ impl<'a> Device<'a> {
 			eventq,
 			_statusq: statusq,
 			notify,
+			isr,
 			events,
 			events_phys_addr,
+			held: [(0, 0, false); MAX_EVENTS as usize],
+			held_count: 0,
+			paused: false,
 		};
 
 		common.device_status.set(
@@ -169,7 +179,7 @@ impl<'a> Device<'a> {
 		);
 
 		assert_eq!(ret.status, 0, "Failed DMA get phys address");
-		for i in 0..Self::MAX_EVENTS.into() {
+		for i in 0..MAX_EVENTS.into() {
 			let size = mem::size_of::<InputEvent>();
 			let phys = slf.events_phys_addr + offt + i * size;
 			let data = [(phys.try_into().unwrap(), size.try_into().unwrap(), true)];
@@ -185,34 +195,67 @@ impl<'a> Device<'a> {
 	/// Collect received entries.
 	///
 	/// This should be called periodically or on interrupt to prevent the queue from getting backed
-	/// up.
+	/// up. The event buffers are given back to the device unless it is paused.
 	pub fn receive(&mut self, callback: &mut dyn FnMut(InputEvent)) -> Result<(), ReceiveError> {
 		let evt = self.events;
 		let evt_phys = self.events_phys_addr;
-		let mut used = [(0, 0, false); Self::MAX_EVENTS as usize];
-		let mut used_count = 0;
+		let used = &mut self.held;
+		let used_count = &mut self.held_count;
 		self.eventq.collect_used(Some(&mut |_, phys, size| {
 			let phys_u = usize::try_from(phys).expect("device returned bad physical address");
 			let i = (phys_u - evt_phys) / mem::size_of::<InputEvent>();
 			assert!(
-				i < usize::from(Self::MAX_EVENTS),
+				i < usize::from(MAX_EVENTS),
 				"device returned bad physical address"
 			);
 
 			callback(unsafe { *evt.as_ptr().add(i) });
 
-			used[used_count] = (phys, size, true);
-			used_count += 1;
+			used[*used_count] = (phys, size, true);
+			*used_count += 1;
 		}));
 
-		for u in used[..used_count].iter().copied() {
+		if !self.paused {
+			self.give_back();
+		}
+
+		Ok(())
+	}
+
+	/// Stop or resume giving event buffers back to the device.
+	///
+	/// While paused, the device runs out of buffers. What happens to events that arrive then is
+	/// up to the device: QEMU drops them. Pausing only keeps the driver from taking events it
+	/// has no room for, so the buffer of the driver must be large enough to hold a burst.
+	pub fn set_paused(&mut self, paused: bool) {
+		self.paused = paused;
+		if !paused {
+			self.give_back();
+		}
+	}
+
+	/// Whether event buffers are kept from the device.
+	pub fn is_paused(&self) -> bool {
+		self.paused
+	}
+
+	/// Give all held event buffers back to the device.
+	fn give_back(&mut self) {
+		if self.held_count == 0 {
+			return;
+		}
+		for u in self.held[..self.held_count].iter().copied() {
 			self.eventq
 				.send([u].iter().copied(), None, None)
 				.expect("failed to send to eventq");
 		}
+		self.held_count = 0;
 		self.flush();
+	}
 
-		Ok(())
+	/// The ISR status register. Reading it acknowledges an interrupt.
+	pub fn isr(&self) -> &'a virtio::pci::ISR {
+		self.isr
 	}
 
 	pub fn name(&self, buf: &mut [u8; 128]) -> u8 {
@@ -240,7 +283,7 @@ impl<'a> Device<'a> {
 	}
 
 	fn flush(&self) {
-		self.notify.send(0)
+		self.notify.send(&self.eventq)
 	}
 }
 
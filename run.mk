@@ -1,6 +1,7 @@
-FIRMWARE    ?= ../riscv/opensbi/build/platform/generic/firmware/fw_jump.bin
-KERNEL      ?= target/kernel.bin
-VIRTIO_DISK ?= target/disk
+FIRMWARE     ?= ../riscv/opensbi/build/platform/generic/firmware/fw_jump.bin
+KERNEL       ?= target/kernel.bin
+VIRTIO_DISK  ?= target/disk
+VIRTIO_DISK2 ?= target/disk2
 
 QEMU=qemu-system-riscv64 \
 		-s \
@@ -10,7 +11,7 @@ QEMU=qemu-system-riscv64 \
 		-bios $(FIRMWARE) \
 		-kernel $(KERNEL) \
 		-drive file=$(VIRTIO_DISK),format=raw,if=none,id=disk0 \
-		-device virtio-blk-pci,drive=disk0 \
+		-device virtio-blk-pci,drive=disk0,serial=disk0 \
 		-device virtio-gpu-pci \
 		-device virtio-keyboard-pci \
 		-device virtio-tablet-pci \
@@ -24,8 +25,49 @@ run: build $(VIRTIO_DISK)
 	@echo Enter Ctrl-A + X to quit
 	$(QEMU) $(QEMU_OPT)
 
+# Attach a second disk and mount it instead of the first one, which is enumerated first
+run-two-disks: build $(VIRTIO_DISK) $(VIRTIO_DISK2)
+	@echo Enter Ctrl-A + X to quit
+	$(QEMU) $(QEMU_OPT) \
+		-drive file=$(VIRTIO_DISK2),format=raw,if=none,id=disk1 \
+		-device virtio-blk-pci,drive=disk1,serial=disk1 \
+		-append "disk=disk1"
+
+FUZZ_TIMEOUT ?= 600
+FUZZ_ARGS    ?= fuzz.iterations=10000
+FUZZ         = scripts/fuzz_qemu.py --timeout $(FUZZ_TIMEOUT) --args "$(FUZZ_ARGS)"
+
+# Fails if the kernel panics before the syscall fuzzer is done
+fuzz-run: build $(VIRTIO_DISK)
+	$(FUZZ) -- $(QEMU) $(QEMU_OPT) -display none
+
+fuzz-regressions-run: build $(VIRTIO_DISK)
+	$(FUZZ) --regressions scripts/fuzz_regressions.list -- $(QEMU) $(QEMU_OPT) -display none
+
 RUST_TARGET ?= riscv64gc-unknown-none-elf
 
+# Fails if the core file of the crash test doesn't match what it wrote to memory
+coredump-check-run: build $(VIRTIO_DISK)
+	scripts/coredump_check.py --disk $(VIRTIO_DISK) \
+		--binary target/$(RUST_TARGET)/release/crash_test \
+		-- $(QEMU) $(QEMU_OPT) -display none
+
+# Fails if the kernel panics or accepts a packet with pointers the sender may not share
+ipc-test-run: build $(VIRTIO_DISK)
+	scripts/ipc_test_qemu.py -- $(QEMU) $(QEMU_OPT) -display none
+
+# Fails if the kernel panics or b0 doesn't restart a supervised task that died
+restart-test-run: build $(VIRTIO_DISK)
+	scripts/restart_test_qemu.py -- $(QEMU) $(QEMU_OPT) -display none
+
+# Fails if the kernel panics or threads sharing a task see a bad counter or bad packets
+thread-test-run: build $(VIRTIO_DISK)
+	scripts/thread_test_qemu.py -- $(QEMU) $(QEMU_OPT) -display none
+
+# Fails if the kernel panics or a 16 KiB paste isn't read completely & rendered within a second
+paste-test-run: build $(VIRTIO_DISK)
+	scripts/paste_test_qemu.py -- $(QEMU) $(QEMU_OPT) -display none
+
 gdb: build $(VIRTIO_DISK)
 	riscv64-unknown-linux-gnu-gdb \
 		-ex='set arch riscv64' \
@@ -36,7 +78,7 @@ gdb-run: build
 	@echo Enter Ctrl-A + X to quit
 	gdb --args $(QEMU) $(QEMU_OPT)
 
-$(VIRTIO_DISK):
+$(VIRTIO_DISK) $(VIRTIO_DISK2):
 	fallocate -l $$((32 * 512)) $@
 
 help-log-trace:
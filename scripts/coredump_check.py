@@ -0,0 +1,81 @@
+#!/usr/bin/env python3
+
+# Boot QEMU with core dumps enabled & the crash test, then check the core file it leaves behind
+# with gdb.
+#
+# Exits with 0 if gdb finds the backtrace, registers & memory the crash test left behind and 1
+# otherwise.
+#
+# Usage: coredump_check.py --disk DISK --binary CRASH_TEST [--timeout SECONDS] -- QEMU...
+#
+# The core file is copied out of the FAT filesystem on DISK with mcopy.
+
+import argparse
+import os
+import re
+import selectors
+import subprocess
+import sys
+import tempfile
+import time
+
+PANIC = b'Kernel panicked!'
+WROTE = re.compile(rb'coredump: wrote (core\.[0-9a-f]+)')
+GDB_SCRIPT = os.path.join(os.path.dirname(os.path.abspath(__file__)), 'coredump.gdb')
+
+
+def run(qemu, timeout):
+    cmd = qemu + ['-append', 'coredump crash']
+    proc = subprocess.Popen(cmd, stdout=subprocess.PIPE, stderr=subprocess.STDOUT)
+    sel = selectors.DefaultSelector()
+    sel.register(proc.stdout, selectors.EVENT_READ)
+    deadline = time.monotonic() + timeout
+    line = b''
+    try:
+        while True:
+            left = deadline - time.monotonic()
+            if left <= 0 or not sel.select(left):
+                return None, 'timed out'
+            data = proc.stdout.read1(4096)
+            if not data:
+                return None, 'QEMU exited'
+            sys.stdout.buffer.write(data)
+            sys.stdout.flush()
+            line += data
+            *lines, line = line.split(b'\n')
+            for l in lines:
+                m = WROTE.search(l)
+                if m:
+                    return m.group(1).decode(), None
+                elif PANIC in l:
+                    return None, 'kernel panicked'
+    finally:
+        proc.kill()
+        proc.wait()
+
+
+def main():
+    p = argparse.ArgumentParser()
+    p.add_argument('--timeout', type=float, default=120)
+    p.add_argument('--disk', required=True)
+    p.add_argument('--binary', required=True)
+    p.add_argument('qemu', nargs='+')
+    a = p.parse_args()
+
+    name, error = run(a.qemu, a.timeout)
+    if name is None:
+        print('\ncoredump: FAILED ({})'.format(error), file=sys.stderr)
+        sys.exit(1)
+
+    with tempfile.TemporaryDirectory() as d:
+        core = os.path.join(d, name)
+        subprocess.run(['mcopy', '-i', a.disk, '::' + name, core], check=True)
+        gdb = ['gdb-multiarch', '-batch', '-x', GDB_SCRIPT, a.binary, core]
+        if subprocess.run(gdb).returncode != 0:
+            print('coredump: FAILED (gdb)', file=sys.stderr)
+            sys.exit(1)
+    print('coredump: OK')
+
+
+if __name__ == '__main__':
+    main()
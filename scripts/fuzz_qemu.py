@@ -0,0 +1,100 @@
+#!/usr/bin/env python3
+
+# Boot QEMU with the syscall fuzzer enabled and scrape the serial output.
+#
+# Exits with 0 if the fuzzer finished and 1 if the kernel panicked, the fuzzer died or the run
+# timed out. The case that was running is printed so it can be replayed.
+#
+# Usage: fuzz_qemu.py [--timeout SECONDS] [--args ARGS] [--regressions FILE] -- QEMU...
+#
+# With --regressions each case in FILE is replayed in a fresh VM instead. Each line holds a case
+# seed and optionally the amount of calls, e.g. "0x1234abcd 3". Lines starting with '#' are
+# ignored.
+
+import argparse
+import re
+import selectors
+import subprocess
+import sys
+import time
+
+PANIC = b'Kernel panicked!'
+DONE = b'fuzz: done'
+EXITED = b'fuzz: fuzzer exited'
+CASE = re.compile(rb'fuzz: case (\d+) seed (0x[0-9a-f]+)')
+
+
+def run(qemu, args, timeout):
+    cmd = qemu + ['-append', ' '.join(['fuzz'] + args)]
+    proc = subprocess.Popen(cmd, stdout=subprocess.PIPE, stderr=subprocess.STDOUT)
+    sel = selectors.DefaultSelector()
+    sel.register(proc.stdout, selectors.EVENT_READ)
+    deadline = time.monotonic() + timeout
+    case, line, result = None, b'', None
+    try:
+        while result is None:
+            left = deadline - time.monotonic()
+            if left <= 0 or not sel.select(left):
+                result = 'timed out'
+                break
+            data = proc.stdout.read1(4096)
+            if not data:
+                result = 'QEMU exited'
+                break
+            sys.stdout.buffer.write(data)
+            sys.stdout.flush()
+            line += data
+            *lines, line = line.split(b'\n')
+            for l in lines:
+                m = CASE.search(l)
+                if m:
+                    case = m.group(2).decode()
+                elif PANIC in l:
+                    result = 'kernel panicked'
+                elif EXITED in l:
+                    result = 'fuzzer died'
+                elif DONE in l:
+                    result = 'done'
+                    break
+    finally:
+        proc.kill()
+        proc.wait()
+    return result, case
+
+
+def main():
+    p = argparse.ArgumentParser()
+    p.add_argument('--timeout', type=float, default=600)
+    p.add_argument('--args', default='')
+    p.add_argument('--regressions')
+    p.add_argument('qemu', nargs='+')
+    a = p.parse_args()
+    args = a.args.split()
+
+    if a.regressions is None:
+        runs = [args]
+    else:
+        runs = []
+        with open(a.regressions) as f:
+            for l in f:
+                l = l.split('#')[0].split()
+                if l:
+                    r = ['fuzz.replay=' + l[0]]
+                    if len(l) > 1:
+                        r.append('fuzz.calls=' + l[1])
+                    runs.append(args + r)
+
+    failed = False
+    for r in runs:
+        result, case = run(a.qemu, r, a.timeout)
+        if result == 'done':
+            continue
+        failed = True
+        print('\nfuzz: FAILED ({}) {}'.format(result, ' '.join(r)), file=sys.stderr)
+        if case is not None:
+            print('fuzz: replay with fuzz.replay={}'.format(case), file=sys.stderr)
+    sys.exit(1 if failed else 0)
+
+
+if __name__ == '__main__':
+    main()
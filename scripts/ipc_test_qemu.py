@@ -0,0 +1,70 @@
+#!/usr/bin/env python3
+
+# Boot QEMU with the IPC pointer test enabled and scrape the serial output.
+#
+# Exits with 0 if every packet was rejected and the kernel is still up a while later, and with 1
+# if the kernel panicked, a packet was accepted or the run timed out.
+#
+# Usage: ipc_test_qemu.py [--timeout SECONDS] [--grace SECONDS] -- QEMU...
+
+import argparse
+import selectors
+import subprocess
+import sys
+import time
+
+PANIC = b'Kernel panicked!'
+OK = b'ipc_test: ok'
+FAILED = b'ipc_test: FAILED'
+
+
+def run(qemu, timeout, grace):
+    cmd = qemu + ['-append', 'ipc-test']
+    proc = subprocess.Popen(cmd, stdout=subprocess.PIPE, stderr=subprocess.STDOUT)
+    sel = selectors.DefaultSelector()
+    sel.register(proc.stdout, selectors.EVENT_READ)
+    deadline = time.monotonic() + timeout
+    line, result = b'', None
+    try:
+        while True:
+            left = deadline - time.monotonic()
+            if left <= 0 or not sel.select(left):
+                break
+            data = proc.stdout.read1(4096)
+            if not data:
+                result = 'QEMU exited'
+                break
+            sys.stdout.buffer.write(data)
+            sys.stdout.flush()
+            line += data
+            *lines, line = line.split(b'\n')
+            for l in lines:
+                if PANIC in l:
+                    return 'kernel panicked'
+                elif FAILED in l:
+                    return 'packets were accepted'
+                elif OK in l and result is None:
+                    # Keep watching for a while in case the kernel was left in a bad state.
+                    result = 'ok'
+                    deadline = time.monotonic() + grace
+    finally:
+        proc.kill()
+        proc.wait()
+    return result or 'timed out'
+
+
+def main():
+    p = argparse.ArgumentParser()
+    p.add_argument('--timeout', type=float, default=120)
+    p.add_argument('--grace', type=float, default=5)
+    p.add_argument('qemu', nargs='+')
+    a = p.parse_args()
+
+    result = run(a.qemu, a.timeout, a.grace)
+    if result != 'ok':
+        print('\nipc_test: FAILED ({})'.format(result), file=sys.stderr)
+        sys.exit(1)
+
+
+if __name__ == '__main__':
+    main()
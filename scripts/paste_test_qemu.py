@@ -0,0 +1,150 @@
+#!/usr/bin/env python3
+
+# Boot QEMU, paste 16 KiB of text into the virtio keyboard with QMP and scrape the serial
+# output.
+#
+# The input driver & console are run with `--latency-debug`, so the driver logs how many bytes
+# were read in total after each burst and the console logs each flush of a burst. Exits with 0
+# if all text was read by the shell & the console flushed it within --max-delay seconds after
+# the last key was sent, and with 1 if the kernel panicked, characters were dropped or the run
+# timed out.
+#
+# Usage: paste_test_qemu.py [--timeout SECONDS] [--boot SECONDS] [--max-delay SECONDS] -- QEMU...
+
+import argparse
+import json
+import os
+import re
+import selectors
+import socket
+import subprocess
+import sys
+import tempfile
+import time
+
+PANIC = b'Kernel panicked!'
+DROPPED = b'virtio_input: buffer full'
+TOTAL = re.compile(rb'virtio_input: burst of \d+ bytes, (\d+) bytes read in total')
+FLUSHED = b'console: flushed burst'
+
+SIZE = 16 * 1024
+# Characters that map to a single key without modifiers.
+KEYS = {c: c for c in 'abcdefghijklmnopqrstuvwxyz'}
+KEYS[' '] = 'spc'
+
+
+def text():
+    # Break it into lines so the shell doesn't have to hold all of it at once.
+    chars = list(KEYS)
+    return ''.join('\n' if i % 64 == 63 else chars[i % len(chars)] for i in range(SIZE))
+
+
+def qmp_connect(path, timeout):
+    deadline = time.monotonic() + timeout
+    while True:
+        try:
+            s = socket.socket(socket.AF_UNIX)
+            s.connect(path)
+            break
+        except OSError:
+            s.close()
+            if time.monotonic() > deadline:
+                raise
+            time.sleep(0.1)
+    f = s.makefile('rw')
+    json.loads(f.readline())  # greeting
+    qmp(f, 'qmp_capabilities')
+    return s, f
+
+
+def qmp(f, command, arguments=None):
+    msg = {'execute': command}
+    if arguments is not None:
+        msg['arguments'] = arguments
+    f.write(json.dumps(msg) + '\n')
+    f.flush()
+    while True:
+        reply = json.loads(f.readline())
+        if 'return' in reply:
+            return reply['return']
+        if 'error' in reply:
+            raise RuntimeError(reply['error'])
+
+
+def paste(f, data):
+    for i in range(0, len(data), 64):
+        events = []
+        for c in data[i:i + 64]:
+            key = {'type': 'qcode', 'data': 'ret' if c == '\n' else KEYS[c]}
+            for down in (True, False):
+                events.append({'type': 'key', 'data': {'down': down, 'key': key}})
+        qmp(f, 'input-send-event', {'events': events})
+
+
+def run(qemu, timeout, boot, max_delay):
+    qmp_path = os.path.join(tempfile.mkdtemp(), 'qmp')
+    qmp_arg = 'unix:{},server=on,wait=off'.format(qmp_path)
+    cmd = qemu + ['-append', 'latency-debug', '-qmp', qmp_arg]
+    proc = subprocess.Popen(cmd, stdout=subprocess.PIPE, stderr=subprocess.STDOUT)
+    sel = selectors.DefaultSelector()
+    sel.register(proc.stdout, selectors.EVENT_READ)
+    start = time.monotonic()
+    deadline = start + timeout
+    line, pasted, total, flushed = b'', None, 0, None
+    try:
+        _, f = qmp_connect(qmp_path, timeout)
+        while True:
+            now = time.monotonic()
+            if pasted is None and now - start >= boot:
+                paste(f, text())
+                pasted = time.monotonic()
+            if pasted is not None and total >= SIZE and flushed is not None:
+                delay = flushed - pasted
+                if delay > max_delay:
+                    return 'took {:.2f} seconds'.format(delay)
+                print('\npaste_test: rendered {:.2f} seconds after the last key'.format(delay))
+                return 'ok'
+            if now >= deadline:
+                break
+            left = deadline - now if pasted else min(deadline, start + boot) - now
+            if not sel.select(max(left, 0.01)):
+                continue
+            data = proc.stdout.read1(4096)
+            if not data:
+                return 'QEMU exited'
+            sys.stdout.buffer.write(data)
+            sys.stdout.flush()
+            line += data
+            *lines, line = line.split(b'\n')
+            for l in lines:
+                if PANIC in l:
+                    return 'kernel panicked'
+                elif DROPPED in l:
+                    return 'characters were dropped'
+                m = TOTAL.search(l)
+                if m:
+                    total = int(m.group(1))
+                elif FLUSHED in l and total >= SIZE and flushed is None:
+                    flushed = time.monotonic()
+    finally:
+        proc.kill()
+        proc.wait()
+    return 'timed out with {} of {} bytes read'.format(total, SIZE)
+
+
+def main():
+    p = argparse.ArgumentParser()
+    p.add_argument('--timeout', type=float, default=120)
+    p.add_argument('--boot', type=float, default=15)
+    p.add_argument('--max-delay', type=float, default=1)
+    p.add_argument('qemu', nargs='+')
+    a = p.parse_args()
+
+    result = run(a.qemu, a.timeout, a.boot, a.max_delay)
+    if result != 'ok':
+        print('\npaste_test: FAILED ({})'.format(result), file=sys.stderr)
+        sys.exit(1)
+
+
+if __name__ == '__main__':
+    main()
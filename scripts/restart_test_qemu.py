@@ -0,0 +1,69 @@
+#!/usr/bin/env python3
+
+# Boot QEMU with the restart test enabled and scrape the serial output.
+#
+# b0 spawns the crash test as a supervised task, which faults each time it runs. Exits with 0 if
+# b0 restarted it and it ran again, and with 1 if the kernel panicked or the run timed out.
+#
+# Usage: restart_test_qemu.py [--timeout SECONDS] -- QEMU...
+
+import argparse
+import selectors
+import subprocess
+import sys
+import time
+
+PANIC = b'Kernel panicked!'
+RUNNING = b'crash_test: faulting'
+RESTARTING = b'Restarting "crash"'
+
+
+def run(qemu, timeout):
+    cmd = qemu + ['-append', 'restart-test']
+    proc = subprocess.Popen(cmd, stdout=subprocess.PIPE, stderr=subprocess.STDOUT)
+    sel = selectors.DefaultSelector()
+    sel.register(proc.stdout, selectors.EVENT_READ)
+    deadline = time.monotonic() + timeout
+    line, runs, restarted = b'', 0, False
+    try:
+        while True:
+            left = deadline - time.monotonic()
+            if left <= 0 or not sel.select(left):
+                break
+            data = proc.stdout.read1(4096)
+            if not data:
+                return 'QEMU exited'
+            sys.stdout.buffer.write(data)
+            sys.stdout.flush()
+            line += data
+            *lines, line = line.split(b'\n')
+            for l in lines:
+                if PANIC in l:
+                    return 'kernel panicked'
+                elif RESTARTING in l:
+                    restarted = True
+                elif RUNNING in l:
+                    runs += 1
+                    if restarted and runs >= 2:
+                        return 'ok'
+    finally:
+        proc.kill()
+        proc.wait()
+    return 'timed out'
+
+
+def main():
+    p = argparse.ArgumentParser()
+    p.add_argument('--timeout', type=float, default=120)
+    p.add_argument('qemu', nargs='+')
+    a = p.parse_args()
+
+    result = run(a.qemu, a.timeout)
+    if result != 'ok':
+        print('\nrestart_test: FAILED ({})'.format(result), file=sys.stderr)
+        sys.exit(1)
+    print('\nrestart_test: ok')
+
+
+if __name__ == '__main__':
+    main()
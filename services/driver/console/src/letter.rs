@@ -1,6 +1,6 @@
 //! Table of letter bitmaps.
 
-use crate::RGBA8;
+use dux::gfx::{FrameBuffer, Pixel};
 
 /// Bitmap of letters stolen from https://forum.osdev.org/viewtopic.php?f=2&t=20833
 ///
@@ -33,30 +33,21 @@ impl Letter {
 		LETTERS[i / 8] & (1 << (i % 8)) > 0
 	}
 
-	/// Copy a letter to the given buffer with the given foreground and background color.
-	pub(crate) fn copy(
-		&self,
-		x: usize,
-		y: usize,
-		buffer: &mut [RGBA8],
-		w: usize,
-		h: usize,
-		fg: RGBA8,
-		bg: RGBA8,
-	) {
-		let _ = h;
-		assert!(x + w * y < buffer.len());
+	/// Copy a letter to the given buffer with the given foreground and background color. The
+	/// parts of the letter outside the buffer are skipped.
+	pub(crate) fn copy(&self, x: usize, y: usize, buffer: &mut FrameBuffer, fg: Pixel, bg: Pixel) {
 		for (ly, wy) in (0..Self::HEIGHT).zip(y..y + Self::HEIGHT) {
-			for (lx, wx) in (0..Self::WIDTH).zip(x..x + Self::WIDTH) {
-				buffer[wx + wy * w] = self.get(lx, ly).then(|| fg).unwrap_or(bg);
+			let row = match buffer.row(wy) {
+				Some(row) => row,
+				None => break,
+			};
+			for (lx, p) in (0..Self::WIDTH).zip(row.iter_mut().skip(x)) {
+				*p = self.get(lx, ly).then(|| fg).unwrap_or(bg);
 			}
 		}
 	}
 }
 
-#[derive(Debug)]
-pub struct OutOfBounds;
-
 /// Return a specific letter.
 #[inline(always)]
 pub fn get(letter: u8) -> Letter {
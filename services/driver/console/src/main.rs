@@ -21,32 +21,23 @@ fn panic_handler(info: &core::panic::PanicInfo) -> ! {
 mod letter;
 mod rtbegin;
 
+use core::convert::TryFrom;
+use dux::gfx::{FrameBuffer, Pixel, Rect, ScanoutInfo};
 use letter::Letter;
 
-#[derive(Clone, Copy)]
-#[repr(C)]
-struct RGBA8 {
-	r: u8,
-	g: u8,
-	b: u8,
-	a: u8,
-}
-
-impl RGBA8 {
-	const fn rgb(r: u8, g: u8, b: u8) -> Self {
-		Self::rgba(r, g, b, 255)
-	}
-
-	const fn rgba(r: u8, g: u8, b: u8, a: u8) -> Self {
-		Self { r, g, b, a }
-	}
-}
+/// How long to wait in microseconds for the echo of a burst announced by the input driver
+/// before flushing what was drawn so far.
+const BURST_TIMEOUT: u64 = 50_000;
 
 #[export_name = "main"]
 fn main() {
 	// FIXME move this to rtbegin
 	unsafe { dux::init() };
 
+	// Leave compatibility mode before looking up the GPU driver so we get a handle to it, which
+	// is also the address of the packets it sends to us.
+	dux::task::set_strict_endpoints(true);
+
 	// Wait for virtio_gpu driver to come online
 	let address = loop {
 		let name = b"virtio_gpu";
@@ -64,32 +55,51 @@ fn main() {
 
 	const OP_OPEN: u8 = 128;
 	const OP_FLUSH: u8 = 129;
+	const UUID_FRAMEBUFFER: u128 = 0;
+	const UUID_METADATA: u128 = 2;
 
-	{
+	let open = |uuid| {
 		*dux::ipc::transmit() = kernel::ipc::Packet {
 			flags: 0,
 			id: 0,
 			offset: 0,
 			opcode: core::num::NonZeroU8::new(OP_OPEN),
-			uuid: kernel::ipc::UUID::new(0),
+			uuid: kernel::ipc::UUID::new(uuid),
 			data: None,
 			length: 0,
 			name: None,
 			name_len: 0,
 			address,
 		};
-	}
+	};
 
-	let buffer = {
+	// Only the first scanout is used.
+	open(UUID_METADATA);
+	let info = {
 		let rx = dux::ipc::receive();
 		assert_eq!(rx.address, address);
-		let ptr = rx.data.unwrap().as_ptr().cast::<RGBA8>();
-		let len = rx.length / core::mem::size_of::<RGBA8>();
-		// SAFETY: while the device will read from it, only we will write to it.
-		unsafe { core::slice::from_raw_parts_mut(ptr, len) }
+		assert!(
+			rx.length >= core::mem::size_of::<ScanoutInfo>(),
+			"no scanouts"
+		);
+		let info = unsafe { &*rx.data.unwrap().as_ptr().cast::<ScanoutInfo>() };
+		assert_eq!(info.enabled, 1, "scanout 0 is disabled");
+		*info
 	};
 
-	let (w, h) = (800, 600);
+	open(UUID_FRAMEBUFFER);
+
+	let mut buffer = {
+		let rx = dux::ipc::receive();
+		assert_eq!(rx.address, address);
+		let ptr = rx.data.unwrap().cast::<Pixel>();
+		assert!(
+			rx.length >= info.stride as usize * info.height as usize,
+			"draw buffer too small"
+		);
+		// SAFETY: while the device will read from it, only we will write to it.
+		unsafe { FrameBuffer::new(ptr, &info) }.expect("unsupported scanout layout")
+	};
 
 	// Add self to registry
 	let name = "console";
@@ -99,74 +109,158 @@ fn main() {
 	let (mut cursor_x, mut cursor_y) = (0, 0);
 	let (cursor_w, _cursor_h) = (50, 24);
 
+	// Measure the time between receiving data and submitting the flush to the GPU, and the
+	// time between the input driver collecting an event and submitting the flush.
+	let latency_debug = rtbegin::args().any(|a| a == b"--latency-debug");
+	let mut latency = dux::time::Stats::new(100);
+	let mut input_latency = dux::time::Stats::new(100);
+	// The time of the oldest input event that hasn't been drawn yet.
+	let mut input_event = None;
+	// Cleared if the GPU driver died, after which writes are still accepted but not drawn.
+	let mut gpu_alive = true;
+	// The amount of bytes of a burst announced by the input driver that weren't written yet,
+	// the time until which we wait for them before flushing anyways and the size of the burst.
+	let mut burst: usize = 0;
+	let mut burst_deadline = 0;
+	let mut burst_size: usize = 0;
+
 	loop {
 		use core::slice;
 
-		let rx = dux::ipc::receive();
-		match rx.opcode.map(|n| n.get()).unwrap_or(0) {
-			op if op == kernel::ipc::Op::Write as u8 => {
-				let data = unsafe {
-					slice::from_raw_parts(rx.data.unwrap().as_ptr().cast::<u8>(), rx.length)
-				};
-				let mut iter = data.iter();
-				let fg = RGBA8::rgb(255, 255, 255);
-				let bg = RGBA8::rgb(0, 0, 0);
-				while let Some(c) = iter.next() {
-					match c {
-						b'\n' => {
-							cursor_x = 0;
-							cursor_y += 1;
-						}
-						b'\r' => cursor_x = 0,
-						b'\x1b' => {
-							assert_eq!(iter.next(), Some(&b'['));
-							match iter.next().unwrap() {
-								b'2' => match iter.next().unwrap() {
-									b'K' => {
-										for x in 0..cursor_w {
-											let (x, y) =
-												(x * Letter::WIDTH, cursor_y * Letter::HEIGHT);
-											letter::get(0).copy(x, y, buffer, w, h, fg, bg);
-										}
+		let mut rx = dux::ipc::receive();
+		let received = dux::time::now();
+		// The time of the input event echoed by the writes drawn in this batch.
+		let mut pending_event = None;
+		// The cells that were drawn to as x, y, width & height.
+		let mut dirty = None;
+
+		// Draw all writes that are already queued before flushing, so a burst (e.g. a paste
+		// echoed by the shell) takes a single flush.
+		loop {
+			if let Some(peer) = dux::task::dead_peer(&rx) {
+				if usize::from(peer) == address && gpu_alive {
+					kernel::sys_log!("console: GPU driver died, no longer drawing");
+					gpu_alive = false;
+				}
+			} else if !dux::ipc::acknowledge_power(&rx) {
+				match rx.opcode.map(|n| n.get()).unwrap_or(0) {
+					op if op == kernel::ipc::Op::Write as u8 => {
+						let data = match gpu_alive {
+							true => unsafe {
+								slice::from_raw_parts(
+									rx.data.unwrap().as_ptr().cast::<u8>(),
+									rx.length,
+								)
+							},
+							false => &[][..],
+						};
+						let mut iter = data.iter();
+						let fg = Pixel::rgb(255, 255, 255);
+						let bg = Pixel::rgb(0, 0, 0);
+						while let Some(c) = iter.next() {
+							match c {
+								b'\n' => {
+									cursor_x = 0;
+									cursor_y += 1;
+								}
+								b'\r' => cursor_x = 0,
+								b'\x1b' => {
+									assert_eq!(iter.next(), Some(&b'['));
+									match iter.next().unwrap() {
+										b'2' => match iter.next().unwrap() {
+											b'K' => {
+												for x in 0..cursor_w {
+													let (x, y) = (
+														x * Letter::WIDTH,
+														cursor_y * Letter::HEIGHT,
+													);
+													letter::get(0).copy(x, y, &mut buffer, fg, bg);
+												}
+												mark(&mut dirty, 0, cursor_y, cursor_w);
+												cursor_x = 0;
+											}
+											_ => panic!(),
+										},
+										_ => panic!(),
+									}
+								}
+								c => {
+									let (x, y) =
+										(cursor_x * Letter::WIDTH, cursor_y * Letter::HEIGHT);
+									letter::get(*c).copy(x, y, &mut buffer, fg, bg);
+									mark(&mut dirty, cursor_x, cursor_y, 1);
+									cursor_x += 1;
+									if cursor_x >= cursor_w {
 										cursor_x = 0;
+										cursor_y += 1;
 									}
-									_ => panic!(),
-								},
-								_ => panic!(),
+								}
 							}
 						}
-						c => {
-							let (x, y) = (cursor_x * Letter::WIDTH, cursor_y * Letter::HEIGHT);
-							letter::get(*c).copy(x, y, buffer, w, h, fg, bg);
-							cursor_x += 1;
-							if cursor_x >= cursor_w {
-								cursor_x = 0;
-								cursor_y += 1;
-							}
+						// The write that follows an input event is assumed to echo it.
+						if let Some(t) = input_event.take() {
+							pending_event = Some(t);
 						}
+						burst = burst.saturating_sub(rx.length);
+						*dux::ipc::transmit() = kernel::ipc::Packet {
+							flags: 0,
+							id: rx.id,
+							opcode: rx.opcode,
+							offset: 0,
+							uuid: kernel::ipc::UUID::INVALID,
+							data: None,
+							length: rx.length,
+							name: None,
+							name_len: 0,
+							address: rx.address,
+						};
+					}
+					dux::time::OP_LATENCY_MARK => {
+						input_event.get_or_insert(rx.offset);
+					}
+					dux::ipc::OP_TEXT_BURST => {
+						let n = usize::try_from(rx.offset).unwrap_or(usize::MAX);
+						burst = burst.saturating_add(n);
+						burst_size = burst_size.saturating_add(n);
+						burst_deadline = dux::time::now() + BURST_TIMEOUT;
 					}
+					_ => todo!(),
 				}
-				*dux::ipc::transmit() = kernel::ipc::Packet {
-					flags: 0,
-					id: rx.id,
-					opcode: rx.opcode,
-					offset: 0,
-					uuid: kernel::ipc::UUID::INVALID,
-					data: None,
-					length: rx.length,
-					name: None,
-					name_len: 0,
-					address: rx.address,
-				};
 			}
-			_ => todo!(),
+			dux::ipc::release(&rx);
+			drop(rx);
+			// Wait for the echo of the rest of a burst, so it takes a single flush too.
+			let next = dux::ipc::try_receive().or_else(|| loop {
+				let now = dux::time::now();
+				if burst == 0 || now >= burst_deadline {
+					burst = 0;
+					break None;
+				}
+				unsafe { kernel::io_wait(burst_deadline - now) };
+				if let Some(next) = dux::ipc::try_receive() {
+					break Some(next);
+				}
+			});
+			match next {
+				Some(next) => rx = next,
+				None => break,
+			}
 		}
-		drop(rx);
 
+		let [x, y, dw, dh] = match dirty {
+			Some(d) => d,
+			None => continue,
+		};
+		let rect = Rect::new(
+			(x * Letter::WIDTH) as u32,
+			(y * Letter::HEIGHT) as u32,
+			(dw * Letter::WIDTH) as u32,
+			(dh * Letter::HEIGHT) as u32,
+		);
 		*dux::ipc::transmit() = kernel::ipc::Packet {
 			flags: 0,
 			id: 0,
-			offset: 0,
+			offset: rect.pack(),
 			opcode: core::num::NonZeroU8::new(OP_FLUSH),
 			uuid: kernel::ipc::UUID::INVALID,
 			data: None,
@@ -175,5 +269,32 @@ fn main() {
 			name_len: 0,
 			address,
 		};
+
+		if latency_debug {
+			let now = dux::time::now();
+			if let Some(s) = latency.add(now.saturating_sub(received)) {
+				kernel::sys_log!("console: flush latency {}", s);
+			}
+			if let Some(s) = pending_event.and_then(|t| input_latency.add(now.saturating_sub(t))) {
+				kernel::sys_log!("console: input to flush latency {}", s);
+			}
+			if burst_size > 0 {
+				kernel::sys_log!("console: flushed burst of {} bytes", burst_size);
+			}
+		}
+		burst_size = 0;
 	}
 }
+
+/// Extend a rect of cells, given as x, y, width & height, to include `count` cells starting
+/// at the given cell.
+fn mark(dirty: &mut Option<[usize; 4]>, x: usize, y: usize, count: usize) {
+	*dirty = Some(match *dirty {
+		None => [x, y, count, 1],
+		Some([dx, dy, dw, dh]) => {
+			let (x0, y0) = (dx.min(x), dy.min(y));
+			let (x1, y1) = ((dx + dw).max(x + count), (dy + dh).max(y + 1));
+			[x0, y0, x1 - x0, y1 - y0]
+		}
+	});
+}
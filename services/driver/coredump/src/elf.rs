@@ -0,0 +1,219 @@
+//! # ELF core files
+//!
+//! A core file starts with the ELF header, followed by a `PT_NOTE` program header and a
+//! `PT_LOAD` program header for each segment. After that comes the note, which holds the
+//! registers in the same format as Linux so debuggers understand it. The memory of the
+//! segments comes last, with each segment starting on a page boundary.
+
+/// The size of the ELF header.
+pub const HEADER_SIZE: usize = 64;
+
+/// The size of a single program header.
+pub const PROGRAM_HEADER_SIZE: usize = 56;
+
+/// The size of the `NT_PRSTATUS` note.
+pub const NOTE_SIZE: usize = 12 + NOTE_NAME.len() + PRSTATUS_SIZE;
+
+/// The name of the note padded to a multiple of 4 bytes.
+const NOTE_NAME: &[u8] = b"CORE\0\0\0\0";
+
+/// The size of `struct elf_prstatus` on RV64.
+const PRSTATUS_SIZE: usize = 376;
+
+/// The offsets of fields in `struct elf_prstatus`.
+const PRSTATUS_CURSIG: usize = 12;
+const PRSTATUS_PID: usize = 32;
+const PRSTATUS_REG: usize = 112;
+
+const ET_CORE: u16 = 4;
+const EM_RISCV: u16 = 243;
+const PT_LOAD: u32 = 1;
+const PT_NOTE: u32 = 4;
+const NT_PRSTATUS: u32 = 1;
+/// Compressed instructions & the double-float ABI, i.e. `riscv64gc`.
+const EF_RISCV: u32 = 0x1 | 0x4;
+
+pub const PF_X: u32 = 0x1;
+pub const PF_W: u32 = 0x2;
+pub const PF_R: u32 = 0x4;
+
+/// The signals used for faults.
+const SIGILL: u16 = 4;
+const SIGBUS: u16 = 7;
+const SIGSEGV: u16 = 11;
+
+/// A `PT_LOAD` or `PT_NOTE` program header.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct ProgramHeader {
+	typ: u32,
+	flags: u32,
+	offset: u64,
+	address: u64,
+	size: u64,
+	align: u64,
+}
+
+impl ProgramHeader {
+	/// Create a header for memory at the given address that is stored at the given offset.
+	pub fn load(offset: u64, address: u64, size: u64, flags: u32) -> Self {
+		Self {
+			typ: PT_LOAD,
+			flags,
+			offset,
+			address,
+			size,
+			align: 4096,
+		}
+	}
+
+	/// Create a header for the note, which is stored at the given offset.
+	pub fn note(offset: u64) -> Self {
+		Self {
+			typ: PT_NOTE,
+			flags: 0,
+			offset,
+			address: 0,
+			size: NOTE_SIZE as u64,
+			align: 4,
+		}
+	}
+
+	pub fn encode(&self, buf: &mut [u8; PROGRAM_HEADER_SIZE]) {
+		let mut w = Writer(&mut buf[..]);
+		w.u32(self.typ);
+		w.u32(self.flags);
+		w.u64(self.offset);
+		w.u64(self.address);
+		// p_paddr
+		w.u64(0);
+		// p_filesz & p_memsz
+		w.u64(self.size);
+		w.u64(self.size);
+		w.u64(self.align);
+	}
+}
+
+/// Encode the ELF header of a core file with the given amount of program headers. The program
+/// headers come right after the ELF header.
+pub fn header(buf: &mut [u8; HEADER_SIZE], program_headers: u16) {
+	let mut w = Writer(&mut buf[..]);
+	// ELFCLASS64, ELFDATA2LSB, EV_CURRENT, ELFOSABI_NONE
+	w.bytes(&[0x7f, b'E', b'L', b'F', 2, 1, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+	w.u16(ET_CORE);
+	w.u16(EM_RISCV);
+	// e_version
+	w.u32(1);
+	// e_entry
+	w.u64(0);
+	// e_phoff
+	w.u64(HEADER_SIZE as u64);
+	// e_shoff
+	w.u64(0);
+	w.u32(EF_RISCV);
+	w.u16(HEADER_SIZE as u16);
+	w.u16(PROGRAM_HEADER_SIZE as u16);
+	w.u16(program_headers);
+	// e_shentsize, e_shnum & e_shstrndx
+	w.u16(64);
+	w.u16(0);
+	w.u16(0);
+}
+
+/// Encode the `NT_PRSTATUS` note of a task that faulted with the given cause.
+pub fn note(buf: &mut [u8; NOTE_SIZE], pid: u32, cause: usize, pc: usize, x: &[usize; 31]) {
+	buf.iter_mut().for_each(|b| *b = 0);
+	let mut w = Writer(&mut buf[..]);
+	w.u32(5);
+	w.u32(PRSTATUS_SIZE as u32);
+	w.u32(NT_PRSTATUS);
+	w.bytes(NOTE_NAME);
+	let status = &mut buf[12 + NOTE_NAME.len()..];
+	let signal = match cause {
+		// Illegal instruction
+		2 => SIGILL,
+		// Misaligned accesses
+		0 | 4 | 6 => SIGBUS,
+		_ => SIGSEGV,
+	};
+	// pr_info.si_signo
+	Writer(&mut status[..]).u32(signal.into());
+	Writer(&mut status[PRSTATUS_CURSIG..]).u16(signal);
+	Writer(&mut status[PRSTATUS_PID..]).u32(pid);
+	let mut w = Writer(&mut status[PRSTATUS_REG..]);
+	w.u64(pc as u64);
+	x.iter().for_each(|&r| w.u64(r as u64));
+}
+
+/// Writes little-endian values to a buffer.
+struct Writer<'a>(&'a mut [u8]);
+
+impl Writer<'_> {
+	fn bytes(&mut self, bytes: &[u8]) {
+		let (head, tail) = core::mem::take(&mut self.0).split_at_mut(bytes.len());
+		head.copy_from_slice(bytes);
+		self.0 = tail;
+	}
+
+	fn u16(&mut self, n: u16) {
+		self.bytes(&n.to_le_bytes())
+	}
+
+	fn u32(&mut self, n: u32) {
+		self.bytes(&n.to_le_bytes())
+	}
+
+	fn u64(&mut self, n: u64) {
+		self.bytes(&n.to_le_bytes())
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test]
+	fn header_layout() {
+		let mut buf = [0; HEADER_SIZE];
+		header(&mut buf, 3);
+		assert_eq!(&buf[..4], b"\x7fELF");
+		assert_eq!(&buf[16..20], &[4, 0, 243, 0]);
+		assert_eq!(&buf[32..40], &64u64.to_le_bytes());
+		assert_eq!(&buf[54..58], &[56, 0, 3, 0]);
+	}
+
+	#[test]
+	fn program_header_layout() {
+		let mut buf = [0; PROGRAM_HEADER_SIZE];
+		ProgramHeader::load(0x2000, 0x10000, 0x3000, PF_R | PF_W).encode(&mut buf);
+		assert_eq!(&buf[..8], &[1, 0, 0, 0, 6, 0, 0, 0]);
+		assert_eq!(&buf[8..16], &0x2000u64.to_le_bytes());
+		assert_eq!(&buf[16..24], &0x10000u64.to_le_bytes());
+		assert_eq!(&buf[32..40], &0x3000u64.to_le_bytes());
+		assert_eq!(&buf[40..48], &0x3000u64.to_le_bytes());
+		assert_eq!(&buf[48..56], &4096u64.to_le_bytes());
+	}
+
+	#[test]
+	fn note_layout() {
+		let mut x = [0; 31];
+		x.iter_mut().enumerate().for_each(|(i, r)| *r = i + 1);
+		let mut buf = [0xff; NOTE_SIZE];
+		note(&mut buf, 7, 15, 0x1234, &x);
+		assert_eq!(NOTE_SIZE % 4, 0);
+		assert_eq!(&buf[..12], &[5, 0, 0, 0, 120, 1, 0, 0, 1, 0, 0, 0]);
+		assert_eq!(&buf[12..17], b"CORE\0");
+		let status = &buf[20..];
+		assert_eq!(status[0], SIGSEGV as u8);
+		assert_eq!(status[PRSTATUS_CURSIG], SIGSEGV as u8);
+		assert_eq!(status[PRSTATUS_PID], 7);
+		let reg = |i: usize| {
+			let r = &status[PRSTATUS_REG + i * 8..][..8];
+			u64::from_le_bytes([r[0], r[1], r[2], r[3], r[4], r[5], r[6], r[7]])
+		};
+		assert_eq!(reg(0), 0x1234);
+		assert_eq!(reg(1), 1);
+		assert_eq!(reg(31), 31);
+		// pr_fpvalid
+		assert!(status[PRSTATUS_REG + 32 * 8..].iter().all(|b| *b == 0));
+	}
+}
@@ -0,0 +1,305 @@
+//! # Core dump service
+//!
+//! Receives core dumps of tasks that faulted from the kernel and writes them as ELF core files
+//! to the filesystem. The file of a task is named `core.<address>`, with the address of the task
+//! in hexadecimal.
+//!
+//! The kernel only sends dumps if enabled with the `coredump` boot argument.
+
+#![cfg_attr(not(test), no_std)]
+#![cfg_attr(not(test), no_main)]
+#![feature(asm)]
+#![feature(global_asm)]
+#![feature(naked_functions)]
+#![feature(panic_info_message)]
+
+#[cfg(not(test))]
+#[panic_handler]
+fn panic_handler(info: &core::panic::PanicInfo) -> ! {
+	kernel::sys_log!("Panic!");
+	if let Some(m) = info.message() {
+		kernel::sys_log!("  Message: {}", m);
+	}
+	if let Some(l) = info.location() {
+		kernel::sys_log!("  Location: {}", l);
+	}
+	loop {}
+}
+
+mod elf;
+#[cfg(not(test))]
+mod rtbegin;
+
+#[cfg(not(test))]
+use core::convert::TryFrom;
+#[cfg(not(test))]
+use kernel::sys_log;
+
+/// The maximum amount of segments that are written. Any segments beyond are dropped.
+const MAX_SEGMENTS: usize = 256;
+
+/// The size of the buffers used to write to the filesystem.
+const BUFFER_SIZE: usize = 4 * 4096;
+
+/// Page-aligned memory that can be sent to the filesystem.
+#[repr(align(4096))]
+struct Buffer([u8; BUFFER_SIZE]);
+
+/// The layout of a core file.
+struct Layout {
+	/// The amount of segments in the file.
+	segments: usize,
+}
+
+impl Layout {
+	/// The offset of the note.
+	fn note(&self) -> usize {
+		elf::HEADER_SIZE + (1 + self.segments) * elf::PROGRAM_HEADER_SIZE
+	}
+
+	/// The offset of the memory of the first segment.
+	fn data(&self) -> usize {
+		(self.note() + elf::NOTE_SIZE + 4095) & !4095
+	}
+
+	/// Encode the ELF header, program headers & note.
+	fn encode(
+		&self,
+		buf: &mut [u8],
+		header: &kernel::ipc::CoreDumpHeader,
+		segments: &[elf::ProgramHeader],
+	) {
+		use core::convert::TryInto;
+		elf::header(
+			(&mut buf[..elf::HEADER_SIZE]).try_into().unwrap(),
+			(1 + segments.len()) as u16,
+		);
+		let mut phdrs =
+			buf[elf::HEADER_SIZE..self.note()].chunks_exact_mut(elf::PROGRAM_HEADER_SIZE);
+		let note = elf::ProgramHeader::note(self.note() as u64);
+		for ph in Some(&note).into_iter().chain(segments) {
+			ph.encode(phdrs.next().unwrap().try_into().unwrap());
+		}
+		elf::note(
+			(&mut buf[self.note()..][..elf::NOTE_SIZE])
+				.try_into()
+				.unwrap(),
+			header.task as u32,
+			header.cause,
+			header.pc,
+			&header.x,
+		);
+	}
+}
+
+/// A core file that is being written.
+#[cfg(not(test))]
+struct Core {
+	header: kernel::ipc::CoreDumpHeader,
+	layout: Layout,
+	/// The segments received so far.
+	segments: [elf::ProgramHeader; MAX_SEGMENTS],
+	received: usize,
+	/// The offset of the memory of the next segment.
+	offset: usize,
+	name: [u8; 32],
+	name_len: usize,
+	/// The address of the filesystem or `None` if writing failed.
+	fs: Option<usize>,
+}
+
+#[cfg(not(test))]
+static mut BUFFER: Buffer = Buffer([0; BUFFER_SIZE]);
+
+#[cfg(not(test))]
+static mut NAME: kernel::Page = kernel::Page::zeroed();
+
+#[cfg(not(test))]
+#[export_name = "main"]
+fn main() {
+	unsafe { dux::init() };
+
+	// Register self as the receiver of core dumps
+	let name = b"coredump";
+	let ret = unsafe { kernel::sys_registry_add(name.as_ptr(), name.len(), usize::MAX) };
+	assert_eq!(ret.status, 0);
+
+	let mut file = None;
+	loop {
+		let rxq_lock = dux::ipc::receive();
+		let rxq = (*rxq_lock).clone();
+		drop(rxq_lock);
+
+		let dump = rxq.address == kernel::ipc::KERNEL_ADDRESS && !dux::ipc::acknowledge_power(&rxq);
+		if dump {
+			let data = rxq.data.map_or(&[][..], |d| unsafe {
+				core::slice::from_raw_parts(d.as_ptr().cast(), rxq.length)
+			});
+			match rxq.opcode.map(kernel::ipc::Op::try_from) {
+				Some(Ok(kernel::ipc::Op::CoreDump)) => file = start(data),
+				Some(Ok(kernel::ipc::Op::CoreDumpSegment)) => {
+					if let Some(c) = file.as_mut() {
+						segment(c, rxq.offset, rxq.flags, data);
+						if c.received >= c.header.segments {
+							finish(c);
+							file = None;
+						}
+					}
+				}
+				_ => (),
+			}
+		}
+
+		dux::ipc::release(&rxq);
+
+		// Ask for the next segment after freeing the pages so the kernel has room to map it.
+		if dump {
+			*dux::ipc::transmit() = kernel::ipc::Packet {
+				opcode: Some(kernel::ipc::Op::CoreDumpSegment.into()),
+				address: kernel::ipc::KERNEL_ADDRESS,
+				..Default::default()
+			};
+		}
+	}
+}
+
+/// Begin writing a core file with the given header.
+#[cfg(not(test))]
+fn start(data: &[u8]) -> Option<Core> {
+	if data.len() < core::mem::size_of::<kernel::ipc::CoreDumpHeader>() {
+		sys_log!("coredump: header is too short");
+		return None;
+	}
+	let header = unsafe { *data.as_ptr().cast::<kernel::ipc::CoreDumpHeader>() };
+	let layout = Layout {
+		segments: header.segments.min(MAX_SEGMENTS),
+	};
+
+	let name = b"fatfs";
+	let ret = unsafe { kernel::sys_registry_get(name.as_ptr(), name.len()) };
+	let fs = (ret.status == 0).then(|| ret.value);
+	if fs.is_none() {
+		sys_log!("coredump: no filesystem");
+	}
+
+	// Name the file after the address of the task.
+	let (mut hex, mut i, mut n) = ([0; 16], 16, header.task);
+	while i == 16 || n > 0 {
+		i -= 1;
+		hex[i] = b"0123456789abcdef"[n & 0xf];
+		n >>= 4;
+	}
+	let mut name = [0; 32];
+	let name_len = b"core.".len() + hex.len() - i;
+	name[..b"core.".len()].copy_from_slice(b"core.");
+	name[b"core.".len()..name_len].copy_from_slice(&hex[i..]);
+
+	sys_log!(
+		"coredump: task {:x} faulted with cause {} at {:x}, {} segments, {} pages left out",
+		header.task,
+		header.cause,
+		header.pc,
+		header.segments,
+		header.omitted
+	);
+	let offset = layout.data();
+	let mut file = Core {
+		header,
+		layout,
+		segments: [Default::default(); MAX_SEGMENTS],
+		received: 0,
+		offset,
+		name,
+		name_len,
+		fs,
+	};
+	if file.header.segments == 0 {
+		finish(&mut file);
+		return None;
+	}
+	Some(file)
+}
+
+/// Write a segment to the core file.
+#[cfg(not(test))]
+fn segment(file: &mut Core, address: u64, flags: u16, data: &[u8]) {
+	let i = file.received;
+	file.received += 1;
+	if i >= file.layout.segments {
+		return;
+	}
+	let f = |flag, pf| if flags & flag > 0 { pf } else { 0 };
+	let flags = f(kernel::ipc::FLAG_READABLE, elf::PF_R)
+		| f(kernel::ipc::FLAG_WRITEABLE, elf::PF_W)
+		| f(kernel::ipc::FLAG_EXECUTABLE, elf::PF_X);
+	let offset = file.offset;
+	file.segments[i] = elf::ProgramHeader::load(offset as u64, address, data.len() as u64, flags);
+	file.offset += (data.len() + 4095) & !4095;
+	for (k, chunk) in data.chunks(BUFFER_SIZE).enumerate() {
+		unsafe { BUFFER.0[..chunk.len()].copy_from_slice(chunk) };
+		write(file, offset + k * BUFFER_SIZE, chunk.len());
+	}
+}
+
+/// Write the headers & the note, which completes the core file.
+#[cfg(not(test))]
+fn finish(file: &mut Core) {
+	// Segments that weren't received are left out.
+	let segments = &file.segments[..file.layout.segments.min(file.received)];
+	let layout = Layout {
+		segments: segments.len(),
+	};
+	let len = layout.note() + elf::NOTE_SIZE;
+	let buf = unsafe { &mut BUFFER.0[..len] };
+	buf.iter_mut().for_each(|b| *b = 0);
+	layout.encode(buf, &file.header, segments);
+	write(file, 0, len);
+	if file.fs.is_some() {
+		sys_log!(
+			"coredump: wrote {}",
+			core::str::from_utf8(&file.name[..file.name_len]).unwrap()
+		);
+	}
+}
+
+/// Write the start of the buffer to the core file at the given offset. Writing stops if an
+/// error occurs.
+#[cfg(not(test))]
+fn write(file: &mut Core, offset: usize, len: usize) {
+	let fs = match file.fs {
+		Some(fs) => fs,
+		None => return,
+	};
+	let (buf, name) = unsafe { (&mut BUFFER.0, &mut NAME) };
+	name.as_mut()[..file.name_len].copy_from_slice(&file.name[..file.name_len]);
+
+	// The filesystem may write less than requested, so send the remainder again.
+	let (mut offset, mut len) = (offset, len);
+	while len > 0 {
+		*dux::ipc::transmit() = kernel::ipc::Packet {
+			uuid: kernel::ipc::UUID::INVALID,
+			opcode: Some(kernel::ipc::Op::Write.into()),
+			name: Some(core::ptr::NonNull::from(&*name).cast()),
+			name_len: file.name_len as u16,
+			flags: 0,
+			id: 0,
+			address: fs,
+			data: Some(core::ptr::NonNull::from(&*buf).cast()),
+			length: len,
+			offset: offset as u64,
+		};
+		let rxq_lock = dux::ipc::receive();
+		let rxq = (*rxq_lock).clone();
+		drop(rxq_lock);
+		let failed = rxq.flags & (kernel::ipc::FLAG_ERROR | kernel::ipc::FLAG_DEAD_PEER) > 0;
+		if rxq.address != fs || failed || rxq.length == 0 {
+			sys_log!("coredump: failed to write {:?}", rxq);
+			file.fs = None;
+			return;
+		}
+		let written = rxq.length.min(len);
+		buf.copy_within(written..len, 0);
+		offset += written;
+		len -= written;
+	}
+}
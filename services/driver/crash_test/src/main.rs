@@ -0,0 +1,55 @@
+//! # Crash test
+//!
+//! Fills a buffer on the stack & a static buffer with a known pattern and then faults, which
+//! exercises the core dump path. It is spawned by b0 if `crash` is passed on the kernel command
+//! line. `scripts/coredump.gdb` checks the resulting core file.
+
+#![no_std]
+#![no_main]
+#![feature(asm)]
+#![feature(global_asm)]
+#![feature(naked_functions)]
+#![feature(panic_info_message)]
+
+#[panic_handler]
+fn panic_handler(info: &core::panic::PanicInfo) -> ! {
+	kernel::sys_log!("Panic!");
+	if let Some(m) = info.message() {
+		kernel::sys_log!("  Message: {}", m);
+	}
+	if let Some(l) = info.location() {
+		kernel::sys_log!("  Location: {}", l);
+	}
+	loop {}
+}
+
+mod rtbegin;
+
+/// The first value of the pattern. Each next value is one higher.
+const PATTERN: u64 = 0xc0ffee_0000;
+
+/// Filled at runtime so it only holds the pattern if the memory of the task was dumped.
+#[no_mangle]
+static mut CRASH_TEST_PATTERN: [u64; 8] = [0; 8];
+
+#[export_name = "main"]
+fn main() {
+	let mut stack = [0; 8];
+	for (i, (s, p)) in stack
+		.iter_mut()
+		.zip(unsafe { CRASH_TEST_PATTERN.iter_mut() })
+		.enumerate()
+	{
+		*s = PATTERN + i as u64;
+		*p = PATTERN + i as u64;
+	}
+	kernel::sys_log!("crash_test: faulting");
+	crash_test_fault(&stack)
+}
+
+/// Store to address 0. The buffer on the stack is passed in `a0`.
+#[no_mangle]
+#[inline(never)]
+fn crash_test_fault(stack: &[u64; 8]) -> ! {
+	unsafe { asm!("sd zero, 0(zero)", in("a0") stack.as_ptr(), options(noreturn)) }
+}
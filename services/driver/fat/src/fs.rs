@@ -0,0 +1,306 @@
+//! # Filesystem operations
+//!
+//! The operations performed on behalf of IPC requests. They don't depend on IPC so they can be
+//! tested on the host.
+//!
+//! Names are long file names. FAT compares names case-insensitively, so a file created as
+//! `MyDocument.txt` can be opened as `mydocument.TXT`. Listings return the name as it was
+//! created.
+
+use fatfs::{Dir, DirEntry, File, OemCpConverter, Read, ReadWriteSeek, Seek, SeekFrom};
+use fatfs::{TimeProvider, Write};
+
+/// The maximum length of a long file name in UTF-16 code units.
+const MAX_NAME_UNITS: usize = 255;
+
+/// The maximum length of a long file name encoded as UTF-8. A single UTF-16 code unit takes
+/// at most 3 bytes.
+pub const MAX_NAME_LEN: usize = MAX_NAME_UNITS * 3;
+
+#[derive(Debug, PartialEq)]
+pub enum Error {
+	/// The path isn't valid UTF-8, refers to a directory or contains a name FAT can't store.
+	InvalidArg,
+	/// The file doesn't exist.
+	NotFound,
+	/// The disk failed, is full or the filesystem is corrupted.
+	Io,
+}
+
+impl Error {
+	/// Return the code to put in an error response.
+	pub fn code(&self) -> u64 {
+		match self {
+			Self::InvalidArg => kernel::ipc::ERROR_INVALID_ARG,
+			Self::NotFound => kernel::ipc::ERROR_NOT_FOUND,
+			Self::Io => kernel::ipc::ERROR_IO,
+		}
+	}
+}
+
+/// Open an existing file.
+pub fn open<'a, IO, TP, OCC>(
+	dir: &Dir<'a, IO, TP, OCC>,
+	path: &[u8],
+) -> Result<File<'a, IO, TP, OCC>, Error>
+where
+	IO: ReadWriteSeek,
+	TP: TimeProvider,
+	OCC: OemCpConverter,
+{
+	dir.open_file(validate(path)?).map_err(map_error)
+}
+
+/// Open a file, creating it if it doesn't exist.
+pub fn create<'a, IO, TP, OCC>(
+	dir: &Dir<'a, IO, TP, OCC>,
+	path: &[u8],
+) -> Result<File<'a, IO, TP, OCC>, Error>
+where
+	IO: ReadWriteSeek,
+	TP: TimeProvider,
+	OCC: OemCpConverter,
+{
+	dir.create_file(validate(path)?).map_err(map_error)
+}
+
+/// Read from a file at the given offset. Returns the amount of bytes read.
+pub fn read<IO, TP, OCC>(
+	file: &mut File<IO, TP, OCC>,
+	offset: u64,
+	buf: &mut [u8],
+) -> Result<usize, Error>
+where
+	IO: ReadWriteSeek,
+	TP: TimeProvider,
+	OCC: OemCpConverter,
+{
+	file.seek(SeekFrom::Start(offset)).map_err(map_error)?;
+	file.read(buf).map_err(map_error)
+}
+
+/// Write to a file at the given offset. Returns the amount of bytes written.
+pub fn write<IO, TP, OCC>(
+	file: &mut File<IO, TP, OCC>,
+	offset: u64,
+	buf: &[u8],
+) -> Result<usize, Error>
+where
+	IO: ReadWriteSeek,
+	TP: TimeProvider,
+	OCC: OemCpConverter,
+{
+	file.seek(SeekFrom::Start(offset)).map_err(map_error)?;
+	file.write(buf).map_err(map_error)
+}
+
+/// Write the name of an entry as UTF-8 to `buf`. The long name is used if there is one,
+/// otherwise the short name.
+pub fn name<'b, IO, TP, OCC>(entry: &DirEntry<IO, TP, OCC>, buf: &'b mut [u8]) -> &'b [u8]
+where
+	IO: ReadWriteSeek,
+	OCC: OemCpConverter,
+{
+	let lfn = match entry.long_file_name_as_ucs2_units() {
+		Some(lfn) => lfn,
+		None => {
+			let sfn = entry.short_file_name_as_bytes();
+			buf[..sfn.len()].copy_from_slice(sfn);
+			return &buf[..sfn.len()];
+		}
+	};
+	let mut len = 0;
+	for c in char::decode_utf16(lfn.iter().copied()) {
+		let c = c.unwrap_or(char::REPLACEMENT_CHARACTER);
+		len += c.encode_utf8(&mut buf[len..]).len();
+	}
+	&buf[..len]
+}
+
+/// Check that every component of the path is a name FAT can store.
+///
+/// fatfs already rejects these names when creating a file, but only after it started looking
+/// up the path, and looking up an invalid name fails with [`Error::NotFound`].
+fn validate(path: &[u8]) -> Result<&str, Error> {
+	let path = core::str::from_utf8(path).map_err(|_| Error::InvalidArg)?;
+	let valid_name = |name: &str| {
+		!name.is_empty()
+			&& name.encode_utf16().count() <= MAX_NAME_UNITS
+			&& name.chars().all(|c| {
+				c.is_ascii_alphanumeric()
+					|| ('\u{80}'..='\u{ffff}').contains(&c)
+					|| " $%'-_@~`!(){}.+,;=[]^#&".contains(c)
+			})
+	};
+	path.trim_matches('/')
+		.split('/')
+		.all(valid_name)
+		.then(|| path)
+		.ok_or(Error::InvalidArg)
+}
+
+fn map_error<E>(error: fatfs::Error<E>) -> Error {
+	match error {
+		fatfs::Error::NotFound => Error::NotFound,
+		fatfs::Error::InvalidInput
+		| fatfs::Error::InvalidFileNameLength
+		| fatfs::Error::UnsupportedFileNameCharacter => Error::InvalidArg,
+		_ => Error::Io,
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+	use fatfs::IoBase;
+	use std::vec::Vec;
+
+	type FileSystem =
+		fatfs::FileSystem<Disk, fatfs::DefaultTimeProvider, fatfs::LossyOemCpConverter>;
+
+	/// An in-memory disk.
+	struct Disk {
+		data: Vec<u8>,
+		position: usize,
+	}
+
+	impl IoBase for Disk {
+		type Error = ();
+	}
+
+	impl Read for Disk {
+		fn read(&mut self, buf: &mut [u8]) -> Result<usize, ()> {
+			let n = buf.len().min(self.data.len() - self.position);
+			buf[..n].copy_from_slice(&self.data[self.position..][..n]);
+			self.position += n;
+			Ok(n)
+		}
+	}
+
+	impl Write for Disk {
+		fn write(&mut self, buf: &[u8]) -> Result<usize, ()> {
+			let n = buf.len().min(self.data.len() - self.position);
+			self.data[self.position..][..n].copy_from_slice(&buf[..n]);
+			self.position += n;
+			Ok(n)
+		}
+
+		fn flush(&mut self) -> Result<(), ()> {
+			Ok(())
+		}
+	}
+
+	impl Seek for Disk {
+		fn seek(&mut self, pos: SeekFrom) -> Result<u64, ()> {
+			self.position = match pos {
+				SeekFrom::Start(n) => n as usize,
+				SeekFrom::Current(n) => (self.position as i64 + n) as usize,
+				SeekFrom::End(n) => (self.data.len() as i64 + n) as usize,
+			};
+			Ok(self.position as u64)
+		}
+	}
+
+	fn filesystem() -> FileSystem {
+		let mut disk = Disk {
+			data: vec![0; 1 << 20],
+			position: 0,
+		};
+		fatfs::format_volume(&mut disk, fatfs::FormatVolumeOptions::new()).unwrap();
+		disk.position = 0;
+		fatfs::FileSystem::new(disk, fatfs::FsOptions::new()).unwrap()
+	}
+
+	fn names(fs: &FileSystem) -> Vec<String> {
+		let mut buf = [0; MAX_NAME_LEN];
+		fs.root_dir()
+			.iter()
+			.map(|e| String::from_utf8(name(&e.unwrap(), &mut buf).into()).unwrap())
+			.collect()
+	}
+
+	#[test]
+	fn long_names() {
+		let fs = filesystem();
+		let long = "a file name that is definitely longer than sixty-four characters.txt";
+		let created = [
+			"MyDocument.txt",
+			"with some spaces",
+			"ünïcödé ファイル",
+			long,
+		];
+		for (i, n) in created.iter().enumerate() {
+			create(&fs.root_dir(), n.as_bytes())
+				.unwrap()
+				.write_all(&[i as u8])
+				.unwrap();
+		}
+		assert_eq!(names(&fs), created);
+		for (i, n) in created.iter().enumerate() {
+			let mut buf = [0xff];
+			open(&fs.root_dir(), n.as_bytes())
+				.unwrap()
+				.read_exact(&mut buf)
+				.unwrap();
+			assert_eq!(buf, [i as u8]);
+		}
+	}
+
+	#[test]
+	fn case_insensitive() {
+		let fs = filesystem();
+		create(&fs.root_dir(), b"MyDocument.txt")
+			.unwrap()
+			.write_all(b"duck")
+			.unwrap();
+		let mut buf = [0; 4];
+		open(&fs.root_dir(), b"mydocument.TXT")
+			.unwrap()
+			.read_exact(&mut buf)
+			.unwrap();
+		assert_eq!(&buf, b"duck");
+		// Creating a file with a name that only differs in case opens the existing file.
+		create(&fs.root_dir(), b"MYDOCUMENT.TXT").unwrap();
+		assert_eq!(names(&fs), ["MyDocument.txt"]);
+	}
+
+	#[test]
+	fn invalid_names() {
+		let fs = filesystem();
+		let root = fs.root_dir();
+		let invalid = Some(Error::InvalidArg);
+		for n in ["", "a:b", "a?", "<>", "a\\b", "a|b", "\"", "\u{1f986}"] {
+			assert_eq!(create(&root, n.as_bytes()).err(), invalid);
+			assert_eq!(open(&root, n.as_bytes()).err(), invalid);
+		}
+		let long = "a".repeat(MAX_NAME_UNITS + 1);
+		assert_eq!(create(&root, long.as_bytes()).err(), invalid);
+		assert_eq!(create(&root, &[0xff]).err(), invalid);
+		assert_eq!(names(&fs), Vec::<String>::new());
+	}
+
+	#[test]
+	fn not_found() {
+		let fs = filesystem();
+		assert_eq!(open(&fs.root_dir(), b"ducks").err(), Some(Error::NotFound));
+	}
+
+	#[test]
+	fn no_space() {
+		let fs = filesystem();
+		let mut file = create(&fs.root_dir(), b"big").unwrap();
+		let buf = vec![0xff; 1 << 16];
+		let mut offset = 0;
+		let error = loop {
+			match write(&mut file, offset, &buf) {
+				Ok(n) => {
+					assert_ne!(n, 0);
+					offset += n as u64;
+				}
+				Err(e) => break e,
+			}
+		};
+		assert_eq!(error, Error::Io);
+		assert_eq!(read(&mut file, 0, &mut [0; 4]), Ok(4));
+	}
+}
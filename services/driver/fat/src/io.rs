@@ -5,12 +5,23 @@ use fatfs::*;
 pub static mut ADDRESS: usize = 0;
 pub static mut UUID: kernel::ipc::UUID = kernel::ipc::UUID::new(0);
 
+/// The time to wait before sending a request the block device was too busy for again, in
+/// microseconds.
+const MIN_RETRY_DELAY: u64 = 10_000;
+/// The maximum time to wait between attempts, in microseconds.
+const MAX_RETRY_DELAY: u64 = 1_000_000;
+
 pub struct GlobalIO<'a> {
 	buffer: &'a mut kernel::Page,
 	position: u64,
 	buffer_sector: u64,
 	max_position: u64,
 	dirty: bool,
+	/// The position at which the data region starts. Everything before it is metadata.
+	///
+	/// Directories outside the root directory of FAT12/16 are in the data region and aren't
+	/// considered metadata, as we can't tell them apart from file data at this level.
+	data_start: u64,
 }
 
 impl<'a> GlobalIO<'a> {
@@ -21,11 +32,63 @@ impl<'a> GlobalIO<'a> {
 			dirty: false,
 			max_position: 512 * 32, // TODO
 			buffer_sector: u64::MAX,
+			data_start: 0,
 		};
 		slf.fetch();
+		slf.data_start = data_start(slf.buffer.as_ref());
 		slf
 	}
 
+	/// Return the flags for a request for the current buffer.
+	fn flags(&self) -> u16 {
+		if self.buffer_sector * (kernel::Page::SIZE as u64) < self.data_start {
+			kernel::ipc::FLAG_PRIORITY_HIGH
+		} else {
+			0
+		}
+	}
+
+	/// Send a request for the current buffer and wait for the reply. The request is sent again
+	/// if the block device is too busy to take it, e.g. because the system is suspending.
+	///
+	/// The delay between attempts doubles each time, so a long suspend doesn't keep waking
+	/// this task.
+	fn request(&self, op: kernel::ipc::Op) {
+		let opcode = Some(op.into());
+		let mut delay = MIN_RETRY_DELAY;
+		loop {
+			unsafe {
+				*dux::ipc::transmit() = kernel::ipc::Packet {
+					opcode,
+					address: ADDRESS,
+					uuid: UUID,
+					data: Some(core::ptr::NonNull::from(&*self.buffer)),
+					length: kernel::Page::SIZE,
+					offset: self.buffer_sector,
+					flags: self.flags(),
+					id: 0,
+					name: None,
+					name_len: 0,
+				};
+			}
+			let pkt = loop {
+				let pkt = dux::ipc::receive();
+				if pkt.address != unsafe { ADDRESS } {
+					pkt.defer();
+					unsafe { kernel::io_wait(10_000) };
+					continue;
+				}
+				break pkt;
+			};
+			if pkt.flags & kernel::ipc::FLAG_ERROR == 0 || pkt.offset != kernel::ipc::ERROR_BUSY {
+				break;
+			}
+			drop(pkt);
+			unsafe { kernel::io_wait(delay) };
+			delay = (delay * 2).min(MAX_RETRY_DELAY);
+		}
+	}
+
 	fn seek_sector(&self) -> u64 {
 		self.position / kernel::Page::SIZE as u64
 	}
@@ -41,29 +104,7 @@ impl<'a> GlobalIO<'a> {
 	fn fetch(&mut self) {
 		self.buffer_sector = self.seek_sector();
 
-		unsafe {
-			*dux::ipc::transmit() = kernel::ipc::Packet {
-				opcode: Some(kernel::ipc::Op::Read.into()),
-				address: ADDRESS,
-				uuid: UUID,
-				data: Some(core::ptr::NonNull::from(&*self.buffer)),
-				length: kernel::Page::SIZE,
-				offset: self.buffer_sector,
-				flags: 0,
-				id: 0,
-				name: None,
-				name_len: 0,
-			};
-		}
-		loop {
-			let pkt = dux::ipc::receive();
-			if pkt.address != unsafe { ADDRESS } {
-				pkt.defer();
-				unsafe { kernel::io_wait(10_000) };
-				continue;
-			}
-			break;
-		}
+		self.request(kernel::ipc::Op::Read);
 	}
 
 	fn max_seek(&self) -> u64 {
@@ -71,6 +112,21 @@ impl<'a> GlobalIO<'a> {
 	}
 }
 
+/// Determine the start of the data region from the boot sector.
+fn data_start(boot: &[u8]) -> u64 {
+	let u16_at = |i: usize| u64::from(u16::from_le_bytes([boot[i], boot[i + 1]]));
+	let bytes_per_sector = u16_at(11);
+	let reserved_sectors = u16_at(14);
+	let fats = u64::from(boot[16]);
+	let root_entries = u16_at(17);
+	let sectors_per_fat = match u16_at(22) {
+		// FAT32 stores the size in a 32 bit field instead.
+		0 => u64::from(u32::from_le_bytes([boot[36], boot[37], boot[38], boot[39]])),
+		n => n,
+	};
+	(reserved_sectors + fats * sectors_per_fat) * bytes_per_sector + root_entries * 32
+}
+
 impl IoBase for GlobalIO<'_> {
 	type Error = ();
 }
@@ -125,29 +181,7 @@ impl Write for GlobalIO<'_> {
 
 		self.buffer_sector = self.seek_sector();
 
-		unsafe {
-			*dux::ipc::transmit() = kernel::ipc::Packet {
-				opcode: Some(kernel::ipc::Op::Write.into()),
-				address: ADDRESS,
-				uuid: UUID,
-				data: Some(core::ptr::NonNull::from(&*self.buffer)),
-				length: kernel::Page::SIZE,
-				offset: self.buffer_sector,
-				flags: 0,
-				id: 0,
-				name: None,
-				name_len: 0,
-			};
-		}
-		loop {
-			let pkt = dux::ipc::receive();
-			if pkt.address != unsafe { ADDRESS } {
-				pkt.defer();
-				unsafe { kernel::io_wait(10_000) };
-				continue;
-			}
-			break;
-		}
+		self.request(kernel::ipc::Op::Write);
 		self.dirty = false;
 		Ok(())
 	}
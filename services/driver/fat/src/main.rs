@@ -1,12 +1,13 @@
 //! # FAT filesystem driver
 
-#![no_std]
-#![no_main]
+#![cfg_attr(not(test), no_std)]
+#![cfg_attr(not(test), no_main)]
 #![feature(asm)]
 #![feature(global_asm)]
 #![feature(naked_functions)]
 #![feature(panic_info_message)]
 
+#[cfg(not(test))]
 #[panic_handler]
 fn panic_handler(info: &core::panic::PanicInfo) -> ! {
 	kernel::sys_log!("Panic!");
@@ -19,18 +20,46 @@ fn panic_handler(info: &core::panic::PanicInfo) -> ! {
 	loop {}
 }
 
+#[cfg(not(test))]
 use core::convert::TryFrom;
 
+mod fs;
+#[cfg(not(test))]
 mod io;
+#[cfg(not(test))]
 mod rtbegin;
 
+#[cfg(not(test))]
 #[export_name = "main"]
 fn main() {
 	unsafe { dux::init() };
 
+	// Use the disk with the given serial if one is specified.
+	let mut name = [0; 32];
+	let mut name_len = 0;
+	let mut args = rtbegin::args();
+	while let Some(arg) = args.next() {
+		match arg {
+			b"--disk" => {
+				let serial = args.next().expect("expected serial after --disk");
+				let prefix = b"virtio_block-";
+				let n = name
+					.get_mut(..prefix.len() + serial.len())
+					.expect("serial too long");
+				n[..prefix.len()].copy_from_slice(prefix);
+				n[prefix.len()..].copy_from_slice(serial);
+				name_len = n.len();
+			}
+			arg => panic!("bad argument: {:?}", arg),
+		}
+	}
+	let name = match name_len {
+		0 => &b"virtio_block"[..],
+		len => &name[..len],
+	};
+
 	// Wait for virtio_block driver to come online
 	let addr = loop {
-		let name = b"virtio_block";
 		let ret = unsafe { kernel::sys_registry_get(name.as_ptr(), name.len()) };
 		if ret.status == 0 {
 			break ret.value;
@@ -74,9 +103,16 @@ fn main() {
 		let rxq_lock = dux::ipc::receive();
 		let rxq = (*rxq_lock).clone();
 		drop(rxq_lock);
-		let opcode = rxq.opcode.unwrap();
 
-		use fatfs::{Read, Seek, SeekFrom, Write};
+		// The pages of list replies belong to the list builder, which already freed them.
+		let list = rxq.opcode == Some(kernel::ipc::Op::List.into());
+		if (list && dux::task::dead_peer(&rxq).is_some()) || dux::ipc::release_dead(&rxq) {
+			continue;
+		}
+		if dux::ipc::acknowledge_power(&rxq) {
+			continue;
+		}
+		let opcode = rxq.opcode.unwrap();
 
 		match kernel::ipc::Op::try_from(opcode) {
 			Ok(kernel::ipc::Op::Read) => {
@@ -84,15 +120,16 @@ fn main() {
 				let data = unsafe {
 					core::slice::from_raw_parts_mut(rxq.data.unwrap().as_ptr().cast(), rxq.length)
 				};
-				let path = rxq.name.map(|name| unsafe {
+				let path = rxq.name.map_or(&[][..], |name| unsafe {
 					core::slice::from_raw_parts(name.cast::<u8>().as_ptr(), rxq.name_len.into())
 				});
 
-				let path = path.unwrap();
-				let path = core::str::from_utf8(path).unwrap();
-				let mut file = fs.root_dir().open_file(path).unwrap();
-				file.seek(SeekFrom::Start(rxq.offset)).unwrap();
-				let length = file.read(&mut data[..rxq.length]).unwrap();
+				let length = fs::open(&fs.root_dir(), path)
+					.and_then(|mut file| fs::read(&mut file, rxq.offset, &mut data[..rxq.length]));
+				let (length, flags, offset) = match length {
+					Ok(length) => (length, 0, rxq.offset),
+					Err(e) => (0, kernel::ipc::FLAG_ERROR, e.code()),
+				};
 
 				// Send completion event
 				*dux::ipc::transmit() = kernel::ipc::Packet {
@@ -100,12 +137,12 @@ fn main() {
 					opcode: Some(opcode),
 					name: None,
 					name_len: 0,
-					flags: 0,
+					flags,
 					id: rxq.id,
 					address: rxq.address,
 					data: None,
 					length,
-					offset: rxq.offset,
+					offset,
 				};
 			}
 			Ok(kernel::ipc::Op::Write) => {
@@ -113,15 +150,16 @@ fn main() {
 				let data = unsafe {
 					core::slice::from_raw_parts_mut(rxq.data.unwrap().as_ptr().cast(), rxq.length)
 				};
-				let path = rxq.name.map(|name| unsafe {
+				let path = rxq.name.map_or(&[][..], |name| unsafe {
 					core::slice::from_raw_parts(name.cast::<u8>().as_ptr(), rxq.name_len.into())
 				});
 
-				let path = path.unwrap();
-				let path = core::str::from_utf8(path).unwrap();
-				let mut file = fs.root_dir().create_file(path).unwrap();
-				file.seek(SeekFrom::Start(rxq.offset)).unwrap();
-				let length = file.write(&mut data[..rxq.length]).unwrap();
+				let length = fs::create(&fs.root_dir(), path)
+					.and_then(|mut file| fs::write(&mut file, rxq.offset, &data[..rxq.length]));
+				let (length, flags, offset) = match length {
+					Ok(length) => (length, 0, rxq.offset),
+					Err(e) => (0, kernel::ipc::FLAG_ERROR, e.code()),
+				};
 
 				// Confirm reception.
 				let mut tx = dux::ipc::transmit();
@@ -130,23 +168,28 @@ fn main() {
 					opcode: Some(opcode),
 					name: None,
 					name_len: 0,
-					flags: 0,
+					flags,
 					id: rxq.id,
 					address: rxq.address,
 					data: None,
 					length,
-					offset: rxq.offset,
+					offset,
 				};
 				// Drop now to prevent a deadlock
 				drop(tx);
 			}
 			Ok(kernel::ipc::Op::List) => {
-				let mut list_builder =
-					dux::ipc::list::Builder::new(fs.root_dir().iter().count(), 50).unwrap();
+				// Names are encoded as UTF-8 and can be much longer than short names, so
+				// determine the space needed for them first.
+				let mut name = [0; fs::MAX_NAME_LEN];
+				let (count, names_len) = fs.root_dir().iter().fold((0, 0), |(c, l), f| {
+					(c + 1, l + fs::name(&f.unwrap(), &mut name).len())
+				});
+				let mut list_builder = dux::ipc::list::Builder::new(count, names_len).unwrap();
 				for f in fs.root_dir().iter() {
 					let f = f.unwrap();
 					let uuid = kernel::ipc::UUID::from(0);
-					let name = f.short_file_name_as_bytes();
+					let name = fs::name(&f, &mut name);
 					let size = f.len();
 					list_builder.add(uuid, name, size).unwrap();
 				}
@@ -173,26 +216,6 @@ fn main() {
 			_ => (),
 		}
 
-		// Free ranges
-		if let Some(data) = rxq.data {
-			let len = dux::Page::min_pages_for_range(rxq.length);
-			let ret = unsafe { kernel::mem_dealloc(data.as_ptr() as *mut _, len) };
-			assert_eq!(ret.status, 0);
-			dux::ipc::add_free_range(
-				dux::Page::new(core::ptr::NonNull::new(data.as_ptr() as *mut _).unwrap()).unwrap(),
-				len,
-			)
-			.unwrap();
-		}
-		if let Some(name) = rxq.name {
-			let len = dux::Page::min_pages_for_range(rxq.name_len.into());
-			let ret = unsafe { kernel::mem_dealloc(name.as_ptr() as *mut _, len) };
-			assert_eq!(ret.status, 0);
-			dux::ipc::add_free_range(
-				dux::Page::new(core::ptr::NonNull::new(name.as_ptr() as *mut _).unwrap()).unwrap(),
-				len,
-			)
-			.unwrap();
-		}
+		dux::ipc::release(&rxq);
 	}
 }
@@ -1,12 +1,17 @@
 #![no_std]
 #![no_main]
+#![feature(allocator_api)]
 #![feature(asm)]
+#![feature(default_alloc_error_handler)]
 #![feature(global_asm)]
 #![feature(maybe_uninit_slice)]
 #![feature(maybe_uninit_uninit_array)]
 #![feature(naked_functions)]
 #![feature(panic_info_message)]
 
+extern crate alloc;
+
+use alloc::vec::Vec;
 use core::convert::{TryFrom, TryInto};
 use core::mem::MaybeUninit;
 use core::ptr::NonNull;
@@ -24,6 +29,9 @@ fn panic_handler(info: &core::panic::PanicInfo) -> ! {
 	loop {}
 }
 
+#[global_allocator]
+static GLOBAL: dux::alloc::NoGlobalAlloc = dux::alloc::NoGlobalAlloc;
+
 mod notification;
 mod rtbegin;
 
@@ -42,29 +50,35 @@ struct InterruptMap {
 	child_address: u128,
 }
 
-static mut TASKS: [Task; 16] = [Task {
-	address: dux::task::Address::new(0),
-	child_address: 0,
-}; 16];
-static mut TASKS_COUNT: usize = 0;
+/// A memory mapped region of the PCI I/O space.
+struct IoRange {
+	/// The address of the region as seen by the CPU.
+	physical: usize,
+	/// The address of the region on the PCI bus.
+	bus: usize,
+	size: usize,
+	/// The offset of the next free I/O address.
+	next: usize,
+}
 
-static mut INTERRUPT_MAP: [InterruptMap; 16] = [InterruptMap {
-	bus: 0,
-	system: 0,
-	child_address: 0,
-}; 16];
-static mut INTERRUPT_MAP_COUNT: usize = 0;
-static mut INTERRUPT_MAP_MASK: driver::InterruptMapMask = driver::InterruptMapMask::new(0, 0);
+/// The maximum amount of pages used for the tables of tasks & interrupts.
+const TABLE_PAGES: usize = 4;
 
 #[export_name = "main"]
 fn main() {
 	unsafe { dux::init() };
 
+	let arena = dux::alloc::Arena::new(TABLE_PAGES);
+	let mut tasks = Vec::<Task, _>::new_in(&arena);
+	let mut interrupt_map = Vec::<InterruptMap, _>::new_in(&arena);
+	let mut interrupt_map_mask = driver::InterruptMapMask::new(0, 0);
+
 	let mut reg = None;
 	let mut mmio = MaybeUninit::<pci::PhysicalMemory>::uninit_array::<8>();
 	let mut mmio_count = 0;
-	let mut unique_irqs = [0; 8];
-	let mut unique_irqs_count = 0;
+	let mut io = None;
+	let mut unique_irqs = Vec::new_in(&arena);
+	let mut latency_debug = false;
 
 	driver::parse_args(rtbegin::args(), |arg, _| match arg {
 		driver::Arg::Reg(r) => {
@@ -73,6 +87,16 @@ fn main() {
 				.expect_err("expecteed only one --reg specifier");
 		}
 		driver::Arg::Range(range) => {
+			// Bits 24-25 of the high cell indicate the address space.
+			if (range.child_address >> (64 + 24)) & 0x3 == 0x1 {
+				io = Some(IoRange {
+					physical: usize::try_from(range.address).expect("physical address too large"),
+					bus: usize::try_from(range.child_address as u64)
+						.expect("I/O address too large"),
+					size: usize::try_from(range.size).expect("size too large"),
+					next: 0,
+				});
+			}
 			mmio[mmio_count].write(pci::PhysicalMemory {
 				physical: usize::try_from(range.address).expect("physical address too large"),
 				virt: NonNull::new(usize::MAX as *mut _).unwrap(),
@@ -80,21 +104,21 @@ fn main() {
 			});
 			mmio_count += 1;
 		}
-		driver::Arg::InterruptMap(m) => unsafe {
+		driver::Arg::InterruptMap(m) => {
 			let system = m.parent_interrupt.try_into().unwrap();
-			INTERRUPT_MAP[INTERRUPT_MAP_COUNT] = InterruptMap {
+			interrupt_map.push(InterruptMap {
 				bus: m.child_interrupt.try_into().unwrap(),
 				system,
 				child_address: m.child_address,
-			};
-			if !unique_irqs[..unique_irqs_count].contains(&system) {
-				unique_irqs[unique_irqs_count] = system;
-				unique_irqs_count += 1
+			});
+			if !unique_irqs.contains(&system) {
+				unique_irqs.push(system);
 			}
-			INTERRUPT_MAP_COUNT += 1;
-		},
-		driver::Arg::InterruptMapMask(m) => unsafe { INTERRUPT_MAP_MASK = m },
-		driver::Arg::Other(o) => panic!("unhandled {:?}", core::str::from_utf8(o)),
+		}
+		driver::Arg::InterruptMapMask(m) => interrupt_map_mask = m,
+		driver::Arg::Other(b"--latency-debug") => latency_debug = true,
+		// Ignore flags meant for other services, so they can be passed on blindly.
+		driver::Arg::Other(o) => kernel::sys_log!("pci: ignoring {:?}", core::str::from_utf8(o)),
 		_ => todo!(),
 	})
 	.expect("failed to parse all arguments");
@@ -147,6 +171,15 @@ fn main() {
 		for dev in bus.iter() {
 			let (v, d) = (dev.vendor_id(), dev.device_id());
 
+			if let pci::Header::H0(h) = dev.header() {
+				if let Some(link) = h.pcie_link_summary() {
+					kernel::sys_log!("{:x}|{:x}: link {}", v, d, link);
+					if link.is_degraded() {
+						kernel::sys_log!("{:x}|{:x}: link is degraded", v, d);
+					}
+				}
+			}
+
 			if let Some(bin) = BINARIES.iter().find(|b| b.vendor == v && b.device == d) {
 				// FIXME completely, utterly unsound
 				let data = unsafe {
@@ -179,6 +212,10 @@ fn main() {
 					Ok(())
 				};
 
+				buf = driver::ArgvVersion::CURRENT
+					.to_args(buf, &mut alloc, &mut add_arg)
+					.unwrap();
+
 				// Pass PCI MMIO area
 				let child_address = u128::from(dev.child_address());
 				let address = u128::try_from(dev.header_physical_address()).unwrap();
@@ -212,8 +249,34 @@ fn main() {
 						b.set(0);
 					}
 
-					// Set bar
 					let size = usize::try_from(size).unwrap();
+					let i = u128::try_from(i).unwrap();
+					let s = u128::try_from(size).unwrap();
+
+					// I/O BARs are allocated from the I/O range, which is memory mapped.
+					if pci::BaseAddress::is_io(og) {
+						let range = match io.as_mut() {
+							Some(range) => range,
+							None => {
+								kernel::sys_log!("No I/O range for BAR {} of {:x}|{:x}", i, v, d);
+								continue;
+							}
+						};
+						let offt = range.next & (size - 1);
+						if offt > 0 {
+							range.next += size - offt;
+						}
+						assert!(range.next + size <= range.size, "out of I/O space");
+						b.set(u32::try_from(range.bus + range.next).unwrap());
+						let a = u128::try_from(range.physical + range.next).unwrap();
+						buf = driver::BarIo::new(i, a, s)
+							.to_args(buf, &mut alloc, &mut add_arg)
+							.unwrap();
+						range.next += size;
+						continue;
+					}
+
+					// Set bar
 					let offt = mmio & (size - 1);
 					if offt > 0 {
 						mmio += size - offt;
@@ -221,30 +284,35 @@ fn main() {
 					b.set(u32::try_from(mmio).unwrap());
 
 					// Push args
-					let i = u128::try_from(i).unwrap();
 					let a = u128::try_from(mmio).unwrap();
-					let s = u128::try_from(size).unwrap();
-					buf = match pci::BaseAddress::is_mmio(og) {
-						true => {
-							driver::BarMmio::new(i, a, s).to_args(buf, &mut alloc, &mut add_arg)
-						}
-						false => driver::BarIo::new(i, a, s).to_args(buf, &mut alloc, &mut add_arg),
-					}
-					.unwrap();
+					buf = driver::BarMmio::new(i, a, s)
+						.to_args(buf, &mut alloc, &mut add_arg)
+						.unwrap();
 
 					mmio += size;
 				}
 
+				// Only the input driver measures latency.
+				if latency_debug && bin.name == "virtio_input" {
+					add_arg("--latency-debug").unwrap();
+				}
+
 				let ret = dux::task::spawn_elf(data, &mut [].iter().copied(), &args[..argc]);
-				let address = ret.unwrap();
+				let address = match ret {
+					Ok(address) => address,
+					Err(e) => {
+						kernel::sys_log!("Failed to spawn driver for {:x}|{:x}: {:?}", v, d, e);
+						continue;
+					}
+				};
 				kernel::sys_log!("Spawned driver as {}", address);
-				unsafe {
-					TASKS[TASKS_COUNT] = Task {
-						address,
-						child_address: child_address << 64,
-					};
-					TASKS_COUNT += 1;
+				if let Err(e) = dux::task::TaskWatch::new(address) {
+					kernel::sys_log!("Failed to watch driver {}: {:?}", address, e);
 				}
+				tasks.push(Task {
+					address,
+					child_address: child_address << 64,
+				});
 			} else {
 				kernel::sys_log!("No driver found for {:x}|{:x}", v, d);
 			}
@@ -252,30 +320,43 @@ fn main() {
 	}
 
 	// Enable notifications / interrupts
-	notification::init(&unique_irqs[..unique_irqs_count]);
+	notification::init(&unique_irqs);
 
 	loop {
 		const OP_OPEN: u8 = 128;
 
 		let rx = dux::ipc::receive();
+		if let Some(address) = dux::task::dead_peer(&rx) {
+			kernel::sys_log!("Driver {} died", address);
+			forget_task(&mut tasks, address);
+			continue;
+		}
 		match rx.opcode.map(|n| n.get()).unwrap_or(0) {
-			OP_OPEN => unsafe {
+			OP_OPEN => {
 				let intr = u128::from(rx.uuid);
-				let task = TASKS[..TASKS_COUNT]
+				let task = tasks
 					.iter()
 					.find(|t| usize::from(t.address) == rx.address)
 					.unwrap();
-				let mask_addr = task.child_address & INTERRUPT_MAP_MASK.child_address;
-				let mask_intr = intr & INTERRUPT_MAP_MASK.child_interrupt;
-				let intr = INTERRUPT_MAP[..INTERRUPT_MAP_COUNT.into()]
+				let mask_addr = task.child_address & interrupt_map_mask.child_address;
+				let mask_intr = intr & interrupt_map_mask.child_interrupt;
+				let intr = interrupt_map
 					.iter()
 					.find(|intr| {
 						intr.child_address == mask_addr && u128::from(intr.bus) == mask_intr
 					})
 					.unwrap();
 				notification::add_interrupt_listener(intr.system, rx.address);
-			},
+			}
 			_ => (),
 		}
 	}
 }
+
+/// Remove all state associated with a driver.
+fn forget_task(tasks: &mut Vec<Task, dux::alloc::ArenaAlloc>, address: dux::task::Address) {
+	if let Some(i) = tasks.iter().position(|t| t.address == address) {
+		tasks.swap_remove(i);
+	}
+	notification::remove_interrupt_listener(address.into());
+}
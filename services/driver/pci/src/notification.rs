@@ -1,3 +1,7 @@
+use core::cell::UnsafeCell;
+use core::sync::atomic::{AtomicBool, Ordering};
+use dux::notification::{self, Handler, Notification};
+
 #[derive(Clone, Copy)]
 struct Interrupt {
 	tasks: [usize; 16],
@@ -9,87 +13,93 @@ struct Interrupt {
 	index: u8,
 }
 
-static mut INTERRUPT_LISTENERS: [Interrupt; 16] = [Interrupt {
-	tasks: [0; 16],
-	tasks_count: 0,
-	interrupt: 0,
-	index: 0,
-}; 16];
-static mut INTERRUPT_LISTENERS_COUNT: u8 = 0;
+struct Table {
+	interrupts: [Interrupt; 16],
+	count: u8,
+}
+
+/// The tasks listening to each interrupt.
+///
+/// The table is shared by the main routine and the notification handler. The handler may
+/// interrupt the main routine while it holds the lock, in which case the handler can't wait
+/// for it.
+struct Listeners {
+	lock: AtomicBool,
+	table: UnsafeCell<Table>,
+}
+
+// SAFETY: the table is only accessed with the lock held.
+unsafe impl Sync for Listeners {}
+
+impl Listeners {
+	/// Run the closure with the table locked. Returns `None` if the lock is held.
+	fn try_with<R>(&self, f: impl FnOnce(&mut Table) -> R) -> Option<R> {
+		self.lock
+			.compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
+			.ok()?;
+		// SAFETY: we hold the lock.
+		let ret = f(unsafe { &mut *self.table.get() });
+		self.lock.store(false, Ordering::Release);
+		Some(ret)
+	}
 
-#[naked]
-extern "C" fn notification_handler_entry() {
-	unsafe {
-		asm!(
-			"
-			# a0: type
-			# a1: value
-			# a7: address
-			#
-			# The original a[0-2] are stored on the stack by the kernel.
-			.equ	GP_REGBYTES, 8
-			.equ	NOTIFY_RETURN, 9
-			addi	sp, sp, -(13 + 4) * GP_REGBYTES
-			sd		t0, 0 * GP_REGBYTES (sp)
-			sd		t1, 1 * GP_REGBYTES (sp)
-			sd		t2, 2 * GP_REGBYTES (sp)
-			sd		t3, 3 * GP_REGBYTES (sp)
-			sd		t4, 4 * GP_REGBYTES (sp)
-			sd		t5, 5 * GP_REGBYTES (sp)
-			sd		t6, 6 * GP_REGBYTES (sp)
-			sd		a3, 7 * GP_REGBYTES (sp)
-			sd		a4, 8 * GP_REGBYTES (sp)
-			sd		a5, 9 * GP_REGBYTES (sp)
-			sd		a6, 10 * GP_REGBYTES (sp)
-			sd		a2, 11 * GP_REGBYTES (sp)
-			sd		ra, 12 * GP_REGBYTES (sp)
-			mv		a2, a7
-			call	notification_handler
-			ld		t0, 0 * GP_REGBYTES (sp)
-			ld		t1, 1 * GP_REGBYTES (sp)
-			ld		t2, 2 * GP_REGBYTES (sp)
-			ld		t3, 3 * GP_REGBYTES (sp)
-			ld		t4, 4 * GP_REGBYTES (sp)
-			ld		t5, 5 * GP_REGBYTES (sp)
-			ld		t6, 6 * GP_REGBYTES (sp)
-			ld		a3, 7 * GP_REGBYTES (sp)
-			ld		a4, 8 * GP_REGBYTES (sp)
-			ld		a5, 9 * GP_REGBYTES (sp)
-			ld		a6, 10 * GP_REGBYTES (sp)
-			ld		a2, 11 * GP_REGBYTES (sp)
-			ld		ra, 12 * GP_REGBYTES (sp)
-			addi	sp, sp, (13 + 4) * GP_REGBYTES
-			li		a7, NOTIFY_RETURN
-			ecall
-		",
-			options(noreturn)
-		);
+	/// Run the closure with the table locked.
+	///
+	/// Only the main routine may call this. The handler releases the lock before it returns,
+	/// so the lock is never held at this point.
+	fn with<R>(&self, f: impl FnOnce(&mut Table) -> R) -> R {
+		self.try_with(f).expect("listener table is locked")
 	}
 }
 
-#[export_name = "notification_handler"]
-extern "C" fn notification_handler(typ: usize, value: usize, address: usize) -> usize {
-	if typ != 0 || address != usize::MAX {
-		return usize::MAX;
+static HANDLER: Handler<Listeners> = Handler::new(
+	notification_handler,
+	Listeners {
+		lock: AtomicBool::new(false),
+		table: UnsafeCell::new(Table {
+			interrupts: [Interrupt {
+				tasks: [0; 16],
+				tasks_count: 0,
+				interrupt: 0,
+				index: 0,
+			}; 16],
+			count: 0,
+		}),
+	},
+);
+
+/// Pick the task to defer an external interrupt to.
+fn notification_handler(listeners: &Listeners, n: Notification) -> Option<dux::task::Address> {
+	if n.typ != kernel::notification::TYPE_INTERRUPT || n.address != dux::task::Address::KERNEL {
+		return None;
 	}
-	let mut addr = usize::MAX;
-	unsafe {
-		INTERRUPT_LISTENERS
+	let addr = listeners.try_with(|table| {
+		table
+			.interrupts
 			.iter_mut()
-			.find(|e| usize::from(e.interrupt) == value)
+			.find(|e| usize::from(e.interrupt) == n.value && e.tasks_count > 0)
 			.map(|e| {
-				addr = e.tasks[usize::from(e.index)];
+				let addr = e.tasks[usize::from(e.index)].into();
 				e.index += 1;
 				e.index %= e.tasks_count;
+				addr
 			})
-			.or_else(|| Some(kernel::sys_log!("Someone's naughty on IRQ 0x{:x}", value)));
+	});
+	match addr {
+		Some(Some(addr)) => Some(addr),
+		Some(None) => {
+			kernel::sys_log!("Someone's naughty on IRQ 0x{:x}", n.value);
+			None
+		}
+		None => {
+			kernel::sys_log!("Dropped IRQ 0x{:x} while updating listeners", n.value);
+			None
+		}
 	}
-	addr
 }
 
 pub(crate) fn init(irqs: &[u16]) {
-	let ret = unsafe { kernel::io_set_notify_handler(notification_handler_entry) };
-	assert_eq!(ret.status, 0, "failed to set notify handler");
+	notification::set_handler(notification::Type::Interrupt, &HANDLER);
 
 	for irq in irqs.iter().copied() {
 		loop {
@@ -105,8 +115,9 @@ pub(crate) fn init(irqs: &[u16]) {
 }
 
 pub(crate) fn add_interrupt_listener(interrupt: u16, address: usize) {
-	unsafe {
-		match INTERRUPT_LISTENERS
+	HANDLER.state().with(|table| {
+		let count = usize::from(table.count);
+		match table.interrupts[..count]
 			.iter_mut()
 			.find(|e| e.interrupt == interrupt)
 		{
@@ -115,15 +126,32 @@ pub(crate) fn add_interrupt_listener(interrupt: u16, address: usize) {
 				e.tasks_count += 1;
 			}
 			None => {
-				INTERRUPT_LISTENERS[usize::from(INTERRUPT_LISTENERS_COUNT)] = Interrupt {
-					tasks: [0; 16],
+				let mut tasks = [0; 16];
+				tasks[0] = address;
+				table.interrupts[count] = Interrupt {
+					tasks,
 					tasks_count: 1,
 					interrupt,
 					index: 0,
 				};
-				INTERRUPT_LISTENERS[usize::from(INTERRUPT_LISTENERS_COUNT)].tasks[0] = address;
-				INTERRUPT_LISTENERS_COUNT += 1;
+				table.count += 1;
 			}
 		}
-	}
+	})
+}
+
+/// Remove a task from all interrupt listener lists.
+pub(crate) fn remove_interrupt_listener(address: usize) {
+	HANDLER.state().with(|table| {
+		for e in table.interrupts[..usize::from(table.count)].iter_mut() {
+			if let Some(i) = e.tasks[..usize::from(e.tasks_count)]
+				.iter()
+				.position(|&t| t == address)
+			{
+				e.tasks_count -= 1;
+				e.tasks[i] = e.tasks[usize::from(e.tasks_count)];
+				e.index = 0;
+			}
+		}
+	})
 }
@@ -33,6 +33,8 @@ extern "C" fn main(argc: usize, argv: *const *const u8) {
 				exit_err_msg("--reg specified multiple times");
 			}
 		}
+		// Ignore flags meant for other services, so they can be passed on blindly.
+		driver::Arg::Other(_) => (),
 		arg => {
 			let a = arg.cmd_arg().map(str::as_bytes).unwrap_or_else(|a| a);
 			exit_err_msg_val("invalid argument ", a);
@@ -26,23 +26,72 @@ fn panic_handler(info: &core::panic::PanicInfo) -> ! {
 
 mod rtbegin;
 
-use core::convert::{TryFrom, TryInto};
+use core::cell::UnsafeCell;
+use core::convert::TryFrom;
 use core::ptr;
+use core::sync::atomic::{AtomicU16, Ordering};
+use dux::notification::{self, Handler, Notification};
 
 /// The base address of the UART.
 const ADDRESS: *mut u8 = 0x1000_0000 as *mut _;
 
-/// Write buffer for data read.
-///
-/// 4 KiB should be quite enough.
-static mut BUFFER: [u8; 1 << 12] = [0; 1 << 12];
+/// Data read from the UART by the notification handler and not yet read by a client.
+struct Input {
+	/// Write buffer for data read.
+	///
+	/// 4 KiB should be quite enough.
+	buffer: UnsafeCell<[u8; 1 << 12]>,
+	// We spin it right round baby right round
+	/// The last index of data read from UART.
+	new_index: AtomicU16,
+	/// The last index of data read from the buffer
+	used_index: AtomicU16,
+}
 
-// We spin it right round baby right round
-/// The last index of data read from UART.
-static mut NEW_INDEX: u16 = 0;
+// SAFETY: only the notification handler writes to the buffer and only the main routine reads
+// from it. The indices ensure they never access the same byte at the same time.
+unsafe impl Sync for Input {}
+
+impl Input {
+	/// Add a byte to the buffer. Returns `false` if the buffer is full.
+	fn push(&self, byte: u8) -> bool {
+		let new = self.new_index.load(Ordering::Relaxed);
+		let len = u16::try_from(unsafe { &*self.buffer.get() }.len()).unwrap();
+		if new == self.used_index.load(Ordering::Acquire).wrapping_add(len) {
+			return false;
+		}
+		unsafe { (*self.buffer.get())[usize::from(new % len)] = byte };
+		self.new_index.store(new.wrapping_add(1), Ordering::Release);
+		true
+	}
 
-/// The last index of data read from the buffer
-static mut USED_INDEX: u16 = 0;
+	/// Take a byte from the buffer, if any.
+	fn pop(&self) -> Option<u8> {
+		let used = self.used_index.load(Ordering::Relaxed);
+		if used == self.new_index.load(Ordering::Acquire) {
+			return None;
+		}
+		let len = u16::try_from(unsafe { &*self.buffer.get() }.len()).unwrap();
+		let byte = unsafe { (*self.buffer.get())[usize::from(used % len)] };
+		self.used_index
+			.store(used.wrapping_add(1), Ordering::Release);
+		Some(byte)
+	}
+
+	/// Check if there is no data in the buffer.
+	fn is_empty(&self) -> bool {
+		self.used_index.load(Ordering::Relaxed) == self.new_index.load(Ordering::Acquire)
+	}
+}
+
+static INPUT: Handler<Input> = Handler::new(
+	notification_handler,
+	Input {
+		buffer: UnsafeCell::new([0; 1 << 12]),
+		new_index: AtomicU16::new(0),
+		used_index: AtomicU16::new(0),
+	},
+);
 
 /// Map & initialize a new UART interface at the given physical address.
 ///
@@ -120,74 +169,20 @@ pub fn write(byte: u8) -> bool {
 	tr
 }
 
-#[naked]
-extern "C" fn notification_handler_entry() {
-	unsafe {
-		asm!(
-			"
-			# a0: type
-			# a1: value
-			# a7: address
-			#
-			# The original a[0-2] are stored on the stack by the kernel.
-			.equ	GP_REGBYTES, 8
-			.equ	NOTIFY_RETURN, 9
-			addi	sp, sp, -(13 + 4) * GP_REGBYTES
-			sd		t0, 0 * GP_REGBYTES (sp)
-			sd		t1, 1 * GP_REGBYTES (sp)
-			sd		t2, 2 * GP_REGBYTES (sp)
-			sd		t3, 3 * GP_REGBYTES (sp)
-			sd		t4, 4 * GP_REGBYTES (sp)
-			sd		t5, 5 * GP_REGBYTES (sp)
-			sd		t6, 6 * GP_REGBYTES (sp)
-			sd		a3, 7 * GP_REGBYTES (sp)
-			sd		a4, 8 * GP_REGBYTES (sp)
-			sd		a5, 9 * GP_REGBYTES (sp)
-			sd		a6, 10 * GP_REGBYTES (sp)
-			sd		a2, 11 * GP_REGBYTES (sp)
-			sd		ra, 12 * GP_REGBYTES (sp)
-			mv		a2, a7
-			call	notification_handler
-			ld		t0, 0 * GP_REGBYTES (sp)
-			ld		t1, 1 * GP_REGBYTES (sp)
-			ld		t2, 2 * GP_REGBYTES (sp)
-			ld		t3, 3 * GP_REGBYTES (sp)
-			ld		t4, 4 * GP_REGBYTES (sp)
-			ld		t5, 5 * GP_REGBYTES (sp)
-			ld		t6, 6 * GP_REGBYTES (sp)
-			ld		a3, 7 * GP_REGBYTES (sp)
-			ld		a4, 8 * GP_REGBYTES (sp)
-			ld		a5, 9 * GP_REGBYTES (sp)
-			ld		a6, 10 * GP_REGBYTES (sp)
-			ld		a2, 11 * GP_REGBYTES (sp)
-			ld		ra, 12 * GP_REGBYTES (sp)
-			addi	sp, sp, (13 + 4) * GP_REGBYTES
-			li		a7, NOTIFY_RETURN
-			li		a0, -1
-			ecall
-		",
-			options(noreturn)
-		);
-	}
-}
-
-#[export_name = "notification_handler"]
-extern "C" fn notification_handler(typ: usize, value: usize, address: usize) {
-	match (typ, value, address) {
-		(0x0, intr, usize::MAX) if intr == 0xa => unsafe {
-			let full_index = USED_INDEX.wrapping_add(BUFFER.len().try_into().unwrap());
+fn notification_handler(input: &Input, n: Notification) -> Option<dux::task::Address> {
+	match (n.typ, n.value, n.address) {
+		(kernel::notification::TYPE_INTERRUPT, 0xa, dux::task::Address::KERNEL) => {
 			while let Some(c) = read() {
-				if NEW_INDEX == full_index {
+				if !input.push(c) {
 					// Disable data available interrupts for now, as we can't read more data anyways.
 					interrupt_data_available(false);
 					break;
 				}
-				BUFFER[usize::from(NEW_INDEX)] = c;
-				NEW_INDEX = NEW_INDEX.wrapping_add(1);
 			}
-		},
+		}
 		_ => (),
 	}
+	None
 }
 
 #[export_name = "main"]
@@ -195,19 +190,24 @@ fn main() {
 	// FIXME move this to rtbegin
 	unsafe { dux::init() };
 
-	let mut args = rtbegin::args();
-	let arg = args.next().unwrap();
-	let addr = args.next().unwrap();
-	let size = args.next().unwrap();
-	args.next().ok_or(()).unwrap_err();
-
-	assert_eq!(arg, b"--reg");
-	let addr = usize::from_str_radix(core::str::from_utf8(addr).unwrap(), 16).unwrap();
-	let size = usize::from_str_radix(core::str::from_utf8(size).unwrap(), 16).unwrap();
+	let mut reg = None;
+	driver::parse_args(rtbegin::args(), |arg, _| match arg {
+		driver::Arg::Reg(r) => reg
+			.replace(r)
+			.ok_or(())
+			.expect_err("--reg specified multiple times"),
+		// Ignore flags meant for other services, so they can be passed on blindly.
+		driver::Arg::Other(o) => kernel::sys_log!("uart: ignoring {:?}", core::str::from_utf8(o)),
+		arg => panic!("bad argument: {:?}", arg),
+	})
+	.unwrap();
+	let reg = reg.expect("--reg not specified");
+	let addr = usize::try_from(reg.address).unwrap();
+	let size = usize::try_from(reg.size).unwrap();
 
 	// Set up the notification handler _now_.
-	let ret = unsafe { kernel::io_set_notify_handler(notification_handler_entry) };
-	assert_eq!(ret.status, 0, "failed to set notify handler");
+	notification::set_handler(notification::Type::Interrupt, &INPUT);
+	let input = INPUT.state();
 
 	// Setup the UART device.
 	unsafe {
@@ -224,11 +224,18 @@ fn main() {
 	// Enable UART data available interrupts.
 	interrupt_data_available(true);
 
+	// We only ever reply to the sender of a request.
+	dux::task::set_strict_endpoints(true);
+
 	// Wait for & respond to requests
 	loop {
 		let rx = dux::ipc::receive();
 		let rxq = rx.clone();
 		drop(rx);
+
+		if dux::ipc::release_dead(&rxq) {
+			continue;
+		}
 		let op = rxq.opcode.unwrap();
 		match kernel::ipc::Op::try_from(op) {
 			Ok(kernel::ipc::Op::Read) => {
@@ -239,27 +246,25 @@ fn main() {
 
 				let mut length = 0;
 
-				unsafe {
-					// Wait until data is available
-					// TODO this blocks writes from other tasks.
-					while USED_INDEX == NEW_INDEX {
-						kernel::io_wait(u64::MAX);
-					}
-
-					while USED_INDEX != NEW_INDEX && length < data.len() {
-						data[length] = BUFFER[usize::from(USED_INDEX) & (BUFFER.len() - 1)];
-						// Workaround QEMU sillyness
-						if data[length] == b'\r' {
-							data[length] = b'\n';
-						}
-						USED_INDEX = USED_INDEX.wrapping_add(1);
-						length += 1;
-					}
+				// Wait until data is available
+				// TODO this blocks writes from other tasks.
+				while input.is_empty() {
+					unsafe { kernel::io_wait(u64::MAX) };
+				}
 
-					// Re-enable UART data available interrupts if it was disabled.
-					interrupt_data_available(true);
+				while length < data.len() {
+					let c = match input.pop() {
+						Some(c) => c,
+						None => break,
+					};
+					// Workaround QEMU sillyness
+					data[length] = if c == b'\r' { b'\n' } else { c };
+					length += 1;
 				}
 
+				// Re-enable UART data available interrupts if it was disabled.
+				interrupt_data_available(true);
+
 				// Send completion event
 				*dux::ipc::transmit() = kernel::ipc::Packet {
 					uuid: kernel::ipc::UUID::from(0x09090909090555577777),
@@ -309,26 +314,6 @@ fn main() {
 			_ => (),
 		}
 
-		// Free ranges
-		if let Some(data) = rxq.data {
-			let len = dux::Page::min_pages_for_range(rxq.length);
-			let ret = unsafe { kernel::mem_dealloc(data.as_ptr() as *mut _, len) };
-			assert_eq!(ret.status, 0);
-			dux::ipc::add_free_range(
-				dux::Page::new(core::ptr::NonNull::new(data.as_ptr() as *mut _).unwrap()).unwrap(),
-				len,
-			)
-			.unwrap();
-		}
-		if let Some(name) = rxq.name {
-			let len = dux::Page::min_pages_for_range(rxq.name_len.into());
-			let ret = unsafe { kernel::mem_dealloc(name.as_ptr() as *mut _, len) };
-			assert_eq!(ret.status, 0);
-			dux::ipc::add_free_range(
-				dux::Page::new(core::ptr::NonNull::new(name.as_ptr() as *mut _).unwrap()).unwrap(),
-				len,
-			)
-			.unwrap();
-		}
+		dux::ipc::release(&rxq);
 	}
 }
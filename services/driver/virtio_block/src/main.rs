@@ -24,6 +24,7 @@ fn panic_handler(info: &core::panic::PanicInfo) -> ! {
 }
 
 mod notification;
+mod pending;
 mod rtbegin;
 
 use core::convert::TryFrom;
@@ -37,6 +38,8 @@ fn main() {
 	// Parse arguments
 	let mut pci = None;
 	let mut bars = [None; 6];
+	#[cfg(feature = "legacy")]
+	let mut io_bars = [None; 6];
 
 	driver::parse_args(rtbegin::args(), |arg, _| {
 		match arg {
@@ -53,8 +56,24 @@ fn main() {
 					.ok_or(())
 					.expect_err("bar specified multiple times");
 			}
+			// Legacy devices put their registers in an I/O BAR.
+			#[cfg(feature = "legacy")]
+			driver::Arg::BarIo(b) => {
+				let e = usize::try_from(b.index)
+					.ok()
+					.and_then(|i| io_bars.get_mut(i))
+					.expect("index out of range");
+				e.replace(b)
+					.ok_or(())
+					.expect_err("bar specified multiple times");
+			}
 			// Ignore I/O, as we only use MMIO.
+			#[cfg(not(feature = "legacy"))]
 			driver::Arg::BarIo(_) => (),
+			// Ignore flags meant for other services, so they can be passed on blindly.
+			driver::Arg::Other(o) => {
+				kernel::sys_log!("virtio_block: ignoring {:?}", core::str::from_utf8(o))
+			}
 			arg => panic!("bad argument: {:?}", arg),
 		}
 	})
@@ -68,13 +87,15 @@ fn main() {
 	let size = usize::try_from(pci.size).expect("size too large");
 	let ret = unsafe { kernel::sys_direct_alloc(virt, addr, size / Page::SIZE, 0b11) };
 	assert_eq!(ret.status, 0, "failed to map pci header");
-	let pci = unsafe { pci::Header::from_raw(virt) };
+	let header_virt = virt;
+	let pci = unsafe { pci::Header::from_raw(header_virt) };
 	virt = virt.wrapping_add(size / Page::SIZE);
 
-	let irq = match pci {
-		pci::Header::H0(h) => h.interrupt_pin.get(),
+	let header = match &pci {
+		pci::Header::H0(h) => *h,
 		_ => todo!(),
 	};
+	let irq = header.interrupt_pin.get();
 
 	// Map BARs
 	let mut virt_bars = [None; 6];
@@ -91,7 +112,25 @@ fn main() {
 		});
 	}
 
-	// Route interrupts to us
+	// Map I/O BARs. These are memory mapped on all supported platforms.
+	#[cfg(feature = "legacy")]
+	for (w, r) in virt_bars.iter_mut().zip(io_bars.iter()) {
+		if let Some(b) = r {
+			let addr =
+				usize::try_from(b.address >> Page::OFFSET_BITS).expect("address out of range");
+			let offset = usize::try_from(b.address).unwrap() & (Page::SIZE - 1);
+			let size = usize::try_from(b.size).expect("size out of range");
+			let count = (offset + size + Page::SIZE - 1) / Page::SIZE;
+			let ret = unsafe { kernel::sys_direct_alloc(virt, addr, count, 0b11) };
+			assert_eq!(ret.status, 0, "failed to map BAR region");
+			let addr = core::ptr::NonNull::new(virt.cast::<u8>().wrapping_add(offset)).unwrap();
+			*w = Some(addr.cast());
+			virt = virt.wrapping_add(count);
+		}
+	}
+
+	// Route interrupts to us. The PCI driver has no endpoint handle we could use, so make sure
+	// the packet is sent before leaving compatibility mode.
 	{
 		let uuid = u128::from(irq);
 		*dux::ipc::transmit() = kernel::ipc::Packet {
@@ -106,29 +145,139 @@ fn main() {
 			offset: 0,
 			opcode: core::num::NonZeroU8::new(128), // OP_OPEN
 		};
+		unsafe { kernel::io_wait(0) };
 	}
 
-	pci.set_command(
-		pci::HeaderCommon::COMMAND_MMIO_MASK | pci::HeaderCommon::COMMAND_BUS_MASTER_MASK,
-	);
+	let mode = virtio::pci::mode(&pci);
+
+	let mut command =
+		pci::HeaderCommon::COMMAND_MMIO_MASK | pci::HeaderCommon::COMMAND_BUS_MASTER_MASK;
+	if mode == virtio::pci::Mode::Legacy {
+		command |= pci::HeaderCommon::COMMAND_IO_MASK;
+	}
+	header.set_command(command);
+	// The configuration may be lost when the device is suspended, so keep a copy.
+	let snapshot = pci::ConfigSnapshot::save(header);
 
 	// TODO move this to behind block device setup but right before we allocate an interrupt.
 	notification::init();
 
 	// Set up block device
-	let mut device = virtio::pci::new_device(pci, &virt_bars[..], virtio_block::BlockDevice::new)
-		.expect("failed to create device");
+	let new_device = || {
+		// SAFETY: the header is mapped above.
+		let pci = unsafe { pci::Header::from_raw(header_virt) };
+		match mode {
+			virtio::pci::Mode::Modern => {
+				virtio::pci::new_device(pci, &virt_bars[..], virtio_block::BlockDevice::new)
+			}
+			#[cfg(feature = "legacy")]
+			virtio::pci::Mode::Legacy => virtio::pci::new_legacy_device(
+				pci,
+				&virt_bars[..],
+				virtio_block::BlockDevice::new_legacy,
+			),
+			#[cfg(not(feature = "legacy"))]
+			virtio::pci::Mode::Legacy => panic!("legacy device, enable the \"legacy\" feature"),
+		}
+		.expect("failed to create device")
+	};
+	let mut device = new_device();
+	kernel::sys_log!("virtio_block: using {:?} interface", device.mode());
 
-	// Add self to registry
+	// Add self to registry. The generic name goes to whichever device registers first, so also
+	// register under the serial of the device, which doesn't depend on the PCI slot.
 	let name = "virtio_block";
 	let ret = unsafe { kernel::sys_registry_add(name.as_ptr(), name.len(), usize::MAX) };
 	assert_eq!(ret.status, 0, "failed to add self to registry");
 
+	let mut serial = [0; virtio_block::DEVICE_ID_LEN];
+	let mut wait = || unsafe { kernel::io_wait(10_000) };
+	match device.device_id(&mut serial, &mut wait) {
+		Ok(0) => (),
+		Ok(len) => {
+			let mut name = [0; 32];
+			let prefix = b"virtio_block-";
+			name[..prefix.len()].copy_from_slice(prefix);
+			name[prefix.len()..][..len].copy_from_slice(&serial[..len]);
+			let name = &name[..prefix.len() + len];
+			let ret = unsafe { kernel::sys_registry_add(name.as_ptr(), name.len(), usize::MAX) };
+			match ret.status {
+				kernel::Return::OK => (),
+				kernel::Return::TOO_LONG => kernel::sys_log!(
+					"virtio_block: serial {:?} is too long to register",
+					core::str::from_utf8(&serial[..len])
+				),
+				s => panic!("failed to add self to registry: {}", s),
+			}
+		}
+		Err(e) => kernel::sys_log!("virtio_block: failed to get serial: {:?}", e),
+	}
+
+	// We only ever reply to the sender of a request.
+	dux::task::set_strict_endpoints(true);
+
+	let mut pending = pending::Pending::new();
+
+	/// Write the metrics of the device to the data page of the request.
+	const OP_METRICS: u8 = 130;
+	/// Set in the offset of an `OP_METRICS` request to reset the metrics after reading them.
+	const METRICS_RESET: u64 = 1;
+	/// The maximum amount of pages of a request that are pinned.
+	const MAX_PINNED: usize = 32;
+
+	// Measure the time between receiving a request and completing it.
+	#[cfg(feature = "latency-debug")]
+	let mut latency = [dux::time::Stats::new(100), dux::time::Stats::new(100)];
+
 	// Wait for & respond to requests
 	loop {
-		let rxq = dux::ipc::receive();
+		// Gather all requests that arrived so high priority requests can be submitted first.
+		if pending.is_empty() {
+			let rx = dux::ipc::receive();
+			if !dux::ipc::release_dead(&rx) {
+				pending.push(rx.clone()).expect("pending list is full");
+			}
+		}
+		while let Some(rx) = dux::ipc::try_receive() {
+			if dux::ipc::release_dead(&rx) {
+				continue;
+			}
+			if pending.push(rx.clone()).is_err() {
+				rx.defer();
+				break;
+			}
+		}
+		let request = match pending.pop() {
+			Some(request) => request,
+			None => continue,
+		};
+		let rxq = request.packet;
 		let op = rxq.opcode.unwrap();
 
+		// Pin the pages of the client so they can't be reused while the device accesses them,
+		// even if the client dies in the meantime.
+		// FIXME only the first MAX_PINNED pages of larger requests are pinned.
+		let mut pinned = [0; MAX_PINNED];
+		let dma = matches!(
+			kernel::ipc::Op::try_from(op),
+			Ok(kernel::ipc::Op::Read) | Ok(kernel::ipc::Op::Write)
+		);
+		let pinned = match rxq.data.filter(|_| dma) {
+			Some(data) => {
+				let count = dux::Page::min_pages_for_range(rxq.length).min(MAX_PINNED);
+				let ret = unsafe {
+					kernel::dev_dma_pin(rxq.address, data.as_ptr(), pinned.as_mut_ptr(), count)
+				};
+				// The client may already be dead, in which case nobody gets the reply anyway.
+				if ret.status == 0 {
+					&pinned[..count]
+				} else {
+					&[][..]
+				}
+			}
+			None => &[][..],
+		};
+
 		let ratio = kernel::Page::SIZE / core::mem::size_of::<virtio_block::Sector>();
 		let length = rxq.length / virtio_block::Sector::SIZE;
 		let offset = rxq.offset * ratio as u64;
@@ -186,30 +335,118 @@ fn main() {
 					offset: offset / ratio as u64,
 				};
 			}
+			_ if op.get() == OP_METRICS => {
+				let size = core::mem::size_of::<virtio_block::Metrics>();
+				let (flags, length, offset) = match rxq.data.filter(|_| rxq.length >= size) {
+					Some(data) => {
+						let metrics = device.metrics();
+						unsafe { data.as_ptr().cast::<virtio_block::Metrics>().write(metrics) };
+						if rxq.offset & METRICS_RESET > 0 {
+							device.reset_metrics();
+						}
+						(0, size, rxq.offset)
+					}
+					None => (kernel::ipc::FLAG_ERROR, 0, kernel::ipc::ERROR_INVALID_ARG),
+				};
+
+				*dux::ipc::transmit() = kernel::ipc::Packet {
+					uuid: kernel::ipc::UUID::INVALID,
+					opcode: Some(op),
+					name: None,
+					name_len: 0,
+					flags,
+					id: rxq.id,
+					address: rxq.address,
+					data: None,
+					length,
+					offset,
+				};
+			}
+			Ok(kernel::ipc::Op::Suspend)
+				if rxq.address == usize::from(dux::task::Address::KERNEL) =>
+			{
+				// Requests are completed one at a time, so there is nothing in flight.
+				let pm = header.power_management();
+				if let Some(pm) = pm {
+					pm.set_state(pci::power::PowerState::D3Hot);
+				}
+				*dux::ipc::transmit() = kernel::ipc::Packet {
+					uuid: kernel::ipc::UUID::INVALID,
+					opcode: Some(op),
+					name: None,
+					name_len: 0,
+					flags: 0,
+					id: rxq.id,
+					address: rxq.address,
+					data: None,
+					length: 0,
+					offset: 0,
+				};
+
+				// Keep any requests that arrive until the system resumed.
+				loop {
+					let rx = dux::ipc::receive();
+					let op = rx.opcode.map(kernel::ipc::Op::try_from);
+					if rx.address == rxq.address && matches!(op, Some(Ok(kernel::ipc::Op::Resume)))
+					{
+						break;
+					}
+					if dux::ipc::release_dead(&rx) {
+						continue;
+					}
+					if pending.push(rx.clone()).is_err() {
+						// Deferring would spin if the RESUME packet is behind requests that
+						// don't fit, so let the client try again later instead.
+						*dux::ipc::transmit() = kernel::ipc::Packet {
+							uuid: kernel::ipc::UUID::INVALID,
+							opcode: rx.opcode,
+							name: None,
+							name_len: 0,
+							flags: kernel::ipc::FLAG_ERROR,
+							id: rx.id,
+							address: rx.address,
+							data: None,
+							length: 0,
+							offset: kernel::ipc::ERROR_BUSY,
+						};
+						dux::ipc::release(&rx);
+					}
+				}
+
+				let wait = |delay| {
+					let until = dux::time::now() + delay;
+					while dux::time::now() < until {
+						unsafe { kernel::io_wait(until - dux::time::now()) };
+					}
+				};
+				if let Some(pm) = pm {
+					if pm.wake(header, Some(&snapshot), wait) {
+						// The device has been reset, which means DRIVER_OK has to be set up
+						// again. The metrics are reset too.
+						// FIXME the old queues are leaked. The device doesn't access them
+						// anymore, but the drop handler doesn't know that.
+						core::mem::forget(core::mem::replace(&mut device, new_device()));
+					}
+				}
+			}
 			// Just ignore other requests for now
 			_ => (),
 		}
 
-		// Free ranges
-		if let Some(data) = rxq.data {
-			let len = dux::Page::min_pages_for_range(rxq.length);
-			let ret = unsafe { kernel::mem_dealloc(data.as_ptr() as *mut _, len) };
-			assert_eq!(ret.status, 0);
-			dux::ipc::add_free_range(
-				dux::Page::new(core::ptr::NonNull::new(data.as_ptr() as *mut _).unwrap()).unwrap(),
-				len,
-			)
-			.unwrap();
+		#[cfg(feature = "latency-debug")]
+		{
+			let priority = pending::Priority::of(&rxq);
+			let delta = dux::time::now().saturating_sub(request.received);
+			if let Some(s) = latency[priority as usize].add(delta) {
+				kernel::sys_log!("virtio_block: {:?} priority latency {}", priority, s);
+			}
 		}
-		if let Some(name) = rxq.name {
-			let len = dux::Page::min_pages_for_range(rxq.name_len.into());
-			let ret = unsafe { kernel::mem_dealloc(name.as_ptr() as *mut _, len) };
+
+		dux::ipc::release(&rxq);
+		// The pages are unmapped, so the kernel may free them if the client died.
+		if !pinned.is_empty() {
+			let ret = unsafe { kernel::dev_dma_unpin(rxq.address, pinned.as_ptr(), pinned.len()) };
 			assert_eq!(ret.status, 0);
-			dux::ipc::add_free_range(
-				dux::Page::new(core::ptr::NonNull::new(name.as_ptr() as *mut _).unwrap()).unwrap(),
-				len,
-			)
-			.unwrap();
 		}
 	}
 }
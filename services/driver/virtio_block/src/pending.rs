@@ -0,0 +1,102 @@
+//! # Pending requests
+//!
+//! Requests are kept in two lists so that high priority requests (e.g. filesystem metadata) are
+//! submitted to the device before normal requests. Requests that were already submitted are
+//! not reordered.
+
+use kernel::ipc::Packet;
+
+/// The maximum amount of pending requests per priority.
+const CAPACITY: usize = 16;
+
+/// The priority of a request, which is set by the client with a flag.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Priority {
+	High = 0,
+	Normal = 1,
+}
+
+impl Priority {
+	pub fn of(packet: &Packet) -> Self {
+		if packet.flags & kernel::ipc::FLAG_PRIORITY_HIGH > 0 {
+			Self::High
+		} else {
+			Self::Normal
+		}
+	}
+}
+
+/// A request that hasn't been submitted yet.
+pub struct Request {
+	pub packet: Packet,
+	/// The time at which the request was received.
+	#[cfg(feature = "latency-debug")]
+	pub received: u64,
+}
+
+/// A ring buffer of requests with the same priority.
+struct List {
+	requests: [Option<Request>; CAPACITY],
+	head: usize,
+	len: usize,
+}
+
+impl List {
+	const fn new() -> Self {
+		const NONE: Option<Request> = None;
+		Self {
+			requests: [NONE; CAPACITY],
+			head: 0,
+			len: 0,
+		}
+	}
+
+	fn push(&mut self, request: Request) -> Result<(), Request> {
+		if self.len == CAPACITY {
+			return Err(request);
+		}
+		self.requests[(self.head + self.len) % CAPACITY] = Some(request);
+		self.len += 1;
+		Ok(())
+	}
+
+	fn pop(&mut self) -> Option<Request> {
+		let request = self.requests[self.head].take()?;
+		self.head = (self.head + 1) % CAPACITY;
+		self.len -= 1;
+		Some(request)
+	}
+}
+
+/// Pending requests of all priorities.
+pub struct Pending {
+	lists: [List; 2],
+}
+
+impl Pending {
+	pub const fn new() -> Self {
+		Self {
+			lists: [List::new(), List::new()],
+		}
+	}
+
+	/// Add a request. The request is returned if there is no room for it.
+	pub fn push(&mut self, packet: Packet) -> Result<(), Packet> {
+		let list = &mut self.lists[Priority::of(&packet) as usize];
+		let request = Request {
+			packet,
+			#[cfg(feature = "latency-debug")]
+			received: dux::time::now(),
+		};
+		list.push(request).map_err(|r| r.packet)
+	}
+
+	/// Remove the oldest request with the highest priority.
+	pub fn pop(&mut self) -> Option<Request> {
+		self.lists.iter_mut().find_map(List::pop)
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.lists.iter().all(|l| l.len == 0)
+	}
+}
@@ -26,8 +26,65 @@ fn panic_handler(info: &core::panic::PanicInfo) -> ! {
 mod rtbegin;
 
 use core::convert::{TryFrom, TryInto};
+use core::ptr::NonNull;
+use dux::gfx::{self, Pixel, ScanoutInfo, ScreenshotInfo};
 use kernel::Page;
 
+/// A scanout with a resource attached to it.
+struct Scanout {
+	resource: virtio_gpu::Resource,
+	rect: virtio_gpu::Rect,
+}
+
+impl Scanout {
+	/// Return the draw buffer of a scanout.
+	fn buffer(index: usize) -> NonNull<Page> {
+		let addr = FRAMEBUFFER_BASE + index * virtio_gpu::MAX_BACKING_PAGES * Page::SIZE;
+		NonNull::new(addr as *mut _).unwrap()
+	}
+
+	fn width(&self) -> usize {
+		self.rect.width().try_into().unwrap()
+	}
+
+	fn height(&self) -> usize {
+		self.rect.height().try_into().unwrap()
+	}
+
+	/// The size of the draw buffer in bytes.
+	fn size(&self) -> usize {
+		self.width() * self.height() * core::mem::size_of::<Pixel>()
+	}
+}
+
+/// The start of the draw buffers. Each scanout gets room for [`virtio_gpu::MAX_BACKING_PAGES`].
+const FRAMEBUFFER_BASE: usize = 0x4000_0000;
+
+/// The format of the draw buffers, which must match the layout of [`Pixel`].
+const FORMAT: virtio_gpu::Format = virtio_gpu::Format::RGBA8Unorm;
+
+/// How often to check whether a display was connected or disconnected, in microseconds.
+const DISPLAY_POLL_INTERVAL: u64 = 100_000;
+
+/// The maximum amount of tasks that can subscribe to display changes.
+const MAX_SUBSCRIBERS: usize = 8;
+
+const OP_OPEN: u8 = 128;
+const OP_FLUSH: u8 = 129;
+const OP_SCREENSHOT: u8 = 130;
+const OP_SCREENSHOT_RELEASE: u8 = 131;
+const OP_DISPLAY_SUBSCRIBE: u8 = 132;
+/// Sent to subscribers when a scanout is enabled, disabled or resized. The offset is a mask of
+/// the enabled scanouts.
+const OP_DISPLAY_CHANGED: u8 = 133;
+
+/// The draw buffer of scanout 0.
+const UUID_FRAMEBUFFER: u128 = 0;
+const UUID_CURSOR: u128 = 1;
+const UUID_METADATA: u128 = 2;
+/// The draw buffer of scanout `n` is opened with `UUID_FRAMEBUFFER_N + n`.
+const UUID_FRAMEBUFFER_N: u128 = 0x100;
+
 #[export_name = "main"]
 fn main() {
 	// FIXME move this to rtbegin
@@ -54,6 +111,10 @@ fn main() {
 			}
 			// Ignore I/O, as we only use MMIO.
 			driver::Arg::BarIo(_) => (),
+			// Ignore flags meant for other services, so they can be passed on blindly.
+			driver::Arg::Other(o) => {
+				kernel::sys_log!("virtio_gpu: ignoring {:?}", core::str::from_utf8(o))
+			}
 			arg => panic!("bad argument: {:?}", arg),
 		}
 	})
@@ -95,124 +156,373 @@ fn main() {
 	let mut device = virtio::pci::new_device(pci, &virt_bars[..], virtio_gpu::Device::new)
 		.expect("failed to create device");
 
-	// Create draw buffer
-	#[repr(C)]
-	struct RGBA8 {
-		r: u8,
-		g: u8,
-		b: u8,
-		a: u8,
-	}
-	let (w, h) = (800, 600);
-	let size = (w * h * core::mem::size_of::<RGBA8>() + kernel::Page::MASK) / kernel::Page::SIZE;
-	let addr = core::ptr::NonNull::new(0x3333_0000 as *mut _).unwrap();
-	let ret = unsafe { kernel::mem_alloc(addr.as_ptr(), size, 0b11) };
-	assert_eq!(ret.status, 0);
-	let buffer = unsafe {
-		let ptr = addr.as_ptr().cast::<RGBA8>();
-		let size = size * kernel::Page::SIZE / core::mem::size_of::<RGBA8>();
-		// SAFETY: while the device will read from it, only we will write to it.
-		core::slice::from_raw_parts_mut(ptr, size)
-	};
-
 	// Create cursor buffer
 	let (cursor_w, cursor_h) = (64, 64);
-	let cursor_addr = core::ptr::NonNull::new(addr.as_ptr().wrapping_add(size)).unwrap();
-	let cursor_size = (cursor_w * cursor_h * core::mem::size_of::<RGBA8>() + kernel::Page::MASK)
+	let cursor_addr = core::ptr::NonNull::new(0x3333_0000 as *mut Page).unwrap();
+	let cursor_size = (cursor_w * cursor_h * core::mem::size_of::<Pixel>() + kernel::Page::MASK)
 		/ kernel::Page::SIZE;
 	let ret = unsafe { kernel::mem_alloc(cursor_addr.cast().as_ptr(), cursor_size, 0b11) };
 	assert_eq!(ret.status, 0);
 
-	// Set up scan
-	let rect = virtio_gpu::Rect::new(0, 0, w.try_into().unwrap(), h.try_into().unwrap());
-	let ret = unsafe { device.init_scanout(virtio_gpu::Format::RGBA8Unorm, rect, addr, size) };
-	let id = ret.unwrap();
+	// The metadata is written out again on every request so it is always up to date.
+	let metadata_addr = cursor_addr.as_ptr().wrapping_add(cursor_size);
+	let metadata_addr = core::ptr::NonNull::new(metadata_addr).unwrap();
+	let ret = unsafe { kernel::mem_alloc(metadata_addr.as_ptr(), 1, 0b11) };
+	assert_eq!(ret.status, 0, "failed to allocate metadata buffer");
 
-	for x in 0..w {
-		for y in 0..h {
-			let r = (x * 127 / w) as u8;
-			let g = (y * 127 / h) as u8;
-			buffer[x + y * w] = RGBA8 {
-				r: r * 2,
-				g: g * 2,
-				b: 255 - r - g,
-				a: 255,
-			};
-		}
-	}
+	// Reserve room for screenshots, which are allocated for each request and moved to the
+	// requester. The first page holds the metadata so the pixels are page aligned.
+	let screenshot_addr = metadata_addr.as_ptr().wrapping_add(1);
+	let screenshot_addr = core::ptr::NonNull::new(screenshot_addr).unwrap();
+	// The task that hasn't released the last screenshot yet.
+	let mut screenshot_owner = None;
+
+	// Set up a resource for each connected display. A resource is only created once a display
+	// is connected, so the array is filled in lazily.
+	let scanout_count = usize::try_from(device.scanout_count()).unwrap();
+	let mut scanouts: [Option<Scanout>; virtio_gpu::MAX_SCANOUTS] = Default::default();
+	// The amount of pages allocated for each draw buffer. The pages are never freed as clients
+	// may still have them mapped.
+	let mut allocated = [0; virtio_gpu::MAX_SCANOUTS];
+	update_scanouts(&mut device, &mut scanouts, &mut allocated);
 
 	// Set up cursor
 	let cursor_rect = virtio_gpu::Rect::new(0, 0, 64, 64);
-	let ret = unsafe {
-		device.init_cursor(
-			0,
-			0,
-			virtio_gpu::Format::RGBA8Unorm,
-			cursor_addr,
-			cursor_size,
-		)
-	};
+	let ret = unsafe { device.init_cursor(0, 0, FORMAT, cursor_addr, cursor_size) };
 	let cursor_id = ret.unwrap();
 
 	// Draw
 	device.draw(cursor_id, cursor_rect).expect("failed to draw");
-	device.draw(id, rect).expect("failed to draw");
+
+	let mut subscribers = [None; MAX_SUBSCRIBERS];
 
 	// Add self to registry
 	let name = "virtio_gpu";
 	let ret = unsafe { kernel::sys_registry_add(name.as_ptr(), name.len(), usize::MAX) };
 	assert_eq!(ret.status, 0, "failed to add self to registry");
 
+	// We only reply to the sender of a request or to subscribers, whose address came from a
+	// request too.
+	dux::task::set_strict_endpoints(true);
+
 	loop {
-		let rx = dux::ipc::receive();
-
-		const OP_OPEN: u8 = 128;
-		const OP_FLUSH: u8 = 129;
-
-		match rx.opcode.map(|n| n.get()).unwrap_or(0) {
-			OP_OPEN => match u128::from(rx.uuid) {
-				0 => {
-					*dux::ipc::transmit() = kernel::ipc::Packet {
-						uuid: kernel::ipc::UUID::INVALID,
-						data: Some(addr),
-						length: w * h * core::mem::size_of::<RGBA8>(),
-						address: rx.address,
-						id: rx.id,
-						name: None,
-						name_len: 0,
-						flags: 0,
-						offset: 0,
-						opcode: rx.opcode,
-					};
+		// Interrupts are disabled, so the config is polled for changes.
+		if device.display_changed() && update_scanouts(&mut device, &mut scanouts, &mut allocated) {
+			let enabled = scanouts
+				.iter()
+				.enumerate()
+				.filter(|(_, s)| s.is_some())
+				.fold(0, |m, (i, _)| m | 1 << i);
+			for &address in subscribers.iter().flatten() {
+				*dux::ipc::transmit() = kernel::ipc::Packet {
+					uuid: kernel::ipc::UUID::INVALID,
+					data: None,
+					length: 0,
+					address,
+					id: 0,
+					name: None,
+					name_len: 0,
+					flags: 0,
+					offset: enabled,
+					opcode: core::num::NonZeroU8::new(OP_DISPLAY_CHANGED),
+				};
+			}
+		}
+
+		while let Some(rx) = dux::ipc::try_receive() {
+			// Replies carry our own buffers, so only forget the state of the client. Screenshots
+			// that couldn't be moved are still mapped.
+			if let Some(peer) = dux::task::dead_peer(&rx) {
+				let peer = usize::from(peer);
+				if screenshot_owner == Some(peer) {
+					screenshot_owner = None;
 				}
-				1 => {
-					*dux::ipc::transmit() = kernel::ipc::Packet {
-						uuid: kernel::ipc::UUID::INVALID,
-						data: Some(cursor_addr),
-						length: cursor_w * cursor_h * core::mem::size_of::<RGBA8>(),
-						address: rx.address,
-						id: rx.id,
-						name: None,
-						name_len: 0,
-						flags: 0,
-						offset: 0,
-						opcode: rx.opcode,
-					};
+				if rx.data == Some(screenshot_addr) {
+					let count = dux::Page::min_pages_for_range(rx.length);
+					let ret = unsafe { kernel::mem_dealloc(screenshot_addr.as_ptr(), count) };
+					assert_eq!(ret.status, 0, "failed to free screenshot buffer");
 				}
-				_ => todo!(),
-			},
-			OP_FLUSH => {
-				device.draw(id, rect).expect("failed to draw");
-				device.draw(cursor_id, cursor_rect).expect("failed to draw");
-				device
-					.update_cursor(cursor_id, 0, 0)
-					.expect("failed to update cursor");
+				for s in subscribers.iter_mut().filter(|s| **s == Some(peer)) {
+					*s = None;
+				}
+				continue;
+			}
+
+			// The device is kept powered while the system is suspended, as all resources would
+			// have to be set up again if it lost its state. Displays may have been turned off in
+			// the meantime, so redraw them after resuming.
+			if dux::ipc::acknowledge_power(&rx) {
+				if rx.opcode == Some(kernel::ipc::Op::Resume.into()) {
+					for s in scanouts.iter().flatten() {
+						device.draw(s.resource, s.rect).expect("failed to draw");
+					}
+					device.draw(cursor_id, cursor_rect).expect("failed to draw");
+				}
+				continue;
+			}
+
+			let reply = |data, length, flags, offset| {
+				*dux::ipc::transmit() = kernel::ipc::Packet {
+					uuid: kernel::ipc::UUID::INVALID,
+					data,
+					length,
+					address: rx.address,
+					id: rx.id,
+					name: None,
+					name_len: 0,
+					flags,
+					offset,
+					opcode: rx.opcode,
+				};
+			};
+			let error = |code| reply(None, 0, kernel::ipc::FLAG_ERROR, code);
+			// Flush & screenshot requests refer to a scanout by their ID.
+			let scanout = || {
+				let i = usize::from(rx.id);
+				scanouts.get(i)?.as_ref().map(|s| (i, s))
+			};
+
+			match rx.opcode.map(|n| n.get()).unwrap_or(0) {
+				OP_OPEN => match u128::from(rx.uuid) {
+					UUID_CURSOR => reply(
+						Some(cursor_addr),
+						cursor_w * cursor_h * core::mem::size_of::<Pixel>(),
+						0,
+						0,
+					),
+					UUID_METADATA => {
+						let info = unsafe {
+							core::slice::from_raw_parts_mut(
+								metadata_addr.cast::<ScanoutInfo>().as_ptr(),
+								scanout_count,
+							)
+						};
+						for (i, (w, s)) in info.iter_mut().zip(scanouts.iter()).enumerate() {
+							*w = s.as_ref().map_or(
+								ScanoutInfo {
+									id: i.try_into().unwrap(),
+									..Default::default()
+								},
+								|s| ScanoutInfo {
+									id: i.try_into().unwrap(),
+									enabled: 1,
+									width: s.rect.width(),
+									height: s.rect.height(),
+									stride: (s.width() * core::mem::size_of::<Pixel>())
+										.try_into()
+										.unwrap(),
+									format: FORMAT.into(),
+								},
+							);
+						}
+						let length = scanout_count * core::mem::size_of::<ScanoutInfo>();
+						reply(Some(metadata_addr), length, 0, 0)
+					}
+					uuid => {
+						let index = match uuid {
+							UUID_FRAMEBUFFER => Some(0),
+							n => n
+								.checked_sub(UUID_FRAMEBUFFER_N)
+								.and_then(|n| usize::try_from(n).ok()),
+						};
+						match index.and_then(|i| Some((i, scanouts.get(i)?.as_ref()?))) {
+							Some((i, s)) => reply(Some(Scanout::buffer(i)), s.size(), 0, 0),
+							None => error(kernel::ipc::ERROR_NOT_FOUND),
+						}
+					}
+				},
+				OP_FLUSH => match scanout() {
+					// The dirty rect is packed in the offset as x, y, width & height, 16 bits
+					// each. 0 flushes the whole scanout.
+					Some((i, s)) => {
+						let bounds = gfx::Rect::new(0, 0, s.rect.width(), s.rect.height());
+						let rect = match rx.offset {
+							0 => Some(bounds),
+							o => gfx::Rect::unpack(o).intersect(&bounds),
+						};
+						if let Some(r) = rect {
+							let rect = virtio_gpu::Rect::new(r.x, r.y, r.width, r.height);
+							device.draw(s.resource, rect).expect("failed to draw");
+						}
+						// The cursor is always on scanout 0.
+						if i == 0 {
+							device.draw(cursor_id, cursor_rect).expect("failed to draw");
+							device
+								.update_cursor(cursor_id, 0, 0)
+								.expect("failed to update cursor");
+						}
+					}
+					None => error(kernel::ipc::ERROR_INVALID_ARG),
+				},
+				OP_SCREENSHOT => {
+					// Only one screenshot can be in flight. The buffer of the last one may also
+					// not have been moved to its requester yet.
+					let busy = screenshot_owner.is_some() || is_mapped(screenshot_addr);
+					match scanout() {
+						_ if busy => error(kernel::ipc::ERROR_BUSY),
+						Some((i, s)) => {
+							let count = 1 + dux::Page::min_pages_for_range(s.size());
+							let ret =
+								unsafe { kernel::mem_alloc(screenshot_addr.as_ptr(), count, 0b11) };
+							assert_eq!(ret.status, 0, "failed to allocate screenshot buffer");
+							// Clients draw into the draw buffer at any time, so the image can
+							// tear if a client is drawing while it is copied.
+							unsafe {
+								let info = screenshot_addr.cast::<ScreenshotInfo>().as_ptr();
+								info.write(ScreenshotInfo {
+									width: s.rect.width(),
+									height: s.rect.height(),
+									stride: (s.width() * core::mem::size_of::<Pixel>())
+										.try_into()
+										.unwrap(),
+									format: FORMAT.into(),
+								});
+								let pixels = screenshot_addr.as_ptr().add(1).cast::<Pixel>();
+								pixels.copy_from_nonoverlapping(
+									Scanout::buffer(i).as_ptr().cast(),
+									s.width() * s.height(),
+								);
+							}
+							screenshot_owner = Some(rx.address);
+							let length = Page::SIZE + s.size();
+							let flags = kernel::ipc::FLAG_MOVE;
+							reply(Some(screenshot_addr), length, flags, Page::SIZE as u64)
+						}
+						None => error(kernel::ipc::ERROR_INVALID_ARG),
+					}
+				}
+				OP_SCREENSHOT_RELEASE => {
+					if screenshot_owner == Some(rx.address) {
+						screenshot_owner = None;
+					}
+				}
+				OP_DISPLAY_SUBSCRIBE => {
+					if !subscribers.contains(&Some(rx.address)) {
+						match subscribers.iter_mut().find(|s| s.is_none()) {
+							Some(s) => *s = Some(rx.address),
+							None => {
+								error(kernel::ipc::ERROR_INVALID_ARG);
+								continue;
+							}
+						}
+					}
+					reply(None, 0, 0, 0);
+				}
+				_ => error(kernel::ipc::ERROR_INVALID_ARG),
 			}
-			_ => todo!(),
 		}
 
 		unsafe {
-			kernel::io_wait(u64::MAX);
+			kernel::io_wait(DISPLAY_POLL_INTERVAL);
 		}
 	}
 }
+
+/// Create, resize or remove the resources of the scanouts to match the connected displays.
+/// Returns `true` if anything changed.
+fn update_scanouts(
+	device: &mut virtio_gpu::Device,
+	scanouts: &mut [Option<Scanout>],
+	allocated: &mut [usize],
+) -> bool {
+	let info = device.display_info().expect("failed to get display info");
+	let count = usize::try_from(device.scanout_count()).unwrap();
+	let mut changed = false;
+	for (i, mode) in info.modes().iter().enumerate().take(count) {
+		let rect = mode.rect();
+		let (w, h) = fit(rect.width(), rect.height());
+		if (w, h) != (rect.width(), rect.height()) && mode.enabled() {
+			kernel::sys_log!(
+				"virtio_gpu: scanout {} is too large ({}x{}), using {}x{}",
+				i,
+				rect.width(),
+				rect.height(),
+				w,
+				h
+			);
+		}
+		let rect = virtio_gpu::Rect::new(0, 0, w, h);
+		let pages = (usize::try_from(w).unwrap()
+			* usize::try_from(h).unwrap()
+			* core::mem::size_of::<Pixel>()
+			+ Page::MASK)
+			/ Page::SIZE;
+		let wanted = mode.enabled() && w > 0 && h > 0;
+
+		if let Some(s) = &scanouts[i] {
+			if wanted && (s.rect.width(), s.rect.height()) == (w, h) {
+				continue;
+			}
+			device.disable_scanout(i.try_into().unwrap(), s.resource);
+			scanouts[i] = None;
+			changed = true;
+		}
+		if !wanted {
+			continue;
+		}
+		// Allocate any pages that are missing.
+		let buffer = Scanout::buffer(i);
+		if allocated[i] < pages {
+			let ret = unsafe {
+				kernel::mem_alloc(
+					buffer.as_ptr().wrapping_add(allocated[i]),
+					pages - allocated[i],
+					0b11,
+				)
+			};
+			assert_eq!(ret.status, 0, "failed to allocate draw buffer");
+			allocated[i] = pages;
+		}
+
+		// Draw a gradient until a client draws something else.
+		let pixels = unsafe {
+			// SAFETY: while the device will read from it, only we will write to it.
+			core::slice::from_raw_parts_mut(buffer.as_ptr().cast::<Pixel>(), (w * h) as usize)
+		};
+		let (w, h) = (w as usize, h as usize);
+		let mut fb = gfx::FrameBuffer::from_slice(pixels, w, h, w).unwrap();
+		for y in 0..h {
+			let g = (y * 127 / h) as u8;
+			for (x, p) in fb.row(y).unwrap().iter_mut().enumerate() {
+				let r = (x * 127 / w) as u8;
+				*p = Pixel::rgb(r * 2, g * 2, 255 - r - g);
+			}
+		}
+
+		let ret =
+			unsafe { device.init_scanout(i.try_into().unwrap(), FORMAT, rect, buffer, pages) };
+		let resource = ret.expect("failed to init scanout");
+		device.draw(resource, rect).expect("failed to draw");
+		scanouts[i] = Some(Scanout { resource, rect });
+		changed = true;
+	}
+	changed
+}
+
+/// Return the largest size with about the same aspect ratio as the given size whose draw buffer
+/// fits in [`virtio_gpu::MAX_BACKING_PAGES`]. Sizes that already fit are returned as is.
+fn fit(width: u32, height: u32) -> (u32, u32) {
+	let max = (virtio_gpu::MAX_BACKING_PAGES * Page::SIZE / core::mem::size_of::<Pixel>()) as u64;
+	let (w, h) = (u64::from(width), u64::from(height));
+	if w * h <= max {
+		return (width, height);
+	}
+	// Find the largest height for which the scaled width still fits.
+	let (mut low, mut high) = (0, h);
+	while low < high {
+		let mid = (low + high + 1) / 2;
+		if mid * (mid * w / h) <= max {
+			low = mid;
+		} else {
+			high = mid - 1;
+		}
+	}
+	((low * w / h) as u32, low as u32)
+}
+
+/// Check whether the page at the given address is mapped.
+fn is_mapped(page: core::ptr::NonNull<Page>) -> bool {
+	let mut address = 0;
+	let ret = unsafe { kernel::mem_physical_address(page.as_ptr(), &mut address, 1) };
+	ret.status == kernel::Return::OK
+}
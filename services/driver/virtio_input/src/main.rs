@@ -23,6 +23,7 @@ fn panic_handler(info: &core::panic::PanicInfo) -> ! {
 	loop {}
 }
 
+mod notification;
 mod rtbegin;
 mod scancode;
 
@@ -30,28 +31,60 @@ use core::convert::{TryFrom, TryInto};
 use core::num::NonZeroU8;
 use kernel::Page;
 
-/// Write buffer for data read.
+/// The state of the driver.
 ///
-/// 4 KiB should be quite enough.
-static mut BUFFER: [u8; 1 << 12] = [0; 1 << 12];
-
-// We spin it right round baby right round
-/// The last index of data read from UART.
-static mut NEW_INDEX: u16 = 0;
-
-/// The last index of data read from the buffer
-static mut USED_INDEX: u16 = 0;
-
-static mut DEVICE: Option<virtio_input::Device> = None;
+/// Events are collected by the main routine. The notification handler only acknowledges
+/// interrupts, so it doesn't need access to this.
+struct Input {
+	device: virtio_input::Device<'static>,
+	set: scancode::ScanCodes,
+	key_modifiers: KeyModifiers,
+	/// UTF-8 text read from the device that hasn't been handed to a reader yet.
+	text: [u8; Input::TEXT_CAPACITY],
+	/// The amount of bytes ever added to `text`.
+	text_tail: usize,
+	/// The amount of bytes ever handed to a reader.
+	text_head: usize,
+	/// The records the text is divided in.
+	records: [Record; Input::RECORDS],
+	// We spin it right round baby right round
+	/// The last index of records read from the device.
+	new_index: u16,
+	/// The last index of records read from the buffer
+	used_index: u16,
+	/// The amount of bytes of the record at `used_index` that have already been handed
+	/// to a reader.
+	sent: u16,
+	/// The time at which the last character was collected.
+	last_event: u64,
+	/// Whether to log the time between collecting an event and handing it to a reader and
+	/// to tell the console when events were collected.
+	latency_debug: bool,
+	latency: dux::time::Stats,
+	/// The console, once it is found in the registry.
+	console: Option<dux::task::Address>,
+}
 
-static mut SET: Option<scancode::ScanCodes> = None;
+/// A part of the text collected from the device.
+#[derive(Clone, Copy)]
+struct Record {
+	kind: RecordKind,
+	/// The amount of bytes of text of this record.
+	len: u16,
+	/// The time at which the first character was collected.
+	time: u64,
+}
 
-static mut KEY_MODIFIERS: KeyModifiers = KeyModifiers(0);
+#[derive(Clone, Copy, PartialEq)]
+enum RecordKind {
+	/// A single character.
+	Char,
+	/// Characters collected in quick succession, e.g. a paste. It holds at most a page of text.
+	Burst,
+}
 
 struct KeyModifiers(u8);
 
-unsafe impl Sync for KeyModifiers {}
-
 impl KeyModifiers {
 	const LSHIFT: u8 = 0x1;
 	const RSHIFT: u8 = 0x2;
@@ -93,6 +126,7 @@ fn main() {
 	// Parse arguments
 	let mut pci = None;
 	let mut bars = [None; 6];
+	let mut latency_debug = false;
 
 	driver::parse_args(rtbegin::args(), |arg, _| {
 		match arg {
@@ -111,6 +145,11 @@ fn main() {
 			}
 			// Ignore I/O, as we only use MMIO.
 			driver::Arg::BarIo(_) => (),
+			driver::Arg::Other(b"--latency-debug") => latency_debug = true,
+			// Ignore flags meant for other services, so they can be passed on blindly.
+			driver::Arg::Other(o) => {
+				kernel::sys_log!("virtio_input: ignoring {:?}", core::str::from_utf8(o))
+			}
 			arg => panic!("bad argument: {:?}", arg),
 		}
 	})
@@ -127,6 +166,14 @@ fn main() {
 	let pci = unsafe { pci::Header::from_raw(virt) };
 	virt = virt.wrapping_add(size / Page::SIZE);
 
+	let irq = match &pci {
+		pci::Header::H0(h) => h.interrupt_pin.get(),
+		_ => {
+			kernel::sys_log!("virtio_input: device doesn't have a type 0 PCI header");
+			dux::thread::exit()
+		}
+	};
+
 	// Map BARs
 	let mut virt_bars = [None; 6];
 	for (w, r) in virt_bars.iter_mut().zip(bars.iter()) {
@@ -143,14 +190,29 @@ fn main() {
 	}
 
 	pci.set_command(
-		pci::HeaderCommon::COMMAND_INTERRUPT_DISABLE
-			| pci::HeaderCommon::COMMAND_MMIO_MASK
-			| pci::HeaderCommon::COMMAND_BUS_MASTER_MASK,
+		pci::HeaderCommon::COMMAND_MMIO_MASK | pci::HeaderCommon::COMMAND_BUS_MASTER_MASK,
 	);
 
 	// Set up device
 	let dev = virtio::pci::new_device(pci, &virt_bars[..], virtio_input::Device::new)
 		.expect("failed to create device");
+	notification::init(dev.isr());
+
+	// Route interrupts to us. The PCI driver has no endpoint handle we could use, so make sure
+	// the packet is sent before leaving compatibility mode.
+	*dux::ipc::transmit() = kernel::ipc::Packet {
+		address: 1,
+		data: None,
+		uuid: kernel::ipc::UUID::new(u128::from(irq)),
+		id: 0,
+		flags: 0,
+		length: 0,
+		name: None,
+		name_len: 0,
+		offset: 0,
+		opcode: NonZeroU8::new(128), // OP_OPEN
+	};
+	unsafe { kernel::io_wait(0) };
 
 	// Add self to registry
 	let mut name = [0; 128];
@@ -159,15 +221,41 @@ fn main() {
 	let ret = unsafe { kernel::sys_registry_add(name.as_ptr(), name_len.into(), usize::MAX) };
 	assert_eq!(ret.status, 0, "failed to add self to registry");
 
-	unsafe { SET = Some(scancode::default()) };
-
-	unsafe {
-		DEVICE = Some(dev);
-	}
+	// We only ever reply to the sender of a request.
+	dux::task::set_strict_endpoints(true);
+
+	let mut input = Input {
+		device: dev,
+		set: scancode::default(),
+		key_modifiers: KeyModifiers(0),
+		text: [0; Input::TEXT_CAPACITY],
+		text_tail: 0,
+		text_head: 0,
+		records: [Record {
+			kind: RecordKind::Char,
+			len: 0,
+			time: 0,
+		}; Input::RECORDS],
+		new_index: 0,
+		used_index: 0,
+		sent: 0,
+		last_event: 0,
+		latency_debug,
+		latency: dux::time::Stats::new(100),
+		console: None,
+	};
 
 	loop {
 		let rx = dux::ipc::receive();
 
+		if dux::ipc::release_dead(&rx) {
+			continue;
+		}
+		// The events queued by the device are kept across a suspend.
+		if dux::ipc::acknowledge_power(&rx) {
+			continue;
+		}
+
 		match kernel::ipc::Op::try_from(rx.opcode.unwrap()) {
 			Ok(kernel::ipc::Op::Read) => {
 				// Figure out object to read.
@@ -175,20 +263,43 @@ fn main() {
 					core::slice::from_raw_parts_mut(rx.data.unwrap().as_ptr().cast(), rx.length)
 				};
 
-				let mut length = 0;
-
-				unsafe {
-					// Wait until data is available
-					// TODO this blocks writes from other tasks.
-					while USED_INDEX == NEW_INDEX {
-						process_events();
-						kernel::io_wait(50_000);
+				// Send a burst as a single reply of at most a page so the reader doesn't have
+				// to handle it one character at a time.
+				let limit = data.len().min(Page::SIZE);
+
+				// Wait until data is available. Events that arrive after they're collected
+				// raise an interrupt, which ends the wait.
+				// TODO this blocks writes from other tasks.
+				loop {
+					input.process_events();
+					if input.used_index != input.new_index {
+						break;
 					}
+					unsafe { kernel::io_wait(u64::MAX) };
+				}
+				while input.len() < limit && input.in_burst() && !input.device.is_paused() {
+					unsafe { kernel::io_wait(Input::BURST_GAP) };
+					input.process_events();
+				}
+
+				let oldest =
+					input.records[usize::from(input.used_index) & (Input::RECORDS - 1)].time;
+				let (length, burst) = input.take(&mut data[..limit]);
+
+				if input.device.is_paused() && input.len() <= Input::LOW_WATER {
+					input.device.set_paused(false);
+				}
 
-					while USED_INDEX != NEW_INDEX && length < data.len() {
-						data[length] = BUFFER[usize::from(USED_INDEX) & (BUFFER.len() - 1)];
-						USED_INDEX = USED_INDEX.wrapping_add(1);
-						length += 1;
+				// Let the console know the echo of a burst is coming before the reader gets it.
+				if burst > 0 {
+					input.announce(burst);
+					if input.latency_debug {
+						// The total is used to check whether a paste arrived complete.
+						kernel::sys_log!(
+							"virtio_input: burst of {} bytes, {} bytes read in total",
+							burst,
+							input.text_head
+						);
 					}
 				}
 
@@ -205,62 +316,219 @@ fn main() {
 					length,
 					offset: 0,
 				};
+				if input.latency_debug {
+					input.mark(oldest);
+				}
 			}
 			// Just ignore other requests for now
 			_ => (),
 		}
 
-		// Free ranges
-		if let Some(data) = rx.data {
-			let len = dux::Page::min_pages_for_range(rx.length);
-			let ret = unsafe { kernel::mem_dealloc(data.as_ptr() as *mut _, len) };
-			assert_eq!(ret.status, 0);
-			dux::ipc::add_free_range(
-				dux::Page::new(core::ptr::NonNull::new(data.as_ptr() as *mut _).unwrap()).unwrap(),
-				len,
-			)
-			.unwrap();
+		dux::ipc::release(&rx);
+	}
+}
+
+impl Input {
+	/// The amount of bytes of text that can be buffered. Must be a power of 2.
+	///
+	/// QEMU drops events while the device is paused, so this should hold a large paste even
+	/// if the reader is slow.
+	const TEXT_CAPACITY: usize = 1 << 14;
+	/// The amount of records that can be buffered. Must be a power of 2.
+	const RECORDS: usize = 1 << 10;
+	/// Stop taking events from the device when there are this many bytes in the buffer.
+	const HIGH_WATER: usize = Self::TEXT_CAPACITY - 64;
+	/// Take events from the device again when the buffer drained to this many bytes.
+	const LOW_WATER: usize = Self::TEXT_CAPACITY / 2;
+
+	/// Characters collected within this many microseconds of each other are part of a burst,
+	/// e.g. a paste. No one types this fast.
+	const BURST_GAP: u64 = 2_000;
+
+	/// Tell the console when the oldest event of a reply was collected, so it can measure the
+	/// time until the glyph is flushed. This includes the hop through the reader, e.g. the
+	/// shell echoing the character.
+	fn mark(&mut self, time: u64) {
+		self.send_console(dux::time::OP_LATENCY_MARK, time);
+	}
+
+	/// Tell the console the given amount of bytes of a burst are about to be handed to a
+	/// reader.
+	fn announce(&mut self, length: usize) {
+		self.send_console(dux::ipc::OP_TEXT_BURST, length as u64);
+	}
+
+	/// Send a packet without data to the console, if it is online.
+	fn send_console(&mut self, opcode: u8, offset: u64) {
+		if self.console.is_none() {
+			self.console = dux::task::registry::get(b"console").ok();
 		}
-		if let Some(name) = rx.name {
-			let len = dux::Page::min_pages_for_range(rx.name_len.into());
-			let ret = unsafe { kernel::mem_dealloc(name.as_ptr() as *mut _, len) };
-			assert_eq!(ret.status, 0);
-			dux::ipc::add_free_range(
-				dux::Page::new(core::ptr::NonNull::new(name.as_ptr() as *mut _).unwrap()).unwrap(),
-				len,
-			)
-			.unwrap();
+		if let Some(console) = self.console {
+			*dux::ipc::transmit() = kernel::ipc::Packet {
+				uuid: kernel::ipc::UUID::INVALID,
+				opcode: NonZeroU8::new(opcode),
+				name: None,
+				name_len: 0,
+				flags: 0,
+				id: 0,
+				address: console.into(),
+				data: None,
+				length: 0,
+				offset,
+			};
 		}
 	}
-}
 
-fn process_events() {
-	let k_mods = unsafe { &mut KEY_MODIFIERS };
-	let putc = |on: bool, c: char| unsafe {
-		if on {
-			let len = c.encode_utf8(&mut BUFFER[usize::from(NEW_INDEX)..]).len();
-			NEW_INDEX += u16::try_from(len).unwrap();
+	/// The amount of bytes in the buffer.
+	fn len(&self) -> usize {
+		self.text_tail.wrapping_sub(self.text_head)
+	}
+
+	/// Whether characters are still arriving in quick succession.
+	fn in_burst(&self) -> bool {
+		dux::time::now().saturating_sub(self.last_event) < Self::BURST_GAP
+	}
+
+	/// Move text from the buffer to `data`. Returns the amount of bytes moved and how many
+	/// of those are part of a burst.
+	fn take(&mut self, data: &mut [u8]) -> (usize, usize) {
+		let now = dux::time::now();
+		let (mut length, mut burst) = (0, 0);
+		while self.used_index != self.new_index {
+			let record = self.records[usize::from(self.used_index) & (Self::RECORDS - 1)];
+			let rest = usize::from(record.len - self.sent);
+			// Don't split characters between replies, unless the reader's buffer is too
+			// small to hold a single one.
+			let n = match rest {
+				n if length + n <= data.len() => n,
+				_ => match self.char_boundary(data.len() - length) {
+					0 if length == 0 => data.len(),
+					n => n,
+				},
+			};
+			for b in data[length..length + n].iter_mut() {
+				*b = self.text[self.text_head & (Self::TEXT_CAPACITY - 1)];
+				self.text_head = self.text_head.wrapping_add(1);
+			}
+			length += n;
+			if record.kind == RecordKind::Burst {
+				burst += n;
+			}
+			if n < rest {
+				self.sent += n as u16;
+				break;
+			}
+			self.sent = 0;
+			self.used_index = self.used_index.wrapping_add(1);
+			if self.latency_debug {
+				if let Some(s) = self.latency.add(now.saturating_sub(record.time)) {
+					kernel::sys_log!("virtio_input: event latency {}", s);
+				}
+			}
 		}
-	};
-	unsafe { DEVICE.as_mut().unwrap() }
-		.receive(&mut |evt| {
-			if let Some(k) = NonZeroU8::new(evt.code().try_into().unwrap()) {
-				use scancode::*;
-				let mut mods = Modifiers::new();
-				mods.set_caps((k_mods.lshift() || k_mods.rshift()) != k_mods.capslock());
-				let on = evt.value() > 0;
-				match unsafe { SET.as_mut().unwrap() }.get(mods, k) {
-					Some(Key::Char(c)) => putc(on, c),
-					Some(Key::LShift) => k_mods.set_lshift(on),
-					Some(Key::RShift) => k_mods.set_rshift(on),
-					Some(Key::Capslock) => k_mods.set_capslock(on),
-					Some(Key::Backspace) => putc(on, '\x08'),
-					Some(Key::Enter) => putc(on, '\n'),
-					Some(Key::Space) => putc(on, ' '),
-					Some(k) => kernel::sys_log!("unhandled key: {:?}", k),
-					None => kernel::sys_log!("unknown event: 0x{:x}", evt.code()),
+		(length, burst)
+	}
+
+	/// Return the start of the last character that begins at most `max` bytes after the
+	/// oldest byte in the buffer. There must be more than `max` bytes in the buffer.
+	fn char_boundary(&self, max: usize) -> usize {
+		(0..=max)
+			.rev()
+			.find(|i| {
+				let b = self.text[self.text_head.wrapping_add(*i) & (Self::TEXT_CAPACITY - 1)];
+				b & 0xc0 != 0x80
+			})
+			.unwrap_or(0)
+	}
+
+	/// Read all pending events from the device and convert them to text.
+	///
+	/// Characters that arrive in quick succession are merged into a burst record. The device
+	/// is paused if the buffer is almost full.
+	fn process_events(&mut self) {
+		let Self {
+			device,
+			set,
+			key_modifiers: k_mods,
+			text,
+			text_tail,
+			text_head,
+			records,
+			new_index,
+			used_index,
+			last_event,
+			..
+		} = self;
+		let mut dropped = 0usize;
+		let mut putc = |on: bool, character: char| {
+			if !on {
+				return;
+			}
+			let mut utf8 = [0; 4];
+			let utf8 = character.encode_utf8(&mut utf8).as_bytes();
+			if text_tail.wrapping_sub(*text_head) + utf8.len() > Self::TEXT_CAPACITY {
+				// Shouldn't happen as the device is paused before the buffer is full.
+				dropped += 1;
+				return;
+			}
+			let now = dux::time::now();
+			let burst = now.saturating_sub(*last_event) < Self::BURST_GAP;
+			let newest = match new_index.wrapping_sub(*used_index) {
+				0 => None,
+				_ => {
+					Some(&mut records[usize::from(new_index.wrapping_sub(1)) & (Self::RECORDS - 1)])
+				}
+			};
+			match newest {
+				Some(r) if burst && usize::from(r.len) + utf8.len() <= Page::SIZE => {
+					r.kind = RecordKind::Burst;
+					r.len += utf8.len() as u16;
+				}
+				_ if usize::from(new_index.wrapping_sub(*used_index)) >= Self::RECORDS => {
+					dropped += 1;
+					return;
+				}
+				_ => {
+					records[usize::from(*new_index) & (Self::RECORDS - 1)] = Record {
+						kind: RecordKind::Char,
+						len: utf8.len() as u16,
+						time: now,
+					};
+					*new_index = new_index.wrapping_add(1);
 				}
 			}
-		})
-		.unwrap();
+			for &b in utf8 {
+				text[*text_tail & (Self::TEXT_CAPACITY - 1)] = b;
+				*text_tail = text_tail.wrapping_add(1);
+			}
+			*last_event = now;
+		};
+		device
+			.receive(&mut |evt| {
+				if let Some(k) = NonZeroU8::new(evt.code().try_into().unwrap()) {
+					use scancode::*;
+					let mut mods = Modifiers::new();
+					mods.set_caps((k_mods.lshift() || k_mods.rshift()) != k_mods.capslock());
+					let on = evt.value() > 0;
+					match set.get(mods, k) {
+						Some(Key::Char(c)) => putc(on, c),
+						Some(Key::LShift) => k_mods.set_lshift(on),
+						Some(Key::RShift) => k_mods.set_rshift(on),
+						Some(Key::Capslock) => k_mods.set_capslock(on),
+						Some(Key::Backspace) => putc(on, '\x08'),
+						Some(Key::Enter) => putc(on, '\n'),
+						Some(Key::Space) => putc(on, ' '),
+						Some(k) => kernel::sys_log!("unhandled key: {:?}", k),
+						None => kernel::sys_log!("unknown event: 0x{:x}", evt.code()),
+					}
+				}
+			})
+			.unwrap();
+		if dropped > 0 {
+			kernel::sys_log!("virtio_input: buffer full, dropped {} characters", dropped);
+		}
+		if self.len() >= Self::HIGH_WATER {
+			self.device.set_paused(true);
+		}
+	}
 }
@@ -0,0 +1,27 @@
+use core::ptr;
+use core::sync::atomic::{AtomicPtr, Ordering};
+use dux::notification::{self, Handler, Notification};
+
+/// The ISR of the device, which is null until the device is set up.
+static HANDLER: Handler<AtomicPtr<virtio::pci::ISR>> =
+	Handler::new(notification_handler, AtomicPtr::new(ptr::null_mut()));
+
+/// Acknowledge the interrupt so the device stops raising it. The kernel wakes the task up,
+/// after which the main routine collects the events.
+fn notification_handler(
+	isr: &AtomicPtr<virtio::pci::ISR>,
+	_: Notification,
+) -> Option<dux::task::Address> {
+	// SAFETY: the ISR is mapped for as long as the task lives.
+	if let Some(isr) = unsafe { isr.load(Ordering::Acquire).as_ref() } {
+		isr.read();
+	}
+	None
+}
+
+pub(crate) fn init(isr: &'static virtio::pci::ISR) {
+	HANDLER
+		.state()
+		.store(isr as *const _ as *mut _, Ordering::Release);
+	notification::set_handler(notification::Type::Interrupt, &HANDLER);
+}
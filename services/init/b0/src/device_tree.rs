@@ -17,14 +17,7 @@ pub fn iter_devices<F>(mut f: F)
 where
 	F: FnMut(Device),
 {
-	let dtb = unsafe {
-		let dtb = 0x100_0000 as *mut _;
-		let ret = kernel::sys_platform_info(dtb, 16);
-		assert_eq!(ret.status, 0);
-		core::slice::from_raw_parts(dtb.cast(), ret.value << 10)
-	};
-
-	let dtb = device_tree::DeviceTree::parse(dtb).unwrap();
+	let dtb = device_tree::DeviceTree::parse(platform_info()).unwrap();
 
 	if let Ok(node) = dtb.root() {
 		for node in node.children() {
@@ -146,6 +139,35 @@ where
 	}
 }
 
+/// Call `f` with the arguments in `/chosen/bootargs`, which is empty if there are none.
+pub fn boot_args<F, R>(f: F) -> R
+where
+	F: FnOnce(&[u8]) -> R,
+{
+	let dtb = device_tree::DeviceTree::parse(platform_info()).unwrap();
+	let args = dtb
+		.root()
+		.ok()
+		.and_then(|root| root.children().find(|node| node.name == b"chosen"))
+		.and_then(|node| node.property(b"bootargs"))
+		.map_or(&[][..], |p| p.value.split(|c| *c == b'\0').next().unwrap());
+	f(args)
+}
+
+/// Map the device tree. It is only mapped once.
+fn platform_info() -> &'static [u32] {
+	static mut DTB: Option<&'static [u32]> = None;
+	// SAFETY: b0 is single threaded.
+	unsafe {
+		*DTB.get_or_insert_with(|| {
+			let dtb = 0x100_0000 as *mut _;
+			let ret = kernel::sys_platform_info(dtb, 16);
+			assert_eq!(ret.status, 0);
+			core::slice::from_raw_parts(dtb.cast(), ret.value << 10)
+		})
+	}
+}
+
 #[track_caller]
 fn unpack_reg(reg: &[u8], cells: u32) -> (u128, &[u8]) {
 	assert!(cells <= 4, "unsupported cells size: {}", cells);
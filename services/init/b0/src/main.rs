@@ -21,6 +21,7 @@ fn panic_handler(info: &core::panic::PanicInfo) -> ! {
 
 mod device_tree;
 mod rtbegin;
+mod supervisor;
 
 include!(concat!(env!("OUT_DIR"), "/list.rs"));
 
@@ -30,6 +31,18 @@ use kernel::sys_log;
 fn main() {
 	unsafe { dux::init() };
 
+	// Run the syscall fuzzer instead of the usual services if asked to.
+	device_tree::boot_args(|args| {
+		let args = args.split(|c| *c == b' ').filter(|a| !a.is_empty());
+		if args.clone().any(|a| a == b"fuzz") {
+			fuzz(args.filter(|a| a.starts_with(b"fuzz.")))
+		}
+	});
+
+	// Log latency statistics of the input driver & the console if asked to.
+	let latency_debug =
+		device_tree::boot_args(|args| args.split(|c| *c == b' ').any(|a| a == b"latency-debug"));
+
 	device_tree::iter_devices(|dev| {
 		for bin in BINARIES.iter() {
 			if !dev.compatible.contains(&bin.compatible.as_bytes()) {
@@ -51,9 +64,9 @@ fn main() {
 			};
 
 			// Push arguments
-			let mut buf = [0u8; 4096];
+			let mut buf = [0u8; supervisor::MAX_ARGS_LEN];
 			let mut buf = &mut buf[..];
-			let mut args = [&[][..]; 128];
+			let mut args = [&[][..]; supervisor::MAX_ARGS];
 			let mut argc = 0;
 
 			fn alloc<'a>(
@@ -72,6 +85,9 @@ fn main() {
 				Ok(())
 			};
 
+			buf = driver::ArgvVersion::CURRENT
+				.to_args(buf, alloc, &mut add_arg)
+				.unwrap();
 			for &r in dev.reg.iter() {
 				buf = r.to_args(buf, alloc, &mut add_arg).unwrap();
 			}
@@ -86,16 +102,15 @@ fn main() {
 					.to_args(buf, alloc, &mut add_arg)
 					.unwrap();
 			}
+			// The PCI manager passes it on to the input driver.
+			if latency_debug && bin.name == "pci" {
+				add_arg("--latency-debug").unwrap();
+			}
 
-			// Spawn
-			let address = dux::task::spawn_elf(data, &mut [].iter().copied(), &args[..argc])
-				.expect("failed to spawn task");
-
-			sys_log!("Registering task {} as {:?}", address, bin.name);
-
-			// Add to registry
-			dux::task::registry::add(bin.name.as_bytes(), address)
-				.expect("failed to add registry entry");
+			// Spawn & add to registry
+			if let Err(e) = supervisor::spawn(bin.name, data, &args[..argc], &[], true) {
+				sys_log!("Failed to spawn {:?}: {:?}", bin.name, e);
+			}
 
 			return;
 		}
@@ -110,9 +125,22 @@ fn main() {
 		}
 	});
 
+	// The serial of the disk the filesystem should use may be given with "disk=<serial>".
+	let mut disk = [0; 32];
+	let disk_len = device_tree::boot_args(|args| {
+		args.split(|c| *c == b' ')
+			.find(|a| a.starts_with(b"disk="))
+			.map_or(0, |a| {
+				let serial = &a[b"disk=".len()..];
+				let len = serial.len().min(disk.len());
+				disk[..len].copy_from_slice(&serial[..len]);
+				len
+			})
+	});
+
 	BINARIES
 		.iter()
-		.filter(|e| ["fs", "console"].contains(&e.compatible))
+		.filter(|e| ["fs", "console", "coredump"].contains(&e.compatible))
 		.for_each(|e| {
 			// FIXME completely, utterly unsound
 			let data = unsafe {
@@ -121,10 +149,16 @@ fn main() {
 					(e.data.len() + dux::Page::OFFSET_MASK) / dux::Page::SIZE,
 				)
 			};
-			// TODO which terminology to use? Ports seems... wrong?
-			let ports = [];
-			let ports = &mut ports.iter().copied();
-			dux::task::spawn_elf(data, ports, &[]).expect("failed to spawn task");
+			let disk_args = [&b"--disk"[..], &disk[..disk_len]];
+			let args = match e.compatible {
+				"fs" if disk_len > 0 => &disk_args[..],
+				"console" if latency_debug => &[&b"--latency-debug"[..]],
+				_ => &[],
+			};
+			// These register themselves.
+			if let Err(err) = supervisor::spawn(e.name, data, args, &[], false) {
+				sys_log!("Failed to spawn {:?}: {:?}", e.name, err);
+			}
 		});
 
 	// Wait for fatfs to come online
@@ -188,11 +222,117 @@ fn main() {
 				),
 			];
 			let ports = &mut ports.iter().copied();
-			dux::task::spawn_elf(data, ports, &[]).expect("failed to spawn task");
+			if let Err(err) = dux::task::spawn_elf(data, ports, &[]) {
+				sys_log!("Failed to spawn {:?}: {:?}", e.name, err);
+			}
 		});
 
+	// Spawn a task that faults to test core dumps if asked to.
+	if device_tree::boot_args(|args| args.split(|c| *c == b' ').any(|a| a == b"crash")) {
+		crash();
+	}
+
+	// Spawn a task that shares pages it doesn't own to test the IPC pointer checks if asked to.
+	if device_tree::boot_args(|args| args.split(|c| *c == b' ').any(|a| a == b"ipc-test")) {
+		spawn_test("ipc-test");
+	}
+
+	// Spawn a task with two threads to test shared state & IPC if asked to.
+	if device_tree::boot_args(|args| args.split(|c| *c == b' ').any(|a| a == b"thread-test")) {
+		spawn_test("thread-test");
+	}
+
+	// Spawn the crash test under supervision to test restarts if asked to.
+	if device_tree::boot_args(|args| args.split(|c| *c == b' ').any(|a| a == b"restart-test")) {
+		restart_test();
+	}
+
+	// We can't exit, so keep the drivers running instead.
+	supervisor::run()
+}
+
+/// Spawn the crash test once the core dump service is online.
+fn crash() {
+	loop {
+		let name = b"coredump";
+		let ret = unsafe { kernel::sys_registry_get(name.as_ptr(), name.len()) };
+		if ret.status == 0 {
+			break;
+		}
+		unsafe { kernel::io_wait(0) };
+	}
+
+	spawn_test("crash");
+}
+
+/// Spawn the test with the given compatible string from the init filesystem.
+fn spawn_test(compatible: &str) {
+	let bin = match BINARIES.iter().find(|e| e.compatible == compatible) {
+		Some(bin) => bin,
+		None => return sys_log!("No {:?} test in the init filesystem", compatible),
+	};
+	// FIXME completely, utterly unsound
+	let data = unsafe {
+		core::slice::from_raw_parts(
+			bin.data.as_ptr().cast(),
+			(bin.data.len() + dux::Page::OFFSET_MASK) / dux::Page::SIZE,
+		)
+	};
+	let ports = &mut core::iter::empty::<(dux::task::Address, kernel::ipc::UUID)>();
+	if let Err(err) = dux::task::spawn_elf(data, ports, &[]) {
+		sys_log!("Failed to spawn {:?}: {:?}", bin.name, err);
+	}
+}
+
+/// Spawn the crash test as a supervised task. It faults each time it runs, so it should be
+/// restarted until b0 gives up on it.
+fn restart_test() {
+	let bin = match BINARIES.iter().find(|e| e.compatible == "crash") {
+		Some(bin) => bin,
+		None => return sys_log!("No \"crash\" test in the init filesystem"),
+	};
+	// FIXME completely, utterly unsound
+	let data = unsafe {
+		core::slice::from_raw_parts(
+			bin.data.as_ptr().cast(),
+			(bin.data.len() + dux::Page::OFFSET_MASK) / dux::Page::SIZE,
+		)
+	};
+	if let Err(err) = supervisor::spawn(bin.name, data, &[], &[], false) {
+		sys_log!("Failed to spawn {:?}: {:?}", bin.name, err);
+	}
+}
+
+/// Spawn the syscall fuzzer with the given arguments and report when it exits.
+fn fuzz<'a>(args: impl Iterator<Item = &'a [u8]>) -> ! {
+	let mut argv = [&[][..]; 16];
+	let mut argc = 0;
+	for a in args {
+		*argv.get_mut(argc).expect("too many fuzzer arguments") = a;
+		argc += 1;
+	}
+
+	let bin = BINARIES
+		.iter()
+		.find(|e| e.compatible == "fuzz")
+		.expect("no fuzzer in the init filesystem");
+	// FIXME completely, utterly unsound
+	let data = unsafe {
+		core::slice::from_raw_parts(
+			bin.data.as_ptr().cast(),
+			(bin.data.len() + dux::Page::OFFSET_MASK) / dux::Page::SIZE,
+		)
+	};
+	let ports = &mut core::iter::empty::<(dux::task::Address, kernel::ipc::UUID)>();
+	let address = dux::task::spawn_elf(data, ports, &argv[..argc]).expect("failed to spawn fuzzer");
+	let watch = dux::task::TaskWatch::new(address).expect("failed to watch fuzzer");
+
 	loop {
-		// Do nothing as we can't exit
 		unsafe { kernel::io_wait(u64::MAX) };
+		while let Some(rx) = dux::ipc::try_receive() {
+			if watch.is_dead(&rx) {
+				sys_log!("fuzz: fuzzer exited");
+			}
+		}
 	}
 }
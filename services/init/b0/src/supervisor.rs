@@ -0,0 +1,212 @@
+//! # Supervision of essential tasks
+//!
+//! b0 remembers how it spawned each driver. When a driver dies it is spawned again with the
+//! same binary, arguments & granted endpoints and its name is added to the registry again.
+//! Restarts are delayed with an exponential backoff and b0 gives up on a driver after a few
+//! attempts.
+//!
+//! A task is always spawned, even if it can't be supervised because the table is full.
+//!
+//! Drivers whose device state doesn't survive a restart must reset the device on init.
+
+use kernel::sys_log;
+
+/// The maximum amount of supervised tasks.
+const MAX_TASKS: usize = 8;
+
+/// The maximum total length of the arguments of a single task.
+pub const MAX_ARGS_LEN: usize = 4096;
+
+/// The maximum amount of arguments of a single task.
+pub const MAX_ARGS: usize = 128;
+
+/// The maximum amount of endpoints granted to a single task.
+const MAX_GRANTS: usize = 32;
+
+/// The delay before each restart attempt in microseconds. b0 gives up once all attempts are
+/// used.
+const BACKOFF: [u64; 5] = [0, 1_000_000, 5_000_000, 25_000_000, 125_000_000];
+
+/// Everything needed to spawn a task again.
+struct Recipe {
+	name: &'static str,
+	data: &'static [kernel::Page],
+	/// Whether b0 should add the task to the registry under `name`.
+	register: bool,
+	buffer: [u8; MAX_ARGS_LEN],
+	/// The start and end of each argument in `buffer`.
+	args: [(u16, u16); MAX_ARGS],
+	argc: usize,
+	/// The endpoints granted to the task.
+	grants: [(dux::task::Address, kernel::ipc::UUID); MAX_GRANTS],
+	grants_count: usize,
+	/// The address of the running task, if any.
+	address: Option<dux::task::Address>,
+	/// The amount of restarts so far.
+	restarts: usize,
+	/// The time at which the task should be restarted.
+	restart_at: Option<u64>,
+}
+
+static mut TASKS: [Option<Recipe>; MAX_TASKS] = {
+	const NONE: Option<Recipe> = None;
+	[NONE; MAX_TASKS]
+};
+
+/// Spawn a task and restart it whenever it dies.
+///
+/// If the task can't be supervised it is still spawned, but it won't be restarted.
+pub fn spawn(
+	name: &'static str,
+	data: &'static [kernel::Page],
+	arguments: &[&[u8]],
+	grants: &[(dux::task::Address, kernel::ipc::UUID)],
+	register: bool,
+) -> Result<dux::task::Address, dux::task::SpawnElfError> {
+	let address = spawn_task(name, data, arguments, grants, register)?;
+
+	// SAFETY: b0 is single threaded.
+	let slot = unsafe { TASKS.iter_mut().find(|t| t.is_none()) };
+	let slot = match slot {
+		Some(slot) => slot,
+		None => {
+			sys_log!("Not supervising {:?}: too many tasks", name);
+			return Ok(address);
+		}
+	};
+	match Recipe::new(name, data, arguments, grants, register) {
+		Some(mut recipe) => {
+			recipe.address = Some(address);
+			*slot = Some(recipe);
+		}
+		None => sys_log!("Not supervising {:?}: too many arguments", name),
+	}
+	Ok(address)
+}
+
+/// Spawn the task, add it to the registry if needed and watch it.
+fn spawn_task(
+	name: &'static str,
+	data: &'static [kernel::Page],
+	arguments: &[&[u8]],
+	grants: &[(dux::task::Address, kernel::ipc::UUID)],
+	register: bool,
+) -> Result<dux::task::Address, dux::task::SpawnElfError> {
+	let grants = &mut grants.iter().copied();
+	let address = dux::task::spawn_elf(data, grants, arguments)?;
+
+	if let Err(e) = dux::task::TaskWatch::new(address) {
+		sys_log!("Failed to watch {:?}: {:?}", name, e);
+	}
+
+	if register {
+		sys_log!("Registering task {} as {:?}", address, name);
+		if let Err(e) = dux::task::registry::add(name.as_bytes(), address) {
+			sys_log!("Failed to register {:?}: {:?}", name, e);
+		}
+	}
+
+	Ok(address)
+}
+
+impl Recipe {
+	/// Copy everything needed to spawn the task again. Returns `None` if it doesn't fit.
+	fn new(
+		name: &'static str,
+		data: &'static [kernel::Page],
+		arguments: &[&[u8]],
+		grants: &[(dux::task::Address, kernel::ipc::UUID)],
+		register: bool,
+	) -> Option<Self> {
+		let mut recipe = Self {
+			name,
+			data,
+			register,
+			buffer: [0; MAX_ARGS_LEN],
+			args: [(0, 0); MAX_ARGS],
+			argc: 0,
+			grants: [(dux::task::Address::from(0), kernel::ipc::UUID::default()); MAX_GRANTS],
+			grants_count: grants.len(),
+			address: None,
+			restarts: 0,
+			restart_at: None,
+		};
+		let mut offset = 0;
+		for a in arguments {
+			let end = offset + a.len();
+			recipe.buffer.get_mut(offset..end)?.copy_from_slice(a);
+			*recipe.args.get_mut(recipe.argc)? = (offset as u16, end as u16);
+			recipe.argc += 1;
+			offset = end;
+		}
+		recipe
+			.grants
+			.get_mut(..grants.len())?
+			.copy_from_slice(grants);
+		Some(recipe)
+	}
+
+	/// Spawn the task again.
+	fn spawn(&mut self) -> Result<dux::task::Address, dux::task::SpawnElfError> {
+		let mut args = [&[][..]; MAX_ARGS];
+		for (a, &(s, e)) in args.iter_mut().zip(&self.args[..self.argc]) {
+			*a = &self.buffer[usize::from(s)..usize::from(e)];
+		}
+		let args = &args[..self.argc];
+		let grants = &self.grants[..self.grants_count];
+		let address = spawn_task(self.name, self.data, args, grants, self.register)?;
+		self.address = Some(address);
+		Ok(address)
+	}
+}
+
+/// Restart dead tasks forever.
+pub fn run() -> ! {
+	loop {
+		let now = dux::time::now();
+
+		// SAFETY: b0 is single threaded.
+		let tasks = unsafe { TASKS.iter_mut().flatten() };
+		let mut next = None::<u64>;
+		for task in tasks {
+			if task.restart_at.map_or(false, |t| t <= now) {
+				task.restart_at = None;
+				task.restarts += 1;
+				sys_log!("Restarting {:?} (attempt {})", task.name, task.restarts);
+				if let Err(e) = task.spawn() {
+					sys_log!("Failed to restart {:?}: {:?}", task.name, e);
+					schedule(task, now);
+				}
+			}
+			if let Some(t) = task.restart_at {
+				next = Some(next.map_or(t, |n| n.min(t)));
+			}
+		}
+
+		let timeout = next.map_or(u64::MAX, |t| t.saturating_sub(now));
+		unsafe { kernel::io_wait(timeout) };
+
+		while let Some(rx) = dux::ipc::try_receive() {
+			let address = match dux::task::dead_peer(&rx) {
+				Some(address) => address,
+				None => continue,
+			};
+			let now = dux::time::now();
+			// SAFETY: b0 is single threaded.
+			let task = unsafe { TASKS.iter_mut().flatten() }.find(|t| t.address == Some(address));
+			if let Some(task) = task {
+				sys_log!("{:?} ({}) died", task.name, address);
+				task.address = None;
+				schedule(task, now);
+			}
+		}
+	}
+}
+
+/// Schedule a restart of the task or give up if it was restarted too often.
+fn schedule(task: &mut Recipe, now: u64) {
+	match BACKOFF.get(task.restarts) {
+		Some(delay) => task.restart_at = Some(now + delay),
+		None => sys_log!("Giving up on {:?}", task.name),
+	}
+}
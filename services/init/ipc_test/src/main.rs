@@ -0,0 +1,144 @@
+//! # IPC pointer test
+//!
+//! Transmits packets whose data or name points to memory the task may not share, such as the
+//! kernel image, the device tree mapping of the kernel, partially unmapped ranges and read-only
+//! pages. The kernel must return each of them with `FLAG_ERROR` & `ERROR_INVALID_POINTER`
+//! instead of faulting. It is spawned by b0 if `ipc-test` is passed on the kernel command line.
+//!
+//! "ipc_test: ok" is logged if every packet was rejected, "ipc_test: FAILED" otherwise.
+//! `scripts/ipc_test_qemu.py` checks for these & for kernel panics.
+
+#![no_std]
+#![no_main]
+#![feature(asm)]
+#![feature(global_asm)]
+#![feature(naked_functions)]
+#![feature(panic_info_message)]
+
+#[panic_handler]
+fn panic_handler(info: &core::panic::PanicInfo) -> ! {
+	kernel::sys_log!("Panic!");
+	if let Some(m) = info.message() {
+		kernel::sys_log!("  Message: {}", m);
+	}
+	if let Some(l) = info.location() {
+		kernel::sys_log!("  Location: {}", l);
+	}
+	loop {}
+}
+
+mod rtbegin;
+
+use core::ptr::NonNull;
+use kernel::{ipc, sys_log, Page};
+
+/// A readable & writeable page. The page after it is never mapped.
+const MAPPED: usize = 0x4000_0000;
+
+/// A page that may only be read.
+const READ_ONLY: usize = 0x4800_0000;
+
+/// The first page of the kernel image.
+const KERNEL_IMAGE: usize = 0xffff_ffff_fffe_0000;
+
+/// The first page of the device tree as mapped by the kernel. See `DEVICE_TREE` in
+/// `kernel/src/memory/reserved.rs`.
+const DEVICE_TREE: usize = 0xffff_ffdb_dadd_0000;
+
+/// The lowest address of the kernel.
+const KERNEL_START: usize = 0xffff_ff80_0000_0000;
+
+/// The highest page of the kernel.
+const KERNEL_END: usize = 0xffff_ffff_ffff_f000;
+
+/// The first address past user space.
+const USER_END: usize = 0x40_0000_0000;
+
+/// An address without a task. The pointers are checked before the destination is looked up, so
+/// a packet that isn't rejected comes back as undeliverable instead of reaching anyone.
+const NOBODY: usize = 0x7fff_fff0_0000_0000;
+
+#[export_name = "main"]
+fn main() {
+	// FIXME move this to rtbegin
+	unsafe { dux::init() };
+
+	let ret = unsafe { kernel::mem_alloc(MAPPED as *mut _, 1, kernel::PROT_READ_WRITE) };
+	assert_eq!(ret.status, 0, "failed to allocate page");
+	let ret = unsafe { kernel::mem_alloc(READ_ONLY as *mut _, 1, kernel::PROT_READ) };
+	assert_eq!(ret.status, 0, "failed to allocate read-only page");
+
+	let mut failed = 0;
+	let mut check = |case: &str, data: Option<usize>, length: usize, name: Option<(usize, u16)>| {
+		if !rejected(data, length, name) {
+			sys_log!("ipc_test: {} was not rejected", case);
+			failed += 1;
+		}
+	};
+
+	check("kernel image", Some(KERNEL_IMAGE), Page::SIZE, None);
+	check("kernel image name", None, 0, Some((KERNEL_IMAGE, 16)));
+	check("device tree", Some(DEVICE_TREE), Page::SIZE, None);
+	check("device tree name", None, 0, Some((DEVICE_TREE, 16)));
+	check("partially unmapped", Some(MAPPED), Page::SIZE * 2, None);
+	check(
+		"partially unmapped name",
+		None,
+		0,
+		Some((MAPPED, Page::SIZE as u16 + 1)),
+	);
+	check("read-only", Some(READ_ONLY), Page::SIZE, None);
+	check("misaligned name", None, 0, Some((MAPPED + 8, 8)));
+	check("misaligned", Some(MAPPED + 8), 8, None);
+	check("past user space", Some(USER_END), Page::SIZE, None);
+	check("wrapping length", Some(MAPPED), usize::MAX, None);
+
+	// Other global kernel mappings whose exact location may shift.
+	for address in (KERNEL_START..KERNEL_END).step_by(1 << 28) {
+		check("kernel space", Some(address), Page::SIZE, None);
+	}
+
+	// Make sure the packets above weren't rejected for another reason.
+	let rx = send(Some(MAPPED), Page::SIZE, None);
+	if rx.flags & ipc::FLAG_ERROR > 0 || rx.flags & ipc::FLAG_DEAD_PEER == 0 {
+		sys_log!("ipc_test: valid packet was not returned as undeliverable");
+		failed += 1;
+	}
+
+	// The kernel must still be able to hand out & take back memory.
+	let ret = unsafe { kernel::mem_dealloc(MAPPED as *mut _, 1) };
+	assert_eq!(ret.status, 0, "failed to deallocate page");
+
+	if failed == 0 {
+		sys_log!("ipc_test: ok");
+	} else {
+		sys_log!("ipc_test: FAILED {} cases", failed);
+	}
+	dux::task::exit()
+}
+
+/// Transmit a packet and wait for the kernel to return it.
+fn send(data: Option<usize>, length: usize, name: Option<(usize, u16)>) -> ipc::Packet {
+	let ptr = |a: usize| NonNull::new(a as *mut Page);
+	*dux::ipc::transmit() = ipc::Packet {
+		flags: 0,
+		id: 0,
+		offset: 0,
+		opcode: core::num::NonZeroU8::new(ipc::Op::Write as u8),
+		uuid: ipc::UUID::INVALID,
+		data: data.and_then(ptr),
+		length,
+		name: name.and_then(|(a, _)| ptr(a)),
+		name_len: name.map_or(0, |(_, l)| l),
+		address: NOBODY,
+	};
+	// The packet comes back as it was sent, so the pages are still ours & mustn't be released.
+	let rx = dux::ipc::receive();
+	(*rx).clone()
+}
+
+/// Whether the kernel returned the packet with `ERROR_INVALID_POINTER`.
+fn rejected(data: Option<usize>, length: usize, name: Option<(usize, u16)>) -> bool {
+	let rx = send(data, length, name);
+	rx.flags & ipc::FLAG_ERROR > 0 && rx.offset == ipc::ERROR_INVALID_POINTER
+}
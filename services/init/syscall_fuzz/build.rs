@@ -0,0 +1,41 @@
+use std::env;
+use std::fs::File;
+use std::io::{Read, Write};
+use std::path::PathBuf;
+
+const BASE_DIR: &str = "../../..";
+const TABLE: &str = "kernel/src/syscall.rs";
+
+fn main() {
+	let table = format!("{}/{}", BASE_DIR, TABLE);
+
+	println!("cargo:rerun-if-changed={}", table);
+
+	let mut table = File::open(table).unwrap();
+	let mut s = String::new();
+	table.read_to_string(&mut s).unwrap();
+	drop(table);
+	let table = s;
+
+	let count = table
+		.lines()
+		.map(str::trim)
+		.find_map(|l| l.strip_prefix("pub const TABLE_LEN: usize ="))
+		.and_then(|l| l.trim().strip_suffix(';'))
+		.map(|n| {
+			n.trim()
+				.parse::<usize>()
+				.expect("TABLE_LEN is not a number")
+		})
+		.expect("expected TABLE_LEN in the syscall table");
+
+	let out = PathBuf::from(env::var("OUT_DIR").unwrap()).join("count.rs");
+	let mut out = File::create(out).unwrap();
+
+	write!(
+		out,
+		"/// One past the highest syscall number.\nconst SYSCALL_COUNT: usize = {};\n",
+		count
+	)
+	.unwrap();
+}
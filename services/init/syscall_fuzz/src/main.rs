@@ -0,0 +1,456 @@
+//! # Syscall fuzzer
+//!
+//! Makes pseudo-random syscalls with hostile arguments to find calls that bring down the kernel.
+//! It is spawned by b0 instead of the usual services if `fuzz` is passed on the kernel command
+//! line. Arguments starting with `fuzz.` are passed on:
+//!
+//! * `fuzz.seed=<n>`: the seed of the run. The current time is used if it isn't given.
+//! * `fuzz.iterations=<n>`: the amount of cases to run.
+//! * `fuzz.calls=<n>`: the amount of calls per case.
+//! * `fuzz.skip=<n,n,...>`: the syscalls that are generated but not made. By default the calls
+//!   that can take down the fuzzer itself or the entire system are skipped.
+//! * `fuzz.replay=<n>`: run only the case with the given seed and log every call.
+//! * `fuzz.drop=<n,n,...>`: the calls of the replayed case that are not made.
+//!
+//! Each case is logged with its seed before it runs, so the last case logged before a kernel
+//! panic is the one that caused it. Replay it and drop calls or lower `fuzz.calls` until the
+//! panic is gone to find the smallest case that still crashes the kernel. Skipped & dropped
+//! calls are still generated, so the remaining calls stay the same.
+//!
+//! After each call the fuzzer checks whether the kernel is still sane by allocating, writing
+//! and freeing a page. "fuzz: done" is logged once all cases have run.
+//!
+//! ## Arguments
+//!
+//! The arguments of each syscall are drawn from distributions matching what the call expects,
+//! e.g. pointers to buffers are usually near the scratch pages while pages to map are in the
+//! window. Addresses of the fuzzer's own code, data & stack are never generated, so a call
+//! can't pull the rug from under the fuzzer. Pointers into the scratch pages are never passed
+//! where a page to map or unmap is expected.
+
+#![no_std]
+#![no_main]
+#![feature(asm)]
+#![feature(global_asm)]
+#![feature(naked_functions)]
+#![feature(panic_info_message)]
+
+#[panic_handler]
+fn panic_handler(info: &core::panic::PanicInfo) -> ! {
+	kernel::sys_log!("Panic!");
+	if let Some(m) = info.message() {
+		kernel::sys_log!("  Message: {}", m);
+	}
+	if let Some(l) = info.location() {
+		kernel::sys_log!("  Location: {}", l);
+	}
+	loop {}
+}
+
+mod rtbegin;
+
+use core::ptr;
+use kernel::{sys_log, Page, Return};
+
+// The length of the syscall table of the kernel.
+include!(concat!(env!("OUT_DIR"), "/count.rs"));
+
+/// Pages the kernel may freely read from & write to. They are filled with hostile values
+/// before each case.
+const SCRATCH: usize = 0x4000_0000;
+const SCRATCH_PAGES: usize = 4;
+const SCRATCH_SIZE: usize = SCRATCH_PAGES * Page::SIZE;
+
+/// The page used to check whether the kernel is still sane. It is never passed to a call.
+const SANITY: usize = 0x4800_0000;
+
+/// Pages that may be mapped & unmapped by the fuzzed calls. Everything above the window up to
+/// the stack is free too, so large counts don't hurt the fuzzer.
+const WINDOW: usize = 0x5000_0000;
+const WINDOW_PAGES: usize = 16;
+
+/// The lowest address of the kernel.
+const KERNEL: usize = 0xffff_ff80_0000_0000;
+
+/// The first address past user space.
+const USER_END: usize = 0x40_0000_0000;
+
+/// The calls skipped by default:
+///
+/// * `io_set_notify_handler` and `thread_spawn`, as jumping to a hostile address kills the
+///   fuzzer.
+/// * `io_notify_return`, as there is no notification to return from.
+/// * `sys_system_suspend`, as it takes down the entire system.
+const DEFAULT_SKIP: u64 = 1 << 2 | 1 << 9 | 1 << 24 | 1 << 26;
+
+/// Calls that are never made as they don't return.
+const EXIT: u64 = 1 << 20 | 1 << 25;
+
+/// What an argument is used for.
+#[derive(Clone, Copy, Debug)]
+enum Kind {
+	/// Any value.
+	Raw,
+	/// A pointer to a buffer.
+	Ptr,
+	/// A length in bytes.
+	Len,
+	/// A page to map or unmap.
+	Page,
+	/// An amount of pages or entries.
+	Count,
+	/// Memory protection or other flags.
+	Flags,
+	/// A program counter.
+	Code,
+	/// The address of or a handle to a task.
+	Task,
+	/// A timeout.
+	Time,
+}
+
+/// The arguments of each syscall. Unlisted arguments are [`Kind::Raw`].
+fn signature(nr: usize) -> &'static [Kind] {
+	use Kind::*;
+	match nr {
+		0 => &[Time],
+		1 => &[Ptr, Raw, Ptr, Count],
+		2 => &[Code, Raw, Raw, Ptr],
+		3 => &[Page, Count, Flags],
+		4 => &[Page, Count],
+		5 => &[Page, Count],
+		6 => &[Page, Count, Flags],
+		7 => &[Page, Ptr, Count],
+		8 => &[Raw, Count, Raw],
+		9 => &[],
+		10 => &[Raw],
+		11 => &[Ptr, Count, Code, Ptr, Ptr, Count],
+		12 => &[Page, Count, Flags],
+		13 => &[Page, Count],
+		14 => &[Page, Raw, Count, Flags],
+		15 => &[Ptr, Len],
+		16 => &[Ptr, Len, Task],
+		17 => &[Ptr, Len],
+		18 => &[],
+		19 => &[Task],
+		21 => &[Page, Count, Ptr, Count],
+		22 => &[Ptr, Len],
+		23 => &[Raw],
+		24 => &[Code, Ptr, Raw],
+		26 => &[Time],
+		27 => &[Raw],
+		28 => &[Ptr, Len, Ptr],
+		29 => &[Task, Page, Ptr, Count],
+		30 => &[Task, Ptr, Count],
+		31 => &[Ptr, Len],
+		32 => &[Task],
+		_ => &[],
+	}
+}
+
+/// SplitMix64, which is good enough & also suitable to derive seeds from seeds.
+struct Rng(u64);
+
+impl Rng {
+	fn next(&mut self) -> u64 {
+		self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+		let mut z = self.0;
+		z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+		z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+		z ^ (z >> 31)
+	}
+
+	/// Return a number in `0..n`.
+	fn below(&mut self, n: usize) -> usize {
+		(self.next() % n as u64) as usize
+	}
+
+	fn usize(&mut self) -> usize {
+		self.next() as usize
+	}
+
+	/// Return a number in `start..=end`.
+	fn range(&mut self, start: usize, end: usize) -> usize {
+		start + self.below(end - start + 1)
+	}
+
+	/// Return a power of two of at least `1 << min`.
+	fn pow2(&mut self, min: usize) -> usize {
+		1 << self.range(min, 63)
+	}
+
+	/// Return a random page in kernel space.
+	fn kernel(&mut self) -> usize {
+		KERNEL + self.below((usize::MAX - KERNEL) / Page::SIZE) * Page::SIZE
+	}
+
+	/// Return an address that isn't in user space.
+	fn hostile(&mut self) -> usize {
+		match self.below(5) {
+			0 => 0,
+			1 => self.kernel(),
+			2 => USER_END - self.range(1, 8),
+			3 => USER_END | self.usize(),
+			_ => usize::MAX - self.below(8),
+		}
+	}
+
+	fn arg(&mut self, kind: Kind) -> usize {
+		match kind {
+			Kind::Raw => match self.below(4) {
+				0 => self.usize(),
+				1 => self.below(64),
+				2 => self.hostile(),
+				_ => {
+					let kinds = [Kind::Ptr, Kind::Len, Kind::Page, Kind::Count, Kind::Task];
+					let kind = kinds[self.below(kinds.len())];
+					self.arg(kind)
+				}
+			},
+			Kind::Ptr => match self.below(6) {
+				0 => SCRATCH,
+				// Unaligned
+				1 => SCRATCH + self.below(SCRATCH_SIZE),
+				// Straddles the end of the scratch pages
+				2 => SCRATCH + SCRATCH_SIZE - self.range(1, 16),
+				3 => WINDOW + self.below(WINDOW_PAGES * Page::SIZE),
+				_ => self.hostile(),
+			},
+			Kind::Len => match self.below(6) {
+				0 => 0,
+				1 => self.range(1, 64),
+				2 => self.range(1, SCRATCH_SIZE + 1),
+				3 => usize::MAX - self.below(8),
+				4 => isize::MAX as usize + self.below(2),
+				_ => self.pow2(32),
+			},
+			Kind::Page => match self.below(5) {
+				0 | 1 => WINDOW + self.below(WINDOW_PAGES) * Page::SIZE,
+				// Unaligned
+				2 => WINDOW + self.range(1, Page::SIZE - 1),
+				_ => self.hostile() & !(Page::SIZE - 1),
+			},
+			Kind::Count => match self.below(6) {
+				0 => 0,
+				1 | 2 => self.range(1, WINDOW_PAGES + 1),
+				// Overflows when converted to bytes
+				3 => usize::MAX / Page::SIZE + 1,
+				4 => usize::MAX - self.below(8),
+				_ => self.pow2(32),
+			},
+			Kind::Flags => match self.below(4) {
+				0 => self.range(0, 7),
+				1 => self.below(256),
+				2 => usize::MAX,
+				_ => self.usize(),
+			},
+			Kind::Code => match self.below(3) {
+				0 => SCRATCH,
+				1 => SCRATCH + self.range(1, 3),
+				_ => self.hostile(),
+			},
+			Kind::Task => match self.below(4) {
+				0 => self.below(8),
+				1 => kernel::ipc::HANDLE_FLAG | self.below(8),
+				2 => usize::MAX,
+				_ => self.usize(),
+			},
+			Kind::Time => self.below(10_000),
+		}
+	}
+}
+
+/// A generated call.
+struct Call {
+	nr: usize,
+	args: [usize; 6],
+}
+
+impl Call {
+	fn generate(rng: &mut Rng) -> Self {
+		let nr = match rng.below(16) {
+			0 => SYSCALL_COUNT + rng.below(4),
+			1 => usize::MAX - rng.below(4),
+			_ => rng.below(SYSCALL_COUNT),
+		};
+		let sig = signature(nr);
+		let mut args = [0; 6];
+		for (i, a) in args.iter_mut().enumerate() {
+			*a = rng.arg(sig.get(i).copied().unwrap_or(Kind::Raw));
+		}
+		Self { nr, args }
+	}
+
+	fn is_masked(&self, mask: u64) -> bool {
+		self.nr < 64 && mask & 1 << self.nr > 0
+	}
+
+	unsafe fn run(&self) -> Return {
+		let (status, value);
+		let a = self.args;
+		asm!(
+			"ecall",
+			in("a7") self.nr,
+			inlateout("a0") a[0] => status,
+			inlateout("a1") a[1] => value,
+			in("a2") a[2],
+			in("a3") a[3],
+			in("a4") a[4],
+			in("a5") a[5],
+		);
+		Return { status, value }
+	}
+}
+
+struct Config {
+	seed: Option<u64>,
+	iterations: u64,
+	calls: usize,
+	skip: u64,
+	replay: Option<u64>,
+	drop: u64,
+}
+
+#[derive(Debug)]
+struct InvalidArgument;
+
+impl Config {
+	fn parse() -> Result<Self, InvalidArgument> {
+		let mut cfg = Self {
+			seed: None,
+			iterations: 10_000,
+			calls: 8,
+			skip: DEFAULT_SKIP,
+			replay: None,
+			drop: 0,
+		};
+		for arg in rtbegin::args() {
+			let arg = core::str::from_utf8(arg).map_err(|_| InvalidArgument)?;
+			let (key, value) = arg.split_once('=').ok_or(InvalidArgument)?;
+			match key {
+				"fuzz.seed" => cfg.seed = Some(parse_num(value)?),
+				"fuzz.iterations" => cfg.iterations = parse_num(value)?,
+				"fuzz.calls" => cfg.calls = parse_num(value)? as usize,
+				"fuzz.skip" => cfg.skip = parse_set(value)?,
+				"fuzz.replay" => cfg.replay = Some(parse_num(value)?),
+				"fuzz.drop" => cfg.drop = parse_set(value)?,
+				_ => return Err(InvalidArgument),
+			}
+		}
+		Ok(cfg)
+	}
+}
+
+/// Parse a decimal or `0x` prefixed hexadecimal number.
+fn parse_num(s: &str) -> Result<u64, InvalidArgument> {
+	match s.strip_prefix("0x") {
+		Some(s) => u64::from_str_radix(s, 16),
+		None => s.parse(),
+	}
+	.map_err(|_| InvalidArgument)
+}
+
+/// Parse a comma separated list of numbers below 64 into a mask.
+fn parse_set(s: &str) -> Result<u64, InvalidArgument> {
+	s.split(',')
+		.filter(|s| !s.is_empty())
+		.try_fold(0, |mask, n| match parse_num(n)? {
+			n if n < 64 => Ok(mask | 1 << n),
+			_ => Err(InvalidArgument),
+		})
+}
+
+/// Fill the scratch pages with hostile values, so structures read by the kernel are hostile
+/// too.
+fn fill_scratch(rng: &mut Rng) {
+	let words = SCRATCH_SIZE / core::mem::size_of::<usize>();
+	for i in 0..words {
+		let kinds = [Kind::Raw, Kind::Ptr, Kind::Len, Kind::Count];
+		let kind = kinds[rng.below(kinds.len())];
+		let v = rng.arg(kind);
+		unsafe { ptr::write_volatile((SCRATCH as *mut usize).add(i), v) };
+	}
+}
+
+/// Check whether the kernel still behaves.
+fn check_sanity() {
+	let ret = unsafe { kernel::sys_time() };
+	assert_eq!(ret.status, Return::OK, "sys_time failed");
+	let page = SANITY as *mut Page;
+	let ret = unsafe { kernel::mem_alloc(page, 1, kernel::PROT_READ_WRITE) };
+	assert_eq!(ret.status, Return::OK, "sanity allocation failed");
+	let word = page.cast::<usize>();
+	unsafe {
+		ptr::write_volatile(word, 0x5a5a_a5a5);
+		assert_eq!(
+			ptr::read_volatile(word),
+			0x5a5a_a5a5,
+			"sanity page is broken"
+		);
+		let ret = kernel::mem_dealloc(page, 1);
+		assert_eq!(ret.status, Return::OK, "sanity deallocation failed");
+	}
+}
+
+/// Run a single case. Calls whose index is in `drop` are not made.
+fn run_case(seed: u64, cfg: &Config, verbose: bool) {
+	let mut rng = Rng(seed);
+	fill_scratch(&mut rng);
+	for i in 0..cfg.calls {
+		let call = Call::generate(&mut rng);
+		let dropped = i < 64 && cfg.drop & 1 << i > 0;
+		if call.is_masked(cfg.skip | EXIT) || dropped {
+			continue;
+		}
+		if verbose {
+			sys_log!("fuzz: call {}: {} {:#x?}", i, call.nr, call.args);
+		}
+		let ret = unsafe { call.run() };
+		if verbose {
+			sys_log!("fuzz:   -> {} {:#x}", ret.status, ret.value);
+		}
+		check_sanity();
+	}
+}
+
+#[export_name = "main"]
+fn main() {
+	let cfg = match Config::parse() {
+		Ok(cfg) => cfg,
+		Err(InvalidArgument) => {
+			sys_log!("fuzz: invalid arguments");
+			rtbegin::args().for_each(|a| sys_log!("  {:?}", core::str::from_utf8(a)));
+			let _ = unsafe { kernel::task_exit() };
+			return;
+		}
+	};
+
+	let ret =
+		unsafe { kernel::mem_alloc(SCRATCH as *mut _, SCRATCH_PAGES, kernel::PROT_READ_WRITE) };
+	assert_eq!(ret.status, Return::OK, "failed to allocate scratch pages");
+
+	if let Some(seed) = cfg.replay {
+		sys_log!("fuzz: replaying case {:#x}, {} calls", seed, cfg.calls);
+		run_case(seed, &cfg, true);
+	} else {
+		let seed = cfg
+			.seed
+			.unwrap_or_else(|| unsafe { kernel::sys_time() }.value as u64);
+		sys_log!(
+			"fuzz: seed {:#x}, {} cases of {} calls, skipping {:#x}",
+			seed,
+			cfg.iterations,
+			cfg.calls,
+			cfg.skip
+		);
+		let mut seeds = Rng(seed);
+		for i in 0..cfg.iterations {
+			let seed = seeds.next();
+			sys_log!("fuzz: case {} seed {:#x}", i, seed);
+			run_case(seed, &cfg, false);
+		}
+	}
+
+	sys_log!("fuzz: done");
+	let _ = unsafe { kernel::task_exit() };
+}
@@ -0,0 +1,133 @@
+//! # Thread test
+//!
+//! Spawns a second thread. Both threads increment a shared counter and send packets to an
+//! address without a task, which the kernel returns to the received ring the threads share.
+//! Every packet must come back exactly once & unchanged, though not necessarily to the thread
+//! that sent it. It is spawned by b0 if `thread-test` is passed on the kernel command line.
+//!
+//! "thread_test: ok" is logged if the counter & all packets are correct, "thread_test: FAILED"
+//! otherwise. `scripts/thread_test_qemu.py` checks for these & for kernel panics.
+
+#![no_std]
+#![no_main]
+#![feature(asm)]
+#![feature(global_asm)]
+#![feature(naked_functions)]
+#![feature(panic_info_message)]
+
+#[panic_handler]
+fn panic_handler(info: &core::panic::PanicInfo) -> ! {
+	kernel::sys_log!("Panic!");
+	if let Some(m) = info.message() {
+		kernel::sys_log!("  Message: {}", m);
+	}
+	if let Some(l) = info.location() {
+		kernel::sys_log!("  Location: {}", l);
+	}
+	loop {}
+}
+
+mod rtbegin;
+
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use kernel::{ipc, sys_log};
+
+/// The amount of threads, including the main thread.
+const THREADS: usize = 2;
+
+/// The amount of packets each thread sends. The ID of a packet is only 8 bits.
+const PACKETS: usize = 64;
+
+/// The amount of times each thread increments the counter per packet.
+const INCREMENTS: usize = 256;
+
+/// An address without a task, so packets sent to it are returned as undeliverable.
+const NOBODY: usize = 0x7fff_fff0_0000_0000;
+
+static COUNTER: AtomicUsize = AtomicUsize::new(0);
+/// Whether the packet with the ID at the index was received.
+static RECEIVED: [AtomicBool; THREADS * PACKETS] = {
+	const FALSE: AtomicBool = AtomicBool::new(false);
+	[FALSE; THREADS * PACKETS]
+};
+/// The amount of packets that were modified, received twice or not returned by the kernel.
+static BAD_PACKETS: AtomicUsize = AtomicUsize::new(0);
+/// The amount of threads that are done.
+static DONE: AtomicUsize = AtomicUsize::new(0);
+
+#[export_name = "main"]
+fn main() {
+	// FIXME move this to rtbegin
+	unsafe { dux::init() };
+
+	for thread in 1..THREADS {
+		dux::thread::spawn(work, thread).expect("failed to spawn thread");
+	}
+	work(0);
+	while DONE.load(Ordering::Acquire) < THREADS {
+		unsafe { kernel::io_wait(0) };
+	}
+
+	let mut failed = 0;
+	let count = COUNTER.load(Ordering::Relaxed);
+	if count != THREADS * PACKETS * INCREMENTS {
+		sys_log!("thread_test: counter is {}", count);
+		failed += 1;
+	}
+	let bad = BAD_PACKETS.load(Ordering::Relaxed);
+	if bad > 0 {
+		sys_log!("thread_test: {} bad packets", bad);
+		failed += 1;
+	}
+	let missing = RECEIVED
+		.iter()
+		.filter(|r| !r.load(Ordering::Relaxed))
+		.count();
+	if missing > 0 {
+		sys_log!("thread_test: {} packets were not received", missing);
+		failed += 1;
+	}
+
+	if failed == 0 {
+		sys_log!("thread_test: ok");
+	} else {
+		sys_log!("thread_test: FAILED {} cases", failed);
+	}
+	dux::task::exit()
+}
+
+/// Increment the counter and send & receive packets.
+fn work(thread: usize) {
+	for i in 0..PACKETS {
+		for _ in 0..INCREMENTS {
+			COUNTER.fetch_add(1, Ordering::Relaxed);
+		}
+		let id = (thread * PACKETS + i) as u8;
+		*dux::ipc::transmit() = ipc::Packet {
+			flags: 0,
+			id,
+			offset: check(id),
+			opcode: core::num::NonZeroU8::new(ipc::Op::Write as u8),
+			uuid: ipc::UUID::INVALID,
+			data: None,
+			length: 0,
+			name: None,
+			name_len: 0,
+			address: NOBODY,
+		};
+		// This may be a packet sent by another thread.
+		let rx = dux::ipc::receive();
+		let received = RECEIVED
+			.get(usize::from(rx.id))
+			.map_or(true, |r| r.swap(true, Ordering::Relaxed));
+		if received || rx.offset != check(rx.id) || rx.flags & ipc::FLAG_DEAD_PEER == 0 {
+			BAD_PACKETS.fetch_add(1, Ordering::Relaxed);
+		}
+	}
+	DONE.fetch_add(1, Ordering::Release);
+}
+
+/// A value derived from the ID of a packet, which is put in the offset to detect corruption.
+fn check(id: u8) -> u64 {
+	u64::from(id).wrapping_mul(0x9e37_79b9_7f4a_7c15)
+}
@@ -732,8 +732,8 @@ pub(crate) struct LfnBuffer {
     ucs2_units: Vec<u16>,
 }
 
-#[cfg(not(feature = "alloc"))]
-const MAX_LFN_LEN: usize = 256;
+// A long name of 255 units takes up 20 LFN entries
+const MAX_LFN_LEN: usize = 20 * LFN_PART_LEN;
 
 #[cfg(all(feature = "lfn", not(feature = "alloc")))]
 #[derive(Clone)]
@@ -789,6 +789,7 @@ impl LfnBuffer {
         };
         for (i, usc2_unit) in usc2_units.enumerate() {
             lfn.ucs2_units[i] = usc2_unit;
+            lfn.len = i + 1;
         }
         lfn
     }
@@ -807,7 +808,7 @@ impl LfnBuffer {
     }
 
     pub(crate) fn as_ucs2_units(&self) -> &[u16] {
-        &self.ucs2_units
+        &self.ucs2_units[..self.len]
     }
 }
 
@@ -857,7 +858,7 @@ impl LongNameBuilder {
 
     fn truncate(&mut self) {
         // Truncate 0 and 0xFFFF characters from LFN buffer
-        let ucs2_units = &self.buf.ucs2_units;
+        let ucs2_units = &self.buf.ucs2_units[..self.buf.len()];
         let new_len = ucs2_units
             .iter()
             .rposition(|c| *c != 0xFFFF && *c != 0)
@@ -874,7 +875,7 @@ impl LongNameBuilder {
     fn process(&mut self, data: &DirLfnEntryData) {
         let is_last = (data.order() & LFN_ENTRY_LAST_FLAG) != 0;
         let index = data.order() & 0x1F;
-        if index == 0 {
+        if index == 0 || usize::from(index) > MAX_LFN_LEN / LFN_PART_LEN {
             // Corrupted entry
             warn!("currupted lfn entry! {:x}", data.order());
             self.clear();
@@ -1044,7 +1045,7 @@ impl ShortNameGenerator {
         let mut short_name = [SFN_PADDING; SFN_SIZE];
         // find extension after last dot
         // Note: short file name cannot start with the extension
-        let dot_index_opt = name[1..].rfind('.').map(|index| index + 1);
+        let dot_index_opt = name.rfind('.').filter(|index| *index > 0);
         // copy basename (part of filename before a dot)
         let basename_src = dot_index_opt.map_or(name, |dot_index| &name[..dot_index]);
         let (basename_len, basename_fits, basename_lossy) =